@@ -1,18 +1,23 @@
 // Copyright 2021-2022 Colin Finck <colin@reactos.org>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+use crate::attribute_definition::NtfsAttributeDefinition;
 use crate::attribute_value::{
     NtfsAttributeListNonResidentAttributeValue, NtfsAttributeValue, NtfsNonResidentAttributeValue,
     NtfsResidentAttributeValue,
 };
 use crate::error::{NtfsError, Result};
 use crate::file::NtfsFile;
+#[cfg(feature = "std")]
+use crate::runlist::NtfsRunlist;
 use crate::string::NtfsString;
 use crate::structured_values::{
     NtfsAttributeList, NtfsAttributeListEntries, NtfsStructuredValue,
     NtfsStructuredValueFromResidentAttributeValue,
 };
 use crate::types::Vcn;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
 use binread::io::{Read, Seek};
 use bitflags::bitflags;
 use byteorder::{ByteOrder, LittleEndian};
@@ -97,6 +102,30 @@ struct NtfsNonResidentAttributeHeader {
     initialized_size: u64,
 }
 
+/// Size of all [`NtfsResidentAttributeHeader`] fields plus a padding byte.
+/// The name or value of a resident attribute begins here.
+#[cfg(feature = "std")]
+const RESIDENT_ATTRIBUTE_HEADER_SIZE: usize = 24;
+
+/// Size of all [`NtfsNonResidentAttributeHeader`] fields.
+/// The name or Data Runs of a non-resident attribute begin here, unless the attribute is compressed or sparse.
+#[cfg(feature = "std")]
+const NON_RESIDENT_ATTRIBUTE_HEADER_SIZE: usize = 64;
+
+/// Compressed and sparse non-resident attributes have an additional 8-byte field after the
+/// [`NtfsNonResidentAttributeHeader`], which contains the number of bytes actually allocated on the filesystem.
+#[cfg(feature = "std")]
+const COMPRESSED_NON_RESIDENT_ATTRIBUTE_HEADER_SIZE: usize = 72;
+
+/// All attributes of a File Record are aligned to 8-byte boundaries.
+#[cfg(feature = "std")]
+const ATTRIBUTE_ALIGNMENT: usize = 8;
+
+/// Size of the end marker (an attribute type of 0xFFFF_FFFF) after the last attribute of a File Record,
+/// including the padding to the attribute alignment.
+#[cfg(feature = "std")]
+pub(crate) const ATTRIBUTE_END_MARKER_SIZE: usize = 8;
+
 /// All known NTFS Attribute types.
 ///
 /// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/attributes/index.html>
@@ -196,6 +225,40 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
         LittleEndian::read_u32(&self.file.record_data()[start..])
     }
 
+    /// Returns the [`NtfsAttributeDefinition`] of the type of this NTFS Attribute, as defined by the
+    /// $AttrDef file of the volume.
+    ///
+    /// Returns `None` if [`Ntfs::read_attribute_definitions`] had not been called or the type is not
+    /// defined.
+    ///
+    /// [`Ntfs::read_attribute_definitions`]: crate::Ntfs::read_attribute_definitions
+    pub fn definition(&self) -> Option<&'n NtfsAttributeDefinition> {
+        let type_code = self.type_code();
+        self.file
+            .ntfs()
+            .attribute_definition_table()?
+            .iter()
+            .find(|definition| definition.type_code() == type_code)
+    }
+
+    /// Returns the allocated size and the data size of the value of this attribute, as duplicated in the
+    /// $FILE_NAME structures of directory entries.
+    ///
+    /// A resident value counts as allocated up to the next 8-byte boundary.
+    #[cfg(feature = "std")]
+    pub(crate) fn directory_entry_sizes(&self) -> (u64, u64) {
+        if self.is_resident() {
+            let data_size = self.value_length();
+            let allocated_size = (data_size + 7) & !7;
+            (allocated_size, data_size)
+        } else {
+            (
+                self.non_resident_value_allocated_size(),
+                self.value_length(),
+            )
+        }
+    }
+
     pub(crate) fn ensure_ty(&self, expected: NtfsAttributeType) -> Result<()> {
         let ty = self.ty()?;
         if ty != expected {
@@ -272,7 +335,9 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
             self.file.ntfs(),
             data,
             position,
+            self.flags(),
             self.non_resident_value_data_size(),
+            self.non_resident_value_initialized_size(),
         )
     }
 
@@ -286,6 +351,13 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
         (data, position)
     }
 
+    #[cfg(feature = "std")]
+    pub(crate) fn non_resident_value_allocated_size(&self) -> u64 {
+        debug_assert!(!self.is_resident());
+        let start = self.offset + offset_of!(NtfsNonResidentAttributeHeader, allocated_size);
+        LittleEndian::read_u64(&self.file.record_data()[start..])
+    }
+
     fn non_resident_value_data_size(&self) -> u64 {
         debug_assert!(!self.is_resident());
         let start = self.offset + offset_of!(NtfsNonResidentAttributeHeader, data_size);
@@ -298,6 +370,19 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
         LittleEndian::read_u16(&self.file.record_data()[start..])
     }
 
+    pub(crate) fn non_resident_value_initialized_size(&self) -> u64 {
+        debug_assert!(!self.is_resident());
+        let start = self.offset + offset_of!(NtfsNonResidentAttributeHeader, initialized_size);
+        LittleEndian::read_u64(&self.file.record_data()[start..])
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn non_resident_value_lowest_vcn(&self) -> Vcn {
+        debug_assert!(!self.is_resident());
+        let start = self.offset + offset_of!(NtfsNonResidentAttributeHeader, lowest_vcn);
+        Vcn::from(LittleEndian::read_i64(&self.file.record_data()[start..]))
+    }
+
     pub(crate) fn offset(&self) -> usize {
         self.offset
     }
@@ -328,6 +413,8 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
             });
         }
 
+        self.validate_definition()?;
+
         let resident_value = self.resident_value()?;
         S::from_resident_attribute_value(resident_value)
     }
@@ -340,7 +427,14 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
         let end = start + self.resident_value_length() as usize;
         let data = &self.file.record_data()[start..end];
 
-        Ok(NtfsResidentAttributeValue::new(data, self.position()))
+        let position = self.file.position() + start as u64;
+
+        Ok(NtfsResidentAttributeValue::new(
+            self.file,
+            self.offset,
+            data,
+            position,
+        ))
     }
 
     fn resident_value_length(&self) -> u32 {
@@ -349,7 +443,7 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
         LittleEndian::read_u32(&self.file.record_data()[start..])
     }
 
-    fn resident_value_offset(&self) -> u16 {
+    pub(crate) fn resident_value_offset(&self) -> u16 {
         debug_assert!(self.is_resident());
         let start = self.offset + offset_of!(NtfsResidentAttributeHeader, value_offset);
         LittleEndian::read_u16(&self.file.record_data()[start..])
@@ -369,11 +463,137 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
         S::from_attribute_value(fs, self.value()?)
     }
 
+    /// Returns the raw bytes of this non-resident attribute as an extent that covers the clusters of `runlist`,
+    /// starting at `lowest_vcn` of the value.
+    ///
+    /// The header (including any name) and all sizes are copied, only the Data Runs, the length, and the VCN range
+    /// are updated.
+    /// This is used to move the clusters of an extent or to merge the extents of a value.
+    #[cfg(feature = "std")]
+    pub(crate) fn to_non_resident_extent_bytes(
+        &self,
+        lowest_vcn: Vcn,
+        runlist: &NtfsRunlist,
+    ) -> Vec<u8> {
+        debug_assert!(!self.is_resident());
+        let data_runs_offset = self.non_resident_value_data_runs_offset() as usize;
+
+        let start = self.offset;
+        let end = start + data_runs_offset;
+        let mut bytes = self.file.record_data()[start..end].to_vec();
+        bytes.extend_from_slice(&runlist.encode());
+        bytes.resize(align_to_attribute(bytes.len()), 0);
+        set_attribute_length(&mut bytes);
+
+        let lowest_vcn = i64::from(lowest_vcn);
+        let highest_vcn = lowest_vcn + runlist.cluster_count() as i64 - 1;
+        LittleEndian::write_i64(
+            &mut bytes[offset_of!(NtfsNonResidentAttributeHeader, lowest_vcn)..],
+            lowest_vcn,
+        );
+        LittleEndian::write_i64(
+            &mut bytes[offset_of!(NtfsNonResidentAttributeHeader, highest_vcn)..],
+            highest_vcn,
+        );
+
+        bytes
+    }
+
+    /// Returns the raw bytes of this resident attribute with its value replaced by `value`.
+    #[cfg(feature = "std")]
+    pub(crate) fn to_resident_bytes_with_value(&self, value: &[u8]) -> Result<Vec<u8>> {
+        let mut bytes = self.to_resized_resident_bytes(value.len() as u32)?;
+        let start = self.resident_value_offset() as usize;
+        bytes[start..start + value.len()].copy_from_slice(value);
+
+        Ok(bytes)
+    }
+
+    /// Returns the raw bytes of this non-resident attribute with new Data Runs and sizes.
+    ///
+    /// The header (including any name) is copied, only the Data Runs, the length, the highest VCN,
+    /// and the sizes are updated.
+    /// The attribute must not be split over multiple attributes.
+    #[cfg(feature = "std")]
+    pub(crate) fn to_resized_non_resident_bytes(
+        &self,
+        runlist: &NtfsRunlist,
+        data_size: u64,
+        initialized_size: u64,
+    ) -> Vec<u8> {
+        debug_assert!(!self.is_resident());
+        let cluster_size = self.file.ntfs().cluster_size() as u64;
+        let data_runs_offset = self.non_resident_value_data_runs_offset() as usize;
+
+        let start = self.offset;
+        let end = start + data_runs_offset;
+        let mut bytes = self.file.record_data()[start..end].to_vec();
+        bytes.extend_from_slice(&runlist.encode());
+        bytes.resize(align_to_attribute(bytes.len()), 0);
+
+        let cluster_count = runlist.cluster_count();
+        set_attribute_length(&mut bytes);
+        set_non_resident_sizes(
+            &mut bytes,
+            cluster_count,
+            cluster_count * cluster_size,
+            data_size,
+            initialized_size,
+        );
+
+        // Compressed and sparse attributes also store the number of bytes actually allocated on the filesystem.
+        if self
+            .flags()
+            .intersects(NtfsAttributeFlags::COMPRESSED | NtfsAttributeFlags::SPARSE)
+            && data_runs_offset >= COMPRESSED_NON_RESIDENT_ATTRIBUTE_HEADER_SIZE
+        {
+            let allocated_cluster_count: u64 = runlist
+                .iter()
+                .filter(|run| !run.is_sparse())
+                .map(|run| run.cluster_count())
+                .sum();
+            LittleEndian::write_u64(
+                &mut bytes[NON_RESIDENT_ATTRIBUTE_HEADER_SIZE..],
+                allocated_cluster_count * cluster_size,
+            );
+        }
+
+        bytes
+    }
+
+    /// Returns the raw bytes of this resident attribute with its value truncated or zero-extended
+    /// to `value_length` bytes.
+    #[cfg(feature = "std")]
+    pub(crate) fn to_resized_resident_bytes(&self, value_length: u32) -> Result<Vec<u8>> {
+        debug_assert!(self.is_resident());
+        self.validate_resident_value_sizes()?;
+
+        // Copy the header and the name.
+        let start = self.offset;
+        let value_start = start + self.resident_value_offset() as usize;
+        let mut bytes = self.file.record_data()[start..value_start].to_vec();
+
+        // Copy as much of the value as we keep.
+        let copy_length = u32::min(self.resident_value_length(), value_length) as usize;
+        bytes.extend_from_slice(&self.file.record_data()[value_start..value_start + copy_length]);
+
+        let value_end = value_start - start + value_length as usize;
+        bytes.resize(align_to_attribute(value_end), 0);
+
+        set_attribute_length(&mut bytes);
+        let value_length_start = offset_of!(NtfsResidentAttributeHeader, value_length);
+        LittleEndian::write_u32(&mut bytes[value_length_start..], value_length);
+
+        Ok(bytes)
+    }
+
     /// Returns the type of this NTFS Attribute, or [`NtfsError::UnsupportedAttributeType`]
     /// if it's an unknown type.
+    ///
+    /// Use [`NtfsAttribute::ty_name`] to get the name of any type defined by the volume,
+    /// including those that are not part of [`NtfsAttributeType`].
     pub fn ty(&self) -> Result<NtfsAttributeType> {
-        let start = self.offset + offset_of!(NtfsAttributeHeader, ty);
-        let ty = LittleEndian::read_u32(&self.file.record_data()[start..]);
+        let ty = self.type_code();
 
         NtfsAttributeType::n(ty).ok_or(NtfsError::UnsupportedAttributeType {
             position: self.position(),
@@ -381,6 +601,40 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
         })
     }
 
+    /// Returns the name of the type of this NTFS Attribute (e.g. "$DATA"), as defined by the
+    /// $AttrDef file of the volume.
+    ///
+    /// This also works for types that [`NtfsAttributeType`] doesn't know.
+    /// Returns `None` under the same conditions as [`NtfsAttribute::definition`].
+    pub fn ty_name(&self) -> Option<NtfsString<'n>> {
+        self.definition().map(|definition| definition.name())
+    }
+
+    /// Returns the raw type code of this NTFS Attribute.
+    ///
+    /// Unlike [`NtfsAttribute::ty`], this also works for types that [`NtfsAttributeType`] doesn't know.
+    pub fn type_code(&self) -> u32 {
+        let start = self.offset + offset_of!(NtfsAttributeHeader, ty);
+        LittleEndian::read_u32(&self.file.record_data()[start..])
+    }
+
+    /// Validates this NTFS Attribute against its [`NtfsAttributeDefinition`] if
+    /// [`Ntfs::read_attribute_definitions`] has been called.
+    ///
+    /// [`Ntfs::read_attribute_definitions`]: crate::Ntfs::read_attribute_definitions
+    fn validate_definition(&self) -> Result<()> {
+        if self.file.ntfs().attribute_definition_table().is_none() {
+            return Ok(());
+        }
+
+        let definition = self.definition().ok_or(NtfsError::UndefinedAttributeType {
+            position: self.position(),
+            actual: self.type_code(),
+        })?;
+
+        definition.validate(self.position(), self.is_resident(), self.value_length())
+    }
+
     fn validate_name_sizes(&self) -> Result<()> {
         let start = self.name_offset();
         if start as u32 >= self.attribute_length() {
@@ -428,19 +682,30 @@ impl<'n, 'f> NtfsAttribute<'n, 'f> {
     }
 
     /// Returns an [`NtfsAttributeValue`] structure to read the value of this NTFS Attribute.
+    ///
+    /// If [`Ntfs::read_attribute_definitions`] has been called, this also validates the attribute against
+    /// the definition of its type.
+    ///
+    /// [`Ntfs::read_attribute_definitions`]: crate::Ntfs::read_attribute_definitions
     pub fn value(&self) -> Result<NtfsAttributeValue<'n, 'f>> {
+        self.validate_definition()?;
+
         if let Some(list_entries) = self.list_entries {
             // The first attribute reports the entire data size for all connected attributes
             // (remaining ones are set to zero).
             // Fortunately, we are the first attribute :)
             let data_size = self.non_resident_value_data_size();
+            let initialized_size = self.non_resident_value_initialized_size();
 
             let value = NtfsAttributeListNonResidentAttributeValue::new(
                 self.file.ntfs(),
+                self.position(),
+                self.flags(),
                 list_entries.clone(),
                 self.instance(),
                 self.ty()?,
                 data_size,
+                initialized_size,
             );
             Ok(NtfsAttributeValue::AttributeListNonResident(value))
         } else if self.is_resident() {
@@ -700,6 +965,187 @@ impl<'n, 'f> Iterator for NtfsAttributesRaw<'n, 'f> {
 
 impl<'n, 'f> FusedIterator for NtfsAttributesRaw<'n, 'f> {}
 
+#[cfg(feature = "std")]
+pub(crate) fn align_to_attribute(length: usize) -> usize {
+    (length + ATTRIBUTE_ALIGNMENT - 1) & !(ATTRIBUTE_ALIGNMENT - 1)
+}
+
+/// Builds the raw bytes of a new non-resident attribute whose value is stored in the clusters of `runlist`.
+///
+/// `name` is the UTF-16LE encoded attribute name (if any).
+#[cfg(feature = "std")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn build_non_resident_attribute(
+    type_code: u32,
+    name: &[u8],
+    flags: NtfsAttributeFlags,
+    instance: u16,
+    runlist: &NtfsRunlist,
+    cluster_size: u32,
+    data_size: u64,
+    initialized_size: u64,
+) -> Vec<u8> {
+    let name_offset = NON_RESIDENT_ATTRIBUTE_HEADER_SIZE;
+    let data_runs_offset = align_to_attribute(name_offset + name.len());
+
+    let mut bytes = vec![0u8; data_runs_offset];
+    bytes[name_offset..name_offset + name.len()].copy_from_slice(name);
+    bytes.extend_from_slice(&runlist.encode());
+    bytes.resize(align_to_attribute(bytes.len()), 0);
+
+    set_attribute_header(&mut bytes, type_code, name, name_offset, flags, instance);
+    bytes[offset_of!(NtfsAttributeHeader, is_non_resident)] = 1;
+    LittleEndian::write_u16(
+        &mut bytes[offset_of!(NtfsNonResidentAttributeHeader, data_runs_offset)..],
+        data_runs_offset as u16,
+    );
+
+    let cluster_count = runlist.cluster_count();
+    set_non_resident_sizes(
+        &mut bytes,
+        cluster_count,
+        cluster_count * cluster_size as u64,
+        data_size,
+        initialized_size,
+    );
+
+    bytes
+}
+
+/// Builds the raw bytes of a new resident attribute with the given value.
+///
+/// `name` is the UTF-16LE encoded attribute name (if any).
+/// `indexed` must be set for attributes referenced in an index (i.e. $FILE_NAME attributes).
+#[cfg(feature = "std")]
+pub(crate) fn build_resident_attribute(
+    type_code: u32,
+    name: &[u8],
+    flags: NtfsAttributeFlags,
+    instance: u16,
+    value: &[u8],
+    indexed: bool,
+) -> Vec<u8> {
+    let name_offset = RESIDENT_ATTRIBUTE_HEADER_SIZE;
+    let value_offset = align_to_attribute(name_offset + name.len());
+
+    let mut bytes = vec![0u8; value_offset];
+    bytes[name_offset..name_offset + name.len()].copy_from_slice(name);
+    bytes.extend_from_slice(value);
+    bytes.resize(align_to_attribute(bytes.len()), 0);
+
+    set_attribute_header(&mut bytes, type_code, name, name_offset, flags, instance);
+    LittleEndian::write_u32(
+        &mut bytes[offset_of!(NtfsResidentAttributeHeader, value_length)..],
+        value.len() as u32,
+    );
+    LittleEndian::write_u16(
+        &mut bytes[offset_of!(NtfsResidentAttributeHeader, value_offset)..],
+        value_offset as u16,
+    );
+    bytes[offset_of!(NtfsResidentAttributeHeader, indexed_flag)] = indexed as u8;
+
+    bytes
+}
+
+/// Returns the name stored in the given raw attribute bytes.
+#[cfg(feature = "std")]
+pub(crate) fn raw_attribute_name(bytes: &[u8]) -> NtfsString<'_> {
+    let name_length = bytes[offset_of!(NtfsAttributeHeader, name_length)] as usize;
+    if name_length == 0 {
+        return NtfsString(&[]);
+    }
+
+    let start = LittleEndian::read_u16(&bytes[offset_of!(NtfsAttributeHeader, name_offset)..]);
+    let start = start as usize;
+    let end = start + name_length * mem::size_of::<u16>();
+    NtfsString(&bytes[start..end])
+}
+
+/// Returns the type code stored in the given raw attribute bytes.
+#[cfg(feature = "std")]
+pub(crate) fn raw_attribute_type_code(bytes: &[u8]) -> u32 {
+    LittleEndian::read_u32(&bytes[offset_of!(NtfsAttributeHeader, ty)..])
+}
+
+#[cfg(feature = "std")]
+fn set_attribute_header(
+    bytes: &mut [u8],
+    type_code: u32,
+    name: &[u8],
+    name_offset: usize,
+    flags: NtfsAttributeFlags,
+    instance: u16,
+) {
+    LittleEndian::write_u32(&mut bytes[offset_of!(NtfsAttributeHeader, ty)..], type_code);
+    set_attribute_length(bytes);
+    bytes[offset_of!(NtfsAttributeHeader, name_length)] =
+        (name.len() / mem::size_of::<u16>()) as u8;
+
+    if !name.is_empty() {
+        LittleEndian::write_u16(
+            &mut bytes[offset_of!(NtfsAttributeHeader, name_offset)..],
+            name_offset as u16,
+        );
+    }
+
+    LittleEndian::write_u16(
+        &mut bytes[offset_of!(NtfsAttributeHeader, flags)..],
+        flags.bits(),
+    );
+    LittleEndian::write_u16(
+        &mut bytes[offset_of!(NtfsAttributeHeader, instance)..],
+        instance,
+    );
+}
+
+/// Sets the attribute instance number in the given raw attribute bytes.
+#[cfg(feature = "std")]
+pub(crate) fn set_raw_attribute_instance(bytes: &mut [u8], instance: u16) {
+    LittleEndian::write_u16(
+        &mut bytes[offset_of!(NtfsAttributeHeader, instance)..],
+        instance,
+    );
+}
+
+/// Sets the length field of the attribute header to the length of the given raw attribute bytes.
+#[cfg(feature = "std")]
+fn set_attribute_length(bytes: &mut [u8]) {
+    let length = bytes.len() as u32;
+    LittleEndian::write_u32(
+        &mut bytes[offset_of!(NtfsAttributeHeader, length)..],
+        length,
+    );
+}
+
+#[cfg(feature = "std")]
+fn set_non_resident_sizes(
+    bytes: &mut [u8],
+    cluster_count: u64,
+    allocated_size: u64,
+    data_size: u64,
+    initialized_size: u64,
+) {
+    // The highest VCN of an empty value is -1.
+    let highest_vcn = cluster_count as i64 - 1;
+
+    LittleEndian::write_i64(
+        &mut bytes[offset_of!(NtfsNonResidentAttributeHeader, highest_vcn)..],
+        highest_vcn,
+    );
+    LittleEndian::write_u64(
+        &mut bytes[offset_of!(NtfsNonResidentAttributeHeader, allocated_size)..],
+        allocated_size,
+    );
+    LittleEndian::write_u64(
+        &mut bytes[offset_of!(NtfsNonResidentAttributeHeader, data_size)..],
+        data_size,
+    );
+    LittleEndian::write_u64(
+        &mut bytes[offset_of!(NtfsNonResidentAttributeHeader, initialized_size)..],
+        initialized_size,
+    );
+}
+
 #[cfg(test)]
 mod tests {
     use crate::indexes::NtfsFileNameIndex;
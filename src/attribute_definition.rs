@@ -0,0 +1,413 @@
+// Copyright 2021-2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::attribute::NtfsAttributeType;
+use crate::collation::NtfsCollationRule;
+use crate::error::{NtfsError, Result};
+use crate::file::KnownNtfsFileRecordNumber;
+use crate::ntfs::Ntfs;
+use crate::string::NtfsString;
+use crate::traits::NtfsReadSeek;
+use alloc::vec;
+use alloc::vec::Vec;
+use arrayvec::ArrayVec;
+use binread::io::{Cursor, Read, Seek};
+use binread::{BinRead, BinReaderExt};
+use bitflags::bitflags;
+#[cfg(feature = "std")]
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Maximum length of the name of an attribute definition, in UTF-16 code points.
+const ATTRIBUTE_DEFINITION_NAME_LENGTH: usize = 64;
+
+/// Size of a single entry in the $AttrDef file.
+const ATTRIBUTE_DEFINITION_SIZE: usize = 160;
+
+/// On-disk structure of a single entry in the $AttrDef file.
+#[derive(BinRead, Clone, Debug)]
+struct AttributeDefinitionHeader {
+    name: [u16; ATTRIBUTE_DEFINITION_NAME_LENGTH],
+    type_code: u32,
+    display_rule: u32,
+    collation_rule: u32,
+    flags: u32,
+    minimum_size: u64,
+    maximum_size: u64,
+}
+
+bitflags! {
+    /// Flags returned by [`NtfsAttributeDefinition::flags`].
+    pub struct NtfsAttributeDefinitionFlags: u32 {
+        /// The attribute can be indexed.
+        const INDEXABLE = 0x0000_0002;
+        /// A file may have multiple attributes of this type.
+        const MULTIPLE = 0x0000_0004;
+        /// The attribute value must contain at least one non-zero byte.
+        const NOT_ZERO = 0x0000_0008;
+        /// The attribute must be indexed and its value must be unique.
+        const INDEXED_UNIQUE = 0x0000_0010;
+        /// The attribute must be named and its name must be unique.
+        const NAMED_UNIQUE = 0x0000_0020;
+        /// The attribute must always be resident.
+        const RESIDENT = 0x0000_0040;
+        /// Modifications to the attribute are always logged, even if it is non-resident.
+        const ALWAYS_LOG = 0x0000_0080;
+    }
+}
+
+/// Attribute types of the $AttrDef file of a new filesystem, as written by Windows and mkntfs:
+/// Name, type, flags, minimum size, and maximum size (`u64::MAX` for unlimited).
+#[cfg(feature = "std")]
+const DEFAULT_ATTRIBUTE_DEFINITIONS: [(
+    &str,
+    NtfsAttributeType,
+    NtfsAttributeDefinitionFlags,
+    u64,
+    u64,
+); 15] = {
+    use NtfsAttributeDefinitionFlags as Flags;
+    use NtfsAttributeType as Type;
+
+    [
+        (
+            "$STANDARD_INFORMATION",
+            Type::StandardInformation,
+            Flags::RESIDENT,
+            0x30,
+            0x48,
+        ),
+        (
+            "$ATTRIBUTE_LIST",
+            Type::AttributeList,
+            Flags::ALWAYS_LOG,
+            0,
+            u64::MAX,
+        ),
+        (
+            "$FILE_NAME",
+            Type::FileName,
+            Flags::RESIDENT.union(Flags::INDEXABLE),
+            0x44,
+            0x242,
+        ),
+        ("$OBJECT_ID", Type::ObjectId, Flags::RESIDENT, 0, 0x100),
+        (
+            "$SECURITY_DESCRIPTOR",
+            Type::SecurityDescriptor,
+            Flags::ALWAYS_LOG,
+            0,
+            u64::MAX,
+        ),
+        ("$VOLUME_NAME", Type::VolumeName, Flags::RESIDENT, 2, 0x100),
+        (
+            "$VOLUME_INFORMATION",
+            Type::VolumeInformation,
+            Flags::RESIDENT,
+            0xc,
+            0xc,
+        ),
+        ("$DATA", Type::Data, Flags::empty(), 0, u64::MAX),
+        ("$INDEX_ROOT", Type::IndexRoot, Flags::RESIDENT, 0, u64::MAX),
+        (
+            "$INDEX_ALLOCATION",
+            Type::IndexAllocation,
+            Flags::ALWAYS_LOG,
+            0,
+            u64::MAX,
+        ),
+        ("$BITMAP", Type::Bitmap, Flags::ALWAYS_LOG, 0, u64::MAX),
+        (
+            "$REPARSE_POINT",
+            Type::ReparsePoint,
+            Flags::ALWAYS_LOG,
+            0,
+            0x4000,
+        ),
+        (
+            "$EA_INFORMATION",
+            Type::EAInformation,
+            Flags::RESIDENT,
+            8,
+            8,
+        ),
+        ("$EA", Type::EA, Flags::empty(), 0, 0x10000),
+        (
+            "$LOGGED_UTILITY_STREAM",
+            Type::LoggedUtilityStream,
+            Flags::ALWAYS_LOG,
+            0,
+            0x10000,
+        ),
+    ]
+};
+
+/// A single attribute definition of the $AttrDef file, describing the constraints of an attribute type.
+///
+/// Every NTFS volume defines the attribute types it supports in its $AttrDef file.
+/// Use [`Ntfs::attribute_definitions`] to get all of them.
+/// After calling [`Ntfs::read_attribute_definitions`], attributes are also validated against them.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/attrdef.html>
+#[derive(Clone, Debug)]
+pub struct NtfsAttributeDefinition {
+    name: ArrayVec<u8, { ATTRIBUTE_DEFINITION_NAME_LENGTH * 2 }>,
+    type_code: u32,
+    display_rule: u32,
+    collation_rule: u32,
+    flags: u32,
+    minimum_size: u64,
+    maximum_size: u64,
+}
+
+impl NtfsAttributeDefinition {
+    fn new(header: AttributeDefinitionHeader) -> Self {
+        let name = header
+            .name
+            .iter()
+            .take_while(|&&code_unit| code_unit != 0)
+            .flat_map(|code_unit| code_unit.to_le_bytes())
+            .collect();
+
+        Self {
+            name,
+            type_code: header.type_code,
+            display_rule: header.display_rule,
+            collation_rule: header.collation_rule,
+            flags: header.flags,
+            minimum_size: header.minimum_size,
+            maximum_size: header.maximum_size,
+        }
+    }
+
+    /// Returns the rule used to sort an index of this attribute type.
+    ///
+    /// Attribute types that cannot be indexed usually specify [`NtfsCollationRule::Binary`].
+    pub fn collation_rule(&self) -> Result<NtfsCollationRule> {
+        NtfsCollationRule::from_value(self.collation_rule, 0)
+    }
+
+    /// Returns the display rule of this attribute type, which is always zero in practice.
+    pub fn display_rule(&self) -> u32 {
+        self.display_rule
+    }
+
+    /// Returns flags set for this attribute type as specified by [`NtfsAttributeDefinitionFlags`].
+    pub fn flags(&self) -> NtfsAttributeDefinitionFlags {
+        NtfsAttributeDefinitionFlags::from_bits_truncate(self.flags)
+    }
+
+    /// Returns the maximum size of an attribute value of this type, in bytes,
+    /// or `None` if the size is unlimited.
+    pub fn maximum_size(&self) -> Option<u64> {
+        if self.maximum_size == u64::MAX {
+            None
+        } else {
+            Some(self.maximum_size)
+        }
+    }
+
+    /// Returns the minimum size of an attribute value of this type, in bytes.
+    pub fn minimum_size(&self) -> u64 {
+        self.minimum_size
+    }
+
+    /// Returns the name of this attribute type (e.g. "$DATA") as an [`NtfsString`].
+    pub fn name(&self) -> NtfsString<'_> {
+        NtfsString(&self.name)
+    }
+
+    /// Returns the type of this attribute definition, or `None` if the [`NtfsAttributeType`] enum
+    /// doesn't know it.
+    ///
+    /// Use [`NtfsAttributeDefinition::type_code`] to get the raw type code.
+    pub fn ty(&self) -> Option<NtfsAttributeType> {
+        NtfsAttributeType::n(self.type_code)
+    }
+
+    /// Returns the raw type code of this attribute definition, as stored in the attribute header.
+    pub fn type_code(&self) -> u32 {
+        self.type_code
+    }
+
+    pub(crate) fn validate(&self, position: u64, is_resident: bool, size: u64) -> Result<()> {
+        if !is_resident
+            && self
+                .flags()
+                .contains(NtfsAttributeDefinitionFlags::RESIDENT)
+        {
+            return Err(NtfsError::UnexpectedNonResidentAttribute { position });
+        }
+
+        // Windows creates an empty $VOLUME_NAME attribute for unnamed volumes, although its minimum size is 2.
+        // Hence, empty resident values are accepted regardless of the minimum size.
+        let is_empty_resident = is_resident && size == 0;
+
+        if (size < self.minimum_size && !is_empty_resident) || size > self.maximum_size {
+            return Err(NtfsError::InvalidAttributeValueSize {
+                position,
+                type_code: self.type_code,
+                minimum: self.minimum_size,
+                maximum: self.maximum_size,
+                actual: size,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds the raw contents of the $AttrDef file of a new filesystem, including the terminating zero entry.
+#[cfg(feature = "std")]
+pub(crate) fn build_attribute_definitions() -> Vec<u8> {
+    let mut data = vec![0u8; (DEFAULT_ATTRIBUTE_DEFINITIONS.len() + 1) * ATTRIBUTE_DEFINITION_SIZE];
+
+    for (entry, (name, ty, flags, minimum_size, maximum_size)) in data
+        .chunks_exact_mut(ATTRIBUTE_DEFINITION_SIZE)
+        .zip(DEFAULT_ATTRIBUTE_DEFINITIONS.iter())
+    {
+        for (i, code_unit) in name.encode_utf16().enumerate() {
+            LittleEndian::write_u16(&mut entry[i * 2..], code_unit);
+        }
+
+        // The display rule and collation rule are always zero.
+        let start = ATTRIBUTE_DEFINITION_NAME_LENGTH * 2;
+        LittleEndian::write_u32(&mut entry[start..], *ty as u32);
+        LittleEndian::write_u32(&mut entry[start + 12..], flags.bits());
+        LittleEndian::write_u64(&mut entry[start + 16..], *minimum_size);
+        LittleEndian::write_u64(&mut entry[start + 24..], *maximum_size);
+    }
+
+    data
+}
+
+/// Reads all entries of the $AttrDef file of the given filesystem.
+pub(crate) fn read_attribute_definitions<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+) -> Result<Vec<NtfsAttributeDefinition>>
+where
+    T: Read + Seek,
+{
+    // Lookup the $AttrDef file and its $DATA attribute.
+    let attrdef_file = ntfs.file(fs, KnownNtfsFileRecordNumber::AttrDef as u64)?;
+    let data_item = attrdef_file
+        .data(fs, "")
+        .ok_or(NtfsError::AttributeNotFound {
+            position: attrdef_file.position(),
+            ty: NtfsAttributeType::Data,
+        })??;
+
+    // Read the entire raw data from the $DATA attribute.
+    // It's just a few KiB, with a single entry for every attribute type.
+    let data_attribute = data_item.to_attribute();
+    let mut data_value = data_attribute.value()?;
+    let mut data = vec![0u8; data_attribute.value_length() as usize];
+    data_value.read_exact(fs, &mut data)?;
+
+    let mut definitions = Vec::new();
+
+    for entry in data.chunks_exact(ATTRIBUTE_DEFINITION_SIZE) {
+        let mut cursor = Cursor::new(entry);
+        let header = cursor.read_le::<AttributeDefinitionHeader>()?;
+
+        // The list is terminated by an entry with a zero type code (or simply the end of the file).
+        if header.type_code == 0 {
+            break;
+        }
+
+        definitions.push(NtfsAttributeDefinition::new(header));
+    }
+
+    Ok(definitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attribute_definitions() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+
+        let definitions = ntfs.attribute_definitions(&mut testfs1).unwrap();
+        assert_eq!(definitions.len(), 15);
+
+        let standard_information = &definitions[0];
+        assert_eq!(standard_information.name(), "$STANDARD_INFORMATION");
+        assert_eq!(
+            standard_information.ty(),
+            Some(NtfsAttributeType::StandardInformation)
+        );
+        assert!(standard_information
+            .flags()
+            .contains(NtfsAttributeDefinitionFlags::RESIDENT));
+        assert_eq!(standard_information.minimum_size(), 48);
+        assert_eq!(standard_information.maximum_size(), Some(72));
+
+        let file_name = &definitions[2];
+        assert_eq!(file_name.name(), "$FILE_NAME");
+        assert!(file_name
+            .flags()
+            .contains(NtfsAttributeDefinitionFlags::INDEXABLE));
+        assert_eq!(
+            file_name.collation_rule().unwrap(),
+            NtfsCollationRule::Binary
+        );
+
+        let data = definitions
+            .iter()
+            .find(|definition| definition.ty() == Some(NtfsAttributeType::Data))
+            .unwrap();
+        assert_eq!(data.name(), "$DATA");
+        assert_eq!(data.maximum_size(), None);
+
+        // After storing the definitions, every attribute is validated against them.
+        ntfs.read_attribute_definitions(&mut testfs1).unwrap();
+
+        // Check all system files, which cover most of the attribute types.
+        for file_record_number in 0..=KnownNtfsFileRecordNumber::Extend as u64 {
+            let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+
+            for attribute in file.attributes_raw() {
+                attribute.value().unwrap();
+
+                let ty_name = attribute.ty_name().unwrap();
+                let expected_name = definitions
+                    .iter()
+                    .find(|definition| definition.type_code() == attribute.type_code())
+                    .unwrap()
+                    .name();
+                assert_eq!(ty_name, expected_name);
+            }
+        }
+    }
+    #[test]
+    fn test_validate() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let ntfs = Ntfs::new(&mut testfs1).unwrap();
+
+        let definitions = ntfs.attribute_definitions(&mut testfs1).unwrap();
+        let volume_name = definitions
+            .iter()
+            .find(|definition| definition.ty() == Some(NtfsAttributeType::VolumeName))
+            .unwrap();
+        assert_eq!(volume_name.minimum_size(), 2);
+
+        // An empty $VOLUME_NAME is valid, but any other value below the minimum size is not.
+        volume_name.validate(0, true, 0).unwrap();
+        volume_name.validate(0, true, 2).unwrap();
+        assert!(matches!(
+            volume_name.validate(0, true, 1),
+            Err(NtfsError::InvalidAttributeValueSize { actual: 1, .. })
+        ));
+        assert!(matches!(
+            volume_name.validate(0, true, 0x102),
+            Err(NtfsError::InvalidAttributeValueSize { .. })
+        ));
+        assert!(matches!(
+            volume_name.validate(0, false, 0),
+            Err(NtfsError::UnexpectedNonResidentAttribute { .. })
+        ));
+    }
+}
@@ -6,14 +6,20 @@
 // Connected attributes are stored in a way that the first attribute reports the entire data size and all further attributes report a zero value length.
 // We have to go down to the Data Run level to get trustable lengths again, and this is what `NtfsAttributeListNonResidentAttributeValue` does here.
 
+#[cfg(feature = "std")]
+use super::{ensure_initialized, ensure_writable};
 use super::{DataRunsState, NtfsDataRuns, StreamState};
-use crate::attribute::{NtfsAttribute, NtfsAttributeType};
+use crate::attribute::{NtfsAttribute, NtfsAttributeFlags, NtfsAttributeType};
 use crate::error::{NtfsError, Result};
 use crate::file::NtfsFile;
 use crate::ntfs::Ntfs;
 use crate::structured_values::{NtfsAttributeListEntries, NtfsAttributeListEntry};
 use crate::traits::NtfsReadSeek;
+#[cfg(feature = "std")]
+use crate::traits::NtfsWrite;
 use binread::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "std")]
+use std::io::Write;
 
 /// Reader for a non-resident attribute value that is part of an Attribute List.
 ///
@@ -24,12 +30,19 @@ use binread::io::{Read, Seek, SeekFrom};
 pub struct NtfsAttributeListNonResidentAttributeValue<'n, 'f> {
     /// Reference to the base `Ntfs` object of this filesystem.
     ntfs: &'n Ntfs,
+    /// Absolute position of the first connected attribute within the filesystem, in bytes.
+    position: u64,
+    /// Flags of the first connected attribute.
+    flags: NtfsAttributeFlags,
     /// An untouched copy of the `attribute_list_entries` passed in [`Self::new`] to rewind to the beginning when desired.
     initial_attribute_list_entries: NtfsAttributeListEntries<'n, 'f>,
     /// Iterator through all connected attributes of this attribute in the Attribute List.
     connected_entries: AttributeListConnectedEntries<'n, 'f>,
     /// Total length of the value data, in bytes.
     data_size: u64,
+    /// Size of the initialized part of the value data, in bytes.
+    /// Windows reads everything beyond as zeros.
+    initialized_size: u64,
     /// File, location, and data runs iteration state of the current attribute.
     attribute_state: Option<AttributeState<'n>>,
     /// Iteration state of the current Data Run.
@@ -37,21 +50,28 @@ pub struct NtfsAttributeListNonResidentAttributeValue<'n, 'f> {
 }
 
 impl<'n, 'f> NtfsAttributeListNonResidentAttributeValue<'n, 'f> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         ntfs: &'n Ntfs,
+        position: u64,
+        flags: NtfsAttributeFlags,
         attribute_list_entries: NtfsAttributeListEntries<'n, 'f>,
         instance: u16,
         ty: NtfsAttributeType,
         data_size: u64,
+        initialized_size: u64,
     ) -> Self {
         let connected_entries =
             AttributeListConnectedEntries::new(attribute_list_entries.clone(), instance, ty);
 
         Self {
             ntfs,
+            position,
+            flags,
             initial_attribute_list_entries: attribute_list_entries,
             connected_entries,
             data_size,
+            initialized_size,
             attribute_state: None,
             stream_state: StreamState::new(data_size),
         }
@@ -261,6 +281,59 @@ impl<'n, 'f> NtfsReadSeek for NtfsAttributeListNonResidentAttributeValue<'n, 'f>
     }
 }
 
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl<'n, 'f> NtfsWrite for NtfsAttributeListNonResidentAttributeValue<'n, 'f> {
+    /// Overwrites existing data of this value at the current seek position.
+    ///
+    /// This neither changes the length of the value nor allocates clusters.
+    /// Hence, writing to a compressed or encrypted value, writing to a sparse Data Run, and writing beyond
+    /// the initialized size of the value fail with an error.
+    fn write<T>(&mut self, fs: &mut T, buf: &[u8]) -> Result<usize>
+    where
+        T: Read + Write + Seek,
+    {
+        ensure_writable(self.flags, self.position)?;
+        ensure_initialized(
+            self.position,
+            self.stream_position(),
+            buf.len(),
+            self.data_size,
+            self.initialized_size,
+        )?;
+
+        let mut bytes_written = 0usize;
+
+        while bytes_written < buf.len() {
+            // Write to the current Data Run if there is one.
+            if self
+                .stream_state
+                .write_data_run(fs, buf, &mut bytes_written, self.position)?
+            {
+                // We wrote something, so check the loop condition again if we need to write more.
+                continue;
+            }
+
+            // Move to the next Data Run of the current attribute.
+            if self.next_data_run()? {
+                // We got another Data Run of the current attribute, so write again.
+                continue;
+            }
+
+            // Move to the first Data Run of the next connected attribute.
+            if self.next_attribute(fs)? {
+                // We got another attribute, so write again.
+                continue;
+            } else {
+                // We wrote everything we could.
+                break;
+            }
+        }
+
+        Ok(bytes_written)
+    }
+}
+
 #[derive(Clone, Debug)]
 struct AttributeListConnectedEntries<'n, 'f> {
     attribute_list_entries: Option<NtfsAttributeListEntries<'n, 'f>>,
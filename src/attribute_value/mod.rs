@@ -16,6 +16,10 @@ use binread::io::{Read, Seek, SeekFrom};
 
 use crate::error::{NtfsError, Result};
 use crate::traits::NtfsReadSeek;
+#[cfg(feature = "std")]
+use crate::traits::NtfsWrite;
+#[cfg(feature = "std")]
+use std::io::Write;
 
 /// Reader that abstracts over all attribute value types, returned by [`NtfsAttribute::value`].
 ///
@@ -100,6 +104,21 @@ impl<'n, 'f> NtfsReadSeek for NtfsAttributeValue<'n, 'f> {
     }
 }
 
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl<'n, 'f> NtfsWrite for NtfsAttributeValue<'n, 'f> {
+    fn write<T>(&mut self, fs: &mut T, buf: &[u8]) -> Result<usize>
+    where
+        T: Read + Write + Seek,
+    {
+        match self {
+            Self::Resident(inner) => inner.write(fs, buf),
+            Self::NonResident(inner) => inner.write(fs, buf),
+            Self::AttributeListNonResident(inner) => inner.write(fs, buf),
+        }
+    }
+}
+
 /// A variant of [`NtfsAttributeValue`] that implements [`Read`] and [`Seek`]
 /// by mutably borrowing the filesystem reader.
 #[derive(Debug)]
@@ -158,6 +177,21 @@ where
     }
 }
 
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl<'n, 'f, 'a, T> Write for NtfsAttributeValueAttached<'n, 'f, 'a, T>
+where
+    T: Read + Write + Seek,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.value.write(self.fs, buf).map_err(io::Error::from)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.fs.flush()
+    }
+}
+
 pub(crate) fn seek_contiguous(
     stream_position: &mut u64,
     length: u64,
@@ -191,3 +225,252 @@ pub(crate) fn seek_contiguous(
         ))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::NtfsAttribute;
+    use crate::file::KnownNtfsFileRecordNumber;
+    use crate::indexes::NtfsFileNameIndex;
+    use crate::ntfs::Ntfs;
+    use crate::runlist::NtfsRunlist;
+
+    #[test]
+    fn test_write_non_resident_value() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let original_image = testfs1.get_ref().clone();
+        let ntfs = Ntfs::new(&mut testfs1).unwrap();
+
+        // Overwrite a few bytes of the $UpCase table across a cluster boundary.
+        let upcase_file = ntfs
+            .file(&mut testfs1, KnownNtfsFileRecordNumber::UpCase as u64)
+            .unwrap();
+        let data_item = upcase_file.data(&mut testfs1, "").unwrap().unwrap();
+        let data_attribute = data_item.to_attribute();
+        let mut data_value = data_attribute.value().unwrap();
+
+        data_value
+            .seek(&mut testfs1, SeekFrom::Start(1020))
+            .unwrap();
+        data_value.write_all(&mut testfs1, b"patched!").unwrap();
+        assert_eq!(data_value.stream_position(), 1028);
+
+        let mut buf = [0u8; 8];
+        data_value
+            .seek(&mut testfs1, SeekFrom::Start(1020))
+            .unwrap();
+        data_value.read_exact(&mut testfs1, &mut buf).unwrap();
+        assert_eq!(&buf, b"patched!");
+
+        // Nothing else has been changed.
+        let changed_bytes = original_image
+            .iter()
+            .zip(testfs1.get_ref())
+            .filter(|(a, b)| a != b)
+            .count();
+        assert!(changed_bytes <= buf.len());
+
+        // Writes stop at the end of the value.
+        let len = data_value.len();
+        data_value.seek(&mut testfs1, SeekFrom::End(-2)).unwrap();
+        assert_eq!(data_value.write(&mut testfs1, b"abcd").unwrap(), 2);
+        assert_eq!(data_value.stream_position(), len);
+        assert_eq!(data_value.write(&mut testfs1, b"abcd").unwrap(), 0);
+
+        // The $Bad stream of $BadClus is a single sparse Data Run over the entire volume.
+        // It reads as zeros and cannot be written to.
+        let badclus_file = ntfs
+            .file(&mut testfs1, KnownNtfsFileRecordNumber::BadClus as u64)
+            .unwrap();
+        let bad_item = badclus_file.data(&mut testfs1, "$Bad").unwrap().unwrap();
+        let bad_attribute = bad_item.to_attribute();
+        let mut bad_value = bad_attribute.value().unwrap();
+        assert_eq!(bad_value.len(), ntfs.size());
+
+        let mut buf = [0xffu8; 16];
+        bad_value.read_exact(&mut testfs1, &mut buf).unwrap();
+        assert_eq!(buf, [0u8; 16]);
+
+        // It is also entirely uninitialized.
+        bad_value.seek(&mut testfs1, SeekFrom::Start(0)).unwrap();
+        assert!(matches!(
+            bad_value.write(&mut testfs1, b"abcd"),
+            Err(NtfsError::UnsupportedWriteBeyondInitializedSize {
+                offset: 0,
+                initialized_size: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_write_beyond_initialized_size() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+
+        let data = vec![0xaau8; 4096];
+        let mut file = ntfs
+            .create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "partial.dat",
+            )
+            .unwrap();
+        file.write_data_stream(&mut testfs1, &mut cluster_allocator, "", &data)
+            .unwrap();
+
+        // Only initialize the first 1000 bytes, like Windows does for preallocated files.
+        let offset = file.find_data_attribute_offset("").unwrap();
+        let attribute = NtfsAttribute::new(&file, offset, None);
+        let data_runs = attribute.non_resident_value().unwrap().data_runs();
+        let runlist = NtfsRunlist::from_data_runs(data_runs).unwrap();
+        let bytes = attribute.to_resized_non_resident_bytes(&runlist, 4096, 1000);
+        file.replace_attribute(offset, &bytes).unwrap();
+        file.write_record(&mut testfs1).unwrap();
+
+        let data_item = file.data(&mut testfs1, "").unwrap().unwrap();
+        let data_attribute = data_item.to_attribute();
+        let mut data_value = data_attribute.value().unwrap();
+        assert_eq!(data_value.write(&mut testfs1, b"abcd").unwrap(), 4);
+
+        // Writes reaching beyond the initialized size are refused as a whole.
+        for (position, expected_offset) in [(998, 1000), (2000, 2000)].iter() {
+            data_value
+                .seek(&mut testfs1, SeekFrom::Start(*position))
+                .unwrap();
+            assert!(matches!(
+                data_value.write(&mut testfs1, b"abcd"),
+                Err(NtfsError::UnsupportedWriteBeyondInitializedSize {
+                    offset,
+                    initialized_size: 1000,
+                    ..
+                }) if offset == *expected_offset
+            ));
+        }
+
+        let mut buf = [0u8; 4];
+        data_value.seek(&mut testfs1, SeekFrom::Start(996)).unwrap();
+        data_value.read_exact(&mut testfs1, &mut buf).unwrap();
+        assert_eq!(buf, [0xaa; 4]);
+
+        // Writes ending exactly at the initialized size are fine.
+        data_value.seek(&mut testfs1, SeekFrom::Start(996)).unwrap();
+        assert_eq!(data_value.write(&mut testfs1, b"abcd").unwrap(), 4);
+    }
+
+    #[test]
+    fn test_write_resident_value_of_changed_record() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+
+        let mut file = ntfs
+            .create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "ads.txt",
+            )
+            .unwrap();
+        file.write_data_stream(&mut testfs1, &mut cluster_allocator, "b", b"12345")
+            .unwrap();
+
+        let data_item = file.data(&mut testfs1, "b").unwrap().unwrap();
+        let data_attribute = data_item.to_attribute();
+        let mut data_value = data_attribute.value().unwrap();
+
+        // Insert another stream in front of the value after it has been read.
+        let mut changed_file = ntfs.file(&mut testfs1, file.file_record_number()).unwrap();
+        changed_file
+            .write_data_stream(&mut testfs1, &mut cluster_allocator, "a", b"abcdefgh")
+            .unwrap();
+
+        // The write must still end up in the value and not at its old offset.
+        data_value.seek(&mut testfs1, SeekFrom::Start(1)).unwrap();
+        assert_eq!(data_value.write(&mut testfs1, b"xyz").unwrap(), 3);
+
+        let mut changed_file = ntfs.file(&mut testfs1, file.file_record_number()).unwrap();
+        for (name, expected) in [("a", &b"abcdefgh"[..]), ("b", &b"1xyz5"[..])].iter() {
+            let data_item = changed_file.data(&mut testfs1, name).unwrap().unwrap();
+            let data_attribute = data_item.to_attribute();
+            let data_value = data_attribute.value().unwrap();
+            assert_eq!(data_value.len(), expected.len() as u64);
+
+            let mut buf = vec![0u8; expected.len()];
+            data_value
+                .attach(&mut testfs1)
+                .read_exact(&mut buf)
+                .unwrap();
+            assert_eq!(&buf, expected);
+        }
+
+        // A value whose length has changed in the meantime is not written to.
+        changed_file
+            .write_data_stream(&mut testfs1, &mut cluster_allocator, "b", b"123")
+            .unwrap();
+        assert!(matches!(
+            data_value.write(&mut testfs1, b"z"),
+            Err(NtfsError::AttributeValueLengthChanged {
+                expected: 5,
+                actual: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_write_resident_value() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let root_dir_index = root_dir.directory_index(&mut testfs1).unwrap();
+
+        {
+            let mut root_dir_finder = root_dir_index.finder();
+            let entry = NtfsFileNameIndex::find(
+                &mut root_dir_finder,
+                &ntfs,
+                &mut testfs1,
+                "file-with-5-zeros",
+            )
+            .unwrap()
+            .unwrap();
+            let file = entry.to_file(&ntfs, &mut testfs1).unwrap();
+            let data_item = file.data(&mut testfs1, "").unwrap().unwrap();
+            let data_attribute = data_item.to_attribute();
+            assert!(data_attribute.is_resident());
+
+            let mut data_value = data_attribute.value().unwrap();
+            data_value.seek(&mut testfs1, SeekFrom::Start(1)).unwrap();
+            assert_eq!(data_value.write(&mut testfs1, b"abc").unwrap(), 3);
+            assert_eq!(data_value.write(&mut testfs1, b"de").unwrap(), 1);
+            assert_eq!(data_value.write(&mut testfs1, b"f").unwrap(), 0);
+        }
+
+        // Read the file again to check that the changes have been written with proper fixups.
+        let mut root_dir_finder = root_dir_index.finder();
+        let entry = NtfsFileNameIndex::find(
+            &mut root_dir_finder,
+            &ntfs,
+            &mut testfs1,
+            "file-with-5-zeros",
+        )
+        .unwrap()
+        .unwrap();
+        let file = entry.to_file(&ntfs, &mut testfs1).unwrap();
+        let data_item = file.data(&mut testfs1, "").unwrap().unwrap();
+        let data_attribute = data_item.to_attribute();
+        let mut data_value = data_attribute.value().unwrap();
+
+        let mut buf = [0u8; 5];
+        data_value.read_exact(&mut testfs1, &mut buf).unwrap();
+        assert_eq!(&buf, b"\0abcd");
+    }
+}
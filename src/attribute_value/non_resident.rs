@@ -15,10 +15,15 @@ use binread::io::{Read, Seek, SeekFrom};
 use binread::BinRead;
 
 use super::seek_contiguous;
+use crate::attribute::NtfsAttributeFlags;
 use crate::error::{NtfsError, Result};
 use crate::ntfs::Ntfs;
 use crate::traits::NtfsReadSeek;
+#[cfg(feature = "std")]
+use crate::traits::NtfsWrite;
 use crate::types::{Lcn, Vcn};
+#[cfg(feature = "std")]
+use std::io::Write;
 
 /// Reader for a non-resident attribute value (whose data is in a cluster range outside the File Record).
 #[derive(Clone, Debug)]
@@ -29,6 +34,11 @@ pub struct NtfsNonResidentAttributeValue<'n, 'f> {
     data: &'f [u8],
     /// Absolute position of the Data Run information within the filesystem, in bytes.
     position: u64,
+    /// Flags of the attribute this value belongs to.
+    flags: NtfsAttributeFlags,
+    /// Size of the initialized part of the value data, in bytes.
+    /// Windows reads everything beyond as zeros.
+    initialized_size: u64,
     /// Iterator of data runs used for reading/seeking.
     stream_data_runs: NtfsDataRuns<'n, 'f>,
     /// Iteration state of the current Data Run.
@@ -40,7 +50,9 @@ impl<'n, 'f> NtfsNonResidentAttributeValue<'n, 'f> {
         ntfs: &'n Ntfs,
         data: &'f [u8],
         position: u64,
+        flags: NtfsAttributeFlags,
         data_size: u64,
+        initialized_size: u64,
     ) -> Result<Self> {
         let mut stream_data_runs = NtfsDataRuns::new(ntfs, data, position);
         let mut stream_state = StreamState::new(data_size);
@@ -55,6 +67,8 @@ impl<'n, 'f> NtfsNonResidentAttributeValue<'n, 'f> {
             ntfs,
             data,
             position,
+            flags,
+            initialized_size,
             stream_data_runs,
             stream_state,
         })
@@ -198,6 +212,53 @@ impl<'n, 'f> NtfsReadSeek for NtfsNonResidentAttributeValue<'n, 'f> {
     }
 }
 
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl<'n, 'f> NtfsWrite for NtfsNonResidentAttributeValue<'n, 'f> {
+    /// Overwrites existing data of this value at the current seek position.
+    ///
+    /// This neither changes the length of the value nor allocates clusters.
+    /// Hence, writing to a compressed or encrypted value, writing to a sparse Data Run, and writing beyond
+    /// the initialized size of the value fail with an error.
+    fn write<T>(&mut self, fs: &mut T, buf: &[u8]) -> Result<usize>
+    where
+        T: Read + Write + Seek,
+    {
+        ensure_writable(self.flags, self.position)?;
+        ensure_initialized(
+            self.position,
+            self.stream_position(),
+            buf.len(),
+            self.len(),
+            self.initialized_size,
+        )?;
+
+        let mut bytes_written = 0usize;
+
+        while bytes_written < buf.len() {
+            // Write to the current Data Run if there is one.
+            if self
+                .stream_state
+                .write_data_run(fs, buf, &mut bytes_written, self.position)?
+            {
+                // We wrote something, so check the loop condition again if we need to write more.
+                continue;
+            }
+
+            // Move to the next Data Run.
+            if self.next_data_run()? {
+                // We got another Data Run, so write again.
+                continue;
+            } else {
+                // We wrote everything we could.
+                break;
+            }
+        }
+
+        Ok(bytes_written)
+    }
+}
+
 /// A variant of [`NtfsNonResidentAttributeValue`] that implements [`Read`] and [`Seek`]
 /// by mutably borrowing the filesystem reader.
 #[derive(Debug)]
@@ -256,6 +317,21 @@ where
     }
 }
 
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl<'n, 'f, 'a, T> Write for NtfsNonResidentAttributeValueAttached<'n, 'f, 'a, T>
+where
+    T: Read + Write + Seek,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.value.write(self.fs, buf).map_err(io::Error::from)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.fs.flush()
+    }
+}
+
 /// Iterator over
 ///   all data runs of a non-resident attribute,
 ///   returning an [`NtfsDataRun`] for each entry,
@@ -303,6 +379,10 @@ impl<'n, 'f> NtfsDataRuns<'n, 'f> {
         self.state
     }
 
+    pub(crate) fn ntfs(&self) -> &'n Ntfs {
+        self.ntfs
+    }
+
     /// Returns the absolute position of the current Data Run header within the filesystem, in bytes.
     pub fn position(&self) -> u64 {
         self.position + self.state.offset as u64
@@ -383,19 +463,27 @@ impl<'n, 'f> Iterator for NtfsDataRuns<'n, 'f> {
 
         // The upper nibble indicates the length of the following VCN variable length integer.
         let vcn_byte_count = (header & 0xf0) >> 4;
-        let vcn = Vcn::from(iter_try!(
-            self.read_variable_length_signed_integer(&mut cursor, vcn_byte_count)
-        ));
-
-        // Turn the read VCN into an absolute LCN.
-        let lcn = iter_try!(self.state.previous_lcn.checked_add(vcn).ok_or({
-            NtfsError::InvalidVcnInDataRunHeader {
-                position: NtfsDataRuns::position(self),
-                vcn,
-                previous_lcn: self.state.previous_lcn,
-            }
-        }));
-        self.state.previous_lcn = lcn;
+
+        let lcn = if vcn_byte_count == 0 {
+            // A Data Run without a VCN is a "sparse" Data Run.
+            // It has no clusters on the filesystem and doesn't change the base for the next relative VCN.
+            Lcn::from(0)
+        } else {
+            let vcn = Vcn::from(iter_try!(
+                self.read_variable_length_signed_integer(&mut cursor, vcn_byte_count)
+            ));
+
+            // Turn the read VCN into an absolute LCN.
+            let lcn = iter_try!(self.state.previous_lcn.checked_add(vcn).ok_or({
+                NtfsError::InvalidVcnInDataRunHeader {
+                    position: NtfsDataRuns::position(self),
+                    vcn,
+                    previous_lcn: self.state.previous_lcn,
+                }
+            }));
+            self.state.previous_lcn = lcn;
+            lcn
+        };
 
         // Only advance after having checked for success.
         // In case of an error, a subsequent call shall output the same error again.
@@ -463,9 +551,39 @@ impl NtfsDataRun {
         }
     }
 
+    /// Returns `true` if this is a "sparse" Data Run, which has no clusters on the filesystem
+    /// and reads as zeros.
+    pub fn is_sparse(&self) -> bool {
+        self.position == 0
+    }
+
     pub(crate) fn remaining_len(&self) -> u64 {
         self.allocated_size().saturating_sub(self.stream_position)
     }
+
+    /// Overwrites data of this Data Run at the current seek position.
+    ///
+    /// The caller must have checked that this is not a sparse Data Run.
+    #[cfg(feature = "std")]
+    pub(crate) fn write<T>(&mut self, fs: &mut T, buf: &[u8]) -> Result<usize>
+    where
+        T: Read + Write + Seek,
+    {
+        debug_assert!(!self.is_sparse());
+
+        if self.remaining_len() == 0 {
+            return Ok(0);
+        }
+
+        let bytes_to_write = usize::min(buf.len(), self.remaining_len() as usize);
+
+        // We have already performed all necessary sanity checks above, so we can just unwrap here.
+        fs.seek(SeekFrom::Start(self.data_position().unwrap()))?;
+        let bytes_written = fs.write(&buf[..bytes_to_write])?;
+
+        self.stream_position += bytes_written as u64;
+        Ok(bytes_written)
+    }
 }
 
 impl NtfsReadSeek for NtfsDataRun {
@@ -480,7 +598,7 @@ impl NtfsReadSeek for NtfsDataRun {
         let bytes_to_read = usize::min(buf.len(), self.remaining_len() as usize);
         let work_slice = &mut buf[..bytes_to_read];
 
-        let bytes_read = if self.position == 0 {
+        let bytes_read = if self.is_sparse() {
             // This is a sparse Data Run.
             work_slice.fill(0);
             work_slice.len()
@@ -697,4 +815,120 @@ impl StreamState {
     pub(crate) fn stream_position(&self) -> u64 {
         self.stream_position
     }
+
+    /// Returns whether we wrote some bytes.
+    ///
+    /// `position` is the absolute position of the value, which is only used for error reporting.
+    #[cfg(feature = "std")]
+    pub(crate) fn write_data_run<T>(
+        &mut self,
+        fs: &mut T,
+        buf: &[u8],
+        bytes_written: &mut usize,
+        position: u64,
+    ) -> Result<bool>
+    where
+        T: Read + Write + Seek,
+    {
+        // Is there a Data Run to write to?
+        let data_run = match &mut self.stream_data_run {
+            Some(data_run) => data_run,
+            None => return Ok(false),
+        };
+
+        // Have we already seeked past the size of the Data Run?
+        if data_run.stream_position() >= data_run.allocated_size() {
+            return Ok(false);
+        }
+
+        // We also must not write past the (used) data size of the entire value.
+        let remaining_data_size = self.data_size.saturating_sub(self.stream_position);
+        if remaining_data_size == 0 {
+            return Ok(false);
+        }
+
+        // A sparse Data Run has no clusters we could write to.
+        if data_run.is_sparse() {
+            return Err(NtfsError::UnsupportedWriteToSparseDataRun {
+                position,
+                offset: self.stream_position,
+            });
+        }
+
+        // Write up to the buffer length or up to the (used) data size, whatever comes first.
+        let start = *bytes_written;
+        let remaining_buf_len = buf.len() - start;
+        let end = start + usize::min(remaining_buf_len, remaining_data_size as usize);
+
+        // Perform the actual write.
+        let bytes_written_in_data_run = data_run.write(fs, &buf[start..end])?;
+        *bytes_written += bytes_written_in_data_run;
+        self.stream_position += bytes_written_in_data_run as u64;
+
+        Ok(true)
+    }
+}
+
+/// Returns an error if writing `buf_len` bytes at `offset` of a value would write beyond its initialized size.
+///
+/// Windows reads all data beyond the initialized size as zeros, so such a write would silently be lost.
+/// Writes are limited to the data size of the value anyway, so only bytes before it are considered.
+#[cfg(feature = "std")]
+pub(crate) fn ensure_initialized(
+    position: u64,
+    offset: u64,
+    buf_len: usize,
+    data_size: u64,
+    initialized_size: u64,
+) -> Result<()> {
+    let end = u64::min(offset.saturating_add(buf_len as u64), data_size);
+
+    if end > initialized_size {
+        Err(NtfsError::UnsupportedWriteBeyondInitializedSize {
+            position,
+            offset: u64::max(offset, initialized_size),
+            initialized_size,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns an error if a value with the given attribute flags cannot be written to.
+#[cfg(feature = "std")]
+pub(crate) fn ensure_writable(flags: NtfsAttributeFlags, position: u64) -> Result<()> {
+    if flags.intersects(NtfsAttributeFlags::COMPRESSED | NtfsAttributeFlags::ENCRYPTED) {
+        Err(NtfsError::UnsupportedWriteToCompressedOrEncryptedValue { position })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_data_runs() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let ntfs = Ntfs::new(&mut testfs1).unwrap();
+        let cluster_size = ntfs.cluster_size() as u64;
+
+        // 4 clusters at LCN 16, 8 sparse clusters, and 2 clusters at LCN 20.
+        // The LCN of the last Data Run is relative to the one before the sparse Data Run.
+        let data = [0x11, 0x04, 0x10, 0x01, 0x08, 0x11, 0x02, 0x04, 0x00];
+        let data_runs = NtfsDataRuns::new(&ntfs, &data, 0)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(data_runs.len(), 3);
+
+        assert_eq!(data_runs[0].data_position(), Some(16 * cluster_size));
+        assert_eq!(data_runs[0].allocated_size(), 4 * cluster_size);
+
+        assert_eq!(data_runs[1].data_position(), None);
+        assert_eq!(data_runs[1].allocated_size(), 8 * cluster_size);
+
+        assert_eq!(data_runs[2].data_position(), Some(20 * cluster_size));
+        assert_eq!(data_runs[2].allocated_size(), 2 * cluster_size);
+    }
 }
@@ -8,22 +8,43 @@
 //! Further accesses to the record data can then happen via slices.
 
 use binread::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "std")]
+use std::io::Write;
 
 use super::seek_contiguous;
+#[cfg(feature = "std")]
+use crate::attribute::{NtfsAttribute, NtfsAttributeType};
+#[cfg(feature = "std")]
+use crate::error::NtfsError;
 use crate::error::Result;
+use crate::file::NtfsFile;
 use crate::traits::NtfsReadSeek;
+#[cfg(feature = "std")]
+use crate::traits::NtfsWrite;
 
 /// Reader for a value of a resident NTFS Attribute (which is entirely contained in the NTFS File Record).
 #[derive(Clone, Debug)]
 pub struct NtfsResidentAttributeValue<'f> {
+    /// The NTFS File Record this value is part of.
+    file: &'f NtfsFile<'f>,
+    /// Offset of the attribute this value belongs to within the File Record, in bytes.
+    attribute_offset: usize,
     data: &'f [u8],
+    /// Absolute position of the value within the filesystem, in bytes.
     position: u64,
     stream_position: u64,
 }
 
 impl<'f> NtfsResidentAttributeValue<'f> {
-    pub(crate) fn new(data: &'f [u8], position: u64) -> Self {
+    pub(crate) fn new(
+        file: &'f NtfsFile<'f>,
+        attribute_offset: usize,
+        data: &'f [u8],
+        position: u64,
+    ) -> Self {
         Self {
+            file,
+            attribute_offset,
             data,
             position,
             stream_position: 0,
@@ -97,3 +118,92 @@ impl<'f> NtfsReadSeek for NtfsResidentAttributeValue<'f> {
         self.stream_position
     }
 }
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl<'f> NtfsWrite for NtfsResidentAttributeValue<'f> {
+    /// Overwrites existing data of this value at the current seek position.
+    ///
+    /// This reads the NTFS File Record of the value again, patches it, and writes it back to the filesystem
+    /// (with proper multi-sector protection).
+    /// The length of the value is never changed.
+    ///
+    /// As the File Record may have changed in the meantime, the attribute is looked up again by its type and
+    /// instance.
+    /// This fails with [`NtfsError::AttributeValueLengthChanged`] if the length of its value differs from
+    /// the length of this value.
+    ///
+    /// Note that neither this value nor the [`NtfsFile`] it has been taken from are updated.
+    /// Get the [`NtfsFile`] again to see the changes.
+    fn write<T>(&mut self, fs: &mut T, buf: &[u8]) -> Result<usize>
+    where
+        T: Read + Write + Seek,
+    {
+        if self.remaining_len() == 0 {
+            return Ok(0);
+        }
+
+        let bytes_to_write = usize::min(buf.len(), self.remaining_len() as usize);
+
+        // Read the File Record from the filesystem again instead of using `self.file`.
+        // This way, we don't revert any changes that have been written since `self.file` has been read.
+        let mut file = NtfsFile::new(
+            self.file.ntfs(),
+            fs,
+            self.file.position(),
+            self.file.file_record_number(),
+        )?;
+
+        // Those changes may have moved our value to a different offset, so we can't reuse `self.position`.
+        let attribute = NtfsAttribute::new(self.file, self.attribute_offset, None);
+        let value_offset =
+            resident_value_offset(&file, attribute.ty()?, attribute.instance(), self.len())?;
+
+        let start = value_offset + self.stream_position as usize;
+        let end = start + bytes_to_write;
+        file.record_data_mut()[start..end].copy_from_slice(&buf[..bytes_to_write]);
+        file.write_record(fs)?;
+
+        self.stream_position += bytes_to_write as u64;
+        Ok(bytes_to_write)
+    }
+}
+
+/// Returns the offset of the value of the resident attribute with the given type and instance within the File Record.
+///
+/// Returns an error if there is no such resident attribute or if its value doesn't have the expected length.
+#[cfg(feature = "std")]
+fn resident_value_offset(
+    file: &NtfsFile,
+    ty: NtfsAttributeType,
+    instance: u16,
+    expected_len: u64,
+) -> Result<usize> {
+    for attribute in file.attributes_raw() {
+        if attribute.ty()? != ty || attribute.instance() != instance {
+            continue;
+        }
+
+        if !attribute.is_resident() {
+            return Err(NtfsError::UnexpectedNonResidentAttribute {
+                position: attribute.position(),
+            });
+        }
+
+        let value = attribute.resident_value()?;
+        if value.len() != expected_len {
+            return Err(NtfsError::AttributeValueLengthChanged {
+                position: attribute.position(),
+                expected: expected_len,
+                actual: value.len(),
+            });
+        }
+
+        return Ok((value.position - file.position()) as usize);
+    }
+
+    Err(NtfsError::AttributeNotFound {
+        position: file.position(),
+        ty,
+    })
+}
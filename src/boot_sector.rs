@@ -4,9 +4,17 @@
 use crate::error::{NtfsError, Result};
 use crate::types::Lcn;
 use binread::BinRead;
+#[cfg(feature = "std")]
+use byteorder::{ByteOrder, LittleEndian};
+#[cfg(feature = "std")]
+use core::convert::TryFrom;
 use core::ops::Range;
 use memoffset::offset_of;
 
+/// Size of the boot sector, which is always the first sector of an NTFS volume.
+#[cfg(feature = "std")]
+pub(crate) const BOOT_SECTOR_SIZE: usize = 512;
+
 // Sources:
 // - https://en.wikipedia.org/wiki/NTFS#Partition_Boot_Sector_(VBR)
 // - https://en.wikipedia.org/wiki/BIOS_parameter_block#NTFS
@@ -40,6 +48,47 @@ pub(crate) struct BiosParameterBlock {
 }
 
 impl BiosParameterBlock {
+    /// Creates a new BIOS Parameter Block for a volume with 512-byte sectors.
+    ///
+    /// The record size infos are encoded as described in [`record_size_info`].
+    #[cfg(feature = "std")]
+    pub(crate) fn new(
+        sectors_per_cluster: u8,
+        total_sectors: u64,
+        mft_lcn: Lcn,
+        mft_mirror_lcn: Lcn,
+        file_record_size_info: i8,
+        index_record_size_info: i8,
+        serial_number: u64,
+    ) -> Self {
+        Self {
+            sector_size: 512,
+            sectors_per_cluster,
+            zeros_1: [0; 7],
+            // Fixed disk
+            media: 0xf8,
+            zeros_2: [0; 2],
+            dummy_sectors_per_track: 0,
+            dummy_heads: 0,
+            hidden_sectors: 0,
+            zeros_3: 0,
+            // First hard disk
+            physical_drive_number: 0x80,
+            flags: 0,
+            extended_boot_signature: 0x80,
+            reserved: 0,
+            total_sectors,
+            mft_lcn,
+            mft_mirror_lcn,
+            file_record_size_info,
+            zeros_4: [0; 3],
+            index_record_size_info,
+            zeros_5: [0; 3],
+            serial_number,
+            checksum: 0,
+        }
+    }
+
     /// Returns the size of a single cluster, in bytes.
     pub(crate) fn cluster_size(&self) -> Result<u32> {
         /// The maximum cluster size supported by Windows is 2 MiB.
@@ -66,6 +115,10 @@ impl BiosParameterBlock {
         self.mft_lcn
     }
 
+    pub(crate) fn mft_mirror_lcn(&self) -> Lcn {
+        self.mft_mirror_lcn
+    }
+
     /// Source: https://en.wikipedia.org/wiki/NTFS#Partition_Boot_Sector_(VBR)
     fn record_size(&self, size_info: i8) -> Result<u32> {
         /// The usual exponent of `BiosParameterBlock::file_record_size_info` is 10 (2^10 = 1024 bytes).
@@ -125,9 +178,40 @@ impl BiosParameterBlock {
         self.serial_number
     }
 
+    #[cfg(feature = "std")]
+    pub(crate) fn set_mft_mirror_lcn(&mut self, mft_mirror_lcn: Lcn) {
+        self.mft_mirror_lcn = mft_mirror_lcn;
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_total_sectors(&mut self, total_sectors: u64) {
+        self.total_sectors = total_sectors;
+    }
+
     pub(crate) fn total_sectors(&self) -> u64 {
         self.total_sectors
     }
+
+    /// Writes the on-disk representation of this BIOS Parameter Block to `bytes`.
+    #[cfg(feature = "std")]
+    fn write_to(&self, bytes: &mut [u8]) {
+        LittleEndian::write_u16(&mut bytes[0..], self.sector_size);
+        bytes[2] = self.sectors_per_cluster;
+        bytes[10] = self.media;
+        LittleEndian::write_u16(&mut bytes[13..], self.dummy_sectors_per_track);
+        LittleEndian::write_u16(&mut bytes[15..], self.dummy_heads);
+        LittleEndian::write_u32(&mut bytes[17..], self.hidden_sectors);
+        bytes[25] = self.physical_drive_number;
+        bytes[26] = self.flags;
+        bytes[27] = self.extended_boot_signature;
+        LittleEndian::write_u64(&mut bytes[29..], self.total_sectors);
+        LittleEndian::write_u64(&mut bytes[37..], self.mft_lcn.into());
+        LittleEndian::write_u64(&mut bytes[45..], self.mft_mirror_lcn.into());
+        bytes[53] = self.file_record_size_info as u8;
+        bytes[57] = self.index_record_size_info as u8;
+        LittleEndian::write_u64(&mut bytes[61..], self.serial_number);
+        LittleEndian::write_u32(&mut bytes[69..], self.checksum);
+    }
 }
 
 #[allow(unused)]
@@ -141,10 +225,49 @@ pub(crate) struct BootSector {
 }
 
 impl BootSector {
+    /// Creates a new boot sector around the given BIOS Parameter Block.
+    ///
+    /// We don't ship a boot loader, so the boot code just halts the CPU.
+    #[cfg(feature = "std")]
+    pub(crate) fn new(bpb: BiosParameterBlock) -> Self {
+        let mut boot_code = [0; 426];
+        boot_code[..4].copy_from_slice(&[
+            0xfa, // cli
+            0xf4, // hlt
+            0xeb, 0xfd, // jmp short (back to hlt)
+        ]);
+
+        Self {
+            // jmp short to the boot code, followed by a nop
+            bootjmp: [0xeb, 0x52, 0x90],
+            oem_name: *b"NTFS    ",
+            bpb,
+            boot_code,
+            signature: [0x55, 0xAA],
+        }
+    }
+
     pub(crate) fn bpb(&self) -> &BiosParameterBlock {
         &self.bpb
     }
 
+    #[cfg(feature = "std")]
+    pub(crate) fn bpb_mut(&mut self) -> &mut BiosParameterBlock {
+        &mut self.bpb
+    }
+
+    /// Returns the raw bytes of this boot sector, as stored on the filesystem.
+    #[cfg(feature = "std")]
+    pub(crate) fn to_bytes(&self) -> [u8; BOOT_SECTOR_SIZE] {
+        let mut bytes = [0; BOOT_SECTOR_SIZE];
+        bytes[0..3].copy_from_slice(&self.bootjmp);
+        bytes[3..11].copy_from_slice(&self.oem_name);
+        self.bpb.write_to(&mut bytes[11..84]);
+        bytes[84..510].copy_from_slice(&self.boot_code);
+        bytes[510..512].copy_from_slice(&self.signature);
+        bytes
+    }
+
     pub(crate) fn validate(&self) -> Result<()> {
         // Validate the infamous [0x55, 0xAA] signature at the end of the boot sector.
         let expected_signature = &[0x55, 0xAA];
@@ -159,3 +282,21 @@ impl BootSector {
         Ok(())
     }
 }
+
+/// Encodes the given File Record or Index Record size for the BIOS Parameter Block, as the inverse of
+/// [`BiosParameterBlock::file_record_size`].
+///
+/// Sizes of at least one cluster are stored as a cluster count, smaller ones as a negated binary exponent.
+/// Returns `None` if the size cannot be encoded or would be rejected when reading the BIOS Parameter Block.
+#[cfg(feature = "std")]
+pub(crate) fn record_size_info(record_size: u32, cluster_size: u32) -> Option<i8> {
+    if !record_size.is_power_of_two() || record_size < 1024 {
+        return None;
+    }
+
+    if record_size >= cluster_size {
+        i8::try_from(record_size / cluster_size).ok()
+    } else {
+        Some(-(record_size.trailing_zeros() as i8))
+    }
+}
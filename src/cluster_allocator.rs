@@ -0,0 +1,509 @@
+// Copyright 2021-2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::attribute::{NtfsAttributeItem, NtfsAttributeType};
+use crate::error::{NtfsError, Result};
+use crate::file::{KnownNtfsFileRecordNumber, NtfsFile};
+#[cfg(feature = "std")]
+use crate::helpers::div_ceil;
+use crate::ntfs::Ntfs;
+use crate::runlist::{NtfsRun, NtfsRunlist};
+use crate::traits::NtfsReadSeek;
+#[cfg(feature = "std")]
+use crate::traits::NtfsWrite;
+use crate::types::Lcn;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use binread::io::SeekFrom;
+use binread::io::{Read, Seek};
+#[cfg(feature = "std")]
+use core::mem;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::io::Write;
+
+/// Windows reserves 1/8 of the volume (12.5%) after the start of the $MFT for its growth by default.
+const MFT_ZONE_DIVISOR: u64 = 8;
+
+/// Signature of the allocation functions of [`NtfsClusterAllocator`], used to choose between regular
+/// allocations and allocations for the $MFT.
+#[cfg(feature = "std")]
+pub(crate) type AllocateFn = fn(&mut NtfsClusterAllocator, u64) -> Result<NtfsRunlist>;
+
+/// Allocates and frees clusters of an NTFS volume, based on the $Bitmap file.
+///
+/// The $Bitmap file stores one bit for every cluster of the volume, which is set if the cluster is in use.
+/// [`NtfsClusterAllocator`] reads that bitmap into memory, and all allocations and frees only modify
+/// the in-memory copy.
+/// Call [`NtfsClusterAllocator::write`] to write the changes back to the filesystem.
+///
+/// Allocations return an [`NtfsRunlist`] describing the allocated clusters.
+/// Unless a contiguous allocation is requested, the allocator prefers a single run, but combines
+/// multiple free cluster ranges if necessary.
+///
+/// Like Windows, the allocator keeps an area after the start of the $MFT (the "MFT zone") free for the
+/// growth of the $MFT.
+/// Regular allocations only go there when the rest of the volume is full.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/bitmap.html>
+#[derive(Clone, Debug)]
+pub struct NtfsClusterAllocator {
+    bitmap: Vec<u8>,
+    cluster_count: u64,
+    mft_zone: Range<u64>,
+    /// Range of bytes in `bitmap` that have been modified since the last write.
+    modified_range: Option<Range<usize>>,
+}
+
+impl NtfsClusterAllocator {
+    /// Reads the $Bitmap file from the given filesystem into a new [`NtfsClusterAllocator`] object.
+    pub(crate) fn read<T>(ntfs: &Ntfs, fs: &mut T) -> Result<Self>
+    where
+        T: Read + Seek,
+    {
+        let bitmap_file = ntfs.file(fs, KnownNtfsFileRecordNumber::Bitmap as u64)?;
+        let data_item = Self::bitmap_data_item(&bitmap_file, fs)?;
+
+        // Read the entire raw data from the $DATA attribute.
+        let data_attribute = data_item.to_attribute();
+        let mut data_value = data_attribute.value()?;
+        let mut bitmap = vec![0u8; data_attribute.value_length() as usize];
+        data_value.read_exact(fs, &mut bitmap)?;
+
+        // Don't trust any bits of the bitmap after the last cluster of the volume.
+        let cluster_count = u64::min(
+            ntfs.size() / ntfs.cluster_size() as u64,
+            bitmap.len() as u64 * 8,
+        );
+
+        // The MFT zone begins at the first cluster of the $MFT.
+        let mft_lcn = ntfs.mft_position() / ntfs.cluster_size() as u64;
+        let mft_zone_start = u64::min(mft_lcn, cluster_count);
+        let mft_zone_end = u64::min(
+            mft_zone_start + cluster_count / MFT_ZONE_DIVISOR,
+            cluster_count,
+        );
+
+        Ok(Self {
+            bitmap,
+            cluster_count,
+            mft_zone: mft_zone_start..mft_zone_end,
+            modified_range: None,
+        })
+    }
+
+    /// Allocates `cluster_count` clusters, preferably in a single run.
+    ///
+    /// Among all free cluster ranges that are large enough, the smallest one is chosen ("best fit").
+    /// If there is none, the largest free cluster ranges are combined.
+    /// The MFT zone is only used if the clusters outside of it don't suffice.
+    pub fn allocate(&mut self, cluster_count: u64) -> Result<NtfsRunlist> {
+        let zones = [self.data_zones(), [self.mft_zone.clone(), 0..0]];
+        self.allocate_in_zones(cluster_count, &zones, false)
+    }
+
+    /// Allocates `cluster_count` clusters in a single run.
+    ///
+    /// This works like [`NtfsClusterAllocator::allocate`], but fails instead of splitting the allocation.
+    pub fn allocate_contiguous(&mut self, cluster_count: u64) -> Result<NtfsRunlist> {
+        let zones = [self.data_zones(), [self.mft_zone.clone(), 0..0]];
+        self.allocate_in_zones(cluster_count, &zones, true)
+    }
+
+    /// Allocates `cluster_count` clusters for growing the $MFT, preferably in a single run.
+    ///
+    /// This works like [`NtfsClusterAllocator::allocate`], but prefers the MFT zone over the rest of
+    /// the volume.
+    pub fn allocate_mft(&mut self, cluster_count: u64) -> Result<NtfsRunlist> {
+        let zones = [[self.mft_zone.clone(), 0..0], self.data_zones()];
+        self.allocate_in_zones(cluster_count, &zones, false)
+    }
+
+    fn allocate_in_zones(
+        &mut self,
+        cluster_count: u64,
+        zones: &[[Range<u64>; 2]],
+        contiguous: bool,
+    ) -> Result<NtfsRunlist> {
+        if cluster_count == 0 {
+            return Ok(NtfsRunlist::new());
+        }
+
+        let mut candidate_extents = Vec::new();
+
+        for zone in zones {
+            for range in zone {
+                candidate_extents.extend(self.free_extents(range.clone()));
+            }
+
+            // Prefer a single free cluster range that is large enough, wasting as few clusters as possible.
+            let best_fit = candidate_extents
+                .iter()
+                .filter(|extent| extent.end - extent.start >= cluster_count)
+                .min_by_key(|extent| extent.end - extent.start);
+
+            if let Some(best_fit) = best_fit {
+                let extent = best_fit.start..best_fit.start + cluster_count;
+                return Ok(self.mark_allocated(vec![extent]));
+            }
+
+            if !contiguous {
+                let available: u64 = candidate_extents
+                    .iter()
+                    .map(|extent| extent.end - extent.start)
+                    .sum();
+
+                if available >= cluster_count {
+                    // Combine the largest free cluster ranges to keep the number of runs low.
+                    candidate_extents
+                        .sort_by_key(|extent| core::cmp::Reverse(extent.end - extent.start));
+
+                    let mut extents = Vec::new();
+                    let mut remaining = cluster_count;
+
+                    for extent in candidate_extents {
+                        let length = u64::min(extent.end - extent.start, remaining);
+                        extents.push(extent.start..extent.start + length);
+
+                        remaining -= length;
+                        if remaining == 0 {
+                            break;
+                        }
+                    }
+
+                    // Keep the runs in ascending order on the disk for better sequential access.
+                    extents.sort_by_key(|extent| extent.start);
+                    return Ok(self.mark_allocated(extents));
+                }
+            }
+        }
+
+        let available = candidate_extents
+            .iter()
+            .map(|extent| extent.end - extent.start);
+
+        if contiguous {
+            Err(NtfsError::NotEnoughContiguousFreeClusters {
+                requested: cluster_count,
+                available: available.max().unwrap_or(0),
+            })
+        } else {
+            Err(NtfsError::NotEnoughFreeClusters {
+                requested: cluster_count,
+                available: available.sum(),
+            })
+        }
+    }
+
+    fn bitmap_data_item<'n, 'f, T>(
+        bitmap_file: &'f NtfsFile<'n>,
+        fs: &mut T,
+    ) -> Result<NtfsAttributeItem<'n, 'f>>
+    where
+        T: Read + Seek,
+    {
+        bitmap_file
+            .data(fs, "")
+            .ok_or(NtfsError::AttributeNotFound {
+                position: bitmap_file.position(),
+                ty: NtfsAttributeType::Data,
+            })?
+    }
+
+    /// Returns the total number of clusters of the volume.
+    pub fn cluster_count(&self) -> u64 {
+        self.cluster_count
+    }
+
+    /// Returns the cluster ranges outside of the MFT zone.
+    fn data_zones(&self) -> [Range<u64>; 2] {
+        [
+            0..self.mft_zone.start,
+            self.mft_zone.end..self.cluster_count,
+        ]
+    }
+
+    /// Frees all clusters of the given runlist.
+    ///
+    /// "Sparse" runs are skipped.
+    /// All clusters must currently be allocated, otherwise nothing is freed and an error is returned.
+    pub fn free(&mut self, runlist: &NtfsRunlist) -> Result<()> {
+        // Check all runs before modifying anything.
+        for run in runlist {
+            if let Some(lcn) = run.lcn() {
+                let start = u64::from(lcn);
+                let end = start.saturating_add(run.cluster_count());
+
+                if end > self.cluster_count || (start..end).any(|lcn| !self.is_set(lcn)) {
+                    return Err(NtfsError::ClustersNotAllocated {
+                        lcn,
+                        cluster_count: run.cluster_count(),
+                    });
+                }
+            }
+        }
+
+        for run in runlist {
+            if let Some(lcn) = run.lcn() {
+                let start = u64::from(lcn);
+                self.set_range(start..start + run.cluster_count(), false);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of free clusters of the volume.
+    pub fn free_cluster_count(&self) -> u64 {
+        self.free_extents(0..self.cluster_count)
+            .map(|extent| extent.end - extent.start)
+            .sum()
+    }
+
+    /// Returns an iterator over all free cluster ranges within the given range.
+    fn free_extents(&self, range: Range<u64>) -> impl Iterator<Item = Range<u64>> + '_ {
+        let mut lcn = range.start;
+        let end = u64::min(range.end, self.cluster_count);
+
+        core::iter::from_fn(move || {
+            // Skip allocated clusters, fully allocated bytes at once.
+            while lcn < end && self.is_set(lcn) {
+                if (lcn & 7) == 0 && self.bitmap[(lcn / 8) as usize] == 0xff {
+                    lcn += 8;
+                } else {
+                    lcn += 1;
+                }
+            }
+
+            if lcn >= end {
+                return None;
+            }
+
+            // Collect free clusters, fully free bytes at once.
+            let start = lcn;
+            while lcn < end && !self.is_set(lcn) {
+                if (lcn & 7) == 0 && self.bitmap[(lcn / 8) as usize] == 0 {
+                    lcn += 8;
+                } else {
+                    lcn += 1;
+                }
+            }
+
+            Some(start..u64::min(lcn, end))
+        })
+    }
+
+    /// Returns `true` if the cluster with the given Logical Cluster Number (LCN) is allocated.
+    ///
+    /// Clusters outside the volume are always reported as allocated.
+    pub fn is_allocated(&self, lcn: Lcn) -> bool {
+        let lcn = u64::from(lcn);
+        lcn >= self.cluster_count || self.is_set(lcn)
+    }
+
+    fn is_set(&self, lcn: u64) -> bool {
+        self.bitmap[(lcn / 8) as usize] & (1 << (lcn % 8)) != 0
+    }
+
+    fn mark_allocated(&mut self, extents: Vec<Range<u64>>) -> NtfsRunlist {
+        let mut runlist = NtfsRunlist::new();
+
+        for extent in extents {
+            runlist.push(NtfsRun::new(
+                Lcn::from(extent.start),
+                extent.end - extent.start,
+            ));
+            self.set_range(extent, true);
+        }
+
+        runlist
+    }
+
+    /// Returns the range of Logical Cluster Numbers (LCNs) reserved for the growth of the $MFT.
+    pub fn mft_zone(&self) -> Range<Lcn> {
+        Lcn::from(self.mft_zone.start)..Lcn::from(self.mft_zone.end)
+    }
+
+    /// Changes the number of clusters of the volume to `cluster_count`, e.g. when resizing the volume.
+    ///
+    /// The in-memory bitmap is resized to the length of a $Bitmap value for that many clusters.
+    /// Added clusters are free, and like Windows, all bits after the last cluster are marked as used.
+    /// Clusters that are cut off are simply dropped, so they can no longer be allocated or freed.
+    #[cfg(feature = "std")]
+    pub(crate) fn resize(&mut self, cluster_count: u64) {
+        let old_cluster_count = self.cluster_count;
+        let bitmap_size = bitmap_size(cluster_count);
+
+        self.bitmap.resize(bitmap_size as usize, 0);
+        self.cluster_count = cluster_count;
+        self.mft_zone.start = u64::min(self.mft_zone.start, cluster_count);
+        self.mft_zone.end = u64::min(self.mft_zone.end, cluster_count);
+
+        if let Some(modified_range) = &mut self.modified_range {
+            modified_range.end = usize::min(modified_range.end, bitmap_size as usize);
+        }
+
+        self.set_range(old_cluster_count..cluster_count, false);
+        self.set_range(cluster_count..bitmap_size * 8, true);
+    }
+
+    fn set_range(&mut self, range: Range<u64>, allocated: bool) {
+        if range.start >= range.end {
+            return;
+        }
+
+        for lcn in range.clone() {
+            let byte = &mut self.bitmap[(lcn / 8) as usize];
+            let mask = 1 << (lcn % 8);
+
+            if allocated {
+                *byte |= mask;
+            } else {
+                *byte &= !mask;
+            }
+        }
+
+        let modified_start = (range.start / 8) as usize;
+        let modified_end = ((range.end - 1) / 8 + 1) as usize;
+        self.modified_range = Some(match self.modified_range.take() {
+            Some(modified_range) => {
+                usize::min(modified_range.start, modified_start)
+                    ..usize::max(modified_range.end, modified_end)
+            }
+            None => modified_start..modified_end,
+        });
+    }
+
+    /// Writes all changes to the in-memory bitmap back to the $Bitmap file of the filesystem.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn write<T>(&mut self, ntfs: &Ntfs, fs: &mut T) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let modified_range = match &self.modified_range {
+            Some(modified_range) => modified_range.clone(),
+            None => return Ok(()),
+        };
+
+        let bitmap_file = ntfs.file(fs, KnownNtfsFileRecordNumber::Bitmap as u64)?;
+        let data_item = Self::bitmap_data_item(&bitmap_file, fs)?;
+        let data_attribute = data_item.to_attribute();
+        let mut data_value = data_attribute.value()?;
+
+        data_value.seek(fs, SeekFrom::Start(modified_range.start as u64))?;
+        data_value.write_all(fs, &self.bitmap[modified_range])?;
+
+        self.modified_range = None;
+        Ok(())
+    }
+}
+
+/// Returns the length of the $Bitmap value for a volume with `cluster_count` clusters, in bytes.
+///
+/// Like Windows, we always use a multiple of 8 bytes.
+#[cfg(feature = "std")]
+pub(crate) fn bitmap_size(cluster_count: u64) -> u64 {
+    div_ceil(cluster_count, 64) * mem::size_of::<u64>() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_allocator() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let ntfs = Ntfs::new(&mut testfs1).unwrap();
+        let mut allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+
+        assert_eq!(
+            allocator.cluster_count(),
+            ntfs.size() / ntfs.cluster_size() as u64
+        );
+        let free_cluster_count = allocator.free_cluster_count();
+        assert!(free_cluster_count > 0);
+
+        // The boot sector and the $MFT are always allocated.
+        assert!(allocator.is_allocated(Lcn::from(0)));
+        let mft_lcn = ntfs.mft_position() / ntfs.cluster_size() as u64;
+        assert!(allocator.is_allocated(Lcn::from(mft_lcn)));
+        assert_eq!(allocator.mft_zone().start, Lcn::from(mft_lcn));
+
+        // A small allocation fits into a single run outside of the MFT zone.
+        let runlist = allocator.allocate(5).unwrap();
+        assert_eq!(runlist.runs().len(), 1);
+        assert_eq!(runlist.cluster_count(), 5);
+        let run = runlist.runs()[0];
+        let lcn = run.lcn().unwrap();
+        assert!(!allocator.mft_zone().contains(&lcn));
+        assert!(allocator.is_allocated(lcn));
+        assert_eq!(allocator.free_cluster_count(), free_cluster_count - 5);
+
+        // The MFT zone of this small volume is entirely in use, so MFT allocations have to fall back
+        // to the rest of the volume.
+        let mft_runlist = allocator.allocate_mft(2).unwrap();
+        let mft_run_lcn = mft_runlist.runs()[0].lcn().unwrap();
+        assert!(!allocator.mft_zone().contains(&mft_run_lcn));
+
+        // Write the changes and read the bitmap again.
+        allocator.write(&ntfs, &mut testfs1).unwrap();
+        let mut allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        assert!(allocator.is_allocated(lcn));
+        assert!(allocator.is_allocated(mft_run_lcn));
+        assert_eq!(allocator.free_cluster_count(), free_cluster_count - 7);
+
+        // Free the clusters again, which may only be done once.
+        allocator.free(&runlist).unwrap();
+        allocator.free(&mft_runlist).unwrap();
+        assert!(!allocator.is_allocated(lcn));
+        assert!(matches!(
+            allocator.free(&runlist),
+            Err(NtfsError::ClustersNotAllocated { .. })
+        ));
+        assert_eq!(allocator.free_cluster_count(), free_cluster_count);
+
+        // Requesting more clusters than available fails without allocating anything.
+        assert!(matches!(
+            allocator.allocate(free_cluster_count + 1),
+            Err(NtfsError::NotEnoughFreeClusters { .. })
+        ));
+        assert_eq!(allocator.free_cluster_count(), free_cluster_count);
+
+        // Allocating all free clusters combines multiple runs.
+        let runlist = allocator.allocate(free_cluster_count).unwrap();
+        assert!(runlist.runs().len() > 1);
+        assert_eq!(runlist.cluster_count(), free_cluster_count);
+        assert_eq!(allocator.free_cluster_count(), 0);
+        assert!(matches!(
+            allocator.allocate_contiguous(1),
+            Err(NtfsError::NotEnoughContiguousFreeClusters { .. })
+        ));
+    }
+
+    #[test]
+    fn test_cluster_allocator_mft_zone() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let ntfs = Ntfs::new(&mut testfs1).unwrap();
+        let mut allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+
+        // Free the last 4 clusters of the MFT zone in memory (never written back).
+        let mft_zone_end = u64::from(allocator.mft_zone().end);
+        let mut zone_runlist = NtfsRunlist::new();
+        zone_runlist.push(NtfsRun::new(Lcn::from(mft_zone_end - 4), 4));
+        allocator.free(&zone_runlist).unwrap();
+
+        // Regular allocations still avoid the MFT zone, while MFT allocations prefer it.
+        let runlist = allocator.allocate(2).unwrap();
+        assert!(!allocator
+            .mft_zone()
+            .contains(&runlist.runs()[0].lcn().unwrap()));
+
+        let mft_runlist = allocator.allocate_mft(2).unwrap();
+        assert_eq!(
+            mft_runlist.runs(),
+            &[NtfsRun::new(Lcn::from(mft_zone_end - 4), 2)]
+        );
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright 2021 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::error::{NtfsError, Result};
+use crate::ntfs::Ntfs;
+use crate::string::{NtfsString, UpcaseOrd};
+use byteorder::{ByteOrder, LittleEndian};
+use core::cmp::Ordering;
+use core::mem;
+use enumn::N;
+
+/// Offset of the `name_length` field within a $FILE_NAME structure.
+const FILE_NAME_NAME_LENGTH_OFFSET: usize = 64;
+
+/// Offset of the actual name within a $FILE_NAME structure.
+const FILE_NAME_NAME_OFFSET: usize = 66;
+
+/// Rule to sort the keys of an NTFS index, returned by [`NtfsIndexRoot::collation_rule`].
+///
+/// Every index stores the rule that has been used to sort its B-tree in its [`NtfsIndexRoot`].
+/// Use [`NtfsCollationRule::compare`] to compare two raw keys according to that rule.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/attributes/index_root.html>
+///
+/// [`NtfsIndexRoot`]: crate::structured_values::NtfsIndexRoot
+/// [`NtfsIndexRoot::collation_rule`]: crate::structured_values::NtfsIndexRoot::collation_rule
+#[derive(Clone, Copy, Debug, Eq, N, PartialEq)]
+#[repr(u32)]
+pub enum NtfsCollationRule {
+    /// Keys are compared byte by byte.
+    Binary = 0x00,
+    /// Keys are $FILE_NAME structures, whose names are compared case-insensitively with respect to the
+    /// $UpCase table.
+    /// Names that only differ in case are then compared case-sensitively.
+    /// Used by the $I30 index of every directory.
+    FileName = 0x01,
+    /// Keys are UTF-16 strings, which are compared like the names of [`NtfsCollationRule::FileName`].
+    UnicodeString = 0x02,
+    /// Keys are a single little-endian `u32` value.
+    /// Used by the $SII index of $Secure and the $Q index of $Quota.
+    NtofsUlong = 0x10,
+    /// Keys are Security Identifiers (SIDs).
+    /// Used by the $O index of $Quota.
+    NtofsSid = 0x11,
+    /// Keys are a security descriptor hash followed by a security ID, both little-endian `u32` values.
+    /// Used by the $SDH index of $Secure.
+    NtofsSecurityHash = 0x12,
+    /// Keys are a sequence of little-endian `u32` values.
+    /// Used by the $O index of $ObjId and the $R index of $Reparse.
+    NtofsUlongs = 0x13,
+}
+
+impl NtfsCollationRule {
+    pub(crate) fn from_value(value: u32, position: u64) -> Result<Self> {
+        Self::n(value).ok_or(NtfsError::UnsupportedCollationRule {
+            position,
+            actual: value,
+        })
+    }
+
+    /// Compares the raw key `a` to the raw key `b` according to this collation rule.
+    ///
+    /// Keys that are too short for the structure expected by the collation rule are compared as far as possible,
+    /// and then by their length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is [`NtfsCollationRule::FileName`] or [`NtfsCollationRule::UnicodeString`] and
+    /// [`read_upcase_table`][Ntfs::read_upcase_table] had not been called on the passed [`Ntfs`] object.
+    pub fn compare(&self, ntfs: &Ntfs, a: &[u8], b: &[u8]) -> Ordering {
+        match self {
+            Self::Binary => a.cmp(b),
+            Self::FileName => Self::compare_unicode_strings(ntfs, file_name(a), file_name(b)),
+            Self::UnicodeString => Self::compare_unicode_strings(ntfs, a, b),
+            Self::NtofsUlong => {
+                // Only the first `u32` value is considered here.
+                // Any further bytes are not part of the key.
+                let a = &a[..usize::min(a.len(), mem::size_of::<u32>())];
+                let b = &b[..usize::min(b.len(), mem::size_of::<u32>())];
+                Self::compare_ulongs(a, b)
+            }
+            // SIDs are compared like a sequence of `u32` values, the same way ntfs-3g does it.
+            // The security hash key is exactly such a sequence, with the hash coming first.
+            Self::NtofsSid | Self::NtofsSecurityHash | Self::NtofsUlongs => {
+                Self::compare_ulongs(a, b)
+            }
+        }
+    }
+
+    fn compare_ulongs(a: &[u8], b: &[u8]) -> Ordering {
+        let a_ulongs = a.chunks_exact(mem::size_of::<u32>());
+        let b_ulongs = b.chunks_exact(mem::size_of::<u32>());
+
+        for (a_ulong, b_ulong) in a_ulongs.zip(b_ulongs) {
+            let a_value = LittleEndian::read_u32(a_ulong);
+            let b_value = LittleEndian::read_u32(b_ulong);
+
+            if a_value != b_value {
+                return a_value.cmp(&b_value);
+            }
+        }
+
+        a.len().cmp(&b.len())
+    }
+
+    fn compare_unicode_strings(ntfs: &Ntfs, a: &[u8], b: &[u8]) -> Ordering {
+        let a = NtfsString(a);
+        let b = NtfsString(b);
+
+        a.upcase_cmp(ntfs, &b).then_with(|| a.cmp(&b))
+    }
+}
+
+/// Returns the raw name of a $FILE_NAME structure stored in a key,
+/// or as much of it as the key contains.
+fn file_name(key: &[u8]) -> &[u8] {
+    if key.len() <= FILE_NAME_NAME_OFFSET {
+        return &[];
+    }
+
+    let name_length = key[FILE_NAME_NAME_LENGTH_OFFSET] as usize * mem::size_of::<u16>();
+    let end = usize::min(FILE_NAME_NAME_OFFSET + name_length, key.len());
+
+    &key[FILE_NAME_NAME_OFFSET..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collation_ulongs() {
+        let testfs1 = &mut crate::helpers::tests::testfs1();
+        let ntfs = Ntfs::new(testfs1).unwrap();
+
+        let rule = NtfsCollationRule::NtofsUlong;
+        let one = 1u32.to_le_bytes();
+        let two_hundred_fifty_six = 256u32.to_le_bytes();
+        assert_eq!(rule.compare(&ntfs, &one, &one), Ordering::Equal);
+        assert_eq!(
+            rule.compare(&ntfs, &two_hundred_fifty_six, &one),
+            Ordering::Greater
+        );
+
+        // A binary comparison would yield a different result, because the values are stored in little-endian.
+        assert_eq!(
+            NtfsCollationRule::Binary.compare(&ntfs, &two_hundred_fifty_six, &one),
+            Ordering::Less
+        );
+
+        // The hash is compared first, then the security ID.
+        let rule = NtfsCollationRule::NtofsSecurityHash;
+        let key1 = [0x01, 0x00, 0x00, 0x00, 0x02, 0x01, 0x00, 0x00];
+        let key2 = [0x02, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00];
+        let key3 = [0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00];
+        assert_eq!(rule.compare(&ntfs, &key1, &key2), Ordering::Less);
+        assert_eq!(rule.compare(&ntfs, &key2, &key3), Ordering::Less);
+        assert_eq!(rule.compare(&ntfs, &key3, &key1), Ordering::Greater);
+
+        // Equal prefixes are sorted by their length.
+        let rule = NtfsCollationRule::NtofsUlongs;
+        assert_eq!(rule.compare(&ntfs, &key1[..4], &key1), Ordering::Less);
+    }
+
+    #[test]
+    fn test_collation_unicode_string() {
+        let testfs1 = &mut crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(testfs1).unwrap();
+        ntfs.read_upcase_table(testfs1).unwrap();
+
+        let utf16 = |s: &str| {
+            s.encode_utf16()
+                .flat_map(|code_unit| code_unit.to_le_bytes())
+                .collect::<Vec<u8>>()
+        };
+
+        let rule = NtfsCollationRule::UnicodeString;
+        let abc = utf16("abc");
+        let abc_upper = utf16("ABC");
+        let abd_upper = utf16("ABD");
+        let ab = utf16("ab");
+
+        // Strings are compared case-insensitively first.
+        assert_eq!(rule.compare(&ntfs, &abc, &abd_upper), Ordering::Less);
+        assert_eq!(rule.compare(&ntfs, &abc, &ab), Ordering::Greater);
+
+        // Strings that only differ in case are then compared case-sensitively.
+        assert_eq!(rule.compare(&ntfs, &abc_upper, &abc), Ordering::Less);
+        assert_eq!(rule.compare(&ntfs, &abc, &abc), Ordering::Equal);
+
+        // $FILE_NAME keys are compared by their names only.
+        let file_name_key = |s: &str| {
+            let name = utf16(s);
+            let mut key = vec![0u8; FILE_NAME_NAME_OFFSET];
+            key[FILE_NAME_NAME_LENGTH_OFFSET] = (name.len() / 2) as u8;
+            key.extend_from_slice(&name);
+            key
+        };
+
+        let rule = NtfsCollationRule::FileName;
+        let mut key1 = file_name_key("abc");
+        let key2 = file_name_key("ABD");
+        key1[0] = 0xff;
+        assert_eq!(rule.compare(&ntfs, &key1, &key2), Ordering::Less);
+    }
+}
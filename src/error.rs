@@ -2,7 +2,9 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use crate::attribute::NtfsAttributeType;
+use crate::collation::NtfsCollationRule;
 use crate::types::{Lcn, Vcn};
+use alloc::string::String;
 use core::ops::Range;
 use displaydoc::Display;
 
@@ -24,8 +26,40 @@ pub enum NtfsError {
         expected: NtfsAttributeType,
         actual: NtfsAttributeType,
     },
+    /// The resident NTFS Attribute at byte position {position:#010x} should have a value length of {expected} bytes, but it now has {actual} bytes
+    AttributeValueLengthChanged {
+        position: u64,
+        expected: u64,
+        actual: u64,
+    },
     /// The given buffer should have at least {expected} bytes, but it only has {actual} bytes
     BufferTooSmall { expected: usize, actual: usize },
+    /// The {cluster_count} clusters starting at LCN {lcn} should be freed, but not all of them are allocated
+    ClustersNotAllocated { lcn: Lcn, cluster_count: u64 },
+    /// The directory at byte position {position:#010x} is not empty
+    DirectoryNotEmpty { position: u64 },
+    /// The index entry at byte position {position:#010x} has the same key as the entry to be inserted
+    DuplicateIndexEntry { position: u64 },
+    /// The NTFS Index Record at byte position {position:#010x} unexpectedly contains no index entries
+    EmptyIndexRecord { position: u64 },
+    /// The directory at byte position {position:#010x} already contains a file named {name:?}
+    FileNameAlreadyExists { position: u64, name: String },
+    /// The directory at byte position {position:#010x} contains no file named {name:?}
+    FileNameNotFound { position: u64, name: String },
+    /// The NTFS File Record at byte position {position:#010x} would need {required} bytes, but only {allocated} bytes are allocated for it
+    FileRecordTooSmall {
+        position: u64,
+        required: u32,
+        allocated: u32,
+    },
+    /// The index root at byte position {position:#010x} belongs to an index that contains no entry with the given key
+    IndexEntryNotFound { position: u64 },
+    /// The NTFS Index Record at byte position {position:#010x} would need {required} bytes, but only {allocated} bytes are allocated for it
+    IndexRecordTooSmall {
+        position: u64,
+        required: u32,
+        allocated: u32,
+    },
     /// The NTFS Attribute at byte position {position:#010x} indicates a name length up to offset {expected}, but the attribute only has a size of {actual} bytes
     InvalidAttributeNameLength {
         position: u64,
@@ -38,6 +72,14 @@ pub enum NtfsError {
         expected: u16,
         actual: u32,
     },
+    /// The NTFS Attribute at byte position {position:#010x} of type {type_code:#010x} has a value size of {actual} bytes, but $AttrDef only allows sizes from {minimum} to {maximum} bytes
+    InvalidAttributeValueSize {
+        position: u64,
+        type_code: u32,
+        minimum: u64,
+        maximum: u64,
+        actual: u64,
+    },
     /// The NTFS Data Run header at byte position {position:#010x} indicates a maximum byte count of {expected}, but {actual} is the limit
     InvalidByteCountInDataRunHeader {
         position: u64,
@@ -46,12 +88,18 @@ pub enum NtfsError {
     },
     /// The NTFS Data Run cluster count {cluster_count} is too big to be multiplied by the cluster size
     InvalidClusterCount { cluster_count: u64 },
+    /// The cluster size is {actual} bytes, but it must be a power of two of at least {minimum} bytes
+    InvalidClusterSize { minimum: u32, actual: u32 },
+    /// {name:?} is not a valid NTFS data stream name
+    InvalidDataStreamName { name: String },
     /// The NTFS File Record at byte position {position:#010x} indicates an allocated size of {expected} bytes, but the record only has a size of {actual} bytes
     InvalidFileAllocatedSize {
         position: u64,
         expected: u32,
         actual: u32,
     },
+    /// {name:?} is not a valid NTFS file name
+    InvalidFileName { name: String },
     /// The requested NTFS File Record Number {file_record_number} is invalid
     InvalidFileRecordNumber { file_record_number: u64 },
     /// The NTFS File Record at byte position {position:#010x} should have signature {expected:?}, but it has signature {actual:?}
@@ -78,6 +126,23 @@ pub enum NtfsError {
         range: Range<usize>,
         size: u16,
     },
+    /// The NTFS Index Entry data at byte position {position:#010x} has a size of {actual} bytes, but {expected} bytes were expected
+    InvalidIndexEntryDataSize {
+        position: u64,
+        expected: u16,
+        actual: u16,
+    },
+    /// The NTFS Index Entry key at byte position {position:#010x} has a size of {actual} bytes, but {expected} bytes were expected
+    InvalidIndexEntryKeySize {
+        position: u64,
+        expected: u16,
+        actual: u16,
+    },
+    /// The NTFS Index Entry at byte position {position:#010x} is not sorted after the previous entry according to collation rule {collation_rule:?}
+    InvalidIndexEntryOrder {
+        position: u64,
+        collation_rule: NtfsCollationRule,
+    },
     /// The NTFS Index Entry at byte position {position:#010x} reports a size of {expected} bytes, but it only has {actual} bytes
     InvalidIndexEntrySize {
         position: u64,
@@ -108,6 +173,12 @@ pub enum NtfsError {
         expected: u32,
         actual: u32,
     },
+    /// The NTFS reparse point data at byte position {position:#010x} should contain a field in the range {range:?}, but it only has a size of {size} bytes
+    InvalidReparsePointDataRange {
+        position: u64,
+        range: Range<usize>,
+        size: usize,
+    },
     /// The resident NTFS Attribute at byte position {position:#010x} indicates a value length up to offset {expected}, but the attribute only has a size of {actual} bytes
     InvalidResidentAttributeValueLength {
         position: u64,
@@ -122,6 +193,14 @@ pub enum NtfsError {
     },
     /// A record size field in the BIOS Parameter Block denotes {size_info}, which is invalid considering the cluster size of {cluster_size} bytes
     InvalidRecordSizeInfo { size_info: i8, cluster_size: u32 },
+    /// The given security descriptor of {size} bytes is not a valid self-relative security descriptor
+    InvalidSecurityDescriptor { size: usize },
+    /// The SID at byte position {position:#010x} should have a size of {expected} bytes, but it only has {actual} bytes
+    InvalidSidSize {
+        position: u64,
+        expected: usize,
+        actual: usize,
+    },
     /// The NTFS structured value at byte position {position:#010x} of type {ty:?} has {actual} bytes where {expected} bytes were expected
     InvalidStructuredValueSize {
         position: u64,
@@ -145,30 +224,72 @@ pub enum NtfsError {
         vcn: Vcn,
         previous_lcn: Lcn,
     },
+    /// {name:?} is not a valid NTFS volume name
+    InvalidVolumeName { name: String },
     /// I/O error: {0:?}
     Io(binread::io::Error),
     /// The Logical Cluster Number (LCN) {lcn} is too big to be multiplied by the cluster size
     LcnTooBig { lcn: Lcn },
     /// The index root at byte position {position:#010x} is a large index, but no matching index allocation attribute was provided
     MissingIndexAllocation { position: u64 },
+    /// The directory at byte position {position:#010x} cannot be moved into itself or one of its subdirectories
+    MoveIntoOwnSubdirectory { position: u64 },
     /// The NTFS file at byte position {position:#010x} is not a directory
     NotADirectory { position: u64 },
+    /// {requested} contiguous clusters should be allocated, but the largest free cluster range only has {available} clusters
+    NotEnoughContiguousFreeClusters { requested: u64, available: u64 },
+    /// {requested} clusters should be allocated, but only {available} clusters are free
+    NotEnoughFreeClusters { requested: u64, available: u64 },
+    /// The parent directory references of the NTFS file at byte position {position:#010x} form a cycle
+    ParentDirectoryCycle { position: u64 },
+    /// The NTFS file at byte position {position:#010x} already has the maximum number of hard links
+    TooManyHardLinks { position: u64 },
     /// The total sector count is too big to be multiplied by the sector size
     TotalSectorsTooBig { total_sectors: u64 },
+    /// The type of the NTFS Attribute at byte position {position:#010x} is {actual:#010x}, which is not defined in $AttrDef
+    UndefinedAttributeType { position: u64, actual: u32 },
     /// The NTFS Attribute at byte position {position:#010x} should not belong to an Attribute List, but it does
     UnexpectedAttributeListAttribute { position: u64 },
     /// The NTFS Attribute at byte position {position:#010x} should be resident, but it is non-resident
     UnexpectedNonResidentAttribute { position: u64 },
     /// The NTFS Attribute at byte position {position:#010x} should be non-resident, but it is resident
     UnexpectedResidentAttribute { position: u64 },
+    /// The cluster at LCN {lcn} is in use beyond the new end of the volume, but cannot be moved
+    UnmovableCluster { lcn: Lcn },
     /// The type of the NTFS Attribute at byte position {position:#010x} is {actual:#010x}, which is not supported
     UnsupportedAttributeType { position: u64, actual: u32 },
     /// The cluster size is {actual} bytes, but the maximum supported one is {expected}
     UnsupportedClusterSize { expected: u32, actual: u32 },
+    /// The NTFS index root at byte position {position:#010x} specifies the collation rule {actual:#010x}, which is not supported
+    UnsupportedCollationRule { position: u64, actual: u32 },
     /// The namespace of the NTFS file name starting at byte position {position:#010x} is {actual}, which is not supported
     UnsupportedFileNamespace { position: u64, actual: u8 },
+    /// The NTFS file at byte position {position:#010x} is a directory, which cannot have hard links
+    UnsupportedHardLinkToDirectory { position: u64 },
+    /// The NTFS index root at byte position {position:#010x} specifies an Index Record size of {index_record_size} bytes, which is not supported with a cluster size of {cluster_size} bytes
+    UnsupportedIndexRecordSize {
+        position: u64,
+        index_record_size: u32,
+        cluster_size: u32,
+    },
+    /// The NTFS file at byte position {position:#010x} is a system file, which cannot be deleted, linked, moved, or renamed
+    UnsupportedOperationOnSystemFile { position: u64 },
+    /// A record size of {record_size} bytes is not supported with a cluster size of {cluster_size} bytes
+    UnsupportedRecordSize { record_size: u32, cluster_size: u32 },
+    /// The NTFS Attribute value at byte position {position:#010x} is sparse and cannot be replaced by a plain value
+    UnsupportedReplacementOfSparseValue { position: u64 },
     /// The sector size is {actual} bytes, but the only supported one is {expected}
     UnsupportedSectorSize { expected: u16, actual: u16 },
+    /// The NTFS Attribute value at byte position {position:#010x} cannot be written at offset {offset}, because that offset lies beyond its initialized size of {initialized_size} bytes
+    UnsupportedWriteBeyondInitializedSize {
+        position: u64,
+        offset: u64,
+        initialized_size: u64,
+    },
+    /// The NTFS Attribute value at byte position {position:#010x} is compressed or encrypted, which is not supported for writing
+    UnsupportedWriteToCompressedOrEncryptedValue { position: u64 },
+    /// The NTFS Attribute value at byte position {position:#010x} cannot be written at offset {offset}, because that offset lies in a sparse Data Run without allocated clusters
+    UnsupportedWriteToSparseDataRun { position: u64, offset: u64 },
     /// The Update Sequence Array (USA) of the record at byte position {position:#010x} has entries for {array_count} sectors of {sector_size} bytes, but the record is only {record_size} bytes long
     UpdateSequenceArrayExceedsRecordSize {
         position: u64,
@@ -192,6 +313,8 @@ pub enum NtfsError {
     VcnOutOfBoundsInIndexAllocation { position: u64, vcn: Vcn },
     /// The Virtual Cluster Number (VCN) {vcn} is too big to be multiplied by the cluster size
     VcnTooBig { vcn: Vcn },
+    /// A volume of {size} bytes is too small for an NTFS filesystem
+    VolumeTooSmall { size: u64 },
 }
 
 impl From<binread::error::Error> for NtfsError {
@@ -214,6 +337,8 @@ impl From<binread::io::Error> for NtfsError {
 // To stay compatible with standardized interfaces (e.g. io::Read, io::Seek),
 // we sometimes need to convert from NtfsError to io::Error.
 impl From<NtfsError> for binread::io::Error {
+    // `io::Error::other` is not available in binread's `no_std` replacement of `io`.
+    #[allow(clippy::io_other_error)]
     fn from(error: NtfsError) -> Self {
         if let NtfsError::Io(io_error) = error {
             io_error
@@ -1,21 +1,83 @@
 // Copyright 2021-2022 Colin Finck <colin@reactos.org>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+#[cfg(feature = "std")]
+use crate::attribute::{
+    align_to_attribute, build_non_resident_attribute, build_resident_attribute, raw_attribute_name,
+    raw_attribute_type_code, set_raw_attribute_instance, NtfsAttribute, NtfsAttributeFlags,
+    ATTRIBUTE_END_MARKER_SIZE,
+};
 use crate::attribute::{NtfsAttributeItem, NtfsAttributeType, NtfsAttributes, NtfsAttributesRaw};
+#[cfg(feature = "std")]
+use crate::attribute_value::ensure_writable;
+#[cfg(feature = "std")]
+use crate::cluster_allocator::{AllocateFn, NtfsClusterAllocator};
+#[cfg(feature = "std")]
+use crate::collation::NtfsCollationRule;
 use crate::error::{NtfsError, Result};
 use crate::file_reference::NtfsFileReference;
+#[cfg(feature = "std")]
+use crate::guid::NtfsGuid;
+#[cfg(feature = "std")]
+use crate::helpers::div_ceil;
+#[cfg(feature = "std")]
+use crate::index::update_index_entries;
 use crate::index::NtfsIndex;
-use crate::indexes::NtfsFileNameIndex;
+#[cfg(feature = "std")]
+use crate::index_entry::{build_data_index_entry, build_file_reference_index_entry};
+#[cfg(feature = "std")]
+use crate::index_writer::{insert_index_entry, remove_index_entry};
+#[cfg(feature = "std")]
+use crate::indexes::{
+    build_reparse_index_key, insert_security_descriptor, NtfsObjectIdIndex, NtfsReparseIndex,
+};
+use crate::indexes::{NtfsFileNameIndex, NtfsIndexEntryType};
+#[cfg(feature = "std")]
+use crate::mft::{allocate_file_record, free_file_record, RESERVED_FILE_RECORD_COUNT};
 use crate::ntfs::Ntfs;
 use crate::record::{Record, RecordHeader};
+#[cfg(feature = "std")]
+use crate::runlist::{NtfsRun, NtfsRunlist};
+#[cfg(feature = "std")]
+use crate::string::{NtfsString, UpcaseOrd};
+#[cfg(feature = "std")]
+use crate::structured_values::{
+    build_attribute_list_entry, build_empty_index_root, build_file_name, build_file_names,
+    build_reparse_link, build_standard_information, copy_file_name_information,
+    insert_raw_attribute_list_entry, insert_standard_information_file_attributes,
+    raw_attribute_list_entries, raw_attribute_list_entry_location,
+    raw_attribute_list_entry_type_code, set_file_name_reparse_point_tag, set_file_name_sizes,
+    set_file_name_times_and_attributes, set_raw_attribute_list_entry_location,
+    set_standard_information_security_id, set_standard_information_times_and_attributes,
+    validate_file_name, NtfsFileAttributeFlags, NtfsReparseTag, REPARSE_POINT_MAX_SIZE,
+    STANDARD_INFORMATION_SIZE_NTFS3,
+};
 use crate::structured_values::{
-    NtfsFileName, NtfsFileNamespace, NtfsIndexRoot, NtfsStandardInformation,
+    NtfsFileName, NtfsFileNamespace, NtfsIndexRoot, NtfsReparsePoint, NtfsStandardInformation,
     NtfsStructuredValueFromResidentAttributeValue,
 };
+#[cfg(feature = "std")]
+use crate::time::NtfsTime;
+#[cfg(feature = "std")]
+use crate::traits::NtfsReadSeek;
+#[cfg(feature = "std")]
+use crate::types::{Lcn, Vcn};
 use binread::io::{Read, Seek, SeekFrom};
 use bitflags::bitflags;
 use byteorder::{ByteOrder, LittleEndian};
 use memoffset::offset_of;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use {
+    alloc::collections::BTreeSet, alloc::string::ToString, alloc::vec::Vec, core::cmp::Ordering,
+    core::convert::TryFrom, core::mem, std::time::SystemTime,
+};
+
+/// Security ID assigned to new files if their parent directory has none.
+/// This is the first Security ID that Windows and mkntfs assign in $Secure.
+#[cfg(feature = "std")]
+const DEFAULT_SECURITY_ID: u32 = 0x100;
 
 /// A list of standardized NTFS File Record Numbers.
 ///
@@ -82,6 +144,9 @@ struct FileRecordHeader {
     allocated_size: u32,
     base_file_record: NtfsFileReference,
     next_attribute_instance: u16,
+    // The following fields only exist since NTFS 3.1.
+    padding: u16,
+    file_record_number: u32,
 }
 
 bitflags! {
@@ -138,12 +203,124 @@ impl<'n> NtfsFile<'n> {
         Ok(file)
     }
 
+    /// Creates an empty File Record in memory, which has no attributes and is not in use.
+    ///
+    /// The record layout follows NTFS 3.1, which stores the File Record Number in the header.
+    /// Use [`NtfsFile::write_record`] to write it to the filesystem at the given position.
+    #[cfg(feature = "std")]
+    pub(crate) fn new_empty(
+        ntfs: &'n Ntfs,
+        position: u64,
+        file_record_number: u64,
+        sequence_number: u16,
+    ) -> Self {
+        let file_record_size = ntfs.file_record_size();
+        let mut data = vec![0; file_record_size as usize];
+        data[..4].copy_from_slice(b"FILE");
+
+        // The Update Sequence Array directly follows the header and has one entry per sector,
+        // plus one for the Update Sequence Number itself.
+        let update_sequence_offset = mem::size_of::<FileRecordHeader>() as u16;
+        let update_sequence_count = (file_record_size / ntfs.sector_size() as u32 + 1) as u16;
+        LittleEndian::write_u16(&mut data[4..], update_sequence_offset);
+        LittleEndian::write_u16(&mut data[6..], update_sequence_count);
+
+        let first_attribute_offset = align_to_attribute(
+            update_sequence_offset as usize + update_sequence_count as usize * 2,
+        );
+        let data_size = first_attribute_offset + ATTRIBUTE_END_MARKER_SIZE;
+
+        let start = offset_of!(FileRecordHeader, sequence_number);
+        LittleEndian::write_u16(&mut data[start..], sequence_number);
+        let start = offset_of!(FileRecordHeader, first_attribute_offset);
+        LittleEndian::write_u16(&mut data[start..], first_attribute_offset as u16);
+        let start = offset_of!(FileRecordHeader, data_size);
+        LittleEndian::write_u32(&mut data[start..], data_size as u32);
+        let start = offset_of!(FileRecordHeader, allocated_size);
+        LittleEndian::write_u32(&mut data[start..], file_record_size);
+        let start = offset_of!(FileRecordHeader, file_record_number);
+        LittleEndian::write_u32(&mut data[start..], file_record_number as u32);
+
+        // The attribute list is terminated by an attribute type of 0xFFFF_FFFF.
+        LittleEndian::write_u32(&mut data[first_attribute_offset..], u32::MAX);
+
+        let record = Record::new(ntfs, data, position);
+
+        Self {
+            record,
+            file_record_number,
+        }
+    }
+
+    /// Allocates a free File Record via the $MFT:$BITMAP and returns an empty [`NtfsFile`] for it,
+    /// which only exists in memory.
+    ///
+    /// The sequence number of a previously deleted file is kept, because it has already been incremented.
+    #[cfg(feature = "std")]
+    fn allocate<T>(
+        ntfs: &'n Ntfs,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+    ) -> Result<Self>
+    where
+        T: Read + Write + Seek,
+    {
+        let file_record_number = allocate_file_record(ntfs, fs, cluster_allocator)?;
+        let position = ntfs.file_record_position(fs, file_record_number)?;
+
+        let sequence_number = match NtfsFile::new(ntfs, fs, position, file_record_number) {
+            Ok(old_file) if old_file.sequence_number() != 0 => old_file.sequence_number(),
+            _ => 1,
+        };
+
+        Ok(NtfsFile::new_empty(
+            ntfs,
+            position,
+            file_record_number,
+            sequence_number,
+        ))
+    }
+
     /// Returns the allocated size of this NTFS File Record, in bytes.
     pub fn allocated_size(&self) -> u32 {
         let start = offset_of!(FileRecordHeader, allocated_size);
         LittleEndian::read_u32(&self.record.data()[start..])
     }
 
+    /// Returns the raw entries of the $ATTRIBUTE_LIST attribute of this file, or `None` if it has none.
+    #[cfg(feature = "std")]
+    fn attribute_list_entries<T>(&self, fs: &mut T) -> Result<Option<Vec<Vec<u8>>>>
+    where
+        T: Read + Seek,
+    {
+        for attribute in self.attributes_raw() {
+            if attribute.ty()? != NtfsAttributeType::AttributeList {
+                continue;
+            }
+
+            let mut value = vec![0u8; attribute.value_length() as usize];
+            attribute.value()?.read_exact(fs, &mut value)?;
+            return Ok(Some(raw_attribute_list_entries(&value)));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the offset of the attribute with the given type and instance within this File Record.
+    #[cfg(feature = "std")]
+    fn attribute_offset(&self, ty: NtfsAttributeType, instance: u16) -> Result<usize> {
+        for attribute in self.attributes_raw() {
+            if attribute.ty()? == ty && attribute.instance() == instance {
+                return Ok(attribute.offset());
+            }
+        }
+
+        Err(NtfsError::AttributeNotFound {
+            position: self.position(),
+            ty,
+        })
+    }
+
     /// Returns an iterator over all attributes of this file.
     ///
     /// This provides a flattened "data-centric" view of the attributes and abstracts away the filesystem details
@@ -171,6 +348,186 @@ impl<'n> NtfsFile<'n> {
         NtfsAttributesRaw::new(self)
     }
 
+    /// Builds the raw Attribute List entries for all attributes of this File Record, e.g. when creating
+    /// a new $ATTRIBUTE_LIST attribute.
+    #[cfg(feature = "std")]
+    fn build_attribute_list_entries(&self) -> Result<Vec<Vec<u8>>> {
+        let mut entries = Vec::new();
+
+        for attribute in self.attributes_raw() {
+            if attribute.ty()? == NtfsAttributeType::AttributeList {
+                continue;
+            }
+
+            let lowest_vcn = if attribute.is_resident() {
+                Vcn::from(0)
+            } else {
+                attribute.non_resident_value_lowest_vcn()
+            };
+
+            entries.push(build_attribute_list_entry(
+                attribute.type_code(),
+                attribute.name()?.0,
+                lowest_vcn,
+                self.file_reference(),
+                attribute.instance(),
+            ));
+        }
+
+        Ok(entries)
+    }
+
+    /// Rewrites the given extents of a non-resident attribute into the first one, which then covers the clusters
+    /// of `runlist`, and removes all other extents along with their $ATTRIBUTE_LIST entries.
+    ///
+    /// Returns the changed File Records (this one first), which only exist in memory, or `None` if the collapsed
+    /// extent doesn't fit into its File Record.
+    #[cfg(feature = "std")]
+    fn collapse_extents<T>(
+        &self,
+        fs: &mut T,
+        extents: &[AttributeExtent],
+        runlist: &NtfsRunlist,
+    ) -> Result<Option<Vec<NtfsFile<'n>>>>
+    where
+        T: Read + Seek,
+    {
+        // A non-resident $ATTRIBUTE_LIST cannot be updated in place.
+        let mut entries = match self.attribute_list_entries(fs)? {
+            Some(entries)
+                if self.attributes_raw().all(|attribute| {
+                    attribute.type_code() != NtfsAttributeType::AttributeList as u32
+                        || attribute.is_resident()
+                }) =>
+            {
+                entries
+            }
+            _ => return Ok(None),
+        };
+
+        let mut records = self.extent_records(fs, extents)?;
+        let (first_extent, other_extents) = extents.split_first().unwrap();
+
+        let record = extent_record(&mut records, first_extent);
+        let offset = record.attribute_offset(first_extent.ty, first_extent.instance)?;
+        let bytes = NtfsAttribute::new(record, offset, None)
+            .to_non_resident_extent_bytes(first_extent.lowest_vcn, runlist);
+
+        match record.replace_attribute(offset, &bytes) {
+            Err(NtfsError::FileRecordTooSmall { .. }) => return Ok(None),
+            result => result?,
+        }
+
+        for extent in other_extents {
+            let record = extent_record(&mut records, extent);
+            let offset = record.attribute_offset(extent.ty, extent.instance)?;
+            record.remove_attribute(offset);
+
+            entries.retain(|entry| {
+                raw_attribute_list_entry_type_code(entry) != extent.ty as u32
+                    || raw_attribute_list_entry_location(entry)
+                        != (extent.file_record_number, extent.instance)
+            });
+        }
+
+        // The $ATTRIBUTE_LIST only gets shorter, so it always fits.
+        records[0].set_attribute_list(&entries)?;
+
+        Ok(Some(records))
+    }
+
+    /// Creates a new empty file or directory named `name` in the directory `parent`.
+    /// See [`Ntfs::create_file`] for details.
+    #[cfg(feature = "std")]
+    pub(crate) fn create<T>(
+        ntfs: &'n Ntfs,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        parent: &mut NtfsFile<'n>,
+        name: &str,
+        is_directory: bool,
+    ) -> Result<Self>
+    where
+        T: Read + Write + Seek,
+    {
+        validate_file_name(name)?;
+
+        let time = NtfsTime::try_from(SystemTime::now()).map_err(|_| NtfsError::InvalidTime)?;
+        let mut file_name_attributes = NtfsFileAttributeFlags::ARCHIVE;
+        if is_directory {
+            file_name_attributes |= NtfsFileAttributeFlags::IS_DIRECTORY;
+        }
+
+        // Gather everything we need from the parent directory before allocating anything.
+        let (file_names, security_id, parent_index_root) = {
+            let parent_index = parent.directory_index(fs)?;
+
+            if let Some(entry) = Self::find_in_directory(&parent_index, ntfs, fs, name) {
+                entry?;
+                return Err(NtfsError::FileNameAlreadyExists {
+                    position: parent.position(),
+                    name: name.to_string(),
+                });
+            }
+
+            let file_names = build_file_names(
+                parent.file_reference(),
+                time,
+                file_name_attributes,
+                name,
+                |dos_name| {
+                    let entry = Self::find_in_directory(&parent_index, ntfs, fs, dos_name);
+                    Ok(entry.transpose()?.is_some())
+                },
+            )?;
+
+            let security_id = parent.info()?.security_id().unwrap_or(DEFAULT_SECURITY_ID);
+
+            // New directories use the same Index Record size as their parent.
+            let index_root =
+                parent.find_resident_attribute_structured_value::<NtfsIndexRoot>(Some("$I30"))?;
+            let parent_index_root = (
+                index_root.index_record_size(),
+                index_root.clusters_per_index_record(),
+            );
+
+            (file_names, security_id, parent_index_root)
+        };
+
+        let index_root = if is_directory {
+            Some(parent_index_root)
+        } else {
+            None
+        };
+
+        let mut file = NtfsFile::allocate(ntfs, fs, cluster_allocator)?;
+        let result = file.initialize_new_file(
+            fs,
+            cluster_allocator,
+            parent,
+            &file_names,
+            time,
+            security_id,
+            index_root,
+        );
+
+        if let Err(e) = result {
+            // Release the File Record again.
+            let file_record_number = file.file_record_number();
+            NtfsFile::new_empty(
+                ntfs,
+                file.position(),
+                file_record_number,
+                file.sequence_number(),
+            )
+            .write_record(fs)?;
+            free_file_record(ntfs, fs, file_record_number)?;
+            return Err(e);
+        }
+
+        Ok(file)
+    }
+
     /// Convenience function to get a $DATA attribute of this file.
     ///
     /// As NTFS supports multiple data streams per file, you can specify the name of the $DATA attribute
@@ -217,268 +574,4394 @@ impl<'n> NtfsFile<'n> {
         LittleEndian::read_u32(&self.record.data()[start..])
     }
 
-    /// Convenience function to return an [`NtfsIndex`] if this file is a directory.
-    /// This structure can be used to iterate over all files of this directory or a find a specific one.
+    /// Moves the clusters of each fragmented non-resident attribute of this file (e.g. a data stream)
+    /// into a single contiguous cluster range.
     ///
-    /// Apart from any propagated error, this function may return [`NtfsError::NotADirectory`]
-    /// if this [`NtfsFile`] is not a directory.
+    /// For every such attribute, a contiguous range of free clusters is allocated via the given
+    /// [`NtfsClusterAllocator`], the clusters of all Data Runs are copied there, and the Data Runs are rewritten.
+    /// Sparse runs are kept.
+    /// An attribute split into multiple extents via the $ATTRIBUTE_LIST is collapsed into its first extent
+    /// if the new Data Runs fit into its File Record.
+    /// Only afterwards, the old clusters are freed and the cluster allocation bitmap is written back to
+    /// the filesystem.
     ///
-    /// If you need more control over the picked up $INDEX_ROOT and $INDEX_ALLOCATION attributes
-    /// you can use [`NtfsFile::attributes`] to iterate over all attributes of this file.
-    pub fn directory_index<'f, T>(
-        &'f self,
+    /// Returns [`NtfsError::NotEnoughContiguousFreeClusters`] if there is no free cluster range that is large
+    /// enough for an attribute.
+    /// All attributes defragmented up to that point are kept.
+    /// Defragmenting system files is not supported and returns [`NtfsError::UnsupportedOperationOnSystemFile`].
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn defragment<T>(
+        &mut self,
         fs: &mut T,
-    ) -> Result<NtfsIndex<'n, 'f, NtfsFileNameIndex>>
+        cluster_allocator: &mut NtfsClusterAllocator,
+    ) -> Result<()>
     where
-        T: Read + Seek,
+        T: Read + Write + Seek,
     {
-        if !self.is_directory() {
-            return Err(NtfsError::NotADirectory {
-                position: self.position(),
-            });
-        }
-
-        // A File Record may contain multiple indexes, so we have to match the name of the directory index.
-        let directory_index_name = "$I30";
-
-        // The IndexRoot attribute is always resident and has to exist for every directory.
-        let index_root_item =
-            self.find_attribute(fs, NtfsAttributeType::IndexRoot, Some(directory_index_name))?;
-        let index_root_attribute = index_root_item.to_attribute();
-        let index_root = index_root_attribute.resident_structured_value::<NtfsIndexRoot>()?;
+        self.ensure_no_system_file()?;
 
-        // The IndexAllocation attribute is only required for "large" indexes.
-        // It is always non-resident and may even be in an Attribute List.
-        let mut index_allocation_item = None;
-        if index_root.is_large_index() {
-            index_allocation_item = Some(self.find_attribute(
-                fs,
-                NtfsAttributeType::IndexAllocation,
-                Some(directory_index_name),
-            )?);
+        let mut keys = Vec::new();
+        for extent in self.non_resident_extents(fs)? {
+            let key = (extent.ty, extent.name);
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
         }
 
-        NtfsIndex::<NtfsFileNameIndex>::new(index_root_item, index_allocation_item)
-    }
+        for (ty, name) in keys {
+            // Every defragmented attribute changes the File Records, so collect the extents again.
+            let extents = self
+                .non_resident_extents(fs)?
+                .into_iter()
+                .filter(|extent| extent.ty == ty && extent.name == name)
+                .collect::<Vec<_>>();
+            self.defragment_attribute(fs, cluster_allocator, &extents)?;
+        }
 
-    /// Returns the NTFS File Record Number of this file.
-    ///
-    /// This number uniquely identifies this file and can be used to recreate this [`NtfsFile`]
-    /// object via [`Ntfs::file`].
-    pub fn file_record_number(&self) -> u64 {
-        self.file_record_number
+        Ok(())
     }
 
-    /// Finds an attribute of a specific type, optionally with a specific name, and returns its [`NtfsAttributeItem`].
-    /// Returns [`NtfsError::AttributeNotFound`] if no such attribute could be found.
-    ///
-    /// This function also traverses Attribute Lists to find the attribute.
-    fn find_attribute<'f, T>(
-        &'f self,
+    /// Defragments the non-resident attribute consisting of the given extents, ordered by their lowest VCN.
+    /// See [`NtfsFile::defragment`] for details.
+    #[cfg(feature = "std")]
+    fn defragment_attribute<T>(
+        &mut self,
         fs: &mut T,
-        ty: NtfsAttributeType,
-        match_name: Option<&str>,
-    ) -> Result<NtfsAttributeItem<'n, 'f>>
+        cluster_allocator: &mut NtfsClusterAllocator,
+        extents: &[AttributeExtent],
+    ) -> Result<()>
     where
-        T: Read + Seek,
+        T: Read + Write + Seek,
     {
-        let mut iter = self.attributes();
-
-        while let Some(item) = iter.next(fs) {
-            let item = item?;
-            let attribute = item.to_attribute();
+        let ntfs = self.ntfs();
 
-            if attribute.ty()? != ty {
-                continue;
+        let mut runlist = NtfsRunlist::new();
+        for extent in extents {
+            for run in &extent.runlist {
+                runlist.push(*run);
             }
+        }
 
-            if let Some(name) = match_name {
-                if attribute.name()? != name {
-                    continue;
+        let mut allocated_cluster_count = 0;
+        let mut next_lcn = None;
+        let mut is_contiguous = true;
+
+        for run in &runlist {
+            if let Some(lcn) = run.lcn() {
+                if next_lcn.is_some() && next_lcn != Some(lcn) {
+                    is_contiguous = false;
                 }
+
+                allocated_cluster_count += run.cluster_count();
+                next_lcn = Some(Lcn::from(u64::from(lcn) + run.cluster_count()));
             }
+        }
 
-            return Ok(item);
+        if is_contiguous && extents.len() == 1 {
+            return Ok(());
         }
 
-        Err(NtfsError::AttributeNotFound {
-            position: self.position(),
-            ty,
-        })
-    }
+        // Clusters that are already contiguous only need their extents to be collapsed.
+        let mut new_runlist = runlist.clone();
+        let mut allocated_runlist = NtfsRunlist::new();
 
-    /// Finds a resident attribute of a specific type, optionally with a specific name, and returns its structured value.
-    /// Returns [`NtfsError::AttributeNotFound`] if no such resident attribute could be found.
-    ///
-    /// The attribute type is given through the passed structured value type parameter.
-    ///
-    /// Note that this function DOES NOT traverse Attribute Lists!
-    pub(crate) fn find_resident_attribute_structured_value<'f, S>(
-        &'f self,
-        match_name: Option<&str>,
-    ) -> Result<S>
-    where
-        S: NtfsStructuredValueFromResidentAttributeValue<'n, 'f>,
-    {
-        // Resident attributes are always stored on the top-level (we don't have to dig into Attribute Lists).
-        let attribute = self
-            .attributes_raw()
-            .find(|attribute| {
-                // TODO: Replace by attribute.ty().contains() once https://github.com/rust-lang/rust/issues/62358 has landed.
-                let ty_matches = attribute.ty().map(|x| x == S::TY).unwrap_or(false);
+        if !is_contiguous {
+            allocated_runlist = cluster_allocator.allocate_contiguous(allocated_cluster_count)?;
+            new_runlist = NtfsRunlist::new();
+            let mut lcn = allocated_runlist.runs()[0].lcn().unwrap();
 
-                let name_matches = if let Some(name) = match_name {
-                    attribute.name().map(|x| x == name).unwrap_or(false)
-                } else {
-                    true
+            for run in &runlist {
+                let old_lcn = match run.lcn() {
+                    Some(old_lcn) => old_lcn,
+                    None => {
+                        new_runlist.push(*run);
+                        continue;
+                    }
                 };
 
-                ty_matches && name_matches
-            })
-            .ok_or(NtfsError::AttributeNotFound {
-                position: self.position(),
-                ty: S::TY,
-            })?;
-        attribute.resident_structured_value::<S>()
-    }
+                if let Err(e) = copy_clusters(ntfs, fs, old_lcn, lcn, run.cluster_count()) {
+                    cluster_allocator.free(&allocated_runlist)?;
+                    return Err(e);
+                }
 
-    pub(crate) fn first_attribute_offset(&self) -> u16 {
-        let start = offset_of!(FileRecordHeader, first_attribute_offset);
-        LittleEndian::read_u16(&self.record.data()[start..])
-    }
+                new_runlist.push(NtfsRun::new(lcn, run.cluster_count()));
+                lcn = Lcn::from(u64::from(lcn) + run.cluster_count());
+            }
 
-    /// Returns flags set for this file as specified by [`NtfsFileFlags`].
-    pub fn flags(&self) -> NtfsFileFlags {
-        let start = offset_of!(FileRecordHeader, flags);
-        NtfsFileFlags::from_bits_truncate(LittleEndian::read_u16(&self.record.data()[start..]))
-    }
+            // Mark the new clusters as used on disk before any File Record references them.
+            if let Err(e) = cluster_allocator.write(ntfs, fs) {
+                cluster_allocator.free(&allocated_runlist)?;
+                return Err(e);
+            }
+        }
 
-    /// Returns the number of hard links to this NTFS File Record.
-    pub fn hard_link_count(&self) -> u16 {
-        let start = offset_of!(FileRecordHeader, hard_link_count);
-        LittleEndian::read_u16(&self.record.data()[start..])
-    }
+        let records = match self.collapse_extents(fs, extents, &new_runlist) {
+            Ok(Some(records)) => Ok(records),
+            Ok(None) => self.split_extents(fs, extents, &new_runlist),
+            Err(e) => Err(e),
+        };
+        let records = match records {
+            Ok(records) => records,
+            Err(e) => {
+                if !is_contiguous {
+                    cluster_allocator.free(&allocated_runlist)?;
+                    cluster_allocator.write(ntfs, fs)?;
+                }
+                return Err(e);
+            }
+        };
 
-    /// Convenience function to get the $STANDARD_INFORMATION attribute of this file
-    /// (see [`NtfsStandardInformation`]).
-    ///
-    /// This internally calls [`NtfsFile::attributes_raw`] to iterate through the file's
-    /// attributes and pick up the first $STANDARD_INFORMATION attribute.
-    pub fn info(&self) -> Result<NtfsStandardInformation> {
-        self.find_resident_attribute_structured_value::<NtfsStandardInformation>(None)
+        for mut record in records {
+            if record.file_record_number == self.file_record_number {
+                record.write_record(fs)?;
+                *self = record;
+            } else if record.attributes_raw().next().is_none() {
+                record.release_file_record(fs)?;
+            } else {
+                record.write_record(fs)?;
+            }
+        }
+
+        // Only free clusters after the File Records no longer reference them.
+        if !is_contiguous {
+            cluster_allocator.free(&runlist)?;
+            cluster_allocator.write(ntfs, fs)?;
+        }
+
+        Ok(())
     }
 
-    /// Returns whether this NTFS File Record represents a directory.
-    pub fn is_directory(&self) -> bool {
-        self.flags().contains(NtfsFileFlags::IS_DIRECTORY)
+    /// Removes the link named `name` from the directory `parent`.
+    /// See [`Ntfs::delete`] for details.
+    #[cfg(feature = "std")]
+    pub(crate) fn delete<T>(
+        ntfs: &'n Ntfs,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        parent: &mut NtfsFile<'n>,
+        name: &str,
+        recursive: bool,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let (file_reference, file_name) = {
+            let parent_index = parent.directory_index(fs)?;
+            let mut finder = parent_index.finder();
+            let entry =
+                NtfsFileNameIndex::find(&mut finder, ntfs, fs, name).ok_or_else(|| {
+                    NtfsError::FileNameNotFound {
+                        position: parent.position(),
+                        name: name.to_string(),
+                    }
+                })??;
+            let file_name = entry.key().unwrap()?;
+
+            (entry.file_reference(), file_name)
+        };
+
+        Self::delete_link(
+            ntfs,
+            fs,
+            cluster_allocator,
+            parent,
+            file_reference,
+            &file_name,
+            recursive,
+        )
     }
 
-    /// Convenience function to get a $FILE_NAME attribute of this file (see [`NtfsFileName`]).
+    /// Removes the link described by `file_name` to the file referenced by `file_reference` from the directory
+    /// `parent`.
     ///
-    /// A file may have multiple $FILE_NAME attributes for each [`NtfsFileNamespace`].
-    /// Files with hard links have further $FILE_NAME attributes for each directory they are in.
-    /// You may optionally filter for a namespace and parent directory via the parameters.
-    ///
-    /// This internally calls [`NtfsFile::attributes`] to iterate through the file's
-    /// attributes and pick up the first matching $FILE_NAME attribute.
-    pub fn name<T>(
+    /// A Win32 name is removed together with its MS-DOS name and vice versa.
+    /// If this was the last link, the file is freed (see [`NtfsFile::free`]).
+    #[cfg(feature = "std")]
+    fn delete_link<T>(
+        ntfs: &'n Ntfs,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        parent: &mut NtfsFile<'n>,
+        file_reference: NtfsFileReference,
+        file_name: &NtfsFileName,
+        recursive: bool,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let mut file = file_reference.to_file(ntfs, fs)?;
+        file.ensure_no_system_file()?;
+
+        if file.is_directory() {
+            // Collect all links of the directory first, skipping MS-DOS names, which are removed along with
+            // their Win32 names.
+            let mut children = Vec::new();
+            let directory_index = file.directory_index(fs)?;
+            let mut iter = directory_index.entries();
+
+            while let Some(entry) = iter.next(fs) {
+                let entry = entry?;
+                let child_name = match entry.key() {
+                    Some(key) => key?,
+                    None => continue,
+                };
+
+                if child_name.namespace() != NtfsFileNamespace::Dos {
+                    children.push((entry.file_reference(), child_name));
+                }
+            }
+
+            if !children.is_empty() && !recursive {
+                return Err(NtfsError::DirectoryNotEmpty {
+                    position: file.position(),
+                });
+            }
+
+            for (child_reference, child_name) in &children {
+                Self::delete_link(
+                    ntfs,
+                    fs,
+                    cluster_allocator,
+                    &mut file,
+                    *child_reference,
+                    child_name,
+                    true,
+                )?;
+            }
+        }
+
+        let removed_file_names = file.link_file_names(fs, file_name)?;
+
+        for file_name_attribute in &removed_file_names {
+            remove_index_entry::<NtfsFileNameIndex, T>(
+                parent,
+                fs,
+                cluster_allocator,
+                "$I30",
+                &file_name_attribute.value,
+            )?;
+        }
+
+        let hard_link_count = file
+            .hard_link_count()
+            .saturating_sub(removed_file_names.len() as u16);
+        if hard_link_count == 0 {
+            return file.free(fs, cluster_allocator);
+        }
+
+        for file_name_attribute in &removed_file_names {
+            file.remove_attribute_from_any_record(
+                fs,
+                NtfsAttributeType::FileName,
+                file_name_attribute.file_record_number,
+                file_name_attribute.instance,
+            )?;
+        }
+
+        file.set_hard_link_count(hard_link_count);
+        file.write_record(fs)
+    }
+
+    /// Returns the raw key of the entry for the given $FILE_NAME attribute value in the directory `parent`,
+    /// which contains the most recent file sizes.
+    /// Falls back to the attribute value if the directory has no such entry for this file.
+    #[cfg(feature = "std")]
+    fn directory_entry_key<T>(
         &self,
         fs: &mut T,
-        match_namespace: Option<NtfsFileNamespace>,
-        match_parent_record_number: Option<u64>,
-    ) -> Option<Result<NtfsFileName>>
+        parent: &NtfsFile,
+        file_name: &NtfsFileName,
+        value: &[u8],
+    ) -> Result<Vec<u8>>
     where
         T: Read + Seek,
     {
-        let mut iter = self.attributes();
+        let parent_index = parent.directory_index(fs)?;
+        let mut finder = parent_index.finder();
+        let name = file_name.name().to_string_lossy();
 
-        while let Some(item) = iter.next(fs) {
-            let item = iter_try!(item);
-            let attribute = item.to_attribute();
+        if let Some(entry) = NtfsFileNameIndex::find(&mut finder, self.ntfs(), fs, &name) {
+            let entry = entry?;
 
-            let ty = iter_try!(attribute.ty());
-            if ty != NtfsAttributeType::FileName {
-                continue;
+            if entry.file_reference().file_record_number() == self.file_record_number {
+                if let Some(key) = entry.key_slice() {
+                    return Ok(key?.to_vec());
+                }
             }
+        }
 
-            let file_name = iter_try!(attribute.structured_value::<_, NtfsFileName>(fs));
+        Ok(value.to_vec())
+    }
 
-            if let Some(namespace) = match_namespace {
-                if file_name.namespace() != namespace {
-                    continue;
-                }
+    /// Convenience function to return an [`NtfsIndex`] if this file is a directory.
+    /// This structure can be used to iterate over all files of this directory or a find a specific one.
+    ///
+    /// Apart from any propagated error, this function may return [`NtfsError::NotADirectory`]
+    /// if this [`NtfsFile`] is not a directory.
+    ///
+    /// If you need more control over the picked up $INDEX_ROOT and $INDEX_ALLOCATION attributes
+    /// you can use [`NtfsFile::attributes`] to iterate over all attributes of this file.
+    pub fn directory_index<'f, T>(
+        &'f self,
+        fs: &mut T,
+    ) -> Result<NtfsIndex<'n, 'f, NtfsFileNameIndex>>
+    where
+        T: Read + Seek,
+    {
+        if !self.is_directory() {
+            return Err(NtfsError::NotADirectory {
+                position: self.position(),
+            });
+        }
+
+        // A File Record may contain multiple indexes, so we have to match the name of the directory index.
+        self.index::<NtfsFileNameIndex, T>(fs, "$I30")
+    }
+
+    /// Returns [`NtfsError::MoveIntoOwnSubdirectory`] if `directory` is this directory or one of its subdirectories.
+    ///
+    /// Returns [`NtfsError::ParentDirectoryCycle`] if the parent directory references of a corrupted
+    /// filesystem lead back to a directory visited before without reaching the root directory.
+    #[cfg(feature = "std")]
+    fn ensure_no_ancestor_of<T>(&self, fs: &mut T, directory: &NtfsFile) -> Result<()>
+    where
+        T: Read + Seek,
+    {
+        let ntfs = self.ntfs();
+        let mut file_record_number = directory.file_record_number();
+        let mut visited = BTreeSet::new();
+
+        while file_record_number != KnownNtfsFileRecordNumber::RootDirectory as u64 {
+            if file_record_number == self.file_record_number {
+                return Err(NtfsError::MoveIntoOwnSubdirectory {
+                    position: self.position(),
+                });
             }
 
-            if let Some(parent_record_number) = match_parent_record_number {
-                if file_name.parent_directory_reference().file_record_number()
-                    != parent_record_number
-                {
-                    continue;
-                }
+            if !visited.insert(file_record_number) {
+                return Err(NtfsError::ParentDirectoryCycle {
+                    position: directory.position(),
+                });
             }
 
-            return Some(Ok(file_name));
+            let file = ntfs.file(fs, file_record_number)?;
+            let file_name = file
+                .name(fs, None, None)
+                .ok_or(NtfsError::AttributeNotFound {
+                    position: file.position(),
+                    ty: NtfsAttributeType::FileName,
+                })??;
+            file_record_number = file_name.parent_directory_reference().file_record_number();
         }
 
-        None
+        Ok(())
     }
 
-    /// Returns the [`Ntfs`] object reference associated to this file.
-    pub fn ntfs(&self) -> &'n Ntfs {
-        self.record.ntfs()
+    /// Returns [`NtfsError::UnsupportedOperationOnSystemFile`] if this is one of the system files,
+    /// which occupy the first File Records.
+    #[cfg(feature = "std")]
+    fn ensure_no_system_file(&self) -> Result<()> {
+        if self.file_record_number < RESERVED_FILE_RECORD_COUNT {
+            return Err(NtfsError::UnsupportedOperationOnSystemFile {
+                position: self.position(),
+            });
+        }
+
+        Ok(())
     }
 
-    /// Returns the absolute byte position of this File Record in the NTFS filesystem.
-    pub fn position(&self) -> u64 {
-        self.record.position()
+    /// Returns the offset of the $DATA attribute named `data_stream_name` within this File Record,
+    /// or `None` if this file has no such data stream.
+    #[cfg(feature = "std")]
+    fn existing_data_attribute_offset<T>(
+        &self,
+        fs: &mut T,
+        data_stream_name: &str,
+    ) -> Result<Option<usize>>
+    where
+        T: Read + Seek,
+    {
+        // Also look into extension File Records to tell a missing stream from an unsupported one.
+        if self.data(fs, data_stream_name).transpose()?.is_none() {
+            return Ok(None);
+        }
+
+        self.find_data_attribute_offset(data_stream_name).map(Some)
     }
 
-    pub(crate) fn record_data(&self) -> &[u8] {
-        self.record.data()
+    /// Returns the File Record Numbers of all extension File Records referenced by the Attribute List of this file.
+    #[cfg(feature = "std")]
+    fn extension_file_record_numbers<T>(&self, fs: &mut T) -> Result<Vec<u64>>
+    where
+        T: Read + Seek,
+    {
+        let mut file_record_numbers = Vec::new();
+
+        for entry in self.attribute_list_entries(fs)?.unwrap_or_default() {
+            let (file_record_number, _) = raw_attribute_list_entry_location(&entry);
+
+            if file_record_number != self.file_record_number
+                && !file_record_numbers.contains(&file_record_number)
+            {
+                file_record_numbers.push(file_record_number);
+            }
+        }
+
+        Ok(file_record_numbers)
     }
 
-    /// Returns the sequence number of this file.
+    /// Returns an extension File Record of this file with at least `size` free bytes.
     ///
-    /// NTFS reuses records of deleted files when new files are created.
-    /// This number is incremented every time a file is deleted.
-    /// Hence, it gives a count how many time this File Record has been reused.
-    pub fn sequence_number(&self) -> u16 {
-        let start = offset_of!(FileRecordHeader, sequence_number);
-        LittleEndian::read_u16(&self.record.data()[start..])
+    /// If no existing extension File Record has enough space, a new one is allocated, which only exists in memory.
+    /// The returned boolean is `true` in that case.
+    #[cfg(feature = "std")]
+    fn extension_record_with_space<T>(
+        &self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        size: usize,
+    ) -> Result<(NtfsFile<'n>, bool)>
+    where
+        T: Read + Write + Seek,
+    {
+        let ntfs = self.ntfs();
+
+        for file_record_number in self.extension_file_record_numbers(fs)? {
+            let extension = ntfs.file(fs, file_record_number)?;
+            if (extension.allocated_size() - extension.data_size()) as usize >= size {
+                return Ok((extension, false));
+            }
+        }
+
+        let mut extension = NtfsFile::allocate(ntfs, fs, cluster_allocator)?;
+        extension.set_flags(NtfsFileFlags::IN_USE);
+        extension.set_base_file_reference(self.file_reference());
+
+        Ok((extension, true))
     }
 
-    fn validate_signature(record: &Record) -> Result<()> {
-        let signature = &record.signature();
-        let expected = b"FILE";
+    /// Returns this File Record along with all extension File Records containing one of the given extents.
+    #[cfg(feature = "std")]
+    fn extent_records<T>(
+        &self,
+        fs: &mut T,
+        extents: &[AttributeExtent],
+    ) -> Result<Vec<NtfsFile<'n>>>
+    where
+        T: Read + Seek,
+    {
+        let mut records = vec![self.clone()];
 
-        if signature == expected {
-            Ok(())
-        } else {
-            Err(NtfsError::InvalidFileSignature {
-                position: record.position(),
-                expected,
-                actual: *signature,
-            })
+        for extent in extents {
+            if records
+                .iter()
+                .all(|record| record.file_record_number != extent.file_record_number)
+            {
+                records.push(self.ntfs().file(fs, extent.file_record_number)?);
+            }
         }
+
+        Ok(records)
     }
 
-    fn validate_sizes(&self) -> Result<()> {
-        if self.allocated_size() > self.record.len() {
-            return Err(NtfsError::InvalidFileAllocatedSize {
-                position: self.record.position(),
-                expected: self.allocated_size(),
-                actual: self.record.len(),
-            });
+    /// Returns all $FILE_NAME attributes of this file, including those in extension File Records.
+    #[cfg(feature = "std")]
+    fn file_name_attributes<T>(&self, fs: &mut T) -> Result<Vec<FileNameAttribute>>
+    where
+        T: Read + Seek,
+    {
+        let mut file_name_attributes = Vec::new();
+        FileNameAttribute::collect(self, fs, &mut file_name_attributes)?;
+
+        for file_record_number in self.extension_file_record_numbers(fs)? {
+            let extension = self.ntfs().file(fs, file_record_number)?;
+            FileNameAttribute::collect(&extension, fs, &mut file_name_attributes)?;
         }
 
-        if self.data_size() > self.allocated_size() {
-            return Err(NtfsError::InvalidFileUsedSize {
-                position: self.record.position(),
-                expected: self.data_size(),
-                actual: self.allocated_size(),
-            });
+        Ok(file_name_attributes)
+    }
+
+    /// Returns the NTFS File Record Number of this file.
+    ///
+    /// This number uniquely identifies this file and can be used to recreate this [`NtfsFile`]
+    /// object via [`Ntfs::file`].
+    pub fn file_record_number(&self) -> u64 {
+        self.file_record_number
+    }
+
+    /// Finds an attribute of a specific type, optionally with a specific name, and returns its [`NtfsAttributeItem`].
+    /// Returns [`NtfsError::AttributeNotFound`] if no such attribute could be found.
+    ///
+    /// This function also traverses Attribute Lists to find the attribute.
+    pub(crate) fn find_attribute<'f, T>(
+        &'f self,
+        fs: &mut T,
+        ty: NtfsAttributeType,
+        match_name: Option<&str>,
+    ) -> Result<NtfsAttributeItem<'n, 'f>>
+    where
+        T: Read + Seek,
+    {
+        let mut iter = self.attributes();
+
+        while let Some(item) = iter.next(fs) {
+            let item = item?;
+            let attribute = item.to_attribute();
+
+            if attribute.ty()? != ty {
+                continue;
+            }
+
+            if let Some(name) = match_name {
+                if attribute.name()? != name {
+                    continue;
+                }
+            }
+
+            return Ok(item);
         }
 
-        Ok(())
+        Err(NtfsError::AttributeNotFound {
+            position: self.position(),
+            ty,
+        })
+    }
+
+    /// Returns the offset of the top-level $DATA attribute with the given name within this File Record.
+    ///
+    /// Returns [`NtfsError::UnexpectedAttributeListAttribute`] if the attribute is not (entirely) described by
+    /// a single top-level attribute, and [`NtfsError::AttributeNotFound`] if there is no such attribute at all.
+    #[cfg(feature = "std")]
+    pub(crate) fn find_data_attribute_offset(&self, data_stream_name: &str) -> Result<usize> {
+        let mut attribute_list_position = None;
+
+        for attribute in self.attributes_raw() {
+            let ty = attribute.ty()?;
+
+            if ty == NtfsAttributeType::AttributeList {
+                attribute_list_position = Some(attribute.position());
+                continue;
+            }
+
+            if ty != NtfsAttributeType::Data || attribute.name()? != data_stream_name {
+                continue;
+            }
+
+            // A non-resident value may be split into multiple attributes connected via an Attribute List.
+            // We only support changing values that are described by this single attribute.
+            if !attribute.is_resident() {
+                let cluster_size = self.ntfs().cluster_size() as u64;
+                let data_runs = attribute.non_resident_value()?.data_runs();
+                let runlist = NtfsRunlist::from_data_runs(data_runs)?;
+
+                if attribute.non_resident_value_lowest_vcn() != 0.into()
+                    || runlist.cluster_count() * cluster_size
+                        != attribute.non_resident_value_allocated_size()
+                {
+                    return Err(NtfsError::UnexpectedAttributeListAttribute {
+                        position: attribute.position(),
+                    });
+                }
+            }
+
+            return Ok(attribute.offset());
+        }
+
+        // The attribute may still exist in an extension File Record referenced by the Attribute List.
+        if let Some(position) = attribute_list_position {
+            return Err(NtfsError::UnexpectedAttributeListAttribute { position });
+        }
+
+        Err(NtfsError::AttributeNotFound {
+            position: self.position(),
+            ty: NtfsAttributeType::Data,
+        })
+    }
+
+    /// Looks up `name` case-insensitively in the given directory index and returns the position of its entry (if any).
+    #[cfg(feature = "std")]
+    fn find_in_directory<T>(
+        index: &NtfsIndex<NtfsFileNameIndex>,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        name: &str,
+    ) -> Option<Result<u64>>
+    where
+        T: Read + Seek,
+    {
+        let mut finder = index.finder();
+        let entry = NtfsFileNameIndex::find(&mut finder, ntfs, fs, name)?;
+        Some(entry.map(|entry| entry.position()))
+    }
+
+    /// Returns the [`NtfsFileReference`] to this file, composed out of its File Record Number and sequence number.
+    pub(crate) fn file_reference(&self) -> NtfsFileReference {
+        NtfsFileReference::from_file_record_number(self.file_record_number, self.sequence_number())
+    }
+
+    /// Finds a resident attribute of a specific type, optionally with a specific name, and returns its structured value.
+    /// Returns [`NtfsError::AttributeNotFound`] if no such resident attribute could be found.
+    ///
+    /// The attribute type is given through the passed structured value type parameter.
+    ///
+    /// Note that this function DOES NOT traverse Attribute Lists!
+    pub(crate) fn find_resident_attribute_structured_value<'f, S>(
+        &'f self,
+        match_name: Option<&str>,
+    ) -> Result<S>
+    where
+        S: NtfsStructuredValueFromResidentAttributeValue<'n, 'f>,
+    {
+        // Resident attributes are always stored on the top-level (we don't have to dig into Attribute Lists).
+        let attribute = self
+            .attributes_raw()
+            .find(|attribute| {
+                // TODO: Replace by attribute.ty().contains() once https://github.com/rust-lang/rust/issues/62358 has landed.
+                let ty_matches = attribute.ty().map(|x| x == S::TY).unwrap_or(false);
+
+                let name_matches = if let Some(name) = match_name {
+                    attribute.name().map(|x| x == name).unwrap_or(false)
+                } else {
+                    true
+                };
+
+                ty_matches && name_matches
+            })
+            .ok_or(NtfsError::AttributeNotFound {
+                position: self.position(),
+                ty: S::TY,
+            })?;
+        attribute.resident_structured_value::<S>()
+    }
+
+    pub(crate) fn first_attribute_offset(&self) -> u16 {
+        let start = offset_of!(FileRecordHeader, first_attribute_offset);
+        LittleEndian::read_u16(&self.record.data()[start..])
+    }
+
+    /// Returns the first $FILE_NAME attribute of this file, which is not an MS-DOS name.
+    #[cfg(feature = "std")]
+    fn first_link_file_name<T>(&self, fs: &mut T) -> Result<FileNameAttribute>
+    where
+        T: Read + Seek,
+    {
+        self.file_name_attributes(fs)?
+            .into_iter()
+            .find(|a| a.file_name.namespace() != NtfsFileNamespace::Dos)
+            .ok_or(NtfsError::AttributeNotFound {
+                position: self.position(),
+                ty: NtfsAttributeType::FileName,
+            })
+    }
+
+    /// Returns flags set for this file as specified by [`NtfsFileFlags`].
+    pub fn flags(&self) -> NtfsFileFlags {
+        let start = offset_of!(FileRecordHeader, flags);
+        NtfsFileFlags::from_bits_truncate(LittleEndian::read_u16(&self.record.data()[start..]))
+    }
+
+    /// Frees this file, which has no links left.
+    ///
+    /// Its Object ID and reparse point are removed from the indexes in $Extend.
+    /// The File Record and all extension File Records are marked as unused (with an incremented sequence number),
+    /// and all clusters of non-resident attributes are freed via the given [`NtfsClusterAllocator`],
+    /// which is written back to $Bitmap afterwards.
+    #[cfg(feature = "std")]
+    fn free<T>(self, fs: &mut T, cluster_allocator: &mut NtfsClusterAllocator) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let ntfs = self.ntfs();
+
+        let object_id = match self.find_attribute(fs, NtfsAttributeType::ObjectId, None) {
+            Ok(item) => {
+                let mut object_id = [0u8; mem::size_of::<NtfsGuid>()];
+                item.to_attribute()
+                    .value()?
+                    .read_exact(fs, &mut object_id)?;
+                Some(object_id)
+            }
+            Err(NtfsError::AttributeNotFound { .. }) => None,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(object_id) = object_id {
+            if let Some(mut object_id_file) = ntfs.extend_file(fs, "$ObjId")? {
+                remove_index_entry::<NtfsObjectIdIndex, T>(
+                    &mut object_id_file,
+                    fs,
+                    cluster_allocator,
+                    "$O",
+                    &object_id,
+                )?;
+            }
+        }
+
+        if let Some(reparse_point) = self.reparse_point(fs) {
+            let tag = reparse_point?.tag();
+
+            if let Some(mut reparse_file) = ntfs.extend_file(fs, "$Reparse")? {
+                remove_index_entry::<NtfsReparseIndex, T>(
+                    &mut reparse_file,
+                    fs,
+                    cluster_allocator,
+                    "$R",
+                    &build_reparse_index_key(tag, self.file_reference()),
+                )?;
+            }
+        }
+
+        let mut files = Vec::new();
+        for file_record_number in self.extension_file_record_numbers(fs)? {
+            files.push(ntfs.file(fs, file_record_number)?);
+        }
+        files.push(self);
+
+        let mut runlists = Vec::new();
+        for file in &mut files {
+            for attribute in file.attributes_raw() {
+                if !attribute.is_resident() {
+                    let data_runs = attribute.non_resident_value()?.data_runs();
+                    runlists.push(NtfsRunlist::from_data_runs(data_runs)?);
+                }
+            }
+
+            file.release_file_record(fs)?;
+        }
+
+        // Only free clusters after the File Records no longer reference them.
+        for runlist in &runlists {
+            cluster_allocator.free(runlist)?;
+        }
+
+        cluster_allocator.write(ntfs, fs)
+    }
+
+    /// Returns the number of hard links to this NTFS File Record.
+    pub fn hard_link_count(&self) -> u16 {
+        let start = offset_of!(FileRecordHeader, hard_link_count);
+        LittleEndian::read_u16(&self.record.data()[start..])
+    }
+
+    /// Returns an [`NtfsIndex`] for the index with the given name, e.g. "$O" of the $ObjId file.
+    /// The `E` type parameter specifies the [`NtfsIndexEntryType`] of the index.
+    ///
+    /// This looks up the matching $INDEX_ROOT and $INDEX_ALLOCATION attributes for you.
+    /// For directory indexes, [`NtfsFile::directory_index`] is the more convenient choice.
+    ///
+    /// [`NtfsIndexEntryType`]: crate::indexes::NtfsIndexEntryType
+    pub fn index<'f, E, T>(&'f self, fs: &mut T, index_name: &str) -> Result<NtfsIndex<'n, 'f, E>>
+    where
+        E: NtfsIndexEntryType,
+        T: Read + Seek,
+    {
+        // The IndexRoot attribute is always resident and has to exist for every index.
+        let index_root_item =
+            self.find_attribute(fs, NtfsAttributeType::IndexRoot, Some(index_name))?;
+        let index_root_attribute = index_root_item.to_attribute();
+        let index_root = index_root_attribute.resident_structured_value::<NtfsIndexRoot>()?;
+
+        // The IndexAllocation attribute is only required for "large" indexes.
+        // It is always non-resident and may even be in an Attribute List.
+        let mut index_allocation_item = None;
+        if index_root.is_large_index() {
+            index_allocation_item = Some(self.find_attribute(
+                fs,
+                NtfsAttributeType::IndexAllocation,
+                Some(index_name),
+            )?);
+        }
+
+        NtfsIndex::<E>::new(index_root_item, index_allocation_item)
+    }
+
+    /// Convenience function to get the $STANDARD_INFORMATION attribute of this file
+    /// (see [`NtfsStandardInformation`]).
+    ///
+    /// This internally calls [`NtfsFile::attributes_raw`] to iterate through the file's
+    /// attributes and pick up the first $STANDARD_INFORMATION attribute.
+    pub fn info(&self) -> Result<NtfsStandardInformation> {
+        self.find_resident_attribute_structured_value::<NtfsStandardInformation>(None)
+    }
+
+    /// Inserts the given raw attribute bytes into this File Record, keeping the attributes sorted by type and name
+    /// (see [`compare_attributes`]).
+    ///
+    /// The attribute is assigned the next attribute instance number of this File Record.
+    /// Returns [`NtfsError::FileRecordTooSmall`] if the attribute doesn't fit into this File Record.
+    /// The File Record is only changed in memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an attribute of the same type with a different name exists and
+    /// [`read_upcase_table`][Ntfs::read_upcase_table] had not been called.
+    #[cfg(feature = "std")]
+    pub(crate) fn insert_attribute(&mut self, mut bytes: Vec<u8>) -> Result<()> {
+        let ntfs = self.ntfs();
+        let new_type_code = raw_attribute_type_code(&bytes);
+        let new_name = raw_attribute_name(&bytes);
+
+        // Attributes of the same type and name (e.g. multiple $FILE_NAME attributes) keep their order.
+        // Without a greater attribute, the new one is inserted before the end marker.
+        let mut offset = self.first_attribute_offset() as usize;
+        for attribute in self.attributes_raw() {
+            let ordering = compare_attributes(
+                ntfs,
+                (attribute.type_code(), &attribute.name()?),
+                (new_type_code, &new_name),
+            );
+            if ordering == Ordering::Greater {
+                break;
+            }
+
+            offset = attribute.offset() + attribute.attribute_length() as usize;
+        }
+
+        let instance = self.next_attribute_instance();
+        set_raw_attribute_instance(&mut bytes, instance);
+        self.splice_attributes(offset, 0, &bytes)?;
+        self.set_next_attribute_instance(instance.wrapping_add(1));
+
+        Ok(())
+    }
+
+    /// Inserts the given raw attribute bytes into this File Record and adds an entry for it to an existing
+    /// $ATTRIBUTE_LIST attribute.
+    ///
+    /// Returns the instance of the inserted attribute or `None` if it doesn't fit into this File Record.
+    /// The File Record is written back to the filesystem.
+    #[cfg(feature = "std")]
+    fn insert_attribute_into_base_record<T>(
+        &mut self,
+        fs: &mut T,
+        bytes: &[u8],
+    ) -> Result<Option<u16>>
+    where
+        T: Read + Write + Seek,
+    {
+        let mut file = self.clone();
+        let instance = file.next_attribute_instance();
+
+        let mut result = file.insert_attribute(bytes.to_vec());
+        if let (Ok(()), Some(mut entries)) = (&result, self.attribute_list_entries(fs)?) {
+            let entry = build_attribute_list_entry(
+                raw_attribute_type_code(bytes),
+                raw_attribute_name(bytes).0,
+                Vcn::from(0),
+                self.file_reference(),
+                instance,
+            );
+            insert_raw_attribute_list_entry(&mut entries, entry);
+            result = file.set_attribute_list(&entries);
+        }
+
+        match result {
+            Ok(()) => {
+                file.write_record(fs)?;
+                *self = file;
+                Ok(Some(instance))
+            }
+            Err(NtfsError::FileRecordTooSmall { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Inserts the given raw attribute bytes into this File Record and returns the File Record Number
+    /// and instance of the inserted attribute.
+    ///
+    /// If this File Record is full, the attribute is stored in an extension File Record instead, which is referenced
+    /// via a resident $ATTRIBUTE_LIST attribute.
+    /// When a new $ATTRIBUTE_LIST does not fit, $FILE_NAME attributes are moved to the extension File Record as well.
+    /// A non-resident attribute must describe its entire value.
+    /// All changed File Records are written back to the filesystem.
+    #[cfg(feature = "std")]
+    pub(crate) fn insert_attribute_into_any_record<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        bytes: Vec<u8>,
+    ) -> Result<(u64, u16)>
+    where
+        T: Read + Write + Seek,
+    {
+        if let Some(instance) = self.insert_attribute_into_base_record(fs, &bytes)? {
+            return Ok((self.file_record_number, instance));
+        }
+
+        let attribute_list_entries = self.attribute_list_entries(fs)?;
+
+        // Store the attribute in an extension File Record.
+        let mut entries = match attribute_list_entries {
+            Some(entries) => entries,
+            None => self.build_attribute_list_entries()?,
+        };
+        let (mut extension, is_new_extension) =
+            self.extension_record_with_space(fs, cluster_allocator, bytes.len())?;
+        let mut file = self.clone();
+
+        let result = Self::spill_attribute(&mut file, &mut extension, &mut entries, bytes);
+        let result = result.and_then(|instance| {
+            extension.write_record(fs)?;
+            file.write_record(fs)?;
+            Ok(instance)
+        });
+
+        match result {
+            Ok(instance) => {
+                *self = file;
+                Ok((extension.file_record_number, instance))
+            }
+            Err(e) => {
+                if is_new_extension {
+                    free_file_record(self.ntfs(), fs, extension.file_record_number)?;
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Inserts an entry for every given $FILE_NAME attribute value of the file referenced by `file_reference`
+    /// into the $I30 index of `parent`.
+    /// If an entry cannot be inserted, the ones inserted before are removed again.
+    #[cfg(feature = "std")]
+    fn insert_directory_entries<T>(
+        parent: &mut NtfsFile,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        file_reference: NtfsFileReference,
+        file_names: &[Vec<u8>],
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        for (i, file_name) in file_names.iter().enumerate() {
+            let entry = build_file_reference_index_entry(file_reference, file_name, None);
+            let result = insert_index_entry::<NtfsFileNameIndex, _>(
+                parent,
+                fs,
+                cluster_allocator,
+                "$I30",
+                &entry,
+            );
+
+            if let Err(e) = result {
+                for file_name in &file_names[..i] {
+                    remove_index_entry::<NtfsFileNameIndex, _>(
+                        parent,
+                        fs,
+                        cluster_allocator,
+                        "$I30",
+                        file_name,
+                    )?;
+                }
+
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the attributes of a newly created file to this empty File Record, writes it to the filesystem,
+    /// and inserts an entry for every $FILE_NAME attribute into the $I30 index of `parent`.
+    /// If an entry cannot be inserted, the ones inserted before are removed again.
+    ///
+    /// `index_root` contains the Index Record size and clusters per Index Record of a new directory.
+    /// It is `None` for regular files, which get an empty $DATA attribute instead.
+    #[allow(clippy::too_many_arguments)]
+    #[cfg(feature = "std")]
+    fn initialize_new_file<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        parent: &mut NtfsFile,
+        file_names: &[Vec<u8>],
+        time: NtfsTime,
+        security_id: u32,
+        index_root: Option<(u32, i8)>,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let mut flags = NtfsFileFlags::IN_USE;
+        if index_root.is_some() {
+            flags |= NtfsFileFlags::IS_DIRECTORY;
+        }
+
+        self.set_flags(flags);
+        self.set_hard_link_count(file_names.len() as u16);
+
+        let standard_information =
+            build_standard_information(time, NtfsFileAttributeFlags::ARCHIVE, security_id);
+        self.insert_attribute(build_resident_attribute(
+            NtfsAttributeType::StandardInformation as u32,
+            &[],
+            NtfsAttributeFlags::empty(),
+            0,
+            &standard_information,
+            false,
+        ))?;
+
+        for file_name in file_names {
+            self.insert_attribute(build_resident_attribute(
+                NtfsAttributeType::FileName as u32,
+                &[],
+                NtfsAttributeFlags::empty(),
+                0,
+                file_name,
+                true,
+            ))?;
+        }
+
+        let attribute = match index_root {
+            Some((index_record_size, clusters_per_index_record)) => {
+                let index_root = build_empty_index_root(
+                    NtfsAttributeType::FileName as u32,
+                    NtfsCollationRule::FileName as u32,
+                    index_record_size,
+                    clusters_per_index_record,
+                );
+                let name = "$I30"
+                    .encode_utf16()
+                    .flat_map(u16::to_le_bytes)
+                    .collect::<Vec<u8>>();
+
+                build_resident_attribute(
+                    NtfsAttributeType::IndexRoot as u32,
+                    &name,
+                    NtfsAttributeFlags::empty(),
+                    0,
+                    &index_root,
+                    false,
+                )
+            }
+            None => build_resident_attribute(
+                NtfsAttributeType::Data as u32,
+                &[],
+                NtfsAttributeFlags::empty(),
+                0,
+                &[],
+                false,
+            ),
+        };
+        self.insert_attribute(attribute)?;
+        self.write_record(fs)?;
+
+        Self::insert_directory_entries(
+            parent,
+            fs,
+            cluster_allocator,
+            self.file_reference(),
+            file_names,
+        )
+    }
+
+    /// Returns whether this NTFS File Record represents a directory.
+    pub fn is_directory(&self) -> bool {
+        self.flags().contains(NtfsFileFlags::IS_DIRECTORY)
+    }
+
+    /// Creates a hard link named `new_name` to this file in the directory `new_parent`.
+    ///
+    /// The new link gets a $FILE_NAME attribute in the [`NtfsFileNamespace::Posix`] namespace, which does not need
+    /// an accompanying MS-DOS name.
+    /// If this File Record has no space left for it, it is stored in an extension File Record, which is referenced
+    /// via a resident $ATTRIBUTE_LIST attribute.
+    /// This function changes the File Records of this file and of `new_parent` on the filesystem.
+    ///
+    /// Returns [`NtfsError::FileNameAlreadyExists`] if `new_parent` already contains a file with that name
+    /// (compared case-insensitively), [`NtfsError::UnsupportedHardLinkToDirectory`] if this file is a directory,
+    /// and [`NtfsError::TooManyHardLinks`] if its hard link count cannot be incremented any further.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`read_upcase_table`][Ntfs::read_upcase_table] had not been called.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn link<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        new_parent: &mut NtfsFile<'n>,
+        new_name: &str,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let utf16_name = validate_file_name(new_name)?;
+        self.ensure_no_system_file()?;
+
+        if self.is_directory() {
+            return Err(NtfsError::UnsupportedHardLinkToDirectory {
+                position: self.position(),
+            });
+        }
+
+        let old_hard_link_count = self.hard_link_count();
+        let new_hard_link_count =
+            old_hard_link_count
+                .checked_add(1)
+                .ok_or(NtfsError::TooManyHardLinks {
+                    position: self.position(),
+                })?;
+
+        let ntfs = self.ntfs();
+
+        {
+            let new_parent_index = new_parent.directory_index(fs)?;
+            let mut finder = new_parent_index.finder();
+            if NtfsFileNameIndex::find(&mut finder, ntfs, fs, new_name).is_some() {
+                return Err(NtfsError::FileNameAlreadyExists {
+                    position: new_parent.position(),
+                    name: new_name.to_string(),
+                });
+            }
+        }
+
+        // Take the times, file attributes, and sizes from the directory entry of an existing link.
+        let existing_link = self.first_link_file_name(fs)?;
+        let existing_parent_record_number = existing_link
+            .file_name
+            .parent_directory_reference()
+            .file_record_number();
+        let existing_parent = ntfs.file(fs, existing_parent_record_number)?;
+        let existing_key = self.directory_entry_key(
+            fs,
+            &existing_parent,
+            &existing_link.file_name,
+            &existing_link.value,
+        )?;
+
+        let mut new_file_name = build_file_name(
+            new_parent.file_reference(),
+            NtfsTime::from(0),
+            NtfsFileAttributeFlags::empty(),
+            NtfsFileNamespace::Posix,
+            &utf16_name,
+        );
+        copy_file_name_information(&mut new_file_name, &existing_key);
+
+        let (file_record_number, instance) = self.insert_attribute_into_any_record(
+            fs,
+            cluster_allocator,
+            build_file_name_attribute(&new_file_name),
+        )?;
+        self.set_hard_link_count(new_hard_link_count);
+        self.write_record(fs)?;
+
+        let result = Self::insert_directory_entries(
+            new_parent,
+            fs,
+            cluster_allocator,
+            self.file_reference(),
+            &[new_file_name],
+        );
+
+        if let Err(e) = result {
+            self.remove_attribute_from_any_record(
+                fs,
+                NtfsAttributeType::FileName,
+                file_record_number,
+                instance,
+            )?;
+            self.set_hard_link_count(old_hard_link_count);
+            self.write_record(fs)?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the $FILE_NAME attributes that make up the link described by `file_name`.
+    ///
+    /// This is the $FILE_NAME attribute with that name in that parent directory,
+    /// along with the accompanying MS-DOS name of a Win32 name and vice versa.
+    #[cfg(feature = "std")]
+    fn link_file_names<T>(
+        &self,
+        fs: &mut T,
+        file_name: &NtfsFileName,
+    ) -> Result<Vec<FileNameAttribute>>
+    where
+        T: Read + Seek,
+    {
+        let parent_record_number = file_name.parent_directory_reference().file_record_number();
+        let mut link_file_names = self.file_name_attributes(fs)?;
+
+        link_file_names.retain(|file_name_attribute| {
+            let candidate = &file_name_attribute.file_name;
+            if candidate.parent_directory_reference().file_record_number() != parent_record_number {
+                return false;
+            }
+
+            match (file_name.namespace(), candidate.namespace()) {
+                (NtfsFileNamespace::Win32, NtfsFileNamespace::Dos)
+                | (NtfsFileNamespace::Dos, NtfsFileNamespace::Win32) => true,
+                (namespace, candidate_namespace) => {
+                    namespace == candidate_namespace && file_name.name() == candidate.name()
+                }
+            }
+        });
+
+        if link_file_names.is_empty() {
+            return Err(NtfsError::AttributeNotFound {
+                position: self.position(),
+                ty: NtfsAttributeType::FileName,
+            });
+        }
+
+        Ok(link_file_names)
+    }
+
+    /// Returns the $LogFile Sequence Number (LSN) of the last change to this File Record that has been
+    /// journaled in the $LogFile.
+    pub fn logfile_sequence_number(&self) -> u64 {
+        self.record.logfile_sequence_number()
+    }
+
+    /// Moves all clusters of the non-resident attributes in this File Record that lie at or beyond the LCN
+    /// `cluster_count` to free clusters allocated via the `allocate` function, e.g. when shrinking the volume.
+    ///
+    /// Every attribute keeps its VCN range, only its Data Runs are rewritten.
+    /// The old clusters are not freed, because they are expected to be cut off the volume.
+    /// If anything has been moved, the given [`NtfsClusterAllocator`] is written back to $Bitmap first,
+    /// and then the File Record is written back to the filesystem.
+    #[cfg(feature = "std")]
+    pub(crate) fn move_clusters_below<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        allocate: AllocateFn,
+        cluster_count: u64,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let ntfs = self.ntfs();
+        let mut is_modified = false;
+        let mut index = 0;
+
+        // Rewriting an attribute keeps the order of attributes, so they can be addressed by index.
+        while let Some(attribute) = self.attributes_raw().nth(index) {
+            index += 1;
+            if attribute.is_resident() {
+                continue;
+            }
+
+            let data_runs = attribute.non_resident_value()?.data_runs();
+            let runlist = NtfsRunlist::from_data_runs(data_runs)?;
+            let mut allocated_runlist = NtfsRunlist::new();
+
+            let new_runlist = match move_runs_below(
+                ntfs,
+                fs,
+                cluster_allocator,
+                allocate,
+                &runlist,
+                cluster_count,
+                &mut allocated_runlist,
+            ) {
+                Ok(new_runlist) => new_runlist,
+                Err(e) => {
+                    cluster_allocator.free(&allocated_runlist)?;
+                    return Err(e);
+                }
+            };
+
+            if allocated_runlist.is_empty() {
+                continue;
+            }
+
+            let offset = attribute.offset();
+            let lowest_vcn = attribute.non_resident_value_lowest_vcn();
+            let bytes = attribute.to_non_resident_extent_bytes(lowest_vcn, &new_runlist);
+
+            if let Err(e) = self.replace_attribute(offset, &bytes) {
+                cluster_allocator.free(&allocated_runlist)?;
+                return Err(e);
+            }
+
+            is_modified = true;
+        }
+
+        if is_modified {
+            // Newly allocated clusters are marked as used on disk before the File Record references them.
+            cluster_allocator.write(ntfs, fs)?;
+            self.write_record(fs)?;
+
+            // If the clusters of $Bitmap itself have been moved, only now its new clusters can be written.
+            if self.file_record_number == KnownNtfsFileRecordNumber::Bitmap as u64 {
+                cluster_allocator.write(ntfs, fs)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Convenience function to get a $FILE_NAME attribute of this file (see [`NtfsFileName`]).
+    ///
+    /// A file may have multiple $FILE_NAME attributes for each [`NtfsFileNamespace`].
+    /// Files with hard links have further $FILE_NAME attributes for each directory they are in.
+    /// You may optionally filter for a namespace and parent directory via the parameters.
+    ///
+    /// This internally calls [`NtfsFile::attributes`] to iterate through the file's
+    /// attributes and pick up the first matching $FILE_NAME attribute.
+    pub fn name<T>(
+        &self,
+        fs: &mut T,
+        match_namespace: Option<NtfsFileNamespace>,
+        match_parent_record_number: Option<u64>,
+    ) -> Option<Result<NtfsFileName>>
+    where
+        T: Read + Seek,
+    {
+        let mut iter = self.attributes();
+
+        while let Some(item) = iter.next(fs) {
+            let item = iter_try!(item);
+            let attribute = item.to_attribute();
+
+            let ty = iter_try!(attribute.ty());
+            if ty != NtfsAttributeType::FileName {
+                continue;
+            }
+
+            let file_name = iter_try!(attribute.structured_value::<_, NtfsFileName>(fs));
+
+            if let Some(namespace) = match_namespace {
+                if file_name.namespace() != namespace {
+                    continue;
+                }
+            }
+
+            if let Some(parent_record_number) = match_parent_record_number {
+                if file_name.parent_directory_reference().file_record_number()
+                    != parent_record_number
+                {
+                    continue;
+                }
+            }
+
+            return Some(Ok(file_name));
+        }
+
+        None
+    }
+
+    fn next_attribute_instance(&self) -> u16 {
+        let start = offset_of!(FileRecordHeader, next_attribute_instance);
+        LittleEndian::read_u16(&self.record.data()[start..])
+    }
+
+    /// Returns the extents of all non-resident attributes of this file, including those in extension File Records.
+    ///
+    /// The extents of each attribute are ordered by their lowest VCN.
+    #[cfg(feature = "std")]
+    fn non_resident_extents<T>(&self, fs: &mut T) -> Result<Vec<AttributeExtent>>
+    where
+        T: Read + Seek,
+    {
+        let mut extents = Vec::new();
+        AttributeExtent::collect(self, &mut extents)?;
+
+        for file_record_number in self.extension_file_record_numbers(fs)? {
+            let extension = self.ntfs().file(fs, file_record_number)?;
+            AttributeExtent::collect(&extension, &mut extents)?;
+        }
+
+        extents.sort_by_key(|extent| extent.lowest_vcn);
+        Ok(extents)
+    }
+
+    /// Returns the [`Ntfs`] object reference associated to this file.
+    pub fn ntfs(&self) -> &'n Ntfs {
+        self.record.ntfs()
+    }
+
+    /// Returns the absolute byte position of this File Record in the NTFS filesystem.
+    pub fn position(&self) -> u64 {
+        self.record.position()
+    }
+
+    pub(crate) fn record_data(&self) -> &[u8] {
+        self.record.data()
+    }
+
+    pub(crate) fn record_data_mut(&mut self) -> &mut [u8] {
+        self.record.data_mut()
+    }
+
+    /// Marks this File Record as unused in its header and in the $MFT:$BITMAP, so that it can be allocated again.
+    ///
+    /// The contents are kept, but the sequence number is incremented to invalidate any remaining references.
+    #[cfg(feature = "std")]
+    fn release_file_record<T>(&mut self, fs: &mut T) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let sequence_number = match self.sequence_number().wrapping_add(1) {
+            0 => 1,
+            sequence_number => sequence_number,
+        };
+
+        self.set_flags(self.flags() - NtfsFileFlags::IN_USE);
+        self.set_sequence_number(sequence_number);
+        self.write_record(fs)?;
+        free_file_record(self.ntfs(), fs, self.file_record_number)
+    }
+
+    /// Removes the attribute at the given offset from this File Record.
+    ///
+    /// All following attributes are moved accordingly.
+    /// The File Record is only changed in memory.
+    #[cfg(feature = "std")]
+    pub(crate) fn remove_attribute(&mut self, offset: usize) {
+        let length = NtfsAttribute::new(self, offset, None).attribute_length() as usize;
+
+        // Removing bytes never exceeds the allocated size.
+        self.splice_attributes(offset, length, &[]).unwrap();
+    }
+
+    /// Removes the resident attribute with the given type and instance from the given File Record of this file,
+    /// which may be this File Record or one of its extension File Records.
+    ///
+    /// The corresponding $ATTRIBUTE_LIST entry is removed as well, and an extension File Record that becomes empty
+    /// is released.
+    /// All changed File Records are written back to the filesystem.
+    #[cfg(feature = "std")]
+    pub(crate) fn remove_attribute_from_any_record<T>(
+        &mut self,
+        fs: &mut T,
+        ty: NtfsAttributeType,
+        file_record_number: u64,
+        instance: u16,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let attribute_list_entries = self.attribute_list_entries(fs)?;
+
+        if file_record_number == self.file_record_number {
+            let offset = self.attribute_offset(ty, instance)?;
+            self.remove_attribute(offset);
+        } else {
+            let mut extension = self.ntfs().file(fs, file_record_number)?;
+            let offset = extension.attribute_offset(ty, instance)?;
+            extension.remove_attribute(offset);
+
+            if extension.attributes_raw().next().is_none() {
+                extension.release_file_record(fs)?;
+            } else {
+                extension.write_record(fs)?;
+            }
+        }
+
+        if let Some(mut entries) = attribute_list_entries {
+            entries.retain(|entry| {
+                raw_attribute_list_entry_type_code(entry) != ty as u32
+                    || raw_attribute_list_entry_location(entry) != (file_record_number, instance)
+            });
+
+            // The $ATTRIBUTE_LIST only gets shorter, so it always fits.
+            self.set_attribute_list(&entries)?;
+        }
+
+        self.write_record(fs)
+    }
+
+    /// Removes the named $DATA attribute `data_stream_name` (also known as an Alternate Data Stream) from this file,
+    /// e.g. to strip the `Zone.Identifier` stream.
+    ///
+    /// Clusters of a non-resident value are freed via the given [`NtfsClusterAllocator`],
+    /// which is written back to $Bitmap afterwards.
+    /// This function writes the File Record back to the filesystem.
+    ///
+    /// Returns [`NtfsError::AttributeNotFound`] if this file has no such data stream.
+    /// The unnamed $DATA attribute cannot be removed.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn remove_data_stream<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        data_stream_name: &str,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        validate_data_stream_name(data_stream_name)?;
+
+        let offset = self
+            .existing_data_attribute_offset(fs, data_stream_name)?
+            .ok_or(NtfsError::AttributeNotFound {
+                position: self.position(),
+                ty: NtfsAttributeType::Data,
+            })?;
+        let attribute = NtfsAttribute::new(self, offset, None);
+        let instance = attribute.instance();
+        let runlist = if attribute.is_resident() {
+            NtfsRunlist::new()
+        } else {
+            NtfsRunlist::from_data_runs(attribute.non_resident_value()?.data_runs())?
+        };
+
+        // Only free clusters after the File Record no longer references them.
+        self.remove_attribute_from_any_record(
+            fs,
+            NtfsAttributeType::Data,
+            self.file_record_number,
+            instance,
+        )?;
+        cluster_allocator.free(&runlist)?;
+        cluster_allocator.write(self.ntfs(), fs)
+    }
+
+    /// Renames this file to `new_name` and moves it into the directory `new_parent`,
+    /// which may also be the directory it is already in.
+    ///
+    /// The $FILE_NAME attribute (along with an accompanying MS-DOS name) is replaced by new ones for `new_name`,
+    /// which follow the same namespace rules as in [`Ntfs::create_file`].
+    /// The file times, sizes, and attributes duplicated in the directory entry are kept.
+    /// The old directory entries are removed and new ones are inserted into the $I30 index of `new_parent`.
+    /// If any of these steps fails, the old directory entries are restored and the File Record is left unchanged.
+    ///
+    /// A file with multiple hard links is renamed by its first link.
+    /// When moving a file to another directory, the previous parent directory is read from and written back to
+    /// the filesystem, so any [`NtfsFile`] object of it needs to be reloaded.
+    ///
+    /// Returns [`NtfsError::FileNameAlreadyExists`] if `new_parent` already contains another file with that name
+    /// (compared case-insensitively) and [`NtfsError::MoveIntoOwnSubdirectory`] if a directory would be moved
+    /// into itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`read_upcase_table`][Ntfs::read_upcase_table] had not been called.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn rename<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        new_parent: &mut NtfsFile<'n>,
+        new_name: &str,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        validate_file_name(new_name)?;
+        self.ensure_no_system_file()?;
+
+        let ntfs = self.ntfs();
+
+        // Find the first link of this file, which is not an MS-DOS name.
+        let old_file_name = self.first_link_file_name(fs)?.file_name;
+        let old_file_names = self.link_file_names(fs, &old_file_name)?;
+
+        let old_parent_record_number = old_file_name
+            .parent_directory_reference()
+            .file_record_number();
+        let mut old_parent = if old_parent_record_number == new_parent.file_record_number() {
+            None
+        } else {
+            if self.is_directory() {
+                self.ensure_no_ancestor_of(fs, new_parent)?;
+            }
+
+            Some(ntfs.file(fs, old_parent_record_number)?)
+        };
+
+        let mut old_keys = Vec::new();
+        for file_name_attribute in &old_file_names {
+            let parent = old_parent.as_ref().unwrap_or(new_parent);
+            old_keys.push(self.directory_entry_key(
+                fs,
+                parent,
+                &file_name_attribute.file_name,
+                &file_name_attribute.value,
+            )?);
+        }
+
+        // Build the new $FILE_NAME values.
+        // Existing names of this file don't count, because they are going to be removed.
+        let mut new_file_names = {
+            let new_parent_index = new_parent.directory_index(fs)?;
+            let mut finder = new_parent_index.finder();
+            let mut exists = |name: &str| -> Result<bool> {
+                match NtfsFileNameIndex::find(&mut finder, ntfs, fs, name) {
+                    Some(entry) => {
+                        let entry_file_reference = entry?.file_reference();
+                        Ok(entry_file_reference.file_record_number() != self.file_record_number)
+                    }
+                    None => Ok(false),
+                }
+            };
+
+            if exists(new_name)? {
+                return Err(NtfsError::FileNameAlreadyExists {
+                    position: new_parent.position(),
+                    name: new_name.to_string(),
+                });
+            }
+
+            build_file_names(
+                new_parent.file_reference(),
+                NtfsTime::from(0),
+                NtfsFileAttributeFlags::empty(),
+                new_name,
+                exists,
+            )?
+        };
+
+        for new_file_name in &mut new_file_names {
+            copy_file_name_information(new_file_name, &old_keys[0]);
+        }
+
+        // Replace the $FILE_NAME attributes.
+        let old_hard_link_count = self.hard_link_count();
+        let hard_link_count = (old_hard_link_count as usize).saturating_sub(old_file_names.len())
+            + new_file_names.len();
+        let hard_link_count =
+            u16::try_from(hard_link_count).map_err(|_| NtfsError::TooManyHardLinks {
+                position: self.position(),
+            })?;
+        let old_locations = old_file_names
+            .iter()
+            .map(|a| (a.file_record_number, a.instance))
+            .collect::<Vec<(u64, u16)>>();
+        let old_values = old_file_names
+            .into_iter()
+            .map(|a| a.value)
+            .collect::<Vec<Vec<u8>>>();
+        let new_locations = self.replace_file_name_attributes(
+            fs,
+            cluster_allocator,
+            &old_locations,
+            &old_values,
+            &new_file_names,
+        )?;
+
+        self.set_hard_link_count(hard_link_count);
+
+        // Write the File Record and replace the directory entries.
+        // If either fails, the $FILE_NAME attributes are restored as well.
+        let result = self.write_record(fs).and_then(|()| {
+            self.replace_directory_entries(
+                fs,
+                cluster_allocator,
+                old_parent.as_mut(),
+                new_parent,
+                &old_keys,
+                &new_file_names,
+            )
+        });
+
+        if let Err(e) = result {
+            self.replace_file_name_attributes(
+                fs,
+                cluster_allocator,
+                &new_locations,
+                &new_file_names,
+                &old_values,
+            )?;
+            self.set_hard_link_count(old_hard_link_count);
+            self.write_record(fs)?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Convenience function to get the $REPARSE_POINT attribute of this file (see [`NtfsReparsePoint`]).
+    ///
+    /// Returns `None` if this file is no reparse point.
+    pub fn reparse_point<T>(&self, fs: &mut T) -> Option<Result<NtfsReparsePoint>>
+    where
+        T: Read + Seek,
+    {
+        match self.find_attribute(fs, NtfsAttributeType::ReparsePoint, None) {
+            Ok(item) => Some(
+                item.to_attribute()
+                    .structured_value::<_, NtfsReparsePoint>(fs),
+            ),
+            Err(NtfsError::AttributeNotFound { .. }) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Replaces the directory entries with the given `old_keys` in `old_parent` (or `new_parent` if that is `None`)
+    /// by entries for the given `new_file_names` in `new_parent`.
+    /// If that fails, the old directory entries are restored.
+    #[cfg(feature = "std")]
+    fn replace_directory_entries<T>(
+        &self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        mut old_parent: Option<&mut NtfsFile<'n>>,
+        new_parent: &mut NtfsFile<'n>,
+        old_keys: &[Vec<u8>],
+        new_file_names: &[Vec<u8>],
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let file_reference = self.file_reference();
+
+        for (i, old_key) in old_keys.iter().enumerate() {
+            let parent = old_parent.as_deref_mut().unwrap_or(&mut *new_parent);
+            let result = remove_index_entry::<NtfsFileNameIndex, _>(
+                parent,
+                fs,
+                cluster_allocator,
+                "$I30",
+                old_key,
+            );
+
+            if let Err(e) = result {
+                Self::insert_directory_entries(
+                    parent,
+                    fs,
+                    cluster_allocator,
+                    file_reference,
+                    &old_keys[..i],
+                )?;
+                return Err(e);
+            }
+        }
+
+        let result = Self::insert_directory_entries(
+            new_parent,
+            fs,
+            cluster_allocator,
+            file_reference,
+            new_file_names,
+        );
+
+        if let Err(e) = result {
+            let parent = old_parent.unwrap_or(new_parent);
+            Self::insert_directory_entries(
+                parent,
+                fs,
+                cluster_allocator,
+                file_reference,
+                old_keys,
+            )?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the $FILE_NAME attributes at the given locations and inserts attributes for the given
+    /// `new_values` into any File Record of this file.
+    /// Returns the locations of the inserted attributes.
+    ///
+    /// If an attribute cannot be inserted, the ones inserted before are removed again and `old_values` are restored.
+    #[cfg(feature = "std")]
+    fn replace_file_name_attributes<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        old_locations: &[(u64, u16)],
+        old_values: &[Vec<u8>],
+        new_values: &[Vec<u8>],
+    ) -> Result<Vec<(u64, u16)>>
+    where
+        T: Read + Write + Seek,
+    {
+        for (file_record_number, instance) in old_locations {
+            self.remove_attribute_from_any_record(
+                fs,
+                NtfsAttributeType::FileName,
+                *file_record_number,
+                *instance,
+            )?;
+        }
+
+        let mut new_locations = Vec::new();
+
+        for new_value in new_values {
+            let result = self.insert_attribute_into_any_record(
+                fs,
+                cluster_allocator,
+                build_file_name_attribute(new_value),
+            );
+
+            match result {
+                Ok(location) => new_locations.push(location),
+                Err(e) => {
+                    for (file_record_number, instance) in new_locations {
+                        self.remove_attribute_from_any_record(
+                            fs,
+                            NtfsAttributeType::FileName,
+                            file_record_number,
+                            instance,
+                        )?;
+                    }
+
+                    for old_value in old_values {
+                        self.insert_attribute_into_any_record(
+                            fs,
+                            cluster_allocator,
+                            build_file_name_attribute(old_value),
+                        )?;
+                    }
+
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(new_locations)
+    }
+
+    /// Replaces the attribute at the given offset within this File Record by the given raw attribute bytes.
+    ///
+    /// All following attributes are moved accordingly.
+    /// Returns [`NtfsError::FileRecordTooSmall`] if the new attribute doesn't fit into this File Record.
+    /// The File Record is only changed in memory.
+    #[cfg(feature = "std")]
+    pub(crate) fn replace_attribute(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        let old_length = NtfsAttribute::new(self, offset, None).attribute_length() as usize;
+        self.splice_attributes(offset, old_length, bytes)
+    }
+
+    /// Returns the sequence number of this file.
+    ///
+    /// NTFS reuses records of deleted files when new files are created.
+    /// This number is incremented every time a file is deleted.
+    /// Hence, it gives a count how many time this File Record has been reused.
+    pub fn sequence_number(&self) -> u16 {
+        let start = offset_of!(FileRecordHeader, sequence_number);
+        LittleEndian::read_u16(&self.record.data()[start..])
+    }
+
+    /// Sets the value of the $ATTRIBUTE_LIST attribute of this File Record to the given raw entries,
+    /// inserting a resident $ATTRIBUTE_LIST attribute if there is none yet.
+    ///
+    /// Returns [`NtfsError::FileRecordTooSmall`] if the attribute doesn't fit into this File Record.
+    /// The File Record is only changed in memory.
+    #[cfg(feature = "std")]
+    fn set_attribute_list(&mut self, entries: &[Vec<u8>]) -> Result<()> {
+        let value = entries.concat();
+        let mut existing_attribute = None;
+
+        for attribute in self.attributes_raw() {
+            if attribute.ty()? != NtfsAttributeType::AttributeList {
+                continue;
+            }
+
+            if !attribute.is_resident() {
+                return Err(NtfsError::UnexpectedNonResidentAttribute {
+                    position: attribute.position(),
+                });
+            }
+
+            let bytes = attribute.to_resident_bytes_with_value(&value)?;
+            existing_attribute = Some((attribute.offset(), bytes));
+            break;
+        }
+
+        match existing_attribute {
+            Some((offset, bytes)) => self.replace_attribute(offset, &bytes),
+            None => self.insert_attribute(build_resident_attribute(
+                NtfsAttributeType::AttributeList as u32,
+                &[],
+                NtfsAttributeFlags::empty(),
+                0,
+                &value,
+                false,
+            )),
+        }
+    }
+
+    /// Changes the value length of the attribute at the given offset to `new_len` bytes and writes the File Record
+    /// and the cluster allocation bitmap back to the filesystem.
+    ///
+    /// New clusters are allocated via the `allocate` function, e.g. [`NtfsClusterAllocator::allocate`].
+    /// See [`NtfsFile::set_len`] for details.
+    ///
+    /// If `initialize` is `true`, all added bytes of a non-resident value are written as zeros and become part
+    /// of its initialized size.
+    /// This is required for metadata that is accessed without regard to the initialized size, e.g. the $MFT.
+    /// Otherwise, the initialized size is kept, because all bytes beyond it read as zeros anyway.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_attribute_len<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        allocate: AllocateFn,
+        offset: usize,
+        new_len: u64,
+        initialize: bool,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let ntfs = self.ntfs();
+        let attribute = NtfsAttribute::new(self, offset, None);
+        ensure_writable(attribute.flags(), attribute.position())?;
+
+        let freed_runlist = if attribute.is_resident() {
+            self.set_resident_len(fs, cluster_allocator, allocate, offset, new_len, initialize)?;
+            NtfsRunlist::new()
+        } else {
+            self.set_non_resident_len(fs, cluster_allocator, allocate, offset, new_len, initialize)?
+        };
+
+        // Newly allocated clusters are marked as used on disk before the File Record references them.
+        // The only exception is $Bitmap itself, whose new clusters can only be written after its File Record.
+        if freed_runlist.is_empty()
+            && self.file_record_number != KnownNtfsFileRecordNumber::Bitmap as u64
+        {
+            cluster_allocator.write(ntfs, fs)?;
+            self.write_record(fs)
+        } else {
+            // Only free clusters after the File Record no longer references them.
+            self.write_record(fs)?;
+            cluster_allocator.free(&freed_runlist)?;
+            cluster_allocator.write(ntfs, fs)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn set_base_file_reference(&mut self, base_file_reference: NtfsFileReference) {
+        let start = offset_of!(FileRecordHeader, base_file_record);
+        self.record.data_mut()[start..start + 8].copy_from_slice(&base_file_reference.to_bytes());
+    }
+
+    #[cfg(feature = "std")]
+    fn set_data_size(&mut self, data_size: u32) {
+        let start = offset_of!(FileRecordHeader, data_size);
+        LittleEndian::write_u32(&mut self.record.data_mut()[start..], data_size);
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_flags(&mut self, flags: NtfsFileFlags) {
+        let start = offset_of!(FileRecordHeader, flags);
+        LittleEndian::write_u16(&mut self.record.data_mut()[start..], flags.bits());
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_hard_link_count(&mut self, hard_link_count: u16) {
+        let start = offset_of!(FileRecordHeader, hard_link_count);
+        LittleEndian::write_u16(&mut self.record.data_mut()[start..], hard_link_count);
+    }
+
+    /// Writes the file times and file attributes of the given [`NtfsStandardInformation`] to the
+    /// $STANDARD_INFORMATION attribute of this file.
+    ///
+    /// Use [`NtfsFile::info`] to get the current values and change them via the setters of
+    /// [`NtfsStandardInformation`].
+    /// All other fields of `info` (like the Security ID) are ignored.
+    /// So are the file attributes that reflect how the data of this file is stored
+    /// ([`NtfsFileAttributeFlags::SPARSE_FILE`], [`NtfsFileAttributeFlags::REPARSE_POINT`],
+    /// [`NtfsFileAttributeFlags::COMPRESSED`], and [`NtfsFileAttributeFlags::ENCRYPTED`]),
+    /// which are kept as they are.
+    ///
+    /// If `update_file_names` is `true`, the copies of these values in all $FILE_NAME attributes of this file
+    /// and in the corresponding directory entries are updated as well.
+    /// Windows only updates these copies when a file is renamed or moved, but tools like `dir` show the copies
+    /// in the directory entries.
+    ///
+    /// This function writes the File Record back to the filesystem.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_info<T>(
+        &mut self,
+        fs: &mut T,
+        info: &NtfsStandardInformation,
+        update_file_names: bool,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        self.update_standard_information(|value| {
+            set_standard_information_times_and_attributes(value, info)
+        })?;
+
+        if !update_file_names {
+            return self.write_record(fs);
+        }
+
+        self.update_file_names(fs, |file_name| {
+            set_file_name_times_and_attributes(file_name, info)
+        })
+    }
+
+    /// Changes the length of a $DATA attribute of this file to `new_len` bytes.
+    ///
+    /// Passing an empty string as `data_stream_name` changes the default unnamed $DATA attribute
+    /// (commonly known as the "file size").
+    ///
+    /// A resident value is resized in place and converted to a non-resident one if it no longer fits
+    /// into the File Record.
+    /// Clusters of a non-resident value are allocated and freed via the given [`NtfsClusterAllocator`],
+    /// which is written back to $Bitmap afterwards.
+    /// Any added bytes read as zeros.
+    /// The initialized size of a non-resident value is kept, so they cannot be overwritten via [`NtfsWrite`](crate::NtfsWrite).
+    /// The sizes duplicated in the directory entries of the file are updated for the unnamed $DATA attribute.
+    ///
+    /// This function writes the File Record back to the filesystem.
+    /// Compressed and encrypted values as well as values split via an Attribute List are not supported.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_len<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        data_stream_name: &str,
+        new_len: u64,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let offset = self.find_data_attribute_offset(data_stream_name)?;
+        self.set_attribute_len(
+            fs,
+            cluster_allocator,
+            NtfsClusterAllocator::allocate,
+            offset,
+            new_len,
+            false,
+        )?;
+
+        if data_stream_name.is_empty() {
+            self.update_directory_entry_sizes(fs, offset)?;
+        }
+
+        Ok(())
+    }
+
+    /// Turns this empty directory into a mount point (also known as junction), which redirects to
+    /// `substitute_name`.
+    ///
+    /// `substitute_name` is the target as processed by Windows, usually an NT namespace path like
+    /// `\??\C:\Users`.
+    /// `print_name` is the user-friendly target, as displayed by Windows Explorer (like `C:\Users`).
+    /// See [`NtfsFile::set_symbolic_link`] for details about the changes.
+    ///
+    /// Returns [`NtfsError::NotADirectory`] if this file is not a directory and
+    /// [`NtfsError::DirectoryNotEmpty`] if it is not empty.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_mount_point<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        substitute_name: &str,
+        print_name: &str,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        if self
+            .directory_index(fs)?
+            .entries()
+            .next(fs)
+            .transpose()?
+            .is_some()
+        {
+            return Err(NtfsError::DirectoryNotEmpty {
+                position: self.position(),
+            });
+        }
+
+        let tag = NtfsReparseTag::MOUNT_POINT;
+        let value = build_reparse_link(tag, substitute_name, print_name, false);
+        self.set_reparse_point(fs, cluster_allocator, tag, &value)
+    }
+
+    #[cfg(feature = "std")]
+    fn set_next_attribute_instance(&mut self, next_attribute_instance: u16) {
+        let start = offset_of!(FileRecordHeader, next_attribute_instance);
+        LittleEndian::write_u16(
+            &mut self.record.data_mut()[start..],
+            next_attribute_instance,
+        );
+    }
+
+    /// Changes the length of the non-resident attribute at the given offset and returns the clusters to free.
+    #[cfg(feature = "std")]
+    fn set_non_resident_len<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        allocate: AllocateFn,
+        offset: usize,
+        new_len: u64,
+        initialize: bool,
+    ) -> Result<NtfsRunlist>
+    where
+        T: Read + Write + Seek,
+    {
+        let ntfs = self.ntfs();
+        let cluster_size = ntfs.cluster_size() as u64;
+        let new_cluster_count = div_ceil(new_len, cluster_size);
+
+        let attribute = NtfsAttribute::new(self, offset, None);
+        let data_runs = attribute.non_resident_value()?.data_runs();
+        let mut runlist = NtfsRunlist::from_data_runs(data_runs)?;
+        let old_cluster_count = runlist.cluster_count();
+        let old_initialized_size = attribute.non_resident_value_initialized_size();
+
+        let mut allocated_runlist = NtfsRunlist::new();
+        let mut freed_runlist = NtfsRunlist::new();
+
+        if new_cluster_count > old_cluster_count {
+            let additional_cluster_count = new_cluster_count - old_cluster_count;
+
+            if attribute.flags().contains(NtfsAttributeFlags::SPARSE) {
+                // Sparse files can grow without allocating anything.
+                runlist.push(NtfsRun::sparse(additional_cluster_count));
+            } else {
+                allocated_runlist = allocate(cluster_allocator, additional_cluster_count)?;
+
+                for run in &allocated_runlist {
+                    runlist.push(*run);
+                }
+            }
+        } else {
+            freed_runlist = runlist.split_off_clusters(new_cluster_count);
+        }
+
+        let new_initialized_size = if initialize {
+            new_len
+        } else {
+            u64::min(old_initialized_size, new_len)
+        };
+
+        let bytes =
+            attribute.to_resized_non_resident_bytes(&runlist, new_len, new_initialized_size);
+        if let Err(e) = self.replace_attribute(offset, &bytes) {
+            cluster_allocator.free(&allocated_runlist)?;
+            return Err(e);
+        }
+
+        zero_beyond_initialized_size(
+            ntfs,
+            fs,
+            &runlist,
+            old_initialized_size,
+            new_len,
+            initialize,
+        )?;
+
+        Ok(freed_runlist)
+    }
+
+    /// Writes the given $REPARSE_POINT value with the given tag to this file, replacing any existing reparse point.
+    ///
+    /// This sets the [`NtfsFileAttributeFlags::REPARSE_POINT`] flag, stores the tag in all $FILE_NAME attributes
+    /// and directory entries of this file, and registers the file in the $R index of $Extend\$Reparse.
+    /// The $R index is only changed after the $REPARSE_POINT attribute has been written, and the new entry is
+    /// inserted before the old one is removed, so that the file is always registered.
+    ///
+    /// Returns [`NtfsError::FileNameNotFound`] if the volume has no $Extend\$Reparse file.
+    #[cfg(feature = "std")]
+    fn set_reparse_point<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        tag: NtfsReparseTag,
+        value: &[u8],
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        if value.len() > REPARSE_POINT_MAX_SIZE {
+            return Err(NtfsError::InvalidStructuredValueSize {
+                position: self.position(),
+                ty: NtfsAttributeType::ReparsePoint,
+                expected: REPARSE_POINT_MAX_SIZE as u64,
+                actual: value.len() as u64,
+            });
+        }
+
+        let ntfs = self.ntfs();
+        let mut reparse_file = match ntfs.extend_file(fs, "$Reparse")? {
+            Some(reparse_file) => reparse_file,
+            None => {
+                let extend_directory = ntfs.file(fs, KnownNtfsFileRecordNumber::Extend as u64)?;
+                return Err(NtfsError::FileNameNotFound {
+                    position: extend_directory.position(),
+                    name: "$Reparse".to_string(),
+                });
+            }
+        };
+        let old_tag = self.reparse_point(fs).transpose()?.map(|r| r.tag());
+
+        let mut offset = None;
+        for attribute in self.attributes_raw() {
+            if attribute.ty()? == NtfsAttributeType::ReparsePoint {
+                offset = Some(attribute.offset());
+                break;
+            }
+        }
+
+        // We only support replacing a reparse point that is stored in this File Record.
+        if let (Some(_), None) = (old_tag, offset) {
+            return Err(NtfsError::UnexpectedAttributeListAttribute {
+                position: self.position(),
+            });
+        }
+
+        self.write_attribute(
+            fs,
+            cluster_allocator,
+            NtfsAttributeType::ReparsePoint,
+            &[],
+            offset,
+            value,
+        )?;
+        self.update_standard_information(|value| {
+            insert_standard_information_file_attributes(
+                value,
+                NtfsFileAttributeFlags::REPARSE_POINT,
+            )
+        })?;
+        self.update_file_names(fs, |file_name| {
+            set_file_name_reparse_point_tag(file_name, tag)
+        })?;
+
+        // The key consists of the tag and the file reference, so it only changes along with the tag.
+        if old_tag == Some(tag) {
+            return Ok(());
+        }
+
+        let key = build_reparse_index_key(tag, self.file_reference());
+        insert_index_entry::<NtfsReparseIndex, T>(
+            &mut reparse_file,
+            fs,
+            cluster_allocator,
+            "$R",
+            &build_data_index_entry(&key, &[], None),
+        )?;
+
+        if let Some(old_tag) = old_tag {
+            remove_index_entry::<NtfsReparseIndex, T>(
+                &mut reparse_file,
+                fs,
+                cluster_allocator,
+                "$R",
+                &build_reparse_index_key(old_tag, self.file_reference()),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Changes the length of the resident attribute at the given offset,
+    /// converting it to a non-resident one if it doesn't fit into the File Record anymore.
+    #[cfg(feature = "std")]
+    fn set_resident_len<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        allocate: AllocateFn,
+        offset: usize,
+        new_len: u64,
+        initialize: bool,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let attribute = NtfsAttribute::new(self, offset, None);
+
+        if let Ok(value_length) = u32::try_from(new_len) {
+            let bytes = attribute.to_resized_resident_bytes(value_length)?;
+
+            match self.replace_attribute(offset, &bytes) {
+                Err(NtfsError::FileRecordTooSmall { .. }) => (),
+                result => return result,
+            }
+        }
+
+        // The value doesn't fit into the File Record anymore, so move it into clusters.
+        let ntfs = self.ntfs();
+        let attribute = NtfsAttribute::new(self, offset, None);
+        let value = attribute.resident_value()?.data().to_vec();
+        let name = attribute.name()?.0.to_vec();
+
+        let cluster_count = div_ceil(new_len, ntfs.cluster_size() as u64);
+        let runlist = allocate(cluster_allocator, cluster_count)?;
+        let initialized_size = if initialize {
+            new_len
+        } else {
+            value.len() as u64
+        };
+        let bytes = build_non_resident_attribute(
+            attribute.type_code(),
+            &name,
+            attribute.flags(),
+            attribute.instance(),
+            &runlist,
+            ntfs.cluster_size(),
+            new_len,
+            initialized_size,
+        );
+
+        if let Err(e) = self.replace_attribute(offset, &bytes) {
+            cluster_allocator.free(&runlist)?;
+            return Err(e);
+        }
+
+        runlist.write_at(ntfs, fs, 0, &value)?;
+        zero_beyond_initialized_size(ntfs, fs, &runlist, value.len() as u64, new_len, initialize)
+    }
+
+    /// Assigns the given self-relative security descriptor to this file, which controls the access to it
+    /// under Windows.
+    ///
+    /// Security descriptors are stored only once in the $Secure file and shared by all files using them.
+    /// If `security_descriptor` is not stored there yet, it is added, allocating clusters via the given
+    /// [`NtfsClusterAllocator`].
+    /// Its Security ID is then written to the $STANDARD_INFORMATION attribute of this file
+    /// (see [`NtfsStandardInformation::security_id`]), which is extended to the NTFS 3.x format if necessary.
+    /// A $SECURITY_DESCRIPTOR attribute of this file (as used before NTFS 3.0) is removed.
+    ///
+    /// This function writes the File Record back to the filesystem.
+    ///
+    /// Returns [`NtfsError::InvalidSecurityDescriptor`] if `security_descriptor` is not a valid self-relative
+    /// security descriptor.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_security_descriptor<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        security_descriptor: &[u8],
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let ntfs = self.ntfs();
+        let security_id =
+            insert_security_descriptor(ntfs, fs, cluster_allocator, security_descriptor)?;
+
+        // Adding a security descriptor may have changed the $Secure file itself.
+        if self.file_record_number == KnownNtfsFileRecordNumber::Secure as u64 {
+            *self = ntfs.file(fs, self.file_record_number)?;
+        }
+
+        let offset = self.standard_information_offset()?;
+        let attribute = NtfsAttribute::new(self, offset, None);
+        if (attribute.value_length() as usize) < STANDARD_INFORMATION_SIZE_NTFS3 {
+            let bytes =
+                attribute.to_resized_resident_bytes(STANDARD_INFORMATION_SIZE_NTFS3 as u32)?;
+            self.replace_attribute(offset, &bytes)?;
+        }
+
+        self.update_resident_value(offset, |value| {
+            set_standard_information_security_id(value, security_id)
+        })?;
+
+        let mut security_descriptor_offset = None;
+        for attribute in self.attributes_raw() {
+            if attribute.ty()? == NtfsAttributeType::SecurityDescriptor {
+                security_descriptor_offset = Some(attribute.offset());
+                break;
+            }
+        }
+
+        let offset = match security_descriptor_offset {
+            Some(offset) => offset,
+            None => return self.write_record(fs),
+        };
+
+        let attribute = NtfsAttribute::new(self, offset, None);
+        let instance = attribute.instance();
+        let runlist = if attribute.is_resident() {
+            NtfsRunlist::new()
+        } else {
+            NtfsRunlist::from_data_runs(attribute.non_resident_value()?.data_runs())?
+        };
+
+        // Only free clusters after the File Record no longer references them.
+        self.remove_attribute_from_any_record(
+            fs,
+            NtfsAttributeType::SecurityDescriptor,
+            self.file_record_number,
+            instance,
+        )?;
+        cluster_allocator.free(&runlist)?;
+        cluster_allocator.write(ntfs, fs)
+    }
+
+    #[cfg(feature = "std")]
+    fn set_sequence_number(&mut self, sequence_number: u16) {
+        let start = offset_of!(FileRecordHeader, sequence_number);
+        LittleEndian::write_u16(&mut self.record.data_mut()[start..], sequence_number);
+    }
+
+    /// Turns this file or directory into a symbolic link, which redirects to `substitute_name`.
+    ///
+    /// `substitute_name` is the target as processed by Windows.
+    /// If `relative` is `true`, it is a path relative to the directory containing the link.
+    /// Otherwise, it is usually an NT namespace path like `\??\C:\Windows`.
+    /// `print_name` is the user-friendly target, as displayed by Windows Explorer (like `C:\Windows`).
+    ///
+    /// This writes a $REPARSE_POINT attribute, replacing any existing reparse point of this file.
+    /// The [`NtfsFileAttributeFlags::REPARSE_POINT`] flag is set, the reparse point tag is stored in all
+    /// $FILE_NAME attributes and directory entries of this file, and the file is registered in the
+    /// $R index of $Extend\$Reparse (see [`Ntfs::reparse_points`]).
+    /// Any data of the file is kept, but ignored by Windows.
+    ///
+    /// This function changes the File Record of this file and the affected indexes on the filesystem.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_symbolic_link<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        substitute_name: &str,
+        print_name: &str,
+        relative: bool,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let tag = NtfsReparseTag::SYMLINK;
+        let value = build_reparse_link(tag, substitute_name, print_name, relative);
+        self.set_reparse_point(fs, cluster_allocator, tag, &value)
+    }
+
+    /// Inserts the given raw attribute bytes into the extension File Record `extension` of `file`,
+    /// adds an entry for it to the given Attribute List entries, and stores them in `file`.
+    /// Returns the instance of the inserted attribute.
+    ///
+    /// $FILE_NAME attributes are moved from `file` to `extension` as long as the $ATTRIBUTE_LIST doesn't fit.
+    /// Both File Records are only changed in memory.
+    #[cfg(feature = "std")]
+    fn spill_attribute(
+        file: &mut NtfsFile,
+        extension: &mut NtfsFile,
+        entries: &mut Vec<Vec<u8>>,
+        bytes: Vec<u8>,
+    ) -> Result<u16> {
+        let type_code = raw_attribute_type_code(&bytes);
+        let name = raw_attribute_name(&bytes).0.to_vec();
+        let instance = extension.next_attribute_instance();
+        extension.insert_attribute(bytes)?;
+
+        let entry = build_attribute_list_entry(
+            type_code,
+            &name,
+            Vcn::from(0),
+            extension.file_reference(),
+            instance,
+        );
+        insert_raw_attribute_list_entry(entries, entry);
+
+        loop {
+            let error = match file.set_attribute_list(entries) {
+                Ok(()) => return Ok(instance),
+                Err(e @ NtfsError::FileRecordTooSmall { .. }) => e,
+                Err(e) => return Err(e),
+            };
+
+            // Move the first $FILE_NAME attribute to make room.
+            let mut file_name_attribute = None;
+            for attribute in file.attributes_raw() {
+                if attribute.ty()? == NtfsAttributeType::FileName {
+                    let offset = attribute.offset();
+                    let end = offset + attribute.attribute_length() as usize;
+                    file_name_attribute = Some((offset, attribute.instance(), end));
+                    break;
+                }
+            }
+
+            let (offset, old_instance, end) = file_name_attribute.ok_or(error)?;
+            let attribute_bytes = file.record_data()[offset..end].to_vec();
+            let new_instance = extension.next_attribute_instance();
+            extension.insert_attribute(attribute_bytes)?;
+            file.remove_attribute(offset);
+
+            for entry in entries.iter_mut() {
+                if raw_attribute_list_entry_type_code(entry) == NtfsAttributeType::FileName as u32
+                    && raw_attribute_list_entry_location(entry)
+                        == (file.file_record_number, old_instance)
+                {
+                    set_raw_attribute_list_entry_location(
+                        entry,
+                        extension.file_reference(),
+                        new_instance,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Replaces the `old_length` bytes at the given offset within this File Record by the given bytes,
+    /// moving all following attributes (including the end marker) accordingly.
+    #[cfg(feature = "std")]
+    fn splice_attributes(&mut self, offset: usize, old_length: usize, bytes: &[u8]) -> Result<()> {
+        let data_size = self.data_size() as usize;
+        let new_data_size = data_size - old_length + bytes.len();
+
+        if new_data_size > self.allocated_size() as usize {
+            return Err(NtfsError::FileRecordTooSmall {
+                position: self.position(),
+                required: new_data_size as u32,
+                allocated: self.allocated_size(),
+            });
+        }
+
+        let data = self.record.data_mut();
+        data.copy_within(offset + old_length..data_size, offset + bytes.len());
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        // Clear the bytes that are no longer used.
+        if new_data_size < data_size {
+            data[new_data_size..data_size].fill(0);
+        }
+
+        self.set_data_size(new_data_size as u32);
+        Ok(())
+    }
+
+    /// Rewrites the given extents of a non-resident attribute, so that they cover the clusters of `runlist`
+    /// while keeping their VCN ranges.
+    ///
+    /// Returns the changed File Records (this one first), which only exist in memory.
+    #[cfg(feature = "std")]
+    fn split_extents<T>(
+        &self,
+        fs: &mut T,
+        extents: &[AttributeExtent],
+        runlist: &NtfsRunlist,
+    ) -> Result<Vec<NtfsFile<'n>>>
+    where
+        T: Read + Seek,
+    {
+        let mut records = self.extent_records(fs, extents)?;
+        let mut remaining_runlist = runlist.clone();
+
+        for extent in extents {
+            let tail = remaining_runlist.split_off_clusters(extent.runlist.cluster_count());
+            let extent_runlist = mem::replace(&mut remaining_runlist, tail);
+
+            let record = extent_record(&mut records, extent);
+            let offset = record.attribute_offset(extent.ty, extent.instance)?;
+            let bytes = NtfsAttribute::new(record, offset, None)
+                .to_non_resident_extent_bytes(extent.lowest_vcn, &extent_runlist);
+            record.replace_attribute(offset, &bytes)?;
+        }
+
+        Ok(records)
+    }
+
+    /// Returns the offset of the $STANDARD_INFORMATION attribute within this File Record.
+    #[cfg(feature = "std")]
+    fn standard_information_offset(&self) -> Result<usize> {
+        for attribute in self.attributes_raw() {
+            if attribute.ty()? == NtfsAttributeType::StandardInformation {
+                return Ok(attribute.offset());
+            }
+        }
+
+        Err(NtfsError::AttributeNotFound {
+            position: self.position(),
+            ty: NtfsAttributeType::StandardInformation,
+        })
+    }
+
+    /// Updates the sizes duplicated in all directory entries of this file to the sizes of the $DATA attribute
+    /// at the given offset.
+    #[cfg(feature = "std")]
+    fn update_directory_entry_sizes<T>(&self, fs: &mut T, data_offset: usize) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let attribute = NtfsAttribute::new(self, data_offset, None);
+        let (allocated_size, data_size) = attribute.directory_entry_sizes();
+
+        // Every hard link has its own $FILE_NAME attribute, but a directory may contain multiple of them.
+        let mut parent_record_numbers = self
+            .file_name_attributes(fs)?
+            .iter()
+            .map(|a| {
+                a.file_name
+                    .parent_directory_reference()
+                    .file_record_number()
+            })
+            .collect::<Vec<u64>>();
+
+        parent_record_numbers.sort_unstable();
+        parent_record_numbers.dedup();
+
+        for parent_record_number in parent_record_numbers {
+            let mut parent = self.ntfs().file(fs, parent_record_number)?;
+
+            update_index_entries::<NtfsFileNameIndex, _, _, _>(
+                &mut parent,
+                fs,
+                "$I30",
+                |entry| entry.file_reference().file_record_number() == self.file_record_number,
+                |key| set_file_name_sizes(key, allocated_size, data_size),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Calls `patch` to change the raw bytes of all $FILE_NAME attributes of this file and of the corresponding
+    /// directory entries in place.
+    /// All changed File Records and Index Records are written back to the filesystem, including this one.
+    #[cfg(feature = "std")]
+    fn update_file_names<T, F>(&mut self, fs: &mut T, patch: F) -> Result<()>
+    where
+        T: Read + Write + Seek,
+        F: Fn(&mut [u8]),
+    {
+        // Update the $FILE_NAME attributes, which are returned in the order of their File Records.
+        let file_name_attributes = self.file_name_attributes(fs)?;
+        let mut file_record_numbers = file_name_attributes
+            .iter()
+            .map(|a| a.file_record_number)
+            .collect::<Vec<u64>>();
+        file_record_numbers.dedup();
+
+        for file_record_number in file_record_numbers {
+            let is_extension = file_record_number != self.file_record_number;
+            let mut extension;
+            let file = if !is_extension {
+                &mut *self
+            } else {
+                extension = self.ntfs().file(fs, file_record_number)?;
+                &mut extension
+            };
+
+            for file_name_attribute in &file_name_attributes {
+                if file_name_attribute.file_record_number == file_record_number {
+                    let offset = file.attribute_offset(
+                        NtfsAttributeType::FileName,
+                        file_name_attribute.instance,
+                    )?;
+                    file.update_resident_value(offset, &patch)?;
+                }
+            }
+
+            if is_extension {
+                file.write_record(fs)?;
+            }
+        }
+
+        self.write_record(fs)?;
+
+        // Update the directory entries, reading every parent directory only once.
+        let mut parent_record_numbers = file_name_attributes
+            .iter()
+            .map(|a| {
+                a.file_name
+                    .parent_directory_reference()
+                    .file_record_number()
+            })
+            .collect::<Vec<u64>>();
+        parent_record_numbers.sort_unstable();
+        parent_record_numbers.dedup();
+
+        for parent_record_number in parent_record_numbers {
+            let mut parent = self.ntfs().file(fs, parent_record_number)?;
+
+            update_index_entries::<NtfsFileNameIndex, _, _, _>(
+                &mut parent,
+                fs,
+                "$I30",
+                |entry| entry.file_reference().file_record_number() == self.file_record_number,
+                &patch,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` to change the value of the resident attribute at the given offset in place.
+    /// The File Record is only changed in memory.
+    #[cfg(feature = "std")]
+    fn update_resident_value<F>(&mut self, offset: usize, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let attribute = NtfsAttribute::new(self, offset, None);
+        if !attribute.is_resident() {
+            return Err(NtfsError::UnexpectedNonResidentAttribute {
+                position: attribute.position(),
+            });
+        }
+
+        let start = offset + attribute.resident_value_offset() as usize;
+        let end = start + attribute.value_length() as usize;
+        f(&mut self.record.data_mut()[start..end]);
+
+        Ok(())
+    }
+
+    /// Calls `f` to change the value of the $STANDARD_INFORMATION attribute of this file in place.
+    /// The File Record is only changed in memory.
+    #[cfg(feature = "std")]
+    fn update_standard_information<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let offset = self.standard_information_offset()?;
+        self.update_resident_value(offset, f)
+    }
+
+    fn validate_signature(record: &Record) -> Result<()> {
+        let signature = &record.signature();
+        let expected = b"FILE";
+
+        if signature == expected {
+            Ok(())
+        } else {
+            Err(NtfsError::InvalidFileSignature {
+                position: record.position(),
+                expected,
+                actual: *signature,
+            })
+        }
+    }
+
+    fn validate_sizes(&self) -> Result<()> {
+        if self.allocated_size() > self.record.len() {
+            return Err(NtfsError::InvalidFileAllocatedSize {
+                position: self.record.position(),
+                expected: self.allocated_size(),
+                actual: self.record.len(),
+            });
+        }
+
+        if self.data_size() > self.allocated_size() {
+            return Err(NtfsError::InvalidFileUsedSize {
+                position: self.record.position(),
+                expected: self.data_size(),
+                actual: self.allocated_size(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Writes the given value to the attribute of the given type and name (as raw UTF-16 bytes) at `existing_offset`
+    /// within this File Record, or inserts a new attribute if `existing_offset` is `None`.
+    ///
+    /// The value is stored resident if it fits into the File Record.
+    /// Otherwise, clusters for a non-resident value are allocated via the given [`NtfsClusterAllocator`],
+    /// which is written back to $Bitmap afterwards along with any clusters freed from the previous value.
+    /// The File Record is written back to the filesystem.
+    #[cfg(feature = "std")]
+    fn write_attribute<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        ty: NtfsAttributeType,
+        name: &[u8],
+        existing_offset: Option<usize>,
+        data: &[u8],
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let ntfs = self.ntfs();
+        let type_code = ty as u32;
+        let flags = NtfsAttributeFlags::empty();
+        let data_len = data.len() as u64;
+        let resident_bytes = build_resident_attribute(type_code, name, flags, 0, data, false);
+        let mut allocated_runlist = NtfsRunlist::new();
+
+        // Builds a non-resident attribute for the data, whose clusters are allocated and written first.
+        let mut build_non_resident_bytes = |cluster_allocator: &mut NtfsClusterAllocator,
+                                            fs: &mut T,
+                                            instance|
+         -> Result<Vec<u8>> {
+            let cluster_count = div_ceil(data_len, ntfs.cluster_size() as u64);
+            allocated_runlist = cluster_allocator.allocate(cluster_count)?;
+            allocated_runlist.write_at(ntfs, fs, 0, data)?;
+
+            Ok(build_non_resident_attribute(
+                type_code,
+                name,
+                flags,
+                instance,
+                &allocated_runlist,
+                ntfs.cluster_size(),
+                data_len,
+                data_len,
+            ))
+        };
+
+        let result = match existing_offset {
+            Some(offset) => {
+                // Replace the existing attribute in place, keeping its instance.
+                let attribute = NtfsAttribute::new(self, offset, None);
+                let instance = attribute.instance();
+                let freed_runlist = if attribute.is_resident() {
+                    NtfsRunlist::new()
+                } else {
+                    NtfsRunlist::from_data_runs(attribute.non_resident_value()?.data_runs())?
+                };
+
+                let mut bytes = resident_bytes;
+                set_raw_attribute_instance(&mut bytes, instance);
+
+                let result = match self.replace_attribute(offset, &bytes) {
+                    Err(NtfsError::FileRecordTooSmall { .. }) => {
+                        build_non_resident_bytes(cluster_allocator, fs, instance)
+                            .and_then(|bytes| self.replace_attribute(offset, &bytes))
+                    }
+                    result => result,
+                };
+
+                // Only free clusters after the File Record no longer references them.
+                result
+                    .and_then(|()| self.write_record(fs))
+                    .and_then(|()| cluster_allocator.free(&freed_runlist))
+            }
+            None => match self.insert_attribute_into_base_record(fs, &resident_bytes)? {
+                Some(_) => Ok(()),
+                None => build_non_resident_bytes(cluster_allocator, fs, 0).and_then(|bytes| {
+                    self.insert_attribute_into_any_record(fs, cluster_allocator, bytes)
+                        .map(|_| ())
+                }),
+            },
+        };
+
+        if let Err(e) = result {
+            cluster_allocator.free(&allocated_runlist)?;
+            return Err(e);
+        }
+
+        cluster_allocator.write(ntfs, fs)
+    }
+
+    /// Creates or overwrites the $DATA attribute named `data_stream_name` of this file with the given data.
+    ///
+    /// Passing an empty string as `data_stream_name` overwrites the default unnamed $DATA attribute.
+    /// Any other name creates or overwrites a named $DATA attribute (also known as an Alternate Data Stream),
+    /// e.g. `Zone.Identifier`.
+    ///
+    /// The data is stored in a resident value if it fits into the File Record.
+    /// Otherwise, clusters for a non-resident value are allocated via the given [`NtfsClusterAllocator`],
+    /// which is written back to $Bitmap afterwards along with any clusters freed from the previous value.
+    /// The sizes duplicated in the directory entries of the file are updated for the unnamed $DATA attribute.
+    /// Replacing a compressed, encrypted, or sparse $DATA attribute is not supported and fails with an error.
+    ///
+    /// This function writes the File Record back to the filesystem.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file already has another named $DATA attribute and
+    /// [`read_upcase_table`][Ntfs::read_upcase_table] had not been called, because attributes are sorted
+    /// by their names via the $UpCase table.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn write_data_stream<T>(
+        &mut self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        data_stream_name: &str,
+        data: &[u8],
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let utf16_name = if data_stream_name.is_empty() {
+            Vec::new()
+        } else {
+            validate_data_stream_name(data_stream_name)?
+        };
+        let name = utf16_name
+            .iter()
+            .flat_map(|code_unit| code_unit.to_le_bytes())
+            .collect::<Vec<u8>>();
+
+        let offset = self.existing_data_attribute_offset(fs, data_stream_name)?;
+        if let Some(offset) = offset {
+            // The new value is written as plain data, so it must not replace a compressed, encrypted,
+            // or sparse value, whose state is also recorded in the file attributes of this file.
+            let attribute = NtfsAttribute::new(self, offset, None);
+            ensure_writable(attribute.flags(), attribute.position())?;
+
+            if attribute.flags().contains(NtfsAttributeFlags::SPARSE) {
+                return Err(NtfsError::UnsupportedReplacementOfSparseValue {
+                    position: attribute.position(),
+                });
+            }
+        }
+
+        self.write_attribute(
+            fs,
+            cluster_allocator,
+            NtfsAttributeType::Data,
+            &name,
+            offset,
+            data,
+        )?;
+
+        if data_stream_name.is_empty() {
+            let offset = self.find_data_attribute_offset(data_stream_name)?;
+            self.update_directory_entry_sizes(fs, offset)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes this File Record back to the filesystem.
+    ///
+    /// The Update Sequence Number (USN) of the record is incremented and the multi-sector protection is applied
+    /// on the fly, so that this [`NtfsFile`] remains usable afterwards.
+    /// The $LogFile Sequence Number (LSN) is kept as is, because no changes are recorded in the $LogFile.
+    ///
+    /// File Records that are mirrored in $MFTMirr (see [`Ntfs::mft_mirror_record_count`]) are also written
+    /// to the mirror.
+    /// This can be used to repair a damaged $MFTMirr.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn write_record<T>(&mut self, fs: &mut T) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let ntfs = self.ntfs();
+        let position = self.position();
+        let mirror_position = if self.file_record_number < ntfs.mft_mirror_record_count() as u64 {
+            Some(
+                ntfs.mft_mirror_position()
+                    + self.file_record_number * ntfs.file_record_size() as u64,
+            )
+        } else {
+            None
+        };
+
+        self.record.write_with(|data| {
+            fs.seek(SeekFrom::Start(position))?;
+            fs.write_all(data)?;
+
+            if let Some(mirror_position) = mirror_position {
+                fs.seek(SeekFrom::Start(mirror_position))?;
+                fs.write_all(data)?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// Builds the raw bytes of a resident $FILE_NAME attribute with the given value.
+#[cfg(feature = "std")]
+fn build_file_name_attribute(value: &[u8]) -> Vec<u8> {
+    build_resident_attribute(
+        NtfsAttributeType::FileName as u32,
+        &[],
+        NtfsAttributeFlags::empty(),
+        0,
+        value,
+        true,
+    )
+}
+
+/// Compares two attributes given by their type code and name in the order NTFS expects them in a File Record.
+///
+/// Attributes are sorted by type code first.
+/// Names of attributes of the same type are compared case-insensitively using the $UpCase table,
+/// and case-sensitively only if they are equal apart from case.
+/// This requires the $UpCase table only if both names are non-empty and different.
+#[cfg(feature = "std")]
+fn compare_attributes(
+    ntfs: &Ntfs,
+    (a_type_code, a_name): (u32, &NtfsString),
+    (b_type_code, b_name): (u32, &NtfsString),
+) -> Ordering {
+    a_type_code.cmp(&b_type_code).then_with(|| {
+        if a_name.0.is_empty() || b_name.0.is_empty() || a_name == b_name {
+            a_name.cmp(b_name)
+        } else {
+            a_name
+                .upcase_cmp(ntfs, b_name)
+                .then_with(|| a_name.cmp(b_name))
+        }
+    })
+}
+
+/// Copies `cluster_count` clusters starting at `source` to the clusters starting at `destination`.
+#[cfg(feature = "std")]
+fn copy_clusters<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    source: Lcn,
+    destination: Lcn,
+    cluster_count: u64,
+) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    const COPY_BUFFER_SIZE: u64 = 65536;
+    let mut buf = [0u8; COPY_BUFFER_SIZE as usize];
+    let source_position = source.position(ntfs)?;
+    let destination_position = destination.position(ntfs)?;
+    let length = cluster_count * ntfs.cluster_size() as u64;
+    let mut offset = 0;
+
+    while offset < length {
+        let chunk_length = u64::min(length - offset, COPY_BUFFER_SIZE) as usize;
+
+        fs.seek(SeekFrom::Start(source_position + offset))?;
+        fs.read_exact(&mut buf[..chunk_length])?;
+        fs.seek(SeekFrom::Start(destination_position + offset))?;
+        fs.write_all(&buf[..chunk_length])?;
+
+        offset += chunk_length as u64;
+    }
+
+    Ok(())
+}
+
+/// Returns the File Record among `records` that contains the given extent.
+#[cfg(feature = "std")]
+fn extent_record<'a, 'n>(
+    records: &'a mut [NtfsFile<'n>],
+    extent: &AttributeExtent,
+) -> &'a mut NtfsFile<'n> {
+    records
+        .iter_mut()
+        .find(|record| record.file_record_number == extent.file_record_number)
+        .unwrap()
+}
+
+/// Returns a copy of `runlist` in which all clusters at or beyond the LCN `cluster_count` have been moved
+/// to free clusters allocated via the `allocate` function.
+///
+/// All newly allocated clusters are added to `allocated_runlist`, so that the caller can free them on error.
+#[cfg(feature = "std")]
+fn move_runs_below<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    allocate: AllocateFn,
+    runlist: &NtfsRunlist,
+    cluster_count: u64,
+    allocated_runlist: &mut NtfsRunlist,
+) -> Result<NtfsRunlist>
+where
+    T: Read + Write + Seek,
+{
+    let mut new_runlist = NtfsRunlist::new();
+
+    for run in runlist {
+        let lcn = match run.lcn() {
+            Some(lcn) if u64::from(lcn) + run.cluster_count() > cluster_count => u64::from(lcn),
+            _ => {
+                new_runlist.push(*run);
+                continue;
+            }
+        };
+
+        let kept_cluster_count = cluster_count.saturating_sub(lcn);
+        if kept_cluster_count > 0 {
+            new_runlist.push(NtfsRun::new(Lcn::from(lcn), kept_cluster_count));
+        }
+
+        let moved_runlist = allocate(cluster_allocator, run.cluster_count() - kept_cluster_count)?;
+        let mut source_lcn = lcn + kept_cluster_count;
+
+        for moved_run in &moved_runlist {
+            allocated_runlist.push(*moved_run);
+
+            let destination_lcn = moved_run.lcn().unwrap();
+            copy_clusters(
+                ntfs,
+                fs,
+                Lcn::from(source_lcn),
+                destination_lcn,
+                moved_run.cluster_count(),
+            )?;
+
+            new_runlist.push(*moved_run);
+            source_lcn += moved_run.cluster_count();
+        }
+    }
+
+    Ok(new_runlist)
+}
+
+/// Returns the UTF-16 code units of `name` if it is a valid name of a named $DATA attribute,
+/// or [`NtfsError::InvalidDataStreamName`] otherwise.
+#[cfg(feature = "std")]
+fn validate_data_stream_name(name: &str) -> Result<Vec<u16>> {
+    let utf16_name = name.encode_utf16().collect::<Vec<u16>>();
+
+    if utf16_name.is_empty()
+        || utf16_name.len() > u8::MAX as usize
+        || name.contains([':', '/', '\\', '\0'])
+    {
+        return Err(NtfsError::InvalidDataStreamName {
+            name: name.to_string(),
+        });
+    }
+
+    Ok(utf16_name)
+}
+
+/// Writes zeros to the bytes of a non-resident value with the given runlist from its old initialized size
+/// up to its new length `new_len`.
+///
+/// If `initialize` is `false`, the initialized size is kept, so only the rest of the cluster containing it
+/// is zeroed.
+/// All bytes beyond the initialized size read as zeros anyway, but the initialized size may grow later.
+#[cfg(feature = "std")]
+fn zero_beyond_initialized_size<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    runlist: &NtfsRunlist,
+    initialized_size: u64,
+    new_len: u64,
+    initialize: bool,
+) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    if new_len <= initialized_size {
+        return Ok(());
+    }
+
+    let end = if initialize {
+        new_len
+    } else {
+        let cluster_size = ntfs.cluster_size() as u64;
+        u64::min(
+            div_ceil(initialized_size, cluster_size) * cluster_size,
+            new_len,
+        )
+    };
+
+    runlist.write_zeros(ntfs, fs, initialized_size..end)
+}
+
+/// A part of a non-resident attribute value along with its location, which may be an extension File Record.
+#[cfg(feature = "std")]
+struct AttributeExtent {
+    ty: NtfsAttributeType,
+    name: Vec<u8>,
+    file_record_number: u64,
+    instance: u16,
+    lowest_vcn: Vcn,
+    runlist: NtfsRunlist,
+}
+
+#[cfg(feature = "std")]
+impl AttributeExtent {
+    /// Appends all non-resident attributes stored in the given File Record to `extents`.
+    fn collect(file: &NtfsFile, extents: &mut Vec<AttributeExtent>) -> Result<()> {
+        for attribute in file.attributes_raw() {
+            if attribute.is_resident() {
+                continue;
+            }
+
+            let data_runs = attribute.non_resident_value()?.data_runs();
+
+            extents.push(Self {
+                ty: attribute.ty()?,
+                name: attribute.name()?.0.to_vec(),
+                file_record_number: file.file_record_number(),
+                instance: attribute.instance(),
+                lowest_vcn: attribute.non_resident_value_lowest_vcn(),
+                runlist: NtfsRunlist::from_data_runs(data_runs)?,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// A $FILE_NAME attribute of a file along with its location, which may be an extension File Record.
+#[cfg(feature = "std")]
+struct FileNameAttribute {
+    file_record_number: u64,
+    instance: u16,
+    file_name: NtfsFileName,
+    value: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl FileNameAttribute {
+    /// Appends all $FILE_NAME attributes stored in the given File Record to `file_name_attributes`.
+    fn collect<T>(
+        file: &NtfsFile,
+        fs: &mut T,
+        file_name_attributes: &mut Vec<FileNameAttribute>,
+    ) -> Result<()>
+    where
+        T: Read + Seek,
+    {
+        for attribute in file.attributes_raw() {
+            if attribute.ty()? != NtfsAttributeType::FileName {
+                continue;
+            }
+
+            file_name_attributes.push(Self {
+                file_record_number: file.file_record_number(),
+                instance: attribute.instance(),
+                file_name: attribute.structured_value::<_, NtfsFileName>(fs)?,
+                value: attribute.resident_value()?.data().to_vec(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexes::{
+        read_security_descriptor, NtfsSecurityHashIndex, NtfsSecurityId, NtfsSecurityIdIndex,
+        SECURITY_DESCRIPTOR_HEADER_SIZE,
+    };
+    use crate::traits::{NtfsReadSeek, NtfsWrite};
+
+    /// Returns the (allocated size, data size) of "file-with-5-zeros" stored in its root directory entry.
+    fn directory_entry_sizes(ntfs: &Ntfs, fs: &mut std::io::Cursor<Vec<u8>>) -> (u64, u64) {
+        let root_dir = ntfs.root_directory(fs).unwrap();
+        let root_dir_index = root_dir.directory_index(fs).unwrap();
+        let mut root_dir_finder = root_dir_index.finder();
+        let entry = NtfsFileNameIndex::find(&mut root_dir_finder, ntfs, fs, "file-with-5-zeros")
+            .unwrap()
+            .unwrap();
+        let file_name = entry.key().unwrap().unwrap();
+
+        (file_name.allocated_size(), file_name.data_size())
+    }
+
+    fn read_data(
+        ntfs: &Ntfs,
+        fs: &mut std::io::Cursor<Vec<u8>>,
+        file_record_number: u64,
+        data_stream_name: &str,
+    ) -> Vec<u8> {
+        let file = ntfs.file(fs, file_record_number).unwrap();
+        let data_item = file.data(fs, data_stream_name).unwrap().unwrap();
+        let data_attribute = data_item.to_attribute();
+        let mut data_value = data_attribute.value().unwrap();
+
+        let mut data = vec![0u8; data_value.len() as usize];
+        data_value.read_exact(fs, &mut data).unwrap();
+        data
+    }
+
+    /// Looks up `name` in the given directory and returns the key of its entry.
+    fn find_file_name(
+        ntfs: &Ntfs,
+        fs: &mut std::io::Cursor<Vec<u8>>,
+        directory_record_number: u64,
+        name: &str,
+    ) -> Option<NtfsFileName> {
+        let directory = ntfs.file(fs, directory_record_number).unwrap();
+        let directory_index = directory.directory_index(fs).unwrap();
+        directory_index.check_ordering(ntfs, fs).unwrap();
+
+        let mut finder = directory_index.finder();
+        let entry = NtfsFileNameIndex::find(&mut finder, ntfs, fs, name)?.unwrap();
+        Some(entry.key().unwrap().unwrap())
+    }
+
+    #[test]
+    fn test_defragment() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+
+        // Grow three files in turns, so that their clusters interleave.
+        let mut files = ["a.dat", "b.dat", "filler.dat"]
+            .iter()
+            .map(|name| {
+                ntfs.create_file(&mut testfs1, &mut cluster_allocator, &mut root_dir, name)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        // The patterns below are written directly, so the grown values must be fully initialized.
+        for i in 1..=4 {
+            for file in &mut files {
+                let offset = file.find_data_attribute_offset("").unwrap();
+                file.set_attribute_len(
+                    &mut testfs1,
+                    &mut cluster_allocator,
+                    NtfsClusterAllocator::allocate,
+                    offset,
+                    i * 1024,
+                    true,
+                )
+                .unwrap();
+            }
+        }
+
+        let file_record_numbers = files
+            .iter()
+            .map(|file| file.file_record_number())
+            .collect::<Vec<_>>();
+        let patterns = (0..3u8)
+            .map(|i| (0..4096).map(|j| (j as u8) ^ i).collect::<Vec<u8>>())
+            .collect::<Vec<_>>();
+        for (&file_record_number, pattern) in file_record_numbers.iter().zip(&patterns) {
+            let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+            file.data(&mut testfs1, "")
+                .unwrap()
+                .unwrap()
+                .to_attribute()
+                .value()
+                .unwrap()
+                .write_all(&mut testfs1, pattern)
+                .unwrap();
+        }
+
+        // Split the $DATA attribute of "b.dat" into two extents stored in extension File Records.
+        let mut file = ntfs.file(&mut testfs1, file_record_numbers[1]).unwrap();
+        let offset = file.find_data_attribute_offset("").unwrap();
+        let attribute = NtfsAttribute::new(&file, offset, None);
+        let mut runlist =
+            NtfsRunlist::from_data_runs(attribute.non_resident_value().unwrap().data_runs())
+                .unwrap();
+        let tail = runlist.split_off_clusters(2);
+        let extent_bytes = vec![
+            (
+                Vcn::from(0),
+                attribute.to_non_resident_extent_bytes(Vcn::from(0), &runlist),
+            ),
+            (
+                Vcn::from(2),
+                attribute.to_non_resident_extent_bytes(Vcn::from(2), &tail),
+            ),
+        ];
+        let instance = attribute.instance();
+        file.remove_attribute(offset);
+
+        let mut entries = file.build_attribute_list_entries().unwrap();
+        let mut extension_record_numbers = Vec::new();
+        for (lowest_vcn, bytes) in extent_bytes {
+            let mut extension =
+                NtfsFile::allocate(&ntfs, &mut testfs1, &mut cluster_allocator).unwrap();
+            extension.set_flags(NtfsFileFlags::IN_USE);
+            extension.set_base_file_reference(file.file_reference());
+
+            // Connected attributes share the instance of the first one.
+            extension.set_next_attribute_instance(instance);
+            extension.insert_attribute(bytes).unwrap();
+            extension.write_record(&mut testfs1).unwrap();
+
+            let entry = build_attribute_list_entry(
+                NtfsAttributeType::Data as u32,
+                &[],
+                lowest_vcn,
+                extension.file_reference(),
+                instance,
+            );
+            insert_raw_attribute_list_entry(&mut entries, entry);
+            extension_record_numbers.push(extension.file_record_number());
+        }
+
+        file.set_attribute_list(&entries).unwrap();
+        file.write_record(&mut testfs1).unwrap();
+        assert_eq!(
+            read_data(&ntfs, &mut testfs1, file_record_numbers[1], ""),
+            patterns[1]
+        );
+
+        let old_extents = file_record_numbers
+            .iter()
+            .map(|&file_record_number| {
+                let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+                file.non_resident_extents(&mut testfs1).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(old_extents[0][0].runlist.runs().len(), 4);
+        assert_eq!(old_extents[1].len(), 2);
+        let free_cluster_count = cluster_allocator.free_cluster_count();
+
+        for &file_record_number in &file_record_numbers[..2] {
+            let mut file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+            file.defragment(&mut testfs1, &mut cluster_allocator)
+                .unwrap();
+        }
+
+        for (i, &file_record_number) in file_record_numbers.iter().enumerate() {
+            assert_eq!(
+                read_data(&ntfs, &mut testfs1, file_record_number, ""),
+                patterns[i]
+            );
+        }
+
+        // Both files now consist of a single run in a single extent, and their old clusters are free again.
+        let extent_record_numbers = [file_record_numbers[0], extension_record_numbers[0]];
+        for (i, &file_record_number) in file_record_numbers[..2].iter().enumerate() {
+            let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+            let extents = file.non_resident_extents(&mut testfs1).unwrap();
+            assert_eq!(extents.len(), 1);
+            assert_eq!(extents[0].file_record_number, extent_record_numbers[i]);
+            assert_eq!(extents[0].runlist.runs().len(), 1);
+            assert_eq!(extents[0].runlist.cluster_count(), 8);
+
+            for extent in &old_extents[i] {
+                for run in &extent.runlist {
+                    assert!(!cluster_allocator.is_allocated(run.lcn().unwrap()));
+                }
+            }
+        }
+
+        // The emptied extension File Record has been released.
+        let file = ntfs.file(&mut testfs1, file_record_numbers[1]).unwrap();
+        assert_eq!(
+            file.extension_file_record_numbers(&mut testfs1).unwrap(),
+            [extension_record_numbers[0]]
+        );
+        let extension = ntfs
+            .file(&mut testfs1, extension_record_numbers[1])
+            .unwrap();
+        assert!(!extension.flags().contains(NtfsFileFlags::IN_USE));
+
+        // The changes to $Bitmap have been written.
+        assert_eq!(cluster_allocator.free_cluster_count(), free_cluster_count);
+        let reread_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        assert_eq!(reread_allocator.free_cluster_count(), free_cluster_count);
+
+        // Defragmenting a contiguous file changes nothing.
+        let mut file = ntfs.file(&mut testfs1, file_record_numbers[0]).unwrap();
+        let record_data = file.record_data().to_vec();
+        file.defragment(&mut testfs1, &mut cluster_allocator)
+            .unwrap();
+        assert_eq!(file.record_data(), &record_data[..]);
+
+        let mut mft = ntfs
+            .file(&mut testfs1, KnownNtfsFileRecordNumber::MFT as u64)
+            .unwrap();
+        assert!(matches!(
+            mft.defragment(&mut testfs1, &mut cluster_allocator),
+            Err(NtfsError::UnsupportedOperationOnSystemFile { .. })
+        ));
+    }
+
+    #[test]
+    fn test_link() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let root_dir_record_number = root_dir.file_record_number();
+
+        let mut file = ntfs
+            .create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "target.txt",
+            )
+            .unwrap();
+        file.set_len(&mut testfs1, &mut cluster_allocator, "", 1000)
+            .unwrap();
+        let file_record_number = file.file_record_number();
+        let mut dir = ntfs
+            .create_dir(&mut testfs1, &mut cluster_allocator, &mut root_dir, "links")
+            .unwrap();
+        let dir_record_number = dir.file_record_number();
+
+        // Add enough links with long names to fill the File Record and need an extension File Record.
+        let names = (0..12)
+            .map(|i| {
+                format!(
+                    "link-{:02}-with-a-rather-long-name-to-fill-the-file-record",
+                    i
+                )
+            })
+            .collect::<Vec<String>>();
+
+        for name in &names {
+            file.link(&mut testfs1, &mut cluster_allocator, &mut dir, name)
+                .unwrap();
+        }
+
+        assert_eq!(file.hard_link_count(), 13);
+        let extension_file_record_numbers =
+            file.extension_file_record_numbers(&mut testfs1).unwrap();
+        assert!(!extension_file_record_numbers.is_empty());
+
+        for name in &names {
+            let file_name = find_file_name(&ntfs, &mut testfs1, dir_record_number, name).unwrap();
+            assert_eq!(file_name.name(), name.as_str());
+            assert_eq!(file_name.namespace(), NtfsFileNamespace::Posix);
+            assert_eq!(file_name.data_size(), 1000);
+        }
+
+        // All names can be found through the $ATTRIBUTE_LIST.
+        let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+        assert_eq!(file.file_name_attributes(&mut testfs1).unwrap().len(), 13);
+        let file_name = file
+            .name(
+                &mut testfs1,
+                Some(NtfsFileNamespace::Posix),
+                Some(dir_record_number),
+            )
+            .unwrap()
+            .unwrap();
+        assert!(names.iter().any(|name| file_name.name() == name.as_str()));
+
+        // Existing names are not overwritten and directories cannot be linked.
+        let mut file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+        assert!(matches!(
+            file.link(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "TARGET.TXT"
+            ),
+            Err(NtfsError::FileNameAlreadyExists { .. })
+        ));
+        let mut dir = ntfs.file(&mut testfs1, dir_record_number).unwrap();
+        assert!(matches!(
+            dir.link(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "dir-link"
+            ),
+            Err(NtfsError::UnsupportedHardLinkToDirectory { .. })
+        ));
+
+        // The hard link count cannot overflow.
+        let mut full_file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+        full_file.set_hard_link_count(u16::MAX);
+        assert!(matches!(
+            full_file.link(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "overflow-link"
+            ),
+            Err(NtfsError::TooManyHardLinks { .. })
+        ));
+        assert!(
+            find_file_name(&ntfs, &mut testfs1, root_dir_record_number, "overflow-link").is_none()
+        );
+
+        // Deleting a link only removes that link.
+        ntfs.delete(
+            &mut testfs1,
+            &mut cluster_allocator,
+            &mut dir,
+            &names[0],
+            false,
+        )
+        .unwrap();
+        let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+        assert_eq!(file.hard_link_count(), 12);
+        assert!(find_file_name(&ntfs, &mut testfs1, dir_record_number, &names[0]).is_none());
+        assert!(find_file_name(&ntfs, &mut testfs1, dir_record_number, &names[1]).is_some());
+
+        // Deleting the last link frees the file along with its extension File Records.
+        ntfs.delete(
+            &mut testfs1,
+            &mut cluster_allocator,
+            &mut root_dir,
+            "target.txt",
+            false,
+        )
+        .unwrap();
+
+        for name in &names[1..] {
+            let mut dir = ntfs.file(&mut testfs1, dir_record_number).unwrap();
+            ntfs.delete(&mut testfs1, &mut cluster_allocator, &mut dir, name, false)
+                .unwrap();
+        }
+
+        for file_record_number in [file_record_number]
+            .iter()
+            .chain(&extension_file_record_numbers)
+        {
+            let position = ntfs
+                .file_record_position(&mut testfs1, *file_record_number)
+                .unwrap();
+            let file = NtfsFile::new(&ntfs, &mut testfs1, position, *file_record_number).unwrap();
+            assert!(!file.flags().contains(NtfsFileFlags::IN_USE));
+        }
+
+        assert!(
+            find_file_name(&ntfs, &mut testfs1, root_dir_record_number, "target.txt").is_none()
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let root_dir_record_number = root_dir.file_record_number();
+
+        let mut file = ntfs
+            .create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "Hello World.txt",
+            )
+            .unwrap();
+        file.set_len(&mut testfs1, &mut cluster_allocator, "", 1000)
+            .unwrap();
+        let mut dir = ntfs
+            .create_dir(&mut testfs1, &mut cluster_allocator, &mut root_dir, "dir")
+            .unwrap();
+        let dir_record_number = dir.file_record_number();
+        let mut subdir = ntfs
+            .create_dir(&mut testfs1, &mut cluster_allocator, &mut dir, "subdir")
+            .unwrap();
+        ntfs.create_file(&mut testfs1, &mut cluster_allocator, &mut dir, "other")
+            .unwrap();
+
+        // Change the case of the name within the same directory, which keeps the generated MS-DOS name.
+        file.rename(
+            &mut testfs1,
+            &mut cluster_allocator,
+            &mut root_dir,
+            "hello world.txt",
+        )
+        .unwrap();
+        assert_eq!(file.hard_link_count(), 2);
+
+        let file_name = find_file_name(
+            &ntfs,
+            &mut testfs1,
+            root_dir_record_number,
+            "HELLO WORLD.TXT",
+        )
+        .unwrap();
+        assert_eq!(file_name.name(), "hello world.txt");
+        assert_eq!(file_name.namespace(), NtfsFileNamespace::Win32);
+        assert_eq!(file_name.data_size(), 1000);
+        let file_name =
+            find_file_name(&ntfs, &mut testfs1, root_dir_record_number, "HELLOW~1.TXT").unwrap();
+        assert_eq!(file_name.namespace(), NtfsFileNamespace::Dos);
+
+        // Move the file into another directory under a valid MS-DOS name, which replaces both names by one.
+        file.rename(&mut testfs1, &mut cluster_allocator, &mut dir, "short.txt")
+            .unwrap();
+        assert_eq!(file.hard_link_count(), 1);
+
+        for name in ["hello world.txt", "HELLOW~1.TXT"] {
+            assert!(find_file_name(&ntfs, &mut testfs1, root_dir_record_number, name).is_none());
+        }
+
+        let file_name =
+            find_file_name(&ntfs, &mut testfs1, dir_record_number, "SHORT.TXT").unwrap();
+        assert_eq!(file_name.name(), "short.txt");
+        assert_eq!(file_name.namespace(), NtfsFileNamespace::Win32AndDos);
+        assert_eq!(file_name.data_size(), 1000);
+        assert_eq!(
+            file_name.parent_directory_reference().file_record_number(),
+            dir_record_number
+        );
+
+        let mut file = ntfs.file(&mut testfs1, file.file_record_number()).unwrap();
+        let file_name = file.name(&mut testfs1, None, None).unwrap().unwrap();
+        assert_eq!(file_name.name(), "short.txt");
+        assert_eq!(
+            file_name.parent_directory_reference().file_record_number(),
+            dir_record_number
+        );
+
+        // Existing names of other files are not overwritten.
+        assert!(matches!(
+            file.rename(&mut testfs1, &mut cluster_allocator, &mut dir, "OTHER"),
+            Err(NtfsError::FileNameAlreadyExists { .. })
+        ));
+        assert!(find_file_name(&ntfs, &mut testfs1, dir_record_number, "short.txt").is_some());
+
+        // A directory cannot be moved into itself.
+        let mut dir = ntfs.file(&mut testfs1, dir_record_number).unwrap();
+        assert!(matches!(
+            dir.rename(&mut testfs1, &mut cluster_allocator, &mut subdir, "dir"),
+            Err(NtfsError::MoveIntoOwnSubdirectory { .. })
+        ));
+
+        // Corrupted parent directory references forming a cycle are detected.
+        let mut cycle_dir = ntfs
+            .create_dir(&mut testfs1, &mut cluster_allocator, &mut root_dir, "cycle")
+            .unwrap();
+        let mut set_parent = |file: &NtfsFile, parent: &NtfsFile| {
+            let item = file
+                .find_attribute(&mut testfs1, NtfsAttributeType::FileName, None)
+                .unwrap();
+            let position = item
+                .to_attribute()
+                .value()
+                .unwrap()
+                .data_position()
+                .unwrap() as usize;
+            testfs1.get_mut()[position..position + 8]
+                .copy_from_slice(&parent.file_reference().to_bytes());
+        };
+        set_parent(&dir, &subdir);
+        set_parent(&subdir, &dir);
+        assert!(matches!(
+            cycle_dir.rename(&mut testfs1, &mut cluster_allocator, &mut subdir, "cycle"),
+            Err(NtfsError::ParentDirectoryCycle { .. })
+        ));
+    }
+
+    #[test]
+    fn test_set_info() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let root_dir_record_number = root_dir.file_record_number();
+
+        let mut file = ntfs
+            .create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "Hello World.txt",
+            )
+            .unwrap();
+        let mut dir = ntfs
+            .create_dir(&mut testfs1, &mut cluster_allocator, &mut root_dir, "dir")
+            .unwrap();
+        let dir_record_number = dir.file_record_number();
+        file.link(&mut testfs1, &mut cluster_allocator, &mut dir, "link")
+            .unwrap();
+
+        let time = NtfsTime::from(132_000_000_000_000_000);
+        let file_attributes = NtfsFileAttributeFlags::READ_ONLY | NtfsFileAttributeFlags::ARCHIVE;
+        let mut info = file.info().unwrap();
+        let old_creation_time = info.creation_time();
+        info.set_creation_time(time);
+        info.set_modification_time(time);
+        info.set_mft_record_modification_time(time);
+        info.set_access_time(time);
+        info.set_file_attributes(file_attributes);
+
+        // Only change $STANDARD_INFORMATION.
+        file.set_info(&mut testfs1, &info, false).unwrap();
+        let mut file = ntfs.file(&mut testfs1, file.file_record_number()).unwrap();
+        let reread_info = file.info().unwrap();
+        assert_eq!(reread_info.creation_time(), time);
+        assert_eq!(reread_info.access_time(), time);
+        assert_eq!(reread_info.file_attributes(), file_attributes);
+        assert_eq!(reread_info.security_id(), info.security_id());
+
+        let file_name = find_file_name(&ntfs, &mut testfs1, dir_record_number, "link").unwrap();
+        assert_eq!(file_name.creation_time(), old_creation_time);
+
+        // Also change the $FILE_NAME attributes and directory entries of all links.
+        file.set_info(&mut testfs1, &info, true).unwrap();
+
+        for (directory_record_number, name) in [
+            (root_dir_record_number, "Hello World.txt"),
+            (root_dir_record_number, "HELLOW~1.TXT"),
+            (dir_record_number, "link"),
+        ] {
+            let file_name =
+                find_file_name(&ntfs, &mut testfs1, directory_record_number, name).unwrap();
+            assert_eq!(file_name.creation_time(), time);
+            assert_eq!(file_name.modification_time(), time);
+            assert_eq!(file_name.mft_record_modification_time(), time);
+            assert_eq!(file_name.access_time(), time);
+            assert_eq!(file_name.file_attributes(), file_attributes);
+        }
+
+        let file = ntfs.file(&mut testfs1, file.file_record_number()).unwrap();
+        for file_name_attribute in file.file_name_attributes(&mut testfs1).unwrap() {
+            assert_eq!(file_name_attribute.file_name.creation_time(), time);
+            assert_eq!(
+                file_name_attribute.file_name.file_attributes(),
+                file_attributes
+            );
+        }
+
+        // Directories keep their directory flag in $FILE_NAME.
+        let mut info = dir.info().unwrap();
+        info.set_file_attributes(NtfsFileAttributeFlags::HIDDEN);
+        dir.set_info(&mut testfs1, &info, true).unwrap();
+        let file_name = find_file_name(&ntfs, &mut testfs1, root_dir_record_number, "dir").unwrap();
+        assert_eq!(
+            file_name.file_attributes(),
+            NtfsFileAttributeFlags::HIDDEN | NtfsFileAttributeFlags::IS_DIRECTORY
+        );
+    }
+
+    #[test]
+    fn test_set_reparse_point() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let root_dir_record_number = root_dir.file_record_number();
+
+        // Turn an empty directory into a junction.
+        let mut junction = ntfs
+            .create_dir(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "Documents and Settings",
+            )
+            .unwrap();
+        junction
+            .set_mount_point(
+                &mut testfs1,
+                &mut cluster_allocator,
+                "\\??\\C:\\Users",
+                "C:\\Users",
+            )
+            .unwrap();
+
+        let junction = ntfs
+            .file(&mut testfs1, junction.file_record_number())
+            .unwrap();
+        let reparse_point = junction.reparse_point(&mut testfs1).unwrap().unwrap();
+        assert_eq!(reparse_point.tag(), NtfsReparseTag::MOUNT_POINT);
+        let link = reparse_point.link().unwrap().unwrap();
+        assert_eq!(link.substitute_name(), "\\??\\C:\\Users");
+        assert_eq!(link.print_name(), "C:\\Users");
+        assert!(junction
+            .info()
+            .unwrap()
+            .file_attributes()
+            .contains(NtfsFileAttributeFlags::REPARSE_POINT));
+
+        for file_name_attribute in junction.file_name_attributes(&mut testfs1).unwrap() {
+            let parent = ntfs.file(&mut testfs1, root_dir_record_number).unwrap();
+            let key = junction
+                .directory_entry_key(
+                    &mut testfs1,
+                    &parent,
+                    &file_name_attribute.file_name,
+                    &file_name_attribute.value,
+                )
+                .unwrap();
+            assert_eq!(
+                LittleEndian::read_u32(&key[60..]),
+                NtfsReparseTag::MOUNT_POINT.0
+            );
+        }
+
+        let file_name = find_file_name(
+            &ntfs,
+            &mut testfs1,
+            root_dir_record_number,
+            "Documents and Settings",
+        )
+        .unwrap();
+        assert!(file_name
+            .file_attributes()
+            .contains(NtfsFileAttributeFlags::REPARSE_POINT));
+        assert!(file_name.is_directory());
+
+        // Create a relative symbolic link and replace its target afterwards.
+        let mut symlink = ntfs
+            .create_file(&mut testfs1, &mut cluster_allocator, &mut root_dir, "link")
+            .unwrap();
+        symlink
+            .set_symbolic_link(&mut testfs1, &mut cluster_allocator, "old", "old", true)
+            .unwrap();
+        symlink
+            .set_symbolic_link(&mut testfs1, &mut cluster_allocator, "new", "new", true)
+            .unwrap();
+
+        let reparse_point = symlink.reparse_point(&mut testfs1).unwrap().unwrap();
+        assert_eq!(reparse_point.tag(), NtfsReparseTag::SYMLINK);
+        let link = reparse_point.link().unwrap().unwrap();
+        assert!(link.is_relative());
+        assert_eq!(link.substitute_name(), "new");
+
+        // Setting file attributes cannot clear the REPARSE_POINT flag of the symbolic link.
+        let mut info = symlink.info().unwrap();
+        info.set_file_attributes(NtfsFileAttributeFlags::HIDDEN);
+        symlink.set_info(&mut testfs1, &info, true).unwrap();
+
+        let symlink = ntfs
+            .file(&mut testfs1, symlink.file_record_number())
+            .unwrap();
+        assert_eq!(
+            symlink.info().unwrap().file_attributes(),
+            NtfsFileAttributeFlags::HIDDEN | NtfsFileAttributeFlags::REPARSE_POINT
+        );
+        let file_name =
+            find_file_name(&ntfs, &mut testfs1, root_dir_record_number, "link").unwrap();
+        assert_eq!(
+            file_name.file_attributes(),
+            NtfsFileAttributeFlags::HIDDEN | NtfsFileAttributeFlags::REPARSE_POINT
+        );
+
+        // Both reparse points are registered in $Extend\$Reparse.
+        let reparse_file = ntfs.extend_file(&mut testfs1, "$Reparse").unwrap().unwrap();
+        reparse_file
+            .index::<NtfsReparseIndex, _>(&mut testfs1, "$R")
+            .unwrap()
+            .check_ordering(&ntfs, &mut testfs1)
+            .unwrap();
+
+        let mut iter = ntfs.reparse_points(&mut testfs1, None).unwrap();
+        let mut file_record_numbers = Vec::new();
+        while let Some(item) = iter.next(&mut testfs1) {
+            let (file, _) = item.unwrap();
+            file_record_numbers.push(file.file_record_number());
+        }
+        assert_eq!(
+            file_record_numbers,
+            [junction.file_record_number(), symlink.file_record_number()]
+        );
+
+        // Deleting a reparse point also unregisters it.
+        ntfs.delete(
+            &mut testfs1,
+            &mut cluster_allocator,
+            &mut root_dir,
+            "link",
+            false,
+        )
+        .unwrap();
+        let mut iter = ntfs
+            .reparse_points(&mut testfs1, Some(NtfsReparseTag::SYMLINK))
+            .unwrap();
+        assert!(iter.next(&mut testfs1).is_none());
+
+        // Mount points need an empty directory.
+        let mut dir = ntfs
+            .create_dir(&mut testfs1, &mut cluster_allocator, &mut root_dir, "dir")
+            .unwrap();
+        let mut file = ntfs
+            .create_file(&mut testfs1, &mut cluster_allocator, &mut dir, "file")
+            .unwrap();
+        assert!(matches!(
+            dir.set_mount_point(&mut testfs1, &mut cluster_allocator, "\\??\\C:\\", "C:\\"),
+            Err(NtfsError::DirectoryNotEmpty { .. })
+        ));
+        assert!(matches!(
+            file.set_mount_point(&mut testfs1, &mut cluster_allocator, "\\??\\C:\\", "C:\\"),
+            Err(NtfsError::NotADirectory { .. })
+        ));
+
+        // Without $Extend\$Reparse, no reparse point can be registered, so none is set.
+        let mut extend_dir = ntfs
+            .file(&mut testfs1, KnownNtfsFileRecordNumber::Extend as u64)
+            .unwrap();
+        let reparse_file = ntfs.extend_file(&mut testfs1, "$Reparse").unwrap().unwrap();
+        let file_name_attribute = reparse_file
+            .file_name_attributes(&mut testfs1)
+            .unwrap()
+            .remove(0);
+        remove_index_entry::<NtfsFileNameIndex, _>(
+            &mut extend_dir,
+            &mut testfs1,
+            &mut cluster_allocator,
+            "$I30",
+            &file_name_attribute.value,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            file.set_symbolic_link(&mut testfs1, &mut cluster_allocator, "new", "new", true),
+            Err(NtfsError::FileNameNotFound { .. })
+        ));
+        assert!(file.reparse_point(&mut testfs1).is_none());
+    }
+
+    #[test]
+    fn test_set_security_descriptor() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+
+        let security_descriptor = |fs: &mut std::io::Cursor<Vec<u8>>, security_id| {
+            let secure_file = ntfs
+                .file(fs, KnownNtfsFileRecordNumber::Secure as u64)
+                .unwrap();
+            let sii_index = secure_file
+                .index::<NtfsSecurityIdIndex, _>(fs, "$SII")
+                .unwrap();
+            let mut finder = sii_index.finder();
+            let entry =
+                NtfsSecurityIdIndex::find(&mut finder, &ntfs, fs, NtfsSecurityId(security_id))
+                    .unwrap()
+                    .unwrap();
+            let header = entry.data().unwrap().unwrap();
+            read_security_descriptor(fs, &secure_file, &header).unwrap()
+        };
+        let sds_len = |fs: &mut std::io::Cursor<Vec<u8>>| {
+            let secure_file = ntfs
+                .file(fs, KnownNtfsFileRecordNumber::Secure as u64)
+                .unwrap();
+            let item = secure_file.data(fs, "$SDS").unwrap().unwrap();
+            let len = item.to_attribute().value_length();
+            len
+        };
+
+        // The root directory still has an NTFS 1.x $STANDARD_INFORMATION and its own $SECURITY_DESCRIPTOR.
+        // Assigning an existing security descriptor reuses its Security ID.
+        let existing = security_descriptor(&mut testfs1, 0x100);
+        let old_sds_len = sds_len(&mut testfs1);
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        assert_eq!(root_dir.info().unwrap().security_id(), None);
+        root_dir
+            .set_security_descriptor(&mut testfs1, &mut cluster_allocator, &existing)
+            .unwrap();
+
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        assert_eq!(root_dir.info().unwrap().security_id(), Some(0x100));
+        assert!(root_dir
+            .attributes_raw()
+            .all(|a| a.ty().unwrap() != NtfsAttributeType::SecurityDescriptor));
+        assert_eq!(sds_len(&mut testfs1), old_sds_len);
+
+        // A new security descriptor is appended to $SDS and its mirror copy.
+        let mut new = existing.clone();
+        let last = new.len() - 1;
+        new[last] ^= 0xff;
+
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let mut file = ntfs
+            .create_file(&mut testfs1, &mut cluster_allocator, &mut root_dir, "file")
+            .unwrap();
+        file.set_security_descriptor(&mut testfs1, &mut cluster_allocator, &new)
+            .unwrap();
+
+        let file = ntfs.file(&mut testfs1, file.file_record_number()).unwrap();
+        assert_eq!(file.info().unwrap().security_id(), Some(0x102));
+        assert_eq!(security_descriptor(&mut testfs1, 0x102), new);
+        assert_eq!(
+            sds_len(&mut testfs1),
+            0x40100 + (SECURITY_DESCRIPTOR_HEADER_SIZE + new.len()) as u64
+        );
+
+        let secure_file = ntfs
+            .file(&mut testfs1, KnownNtfsFileRecordNumber::Secure as u64)
+            .unwrap();
+        let item = secure_file.data(&mut testfs1, "$SDS").unwrap().unwrap();
+        let mut value = item.to_attribute().value().unwrap();
+        let mut primary = vec![0u8; SECURITY_DESCRIPTOR_HEADER_SIZE + new.len()];
+        let mut mirror = primary.clone();
+        value.seek(&mut testfs1, SeekFrom::Start(0x100)).unwrap();
+        value.read_exact(&mut testfs1, &mut primary).unwrap();
+        value.seek(&mut testfs1, SeekFrom::Start(0x40100)).unwrap();
+        value.read_exact(&mut testfs1, &mut mirror).unwrap();
+        assert_eq!(primary, mirror);
+        assert_eq!(&primary[SECURITY_DESCRIPTOR_HEADER_SIZE..], new);
+
+        secure_file
+            .index::<NtfsSecurityIdIndex, _>(&mut testfs1, "$SII")
+            .unwrap()
+            .check_ordering(&ntfs, &mut testfs1)
+            .unwrap();
+        secure_file
+            .index::<NtfsSecurityHashIndex, _>(&mut testfs1, "$SDH")
+            .unwrap()
+            .check_ordering(&ntfs, &mut testfs1)
+            .unwrap();
+
+        // Assigning the same security descriptor again finds it via $SDH.
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        root_dir
+            .set_security_descriptor(&mut testfs1, &mut cluster_allocator, &new)
+            .unwrap();
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        assert_eq!(root_dir.info().unwrap().security_id(), Some(0x102));
+        assert_eq!(
+            sds_len(&mut testfs1),
+            0x40100 + (SECURITY_DESCRIPTOR_HEADER_SIZE + new.len()) as u64
+        );
+
+        // Only self-relative security descriptors are accepted.
+        let mut absolute = new.clone();
+        absolute[3] &= 0x7f;
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        assert!(matches!(
+            root_dir.set_security_descriptor(&mut testfs1, &mut cluster_allocator, &absolute),
+            Err(NtfsError::InvalidSecurityDescriptor { .. })
+        ));
+        assert!(matches!(
+            root_dir.set_security_descriptor(&mut testfs1, &mut cluster_allocator, &new[..8]),
+            Err(NtfsError::InvalidSecurityDescriptor { .. })
+        ));
+    }
+
+    #[test]
+    fn test_set_len() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let root_dir_index = root_dir.directory_index(&mut testfs1).unwrap();
+        let mut root_dir_finder = root_dir_index.finder();
+        let entry = NtfsFileNameIndex::find(
+            &mut root_dir_finder,
+            &ntfs,
+            &mut testfs1,
+            "file-with-5-zeros",
+        )
+        .unwrap()
+        .unwrap();
+        let mut file = entry.to_file(&ntfs, &mut testfs1).unwrap();
+        let file_record_number = file.file_record_number();
+        assert_eq!(directory_entry_sizes(&ntfs, &mut testfs1).1, 5);
+
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let free_cluster_count = cluster_allocator.free_cluster_count();
+
+        // Grow the resident value in place.
+        file.set_len(&mut testfs1, &mut cluster_allocator, "", 100)
+            .unwrap();
+        let data = read_data(&ntfs, &mut testfs1, file_record_number, "");
+        assert_eq!(data, [0u8; 100]);
+        assert_eq!(directory_entry_sizes(&ntfs, &mut testfs1), (104, 100));
+        assert_eq!(cluster_allocator.free_cluster_count(), free_cluster_count);
+
+        // Grow it beyond the File Record size, converting it to a non-resident value.
+        file.data(&mut testfs1, "")
+            .unwrap()
+            .unwrap()
+            .to_attribute()
+            .value()
+            .unwrap()
+            .write_all(&mut testfs1, b"abc")
+            .unwrap();
+        file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+        file.set_len(&mut testfs1, &mut cluster_allocator, "", 5000)
+            .unwrap();
+
+        let data = read_data(&ntfs, &mut testfs1, file_record_number, "");
+        assert_eq!(data.len(), 5000);
+        assert_eq!(&data[..3], b"abc");
+        assert!(data[3..].iter().all(|&byte| byte == 0));
+        assert!(!file
+            .data(&mut testfs1, "")
+            .unwrap()
+            .unwrap()
+            .to_attribute()
+            .is_resident());
+        assert_eq!(directory_entry_sizes(&ntfs, &mut testfs1), (5120, 5000));
+        assert_eq!(
+            cluster_allocator.free_cluster_count(),
+            free_cluster_count - 10
+        );
+
+        // The changes to $Bitmap have been written.
+        let reread_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        assert_eq!(
+            reread_allocator.free_cluster_count(),
+            free_cluster_count - 10
+        );
+
+        // Grow the non-resident value, keeping its initialized size.
+        file.set_len(&mut testfs1, &mut cluster_allocator, "", 8000)
+            .unwrap();
+        let data = read_data(&ntfs, &mut testfs1, file_record_number, "");
+        assert_eq!(data.len(), 8000);
+        assert_eq!(&data[..3], b"abc");
+        assert!(data[3..].iter().all(|&byte| byte == 0));
+        let initialized_size = file
+            .data(&mut testfs1, "")
+            .unwrap()
+            .unwrap()
+            .to_attribute()
+            .non_resident_value_initialized_size();
+        assert_eq!(initialized_size, 100);
+        assert_eq!(directory_entry_sizes(&ntfs, &mut testfs1), (8192, 8000));
+        assert_eq!(
+            cluster_allocator.free_cluster_count(),
+            free_cluster_count - 16
+        );
+
+        // Shrink the non-resident value, freeing clusters.
+        file.set_len(&mut testfs1, &mut cluster_allocator, "", 600)
+            .unwrap();
+        let data = read_data(&ntfs, &mut testfs1, file_record_number, "");
+        assert_eq!(data.len(), 600);
+        assert_eq!(&data[..3], b"abc");
+        assert_eq!(directory_entry_sizes(&ntfs, &mut testfs1), (1024, 600));
+        assert_eq!(
+            cluster_allocator.free_cluster_count(),
+            free_cluster_count - 2
+        );
+
+        file.set_len(&mut testfs1, &mut cluster_allocator, "", 0)
+            .unwrap();
+        assert!(read_data(&ntfs, &mut testfs1, file_record_number, "").is_empty());
+        assert_eq!(directory_entry_sizes(&ntfs, &mut testfs1), (0, 0));
+        assert_eq!(cluster_allocator.free_cluster_count(), free_cluster_count);
+
+        let reread_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        assert_eq!(reread_allocator.free_cluster_count(), free_cluster_count);
+
+        // There is no such stream.
+        assert!(matches!(
+            file.set_len(&mut testfs1, &mut cluster_allocator, "nonexistent", 0),
+            Err(NtfsError::AttributeNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_data_stream() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let root_dir_record_number = root_dir.file_record_number();
+
+        let mut file = ntfs
+            .create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "download.exe",
+            )
+            .unwrap();
+        let file_record_number = file.file_record_number();
+        let free_cluster_count = cluster_allocator.free_cluster_count();
+
+        // Small streams are resident.
+        let zone_identifier = b"[ZoneTransfer]\r\nZoneId=3\r\n";
+        file.write_data_stream(
+            &mut testfs1,
+            &mut cluster_allocator,
+            "Zone.Identifier",
+            zone_identifier,
+        )
+        .unwrap();
+        file.write_data_stream(&mut testfs1, &mut cluster_allocator, "", b"MZ")
+            .unwrap();
+        assert_eq!(
+            read_data(&ntfs, &mut testfs1, file_record_number, "Zone.Identifier"),
+            zone_identifier
+        );
+        assert_eq!(
+            read_data(&ntfs, &mut testfs1, file_record_number, ""),
+            b"MZ"
+        );
+        assert_eq!(cluster_allocator.free_cluster_count(), free_cluster_count);
+
+        let file_name =
+            find_file_name(&ntfs, &mut testfs1, root_dir_record_number, "download.exe").unwrap();
+        assert_eq!(file_name.data_size(), 2);
+
+        // Large streams are non-resident.
+        let big_data = (0..5000).map(|i| i as u8).collect::<Vec<u8>>();
+        file.write_data_stream(&mut testfs1, &mut cluster_allocator, "big", &big_data)
+            .unwrap();
+        assert_eq!(
+            read_data(&ntfs, &mut testfs1, file_record_number, "big"),
+            big_data
+        );
+        assert_eq!(
+            cluster_allocator.free_cluster_count(),
+            free_cluster_count - 10
+        );
+
+        // Attributes are ordered by type and have unique instances.
+        let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+        let mut type_codes = Vec::new();
+        let mut data_stream_names = Vec::new();
+        let mut instances = Vec::new();
+        for attribute in file.attributes_raw() {
+            type_codes.push(attribute.type_code());
+            if attribute.ty().unwrap() == NtfsAttributeType::Data {
+                data_stream_names.push(attribute.name().unwrap().to_string_lossy());
+            }
+            instances.push(attribute.instance());
+            assert!(attribute.instance() < file.next_attribute_instance());
+        }
+        assert!(type_codes.windows(2).all(|pair| pair[0] <= pair[1]));
+        instances.sort_unstable();
+        instances.dedup();
+        assert_eq!(instances.len(), type_codes.len());
+
+        // Names are ordered case-insensitively via the $UpCase table, so "big" comes before "Zone.Identifier".
+        assert_eq!(data_stream_names, ["", "big", "Zone.Identifier"]);
+
+        // Overwriting a large stream with small data makes it resident again and frees its clusters.
+        let mut file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+        file.write_data_stream(&mut testfs1, &mut cluster_allocator, "big", b"small")
+            .unwrap();
+        assert_eq!(
+            read_data(&ntfs, &mut testfs1, file_record_number, "big"),
+            b"small"
+        );
+        assert!(file
+            .data(&mut testfs1, "big")
+            .unwrap()
+            .unwrap()
+            .to_attribute()
+            .is_resident());
+        assert_eq!(cluster_allocator.free_cluster_count(), free_cluster_count);
+
+        // Streams can be removed.
+        file.remove_data_stream(&mut testfs1, &mut cluster_allocator, "Zone.Identifier")
+            .unwrap();
+        let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+        assert!(file.data(&mut testfs1, "Zone.Identifier").is_none());
+        assert_eq!(
+            read_data(&ntfs, &mut testfs1, file_record_number, "big"),
+            b"small"
+        );
+
+        let mut file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+        assert!(matches!(
+            file.remove_data_stream(&mut testfs1, &mut cluster_allocator, "Zone.Identifier"),
+            Err(NtfsError::AttributeNotFound { .. })
+        ));
+        assert!(matches!(
+            file.remove_data_stream(&mut testfs1, &mut cluster_allocator, ""),
+            Err(NtfsError::InvalidDataStreamName { .. })
+        ));
+        assert!(matches!(
+            file.write_data_stream(&mut testfs1, &mut cluster_allocator, "a:b", b""),
+            Err(NtfsError::InvalidDataStreamName { .. })
+        ));
+
+        // The changes to $Bitmap have been written.
+        let reread_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        assert_eq!(reread_allocator.free_cluster_count(), free_cluster_count);
+
+        // Compressed, encrypted, and sparse streams are not replaced by plain data.
+        for flags in [
+            NtfsAttributeFlags::COMPRESSED,
+            NtfsAttributeFlags::ENCRYPTED,
+            NtfsAttributeFlags::SPARSE,
+        ]
+        .iter()
+        {
+            let mut file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+            let offset = file.find_data_attribute_offset("big").unwrap();
+
+            // The flags are at offset 12 of the attribute header.
+            let start = offset + 12;
+            file.record_data_mut()[start..start + 2].copy_from_slice(&flags.bits().to_le_bytes());
+            file.write_record(&mut testfs1).unwrap();
+
+            let result =
+                file.write_data_stream(&mut testfs1, &mut cluster_allocator, "big", b"plain");
+            if *flags == NtfsAttributeFlags::SPARSE {
+                assert!(matches!(
+                    result,
+                    Err(NtfsError::UnsupportedReplacementOfSparseValue { .. })
+                ));
+            } else {
+                assert!(matches!(
+                    result,
+                    Err(NtfsError::UnsupportedWriteToCompressedOrEncryptedValue { .. })
+                ));
+            }
+
+            let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+            let data_item = file.data(&mut testfs1, "big").unwrap().unwrap();
+            assert_eq!(data_item.to_attribute().flags(), *flags);
+        }
     }
 }
@@ -18,6 +18,20 @@ impl NtfsFileReference {
         Self(file_reference_bytes)
     }
 
+    /// Creates a reference from a 48-bit File Record Number and a 16-bit sequence number.
+    pub(crate) const fn from_file_record_number(
+        file_record_number: u64,
+        sequence_number: u16,
+    ) -> Self {
+        let value = (file_record_number & 0xffff_ffff_ffff) | ((sequence_number as u64) << 48);
+        Self(value.to_le_bytes())
+    }
+
+    /// Returns the raw bytes of this reference, as stored on the filesystem.
+    pub(crate) const fn to_bytes(self) -> [u8; 8] {
+        self.0
+    }
+
     /// Returns the 48-bit File Record Number.
     ///
     /// This can be fed into [`Ntfs::file`] to create an [`NtfsFile`] object for the corresponding File Record
@@ -0,0 +1,1449 @@
+// Copyright 2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::attribute::{
+    build_non_resident_attribute, build_resident_attribute, NtfsAttributeFlags, NtfsAttributeType,
+};
+use crate::attribute_definition::build_attribute_definitions;
+use crate::boot_sector::{record_size_info, BiosParameterBlock, BootSector, BOOT_SECTOR_SIZE};
+use crate::cluster_allocator::{bitmap_size, NtfsClusterAllocator};
+use crate::collation::NtfsCollationRule;
+use crate::error::{NtfsError, Result};
+use crate::file::{KnownNtfsFileRecordNumber, NtfsFile, NtfsFileFlags};
+use crate::file_reference::NtfsFileReference;
+use crate::helpers::div_ceil;
+use crate::index_entry::{build_data_index_entry, build_file_reference_index_entry};
+use crate::index_writer::insert_index_entry;
+use crate::indexes::{
+    insert_security_descriptor, NtfsFileNameIndex, NtfsQuotaControlIndex, NtfsQuotaOwnerIndex,
+    FIRST_SECURITY_ID,
+};
+use crate::mft::RESERVED_FILE_RECORD_COUNT;
+use crate::ntfs::Ntfs;
+use crate::record::Record;
+use crate::runlist::{NtfsRun, NtfsRunlist};
+use crate::sid::NtfsSid;
+use crate::structured_values::{
+    build_empty_index_root, build_file_name, build_standard_information, build_volume_information,
+    set_file_name_sizes, NtfsFileAttributeFlags, NtfsFileNamespace, VOLUME_NAME_MAX_SIZE,
+};
+use crate::time::NtfsTime;
+use crate::types::Lcn;
+use crate::upcase_table::{
+    build_upcase_info, build_upcase_table, NtfsUpcaseTableVersion, UPCASE_INFO_STREAM_NAME,
+};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use binread::io::{Read, Seek, SeekFrom};
+use byteorder::{ByteOrder, LittleEndian};
+use core::convert::TryFrom;
+use core::mem;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::time::SystemTime;
+
+/// The only sector size supported by this crate (see `BiosParameterBlock::sector_size`).
+const SECTOR_SIZE: u64 = 512;
+
+/// Like mkntfs, we refuse to format volumes smaller than 1 MiB.
+const MINIMUM_VOLUME_SIZE: u64 = 1024 * 1024;
+
+/// The largest cluster size supported by all Windows versions.
+const MAXIMUM_CLUSTER_SIZE: u32 = 65536;
+
+/// The $Boot file covers the first 8 KiB of the volume, beginning with the boot sector.
+const BOOT_FILE_SIZE: u64 = 8192;
+
+/// Like mkntfs, we begin the $MFT 16 KiB into the volume.
+const MFT_OFFSET: u64 = 16384;
+
+/// Size of a $LogFile page, and also of each of the two restart pages at the beginning of $LogFile.
+const LOGFILE_PAGE_SIZE: usize = 4096;
+
+/// File Records of all files created by [`format`] (including the ones of $Extend) are marked as used
+/// in the $MFT:$BITMAP, just like the reserved ones from 12 to 15.
+const USED_FILE_RECORDS: [u64; 19] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 24, 25, 26,
+];
+
+/// File Record Numbers of the files in the $Extend directory, as assigned by Windows and mkntfs.
+const QUOTA_FILE_RECORD_NUMBER: u64 = 24;
+const OBJECT_ID_FILE_RECORD_NUMBER: u64 = 25;
+const REPARSE_FILE_RECORD_NUMBER: u64 = 26;
+
+/// Security ID of the security descriptor granting read access to the Administrators and SYSTEM.
+const READ_ONLY_SECURITY_ID: u32 = FIRST_SECURITY_ID;
+
+/// Security ID of the security descriptor granting read/write access to the Administrators and SYSTEM.
+const READ_WRITE_SECURITY_ID: u32 = FIRST_SECURITY_ID + 1;
+
+/// Security ID of the security descriptor of the root directory, which is inherited by all new files.
+const ROOT_SECURITY_ID: u32 = FIRST_SECURITY_ID + 2;
+
+/// Access masks used in the security descriptors of a new filesystem.
+const FILE_GENERIC_READ: u32 = 0x0012_0089;
+const FILE_GENERIC_READ_WRITE: u32 = 0x0012_019f;
+const FILE_GENERIC_READ_EXECUTE: u32 = 0x0012_00a9;
+const FILE_MODIFY: u32 = 0x0013_01bf;
+const FILE_ALL_ACCESS: u32 = 0x001f_01ff;
+
+/// ACE flags for inheriting an ACE to files and directories.
+const OBJECT_AND_CONTAINER_INHERIT_ACE: u8 = 0x03;
+
+/// Relative Identifiers of the well-known SIDs used in the security descriptors of a new filesystem
+/// (all of them below the NT Authority).
+const AUTHENTICATED_USERS_RIDS: &[u32] = &[11];
+const LOCAL_SYSTEM_RIDS: &[u32] = &[18];
+const ADMINISTRATORS_RIDS: &[u32] = &[32, 544];
+const USERS_RIDS: &[u32] = &[32, 545];
+
+/// Options for formatting a new NTFS filesystem via [`Ntfs::format`].
+///
+/// The defaults match the ones of Windows for volumes of up to 16 TiB:
+/// 4096-byte clusters, 1024-byte File Records, 4096-byte Index Records, no volume name, a random serial number,
+/// and the Upcase Table of Windows 8.
+#[derive(Clone, Debug)]
+pub struct NtfsFormatOptions {
+    cluster_size: u32,
+    file_record_size: u32,
+    index_record_size: Option<u32>,
+    serial_number: Option<u64>,
+    upcase_table_version: NtfsUpcaseTableVersion,
+    volume_name: String,
+}
+
+impl NtfsFormatOptions {
+    /// Creates a new [`NtfsFormatOptions`] object with the default options.
+    pub fn new() -> Self {
+        Self {
+            cluster_size: 4096,
+            file_record_size: 1024,
+            index_record_size: None,
+            serial_number: None,
+            upcase_table_version: NtfsUpcaseTableVersion::Windows8,
+            volume_name: String::new(),
+        }
+    }
+
+    /// Sets the size of a single cluster, in bytes.
+    ///
+    /// This must be a power of two between 512 bytes and 64 KiB.
+    pub fn cluster_size(&mut self, cluster_size: u32) -> &mut Self {
+        self.cluster_size = cluster_size;
+        self
+    }
+
+    /// Sets the size of a single File Record of the $MFT, in bytes.
+    ///
+    /// This must be a power of two of at least 1024 bytes.
+    pub fn file_record_size(&mut self, file_record_size: u32) -> &mut Self {
+        self.file_record_size = file_record_size;
+        self
+    }
+
+    /// Sets the size of a single Index Record (e.g. of a directory index), in bytes.
+    ///
+    /// This must be a power of two of at least the cluster size.
+    /// The default is 4096 bytes or the cluster size, whichever is larger.
+    pub fn index_record_size(&mut self, index_record_size: u32) -> &mut Self {
+        self.index_record_size = Some(index_record_size);
+        self
+    }
+
+    /// Sets the 64-bit serial number of the new volume instead of a random one.
+    pub fn serial_number(&mut self, serial_number: u64) -> &mut Self {
+        self.serial_number = Some(serial_number);
+        self
+    }
+
+    /// Sets the Windows version whose built-in Upcase Table is written to the $UpCase file of the new volume.
+    pub fn upcase_table(&mut self, version: NtfsUpcaseTableVersion) -> &mut Self {
+        self.upcase_table_version = version;
+        self
+    }
+
+    /// Sets the volume name (also called volume label) of the new volume.
+    ///
+    /// An empty name creates a volume without a name.
+    pub fn volume_name(&mut self, volume_name: &str) -> &mut Self {
+        self.volume_name = volume_name.to_string();
+        self
+    }
+}
+
+impl Default for NtfsFormatOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Positions and sizes of the files that [`format`] writes before the filesystem can be opened.
+/// All positions are Logical Cluster Numbers (LCNs), all sizes are in clusters unless noted otherwise.
+#[derive(Debug)]
+struct FormatLayout {
+    cluster_size: u32,
+    file_record_size_info: i8,
+    index_record_size: u32,
+    index_record_size_info: i8,
+    /// Number of sectors covered by the filesystem, which leaves out the backup boot sector in the last sector.
+    total_sectors: u64,
+    cluster_count: u64,
+    boot_clusters: u64,
+    mft_bitmap_lcn: u64,
+    mft_lcn: u64,
+    mft_clusters: u64,
+    mft_mirror_lcn: u64,
+    mft_mirror_clusters: u64,
+    logfile_lcn: u64,
+    logfile_clusters: u64,
+    bitmap_lcn: u64,
+    bitmap_clusters: u64,
+    /// Size of the $Bitmap value, in bytes.
+    bitmap_size: u64,
+}
+
+impl FormatLayout {
+    fn new(options: &NtfsFormatOptions, device_size: u64) -> Result<Self> {
+        let cluster_size = options.cluster_size;
+        if !cluster_size.is_power_of_two() || cluster_size < SECTOR_SIZE as u32 {
+            return Err(NtfsError::InvalidClusterSize {
+                minimum: SECTOR_SIZE as u32,
+                actual: cluster_size,
+            });
+        }
+
+        if cluster_size > MAXIMUM_CLUSTER_SIZE {
+            return Err(NtfsError::UnsupportedClusterSize {
+                expected: MAXIMUM_CLUSTER_SIZE,
+                actual: cluster_size,
+            });
+        }
+
+        let file_record_size = options.file_record_size;
+        let file_record_size_info = record_size_info(file_record_size, cluster_size).ok_or(
+            NtfsError::UnsupportedRecordSize {
+                record_size: file_record_size,
+                cluster_size,
+            },
+        )?;
+
+        // This crate addresses Index Records in units of clusters, so they must not be smaller than a cluster.
+        let index_record_size = options
+            .index_record_size
+            .unwrap_or_else(|| u32::max(4096, cluster_size));
+        let index_record_size_info = record_size_info(index_record_size, cluster_size)
+            .filter(|_| index_record_size >= cluster_size)
+            .ok_or(NtfsError::UnsupportedRecordSize {
+                record_size: index_record_size,
+                cluster_size,
+            })?;
+
+        if device_size < MINIMUM_VOLUME_SIZE {
+            return Err(NtfsError::VolumeTooSmall { size: device_size });
+        }
+
+        let cluster_size_u64 = cluster_size as u64;
+        let clusters = |size: u64| div_ceil(size, cluster_size_u64);
+
+        let total_sectors = device_size / SECTOR_SIZE - 1;
+        let cluster_count = total_sectors * SECTOR_SIZE / cluster_size_u64;
+
+        // The $MFT:$BITMAP follows $Boot, and the $MFT follows after the first 16 KiB.
+        let boot_clusters = clusters(BOOT_FILE_SIZE);
+        let mft_bitmap_lcn = boot_clusters;
+        let mft_lcn = u64::max(clusters(MFT_OFFSET), mft_bitmap_lcn + 1);
+        let mft_clusters = clusters(RESERVED_FILE_RECORD_COUNT * file_record_size as u64);
+
+        // $MFTMirr is placed in the middle of the volume, followed by $LogFile and $Bitmap.
+        let mft_mirror_record_count = u32::max(4, cluster_size / file_record_size);
+        let mft_mirror_lcn = cluster_count / 2;
+        let mft_mirror_clusters =
+            clusters(mft_mirror_record_count as u64 * file_record_size as u64);
+        let logfile_lcn = mft_mirror_lcn + mft_mirror_clusters;
+        let logfile_clusters =
+            logfile_size(cluster_count * cluster_size_u64, cluster_size) / cluster_size_u64;
+        let bitmap_lcn = logfile_lcn + logfile_clusters;
+        let bitmap_size = bitmap_size(cluster_count);
+        let bitmap_clusters = clusters(bitmap_size);
+
+        if mft_lcn + mft_clusters > mft_mirror_lcn || bitmap_lcn + bitmap_clusters > cluster_count {
+            return Err(NtfsError::VolumeTooSmall { size: device_size });
+        }
+
+        Ok(Self {
+            cluster_size,
+            file_record_size_info,
+            index_record_size,
+            index_record_size_info,
+            total_sectors,
+            cluster_count,
+            boot_clusters,
+            mft_bitmap_lcn,
+            mft_lcn,
+            mft_clusters,
+            mft_mirror_lcn,
+            mft_mirror_clusters,
+            logfile_lcn,
+            logfile_clusters,
+            bitmap_lcn,
+            bitmap_clusters,
+            bitmap_size,
+        })
+    }
+
+    /// Returns the initial contents of $Bitmap, with all clusters of this layout marked as used.
+    ///
+    /// Like Windows, we also mark the bits after the last cluster as used.
+    fn bitmap(&self) -> Vec<u8> {
+        let mut bitmap = vec![0u8; self.bitmap_size as usize];
+        let used_ranges = [
+            0..self.boot_clusters,
+            self.mft_bitmap_lcn..self.mft_bitmap_lcn + 1,
+            self.mft_lcn..self.mft_lcn + self.mft_clusters,
+            self.mft_mirror_lcn..self.bitmap_lcn + self.bitmap_clusters,
+            self.cluster_count..self.bitmap_size * 8,
+        ];
+
+        for lcn in IntoIterator::into_iter(used_ranges).flatten() {
+            bitmap[(lcn / 8) as usize] |= 1 << (lcn % 8);
+        }
+
+        bitmap
+    }
+
+    fn clusters_per_index_record(&self) -> i8 {
+        self.index_record_size_info
+    }
+
+    fn runlist(&self, lcn: u64, cluster_count: u64) -> NtfsRunlist {
+        let mut runlist = NtfsRunlist::new();
+        runlist.push(NtfsRun::new(Lcn::from(lcn), cluster_count));
+        runlist
+    }
+}
+
+/// Formats the given filesystem with a new NTFS 3.1 filesystem and returns an [`Ntfs`] object for it.
+///
+/// This is the implementation of [`Ntfs::format`].
+pub(crate) fn format<T>(fs: &mut T, options: &NtfsFormatOptions) -> Result<Ntfs>
+where
+    T: Read + Write + Seek,
+{
+    let device_size = fs.seek(SeekFrom::End(0))?;
+    let layout = FormatLayout::new(options, device_size)?;
+
+    let volume_name = options.volume_name.encode_utf16().collect::<Vec<u16>>();
+    if volume_name.len() * mem::size_of::<u16>() > VOLUME_NAME_MAX_SIZE {
+        return Err(NtfsError::InvalidVolumeName {
+            name: options.volume_name.clone(),
+        });
+    }
+
+    let time = NtfsTime::try_from(SystemTime::now()).map_err(|_| NtfsError::InvalidTime)?;
+    let serial_number = options.serial_number.unwrap_or_else(|| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(time.nt_timestamp());
+        hasher.finish()
+    });
+
+    // Write the boot sector and its backup copy in the last sector, which is not part of the filesystem.
+    let bpb = BiosParameterBlock::new(
+        (layout.cluster_size / SECTOR_SIZE as u32) as u8,
+        layout.total_sectors,
+        Lcn::from(layout.mft_lcn),
+        Lcn::from(layout.mft_mirror_lcn),
+        layout.file_record_size_info,
+        layout.index_record_size_info,
+        serial_number,
+    );
+    let boot_sector = BootSector::new(bpb).to_bytes();
+    let mut boot_file = vec![0u8; (layout.boot_clusters * layout.cluster_size as u64) as usize];
+    boot_file[..BOOT_SECTOR_SIZE].copy_from_slice(&boot_sector);
+
+    fs.seek(SeekFrom::Start(0))?;
+    fs.write_all(&boot_file)?;
+    fs.seek(SeekFrom::Start(layout.total_sectors * SECTOR_SIZE))?;
+    fs.write_all(&boot_sector)?;
+
+    let mut ntfs = Ntfs::new(fs)?;
+
+    // Attributes of the same type are sorted by their names via the $UpCase table, which we are about to write.
+    ntfs.use_default_upcase_table(options.upcase_table_version);
+    let mut root_entries = write_bootstrap_files(&ntfs, fs, &layout, time)?;
+
+    let mut cluster_allocator = ntfs.cluster_allocator(fs)?;
+    root_entries.extend(create_basic_files(
+        &ntfs,
+        fs,
+        &mut cluster_allocator,
+        &layout,
+        time,
+        &volume_name,
+        options.upcase_table_version,
+    )?);
+
+    // Everything from here on inserts into directory indexes, which requires the $UpCase table.
+    ntfs.read_upcase_table(fs)?;
+    root_entries.push(create_extend_directory(
+        &ntfs,
+        fs,
+        &mut cluster_allocator,
+        &layout,
+        time,
+    )?);
+    create_root_directory(
+        &ntfs,
+        fs,
+        &mut cluster_allocator,
+        &layout,
+        time,
+        &root_entries,
+    )?;
+
+    cluster_allocator.write(&ntfs, fs)?;
+    fs.flush()?;
+
+    Ok(ntfs)
+}
+
+/// Builds the raw value of a $FILE_NAME attribute for the system file `file` in the directory `parent`,
+/// adds it to the File Record, and returns it for the directory entry.
+///
+/// The file sizes are taken from the unnamed $DATA attribute (if any), so that must have been written before.
+fn add_file_name<T>(
+    file: &mut NtfsFile,
+    fs: &mut T,
+    parent: NtfsFileReference,
+    name: &str,
+    time: NtfsTime,
+    file_attributes: NtfsFileAttributeFlags,
+) -> Result<(NtfsFileReference, Vec<u8>)>
+where
+    T: Read + Seek,
+{
+    let (allocated_size, data_size) = match file.data(fs, "") {
+        Some(data_item) => data_item?.to_attribute().directory_entry_sizes(),
+        None => (0, 0),
+    };
+
+    let name = name.encode_utf16().collect::<Vec<u16>>();
+    let mut value = build_file_name(
+        parent,
+        time,
+        file_attributes,
+        NtfsFileNamespace::Win32AndDos,
+        &name,
+    );
+    set_file_name_sizes(&mut value, allocated_size, data_size);
+
+    file.insert_attribute(build_resident_attribute(
+        NtfsAttributeType::FileName as u32,
+        &[],
+        NtfsAttributeFlags::empty(),
+        0,
+        &value,
+        true,
+    ))?;
+    file.set_hard_link_count(1);
+
+    Ok((file.file_reference(), value))
+}
+
+/// Returns the attribute name bytes of an index or named data stream.
+fn attribute_name(name: &str) -> Vec<u8> {
+    name.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// Builds an empty, resident $DATA attribute with the given name.
+fn build_empty_data_attribute(name: &str) -> Vec<u8> {
+    build_resident_attribute(
+        NtfsAttributeType::Data as u32,
+        &attribute_name(name),
+        NtfsAttributeFlags::empty(),
+        0,
+        &[],
+        false,
+    )
+}
+
+/// Builds an empty $INDEX_ROOT attribute for the index with the given name.
+///
+/// `ty` is the type of the indexed attribute (e.g. $FILE_NAME for directories) or zero for view indexes.
+fn build_empty_index_root_attribute(
+    layout: &FormatLayout,
+    name: &str,
+    ty: u32,
+    collation_rule: NtfsCollationRule,
+) -> Vec<u8> {
+    let index_root = build_empty_index_root(
+        ty,
+        collation_rule as u32,
+        layout.index_record_size,
+        layout.clusters_per_index_record(),
+    );
+
+    build_resident_attribute(
+        NtfsAttributeType::IndexRoot as u32,
+        &attribute_name(name),
+        NtfsAttributeFlags::empty(),
+        0,
+        &index_root,
+        false,
+    )
+}
+
+/// Builds the first two pages of a clean $LogFile of the given size, which contain identical restart areas
+/// for a single "NTFS" client and no log records.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/logfile.html>
+fn build_logfile_restart_pages(ntfs: &Ntfs, logfile_size: u64) -> Result<Vec<u8>> {
+    const UPDATE_SEQUENCE_OFFSET: usize = 0x1e;
+    const RESTART_AREA_OFFSET: usize = 0x30;
+    const RESTART_AREA_SIZE: usize = 0x30;
+    const CLIENT_RECORD_SIZE: usize = 0xa0;
+    const NO_CLIENT: u16 = 0xffff;
+    const RESTART_VOLUME_IS_CLEAN: u16 = 0x0002;
+
+    let mut page = vec![0u8; LOGFILE_PAGE_SIZE];
+
+    // Restart Page Header
+    page[..4].copy_from_slice(b"RSTR");
+    LittleEndian::write_u16(&mut page[4..], UPDATE_SEQUENCE_OFFSET as u16);
+    let update_sequence_count = LOGFILE_PAGE_SIZE / SECTOR_SIZE as usize + 1;
+    LittleEndian::write_u16(&mut page[6..], update_sequence_count as u16);
+    LittleEndian::write_u32(&mut page[16..], LOGFILE_PAGE_SIZE as u32);
+    LittleEndian::write_u32(&mut page[20..], LOGFILE_PAGE_SIZE as u32);
+    LittleEndian::write_u16(&mut page[24..], RESTART_AREA_OFFSET as u16);
+    LittleEndian::write_i16(&mut page[26..], 1);
+    LittleEndian::write_i16(&mut page[28..], 1);
+
+    // Restart Area
+    // Log Sequence Numbers (LSNs) are composed of a sequence number and an offset into the file in
+    // 8-byte units.
+    let area = &mut page[RESTART_AREA_OFFSET..];
+    let file_size_bits = 63 - logfile_size.leading_zeros();
+    let sequence_number_bits = 64 - (file_size_bits - 3);
+    LittleEndian::write_u16(&mut area[8..], 1);
+    LittleEndian::write_u16(&mut area[10..], NO_CLIENT);
+    LittleEndian::write_u16(&mut area[12..], 0);
+    LittleEndian::write_u16(&mut area[14..], RESTART_VOLUME_IS_CLEAN);
+    LittleEndian::write_u32(&mut area[16..], sequence_number_bits);
+    LittleEndian::write_u16(
+        &mut area[20..],
+        (RESTART_AREA_SIZE + CLIENT_RECORD_SIZE) as u16,
+    );
+    LittleEndian::write_u16(&mut area[22..], RESTART_AREA_SIZE as u16);
+    LittleEndian::write_u64(&mut area[24..], logfile_size);
+    LittleEndian::write_u16(&mut area[36..], 0x30);
+    LittleEndian::write_u16(&mut area[38..], 0x40);
+
+    // Log Client Record
+    let client = &mut area[RESTART_AREA_SIZE..];
+    LittleEndian::write_u16(&mut client[16..], NO_CLIENT);
+    LittleEndian::write_u16(&mut client[18..], NO_CLIENT);
+    let client_name = attribute_name("NTFS");
+    LittleEndian::write_u32(&mut client[28..], client_name.len() as u32);
+    client[32..32 + client_name.len()].copy_from_slice(&client_name);
+
+    let mut pages = Vec::with_capacity(2 * LOGFILE_PAGE_SIZE);
+    for _ in 0..2 {
+        let mut record = Record::new(ntfs, page.clone(), 0);
+        record.write_with(|data| {
+            pages.extend_from_slice(data);
+            Ok(())
+        })?;
+    }
+
+    Ok(pages)
+}
+
+/// Builds a self-relative security descriptor owned by the Administrators, with a DACL consisting of the
+/// given access allowed ACEs (flags, access mask, and SID relative identifiers below the NT Authority).
+fn build_security_descriptor(aces: &[(u8, u32, &[u32])]) -> Vec<u8> {
+    const SE_DACL_PRESENT: u16 = 0x0004;
+    const SE_SELF_RELATIVE: u16 = 0x8000;
+    const SECURITY_DESCRIPTOR_SIZE: usize = 20;
+    const ACL_HEADER_SIZE: usize = 8;
+    const ACL_REVISION: u8 = 2;
+    const ACE_HEADER_SIZE: usize = 8;
+    const ACCESS_ALLOWED_ACE_TYPE: u8 = 0;
+
+    let mut acl = vec![0u8; ACL_HEADER_SIZE];
+    for (flags, access_mask, rids) in aces {
+        let sid = nt_authority_sid(rids);
+        acl.push(ACCESS_ALLOWED_ACE_TYPE);
+        acl.push(*flags);
+        acl.extend_from_slice(&((ACE_HEADER_SIZE + sid.len()) as u16).to_le_bytes());
+        acl.extend_from_slice(&access_mask.to_le_bytes());
+        acl.extend_from_slice(&sid);
+    }
+
+    acl[0] = ACL_REVISION;
+    let acl_size = acl.len() as u16;
+    LittleEndian::write_u16(&mut acl[2..], acl_size);
+    LittleEndian::write_u16(&mut acl[4..], aces.len() as u16);
+
+    // The DACL is followed by the owner and group SIDs.
+    let owner = nt_authority_sid(ADMINISTRATORS_RIDS);
+    let dacl_offset = SECURITY_DESCRIPTOR_SIZE;
+    let owner_offset = dacl_offset + acl.len();
+    let group_offset = owner_offset + owner.len();
+
+    let mut security_descriptor = vec![0u8; SECURITY_DESCRIPTOR_SIZE];
+    security_descriptor[0] = 1;
+    LittleEndian::write_u16(
+        &mut security_descriptor[2..],
+        SE_SELF_RELATIVE | SE_DACL_PRESENT,
+    );
+    LittleEndian::write_u32(&mut security_descriptor[4..], owner_offset as u32);
+    LittleEndian::write_u32(&mut security_descriptor[8..], group_offset as u32);
+    LittleEndian::write_u32(&mut security_descriptor[16..], dacl_offset as u32);
+    security_descriptor.extend_from_slice(&acl);
+    security_descriptor.extend_from_slice(&owner);
+    security_descriptor.extend_from_slice(&owner);
+
+    security_descriptor
+}
+
+/// Creates $Volume, $AttrDef, $Secure, $UpCase (along with its $Info data stream), and the reserved File Records
+/// 12 to 15, and returns the directory entries for the root directory.
+fn create_basic_files<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    layout: &FormatLayout,
+    time: NtfsTime,
+    volume_name: &[u16],
+    upcase_table_version: NtfsUpcaseTableVersion,
+) -> Result<Vec<(NtfsFileReference, Vec<u8>)>>
+where
+    T: Read + Write + Seek,
+{
+    let mut root_entries = Vec::new();
+    let system = NtfsFileAttributeFlags::HIDDEN | NtfsFileAttributeFlags::SYSTEM;
+
+    let mut volume = new_system_file(
+        ntfs,
+        KnownNtfsFileRecordNumber::Volume as u64,
+        system,
+        READ_WRITE_SECURITY_ID,
+        time,
+    )?;
+    if !volume_name.is_empty() {
+        let value = volume_name
+            .iter()
+            .flat_map(|code_unit| code_unit.to_le_bytes())
+            .collect::<Vec<u8>>();
+        volume.insert_attribute(build_resident_attribute(
+            NtfsAttributeType::VolumeName as u32,
+            &[],
+            NtfsAttributeFlags::empty(),
+            0,
+            &value,
+            false,
+        ))?;
+    }
+    volume.insert_attribute(build_resident_attribute(
+        NtfsAttributeType::VolumeInformation as u32,
+        &[],
+        NtfsAttributeFlags::empty(),
+        0,
+        &build_volume_information(3, 1),
+        false,
+    ))?;
+    volume.insert_attribute(build_empty_data_attribute(""))?;
+    root_entries.push(add_file_name(
+        &mut volume,
+        fs,
+        root_reference(),
+        "$Volume",
+        time,
+        system,
+    )?);
+    volume.write_record(fs)?;
+
+    for (file_record_number, name, data) in [
+        (
+            KnownNtfsFileRecordNumber::AttrDef,
+            "$AttrDef",
+            build_attribute_definitions(),
+        ),
+        (
+            KnownNtfsFileRecordNumber::UpCase,
+            "$UpCase",
+            build_upcase_table(upcase_table_version),
+        ),
+    ] {
+        let file_record_number = file_record_number as u64;
+        let mut file = new_system_file(
+            ntfs,
+            file_record_number,
+            system,
+            READ_ONLY_SECURITY_ID,
+            time,
+        )?;
+        file.insert_attribute(build_empty_data_attribute(""))?;
+        if file_record_number == KnownNtfsFileRecordNumber::UpCase as u64 {
+            file.insert_attribute(build_resident_attribute(
+                NtfsAttributeType::Data as u32,
+                &attribute_name(UPCASE_INFO_STREAM_NAME),
+                NtfsAttributeFlags::empty(),
+                0,
+                &build_upcase_info(upcase_table_version, &data),
+                false,
+            ))?;
+        }
+        file.write_record(fs)?;
+        file.write_data_stream(fs, cluster_allocator, "", &data)?;
+
+        root_entries.push(add_file_name(
+            &mut file,
+            fs,
+            root_reference(),
+            name,
+            time,
+            system,
+        )?);
+        file.write_record(fs)?;
+    }
+
+    // File Records 12 to 15 are marked as used, but don't belong to any file.
+    for file_record_number in 12..16 {
+        let mut file = new_system_file(
+            ntfs,
+            file_record_number,
+            system,
+            READ_WRITE_SECURITY_ID,
+            time,
+        )?;
+        file.insert_attribute(build_empty_data_attribute(""))?;
+        file.write_record(fs)?;
+    }
+
+    let mut secure = new_system_file(
+        ntfs,
+        KnownNtfsFileRecordNumber::Secure as u64,
+        system,
+        READ_WRITE_SECURITY_ID,
+        time,
+    )?;
+    secure.insert_attribute(build_empty_data_attribute("$SDS"))?;
+    secure.insert_attribute(build_empty_index_root_attribute(
+        layout,
+        "$SDH",
+        0,
+        NtfsCollationRule::NtofsSecurityHash,
+    ))?;
+    secure.insert_attribute(build_empty_index_root_attribute(
+        layout,
+        "$SII",
+        0,
+        NtfsCollationRule::NtofsUlong,
+    ))?;
+    root_entries.push(add_file_name(
+        &mut secure,
+        fs,
+        root_reference(),
+        "$Secure",
+        time,
+        system,
+    )?);
+    secure.write_record(fs)?;
+
+    let security_descriptors = [
+        (READ_ONLY_SECURITY_ID, FILE_GENERIC_READ),
+        (READ_WRITE_SECURITY_ID, FILE_GENERIC_READ_WRITE),
+    ];
+    for (security_id, access_mask) in security_descriptors {
+        let security_descriptor = build_security_descriptor(&[
+            (0, access_mask, LOCAL_SYSTEM_RIDS),
+            (0, access_mask, ADMINISTRATORS_RIDS),
+        ]);
+        let inserted_security_id =
+            insert_security_descriptor(ntfs, fs, cluster_allocator, &security_descriptor)?;
+        debug_assert_eq!(inserted_security_id, security_id);
+    }
+
+    let root_security_descriptor = build_security_descriptor(&[
+        (
+            OBJECT_AND_CONTAINER_INHERIT_ACE,
+            FILE_ALL_ACCESS,
+            ADMINISTRATORS_RIDS,
+        ),
+        (
+            OBJECT_AND_CONTAINER_INHERIT_ACE,
+            FILE_ALL_ACCESS,
+            LOCAL_SYSTEM_RIDS,
+        ),
+        (
+            OBJECT_AND_CONTAINER_INHERIT_ACE,
+            FILE_MODIFY,
+            AUTHENTICATED_USERS_RIDS,
+        ),
+        (
+            OBJECT_AND_CONTAINER_INHERIT_ACE,
+            FILE_GENERIC_READ_EXECUTE,
+            USERS_RIDS,
+        ),
+    ]);
+    let inserted_security_id =
+        insert_security_descriptor(ntfs, fs, cluster_allocator, &root_security_descriptor)?;
+    debug_assert_eq!(inserted_security_id, ROOT_SECURITY_ID);
+
+    Ok(root_entries)
+}
+
+/// Creates the $Extend directory along with its $Quota, $ObjId, and $Reparse files,
+/// and returns the directory entry of $Extend for the root directory.
+fn create_extend_directory<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    layout: &FormatLayout,
+    time: NtfsTime,
+) -> Result<(NtfsFileReference, Vec<u8>)>
+where
+    T: Read + Write + Seek,
+{
+    let system = NtfsFileAttributeFlags::HIDDEN | NtfsFileAttributeFlags::SYSTEM;
+
+    let mut extend = new_system_file(
+        ntfs,
+        KnownNtfsFileRecordNumber::Extend as u64,
+        system,
+        READ_WRITE_SECURITY_ID,
+        time,
+    )?;
+    extend.set_flags(NtfsFileFlags::IN_USE | NtfsFileFlags::IS_DIRECTORY);
+    extend.insert_attribute(build_empty_index_root_attribute(
+        layout,
+        "$I30",
+        NtfsAttributeType::FileName as u32,
+        NtfsCollationRule::FileName,
+    ))?;
+    let extend_entry = add_file_name(
+        &mut extend,
+        fs,
+        root_reference(),
+        "$Extend",
+        time,
+        system | NtfsFileAttributeFlags::IS_DIRECTORY,
+    )?;
+    extend.write_record(fs)?;
+
+    // The view indexes (name and collation rule) of each file.
+    let files = [
+        (
+            QUOTA_FILE_RECORD_NUMBER,
+            "$Quota",
+            &[
+                ("$O", NtfsCollationRule::NtofsSid),
+                ("$Q", NtfsCollationRule::NtofsUlong),
+            ][..],
+        ),
+        (
+            OBJECT_ID_FILE_RECORD_NUMBER,
+            "$ObjId",
+            &[("$O", NtfsCollationRule::NtofsUlongs)],
+        ),
+        (
+            REPARSE_FILE_RECORD_NUMBER,
+            "$Reparse",
+            &[("$R", NtfsCollationRule::NtofsUlongs)],
+        ),
+    ];
+
+    for (file_record_number, name, indexes) in files {
+        let file_attributes = system | NtfsFileAttributeFlags::ARCHIVE;
+        let mut file = new_system_file(
+            ntfs,
+            file_record_number,
+            file_attributes,
+            READ_WRITE_SECURITY_ID,
+            time,
+        )?;
+        for (index_name, collation_rule) in indexes {
+            file.insert_attribute(build_empty_index_root_attribute(
+                layout,
+                index_name,
+                0,
+                *collation_rule,
+            ))?;
+        }
+        let (file_reference, file_name) = add_file_name(
+            &mut file,
+            fs,
+            extend.file_reference(),
+            name,
+            time,
+            file_attributes,
+        )?;
+        file.write_record(fs)?;
+
+        if file_record_number == QUOTA_FILE_RECORD_NUMBER {
+            insert_default_quotas(&mut file, fs, cluster_allocator, time)?;
+        }
+
+        let entry = build_file_reference_index_entry(file_reference, &file_name, None);
+        insert_index_entry::<NtfsFileNameIndex, T>(
+            &mut extend,
+            fs,
+            cluster_allocator,
+            "$I30",
+            &entry,
+        )?;
+    }
+
+    Ok(extend_entry)
+}
+
+/// Creates the root directory and inserts the given directory entries (of all other system files)
+/// as well as the "." entry referring to itself.
+fn create_root_directory<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    layout: &FormatLayout,
+    time: NtfsTime,
+    root_entries: &[(NtfsFileReference, Vec<u8>)],
+) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    let system = NtfsFileAttributeFlags::HIDDEN | NtfsFileAttributeFlags::SYSTEM;
+
+    let mut root = new_system_file(
+        ntfs,
+        KnownNtfsFileRecordNumber::RootDirectory as u64,
+        system,
+        ROOT_SECURITY_ID,
+        time,
+    )?;
+    root.set_flags(NtfsFileFlags::IN_USE | NtfsFileFlags::IS_DIRECTORY);
+    root.insert_attribute(build_empty_index_root_attribute(
+        layout,
+        "$I30",
+        NtfsAttributeType::FileName as u32,
+        NtfsCollationRule::FileName,
+    ))?;
+    let root_entry = add_file_name(
+        &mut root,
+        fs,
+        root_reference(),
+        ".",
+        time,
+        system | NtfsFileAttributeFlags::IS_DIRECTORY,
+    )?;
+    root.write_record(fs)?;
+
+    for (file_reference, file_name) in root_entries.iter().chain([&root_entry]) {
+        let entry = build_file_reference_index_entry(*file_reference, file_name, None);
+        insert_index_entry::<NtfsFileNameIndex, T>(
+            &mut root,
+            fs,
+            cluster_allocator,
+            "$I30",
+            &entry,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Inserts the quota control entries for the default limits and for the Administrators into the
+/// $Q index of $Quota, along with an $O entry mapping the SID of the Administrators to their owner ID.
+fn insert_default_quotas<T>(
+    quota: &mut NtfsFile,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    time: NtfsTime,
+) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    const QUOTA_VERSION: u32 = 2;
+    const QUOTA_FLAG_DEFAULT_LIMITS: u32 = 0x0000_0001;
+    const DEFAULT_OWNER_ID: u32 = 1;
+    const ADMINISTRATORS_OWNER_ID: u32 = 0x100;
+
+    let administrators = nt_authority_sid(ADMINISTRATORS_RIDS);
+
+    for (owner_id, sid) in [
+        (DEFAULT_OWNER_ID, &[][..]),
+        (ADMINISTRATORS_OWNER_ID, &administrators[..]),
+    ] {
+        // Version, flags, bytes used, change time, unlimited threshold and limit, and the exceeded time.
+        let mut data = Vec::new();
+        data.extend_from_slice(&QUOTA_VERSION.to_le_bytes());
+        data.extend_from_slice(&QUOTA_FLAG_DEFAULT_LIMITS.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&time.nt_timestamp().to_le_bytes());
+        data.extend_from_slice(&(-1i64).to_le_bytes());
+        data.extend_from_slice(&(-1i64).to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(sid);
+
+        let entry = build_data_index_entry(&owner_id.to_le_bytes(), &data, None);
+        insert_index_entry::<NtfsQuotaControlIndex, T>(quota, fs, cluster_allocator, "$Q", &entry)?;
+    }
+
+    let entry = build_data_index_entry(
+        &administrators,
+        &ADMINISTRATORS_OWNER_ID.to_le_bytes(),
+        None,
+    );
+    insert_index_entry::<NtfsQuotaOwnerIndex, T>(quota, fs, cluster_allocator, "$O", &entry)
+}
+
+/// Returns the size of the $LogFile for a volume of the given size, in bytes.
+///
+/// This follows the heuristics of mkntfs, which are in turn modeled after Windows.
+fn logfile_size(volume_size: u64, cluster_size: u32) -> u64 {
+    const MIB: u64 = 1024 * 1024;
+
+    let size = if volume_size < 2 * MIB {
+        256 * 1024
+    } else if volume_size < 4_000_000 {
+        512 * 1024
+    } else if volume_size <= 200 * MIB {
+        2 * MIB
+    } else if volume_size >= 12 * 1024 * MIB {
+        64 * MIB
+    } else {
+        volume_size / 200
+    };
+
+    // The $LogFile must consist of whole pages and clusters.
+    let alignment = u64::max(LOGFILE_PAGE_SIZE as u64, cluster_size as u64);
+    size / alignment * alignment
+}
+
+/// Creates an empty File Record for the system file with the given File Record Number in memory,
+/// which is marked as used and only has a $STANDARD_INFORMATION attribute.
+///
+/// The sequence numbers follow Windows and mkntfs.
+fn new_system_file<'n>(
+    ntfs: &'n Ntfs,
+    file_record_number: u64,
+    file_attributes: NtfsFileAttributeFlags,
+    security_id: u32,
+    time: NtfsTime,
+) -> Result<NtfsFile<'n>> {
+    let mut file = NtfsFile::new_empty(
+        ntfs,
+        system_file_record_position(ntfs, file_record_number),
+        file_record_number,
+        system_file_sequence_number(file_record_number),
+    );
+    file.set_flags(NtfsFileFlags::IN_USE);
+
+    let standard_information = build_standard_information(time, file_attributes, security_id);
+    file.insert_attribute(build_resident_attribute(
+        NtfsAttributeType::StandardInformation as u32,
+        &[],
+        NtfsAttributeFlags::empty(),
+        0,
+        &standard_information,
+        false,
+    ))?;
+
+    Ok(file)
+}
+
+/// Returns the on-disk representation of the SID with the given relative identifiers below the NT Authority.
+fn nt_authority_sid(rids: &[u32]) -> Vec<u8> {
+    const SID_REVISION: u8 = 1;
+    const SECURITY_NT_AUTHORITY: u64 = 5;
+
+    NtfsSid::new(SID_REVISION, SECURITY_NT_AUTHORITY, rids)
+        .expect("Well-known SIDs have few sub authorities")
+        .to_bytes()
+}
+
+fn root_reference() -> NtfsFileReference {
+    let file_record_number = KnownNtfsFileRecordNumber::RootDirectory as u64;
+    NtfsFileReference::from_file_record_number(
+        file_record_number,
+        system_file_sequence_number(file_record_number),
+    )
+}
+
+/// Returns the absolute byte position of the File Record with the given number.
+///
+/// The $MFT of a new filesystem is contiguous, so we don't need to look up its Data Runs.
+fn system_file_record_position(ntfs: &Ntfs, file_record_number: u64) -> u64 {
+    ntfs.mft_position() + file_record_number * ntfs.file_record_size() as u64
+}
+
+/// Returns the sequence number of the given File Record of a new filesystem.
+///
+/// Like Windows and mkntfs, we use the File Record Number for the first 24 records and 1 for all others.
+fn system_file_sequence_number(file_record_number: u64) -> u16 {
+    match file_record_number {
+        1..=23 => file_record_number as u16,
+        _ => 1,
+    }
+}
+
+/// Writes the empty $MFT along with the files that are needed to open the filesystem and to allocate clusters:
+/// $MFT, $MFTMirr, $LogFile, $Bitmap, $Boot, and $BadClus.
+///
+/// Returns the directory entries of these files for the root directory.
+fn write_bootstrap_files<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    layout: &FormatLayout,
+    time: NtfsTime,
+) -> Result<Vec<(NtfsFileReference, Vec<u8>)>>
+where
+    T: Read + Write + Seek,
+{
+    let cluster_size = layout.cluster_size;
+    let file_record_size = ntfs.file_record_size() as u64;
+    let system = NtfsFileAttributeFlags::HIDDEN | NtfsFileAttributeFlags::SYSTEM;
+
+    // Initialize all File Records (and thereby the $MFTMirr) with empty records that are not in use.
+    for file_record_number in 0..RESERVED_FILE_RECORD_COUNT {
+        NtfsFile::new_empty(
+            ntfs,
+            system_file_record_position(ntfs, file_record_number),
+            file_record_number,
+            system_file_sequence_number(file_record_number),
+        )
+        .write_record(fs)?;
+    }
+
+    // Every File Record has a bit in the $MFT:$BITMAP, which is a multiple of 8 bytes long.
+    let mft_bitmap_runlist = layout.runlist(layout.mft_bitmap_lcn, 1);
+    let mut mft_bitmap = vec![0u8; cluster_size as usize];
+    for file_record_number in USED_FILE_RECORDS {
+        mft_bitmap[(file_record_number / 8) as usize] |= 1 << (file_record_number % 8);
+    }
+    mft_bitmap_runlist.write_at(ntfs, fs, 0, &mft_bitmap)?;
+    let mft_bitmap_size = RESERVED_FILE_RECORD_COUNT / 8;
+
+    let bitmap = layout.bitmap();
+    let bitmap_runlist = layout.runlist(layout.bitmap_lcn, layout.bitmap_clusters);
+    bitmap_runlist.write_at(ntfs, fs, 0, &bitmap)?;
+
+    let logfile_runlist = layout.runlist(layout.logfile_lcn, layout.logfile_clusters);
+    let logfile_size = layout.logfile_clusters * cluster_size as u64;
+    let mut logfile = vec![0xffu8; logfile_size as usize];
+    let restart_pages = build_logfile_restart_pages(ntfs, logfile_size)?;
+    logfile[..restart_pages.len()].copy_from_slice(&restart_pages);
+    logfile_runlist.write_at(ntfs, fs, 0, &logfile)?;
+
+    let mut bad_clusters_runlist = NtfsRunlist::new();
+    bad_clusters_runlist.push(NtfsRun::sparse(layout.cluster_count));
+    let volume_size = layout.cluster_count * cluster_size as u64;
+
+    let mft_mirror_size = ntfs.mft_mirror_record_count() as u64 * file_record_size;
+    let mft_size = RESERVED_FILE_RECORD_COUNT * file_record_size;
+
+    let files = [
+        (
+            KnownNtfsFileRecordNumber::MFT,
+            "$MFT",
+            layout.runlist(layout.mft_lcn, layout.mft_clusters),
+            mft_size,
+        ),
+        (
+            KnownNtfsFileRecordNumber::MFTMirr,
+            "$MFTMirr",
+            layout.runlist(layout.mft_mirror_lcn, layout.mft_mirror_clusters),
+            mft_mirror_size,
+        ),
+        (
+            KnownNtfsFileRecordNumber::LogFile,
+            "$LogFile",
+            logfile_runlist,
+            logfile_size,
+        ),
+        (
+            KnownNtfsFileRecordNumber::Bitmap,
+            "$Bitmap",
+            bitmap_runlist,
+            layout.bitmap_size,
+        ),
+        (
+            KnownNtfsFileRecordNumber::Boot,
+            "$Boot",
+            layout.runlist(0, layout.boot_clusters),
+            BOOT_FILE_SIZE,
+        ),
+        (
+            KnownNtfsFileRecordNumber::BadClus,
+            "$BadClus",
+            NtfsRunlist::new(),
+            0,
+        ),
+    ];
+
+    let mut root_entries = Vec::new();
+
+    for (file_record_number, name, runlist, data_size) in files {
+        let file_record_number = file_record_number as u64;
+        let mut file = new_system_file(
+            ntfs,
+            file_record_number,
+            system,
+            READ_ONLY_SECURITY_ID,
+            time,
+        )?;
+
+        if file_record_number == KnownNtfsFileRecordNumber::BadClus as u64 {
+            // $BadClus has an empty unnamed $DATA attribute and maps bad clusters into its sparse $Bad stream,
+            // which spans the entire volume.
+            file.insert_attribute(build_empty_data_attribute(""))?;
+            file.insert_attribute(build_non_resident_attribute(
+                NtfsAttributeType::Data as u32,
+                &attribute_name("$Bad"),
+                NtfsAttributeFlags::empty(),
+                0,
+                &bad_clusters_runlist,
+                cluster_size,
+                volume_size,
+                volume_size,
+            ))?;
+        } else {
+            file.insert_attribute(build_non_resident_attribute(
+                NtfsAttributeType::Data as u32,
+                &[],
+                NtfsAttributeFlags::empty(),
+                0,
+                &runlist,
+                cluster_size,
+                data_size,
+                data_size,
+            ))?;
+        }
+
+        if file_record_number == KnownNtfsFileRecordNumber::MFT as u64 {
+            file.insert_attribute(build_non_resident_attribute(
+                NtfsAttributeType::Bitmap as u32,
+                &[],
+                NtfsAttributeFlags::empty(),
+                0,
+                &mft_bitmap_runlist,
+                cluster_size,
+                mft_bitmap_size,
+                mft_bitmap_size,
+            ))?;
+        }
+
+        root_entries.push(add_file_name(
+            &mut file,
+            fs,
+            root_reference(),
+            name,
+            time,
+            system,
+        )?);
+        file.write_record(fs)?;
+    }
+
+    Ok(root_entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexes::read_security_descriptor;
+    use crate::indexes::{NtfsSecurityId, NtfsSecurityIdIndex};
+    use crate::traits::NtfsReadSeek;
+    use std::io::Cursor;
+
+    const VOLUME_SIZE: usize = 8 * 1024 * 1024;
+
+    fn security_descriptor(ntfs: &Ntfs, fs: &mut Cursor<Vec<u8>>, security_id: u32) -> Vec<u8> {
+        let secure_file = ntfs
+            .file(fs, KnownNtfsFileRecordNumber::Secure as u64)
+            .unwrap();
+        let sii_index = secure_file
+            .index::<NtfsSecurityIdIndex, _>(fs, "$SII")
+            .unwrap();
+        let mut finder = sii_index.finder();
+        let entry = NtfsSecurityIdIndex::find(&mut finder, ntfs, fs, NtfsSecurityId(security_id))
+            .unwrap()
+            .unwrap();
+        let header = entry.data().unwrap().unwrap();
+        read_security_descriptor(fs, &secure_file, &header).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let testfs1_ntfs = Ntfs::new(&mut testfs1).unwrap();
+        let testfs1_attribute_definitions =
+            testfs1_ntfs.attribute_definitions(&mut testfs1).unwrap();
+
+        for cluster_size in [512, 4096, 65536] {
+            let mut fs = Cursor::new(vec![0u8; VOLUME_SIZE]);
+            Ntfs::format(
+                &mut fs,
+                NtfsFormatOptions::new()
+                    .cluster_size(cluster_size)
+                    .serial_number(0x1234_5678_9abc_def0)
+                    .volume_name("Test Volume"),
+            )
+            .unwrap();
+
+            // The backup boot sector in the last sector matches the boot sector.
+            let bytes = fs.get_ref();
+            assert_eq!(bytes[..512], bytes[VOLUME_SIZE - 512..]);
+
+            let mut ntfs = Ntfs::new(&mut fs).unwrap();
+            ntfs.read_upcase_table(&mut fs).unwrap();
+            assert_eq!(ntfs.cluster_size(), cluster_size);
+            assert_eq!(ntfs.file_record_size(), 1024);
+            assert_eq!(ntfs.serial_number(), 0x1234_5678_9abc_def0);
+            assert_eq!(ntfs.size(), (VOLUME_SIZE - 512) as u64);
+
+            let volume_name = ntfs.volume_name(&mut fs).unwrap().unwrap();
+            assert_eq!(volume_name.name(), "Test Volume");
+            let volume_info = ntfs.volume_info(&mut fs).unwrap();
+            assert_eq!(volume_info.major_version(), 3);
+            assert_eq!(volume_info.minor_version(), 1);
+
+            // The Upcase Table of Windows 8 is written by default, along with its $Info stream.
+            assert_eq!(
+                ntfs.upcase_table_version(&mut fs).unwrap(),
+                Some(NtfsUpcaseTableVersion::Windows8)
+            );
+            let upcase_info = ntfs.upcase_info(&mut fs).unwrap().unwrap();
+            assert!(upcase_info.matches(&build_upcase_table(NtfsUpcaseTableVersion::Windows8)));
+            assert_eq!(upcase_info.os_major_version(), 6);
+            assert_eq!(upcase_info.os_minor_version(), 2);
+
+            let attribute_definitions = ntfs.attribute_definitions(&mut fs).unwrap();
+            assert_eq!(
+                attribute_definitions.len(),
+                testfs1_attribute_definitions.len()
+            );
+            for (a, b) in attribute_definitions
+                .iter()
+                .zip(&testfs1_attribute_definitions)
+            {
+                assert_eq!(a.name(), b.name());
+                assert_eq!(a.type_code(), b.type_code());
+                assert_eq!(a.flags(), b.flags());
+                assert_eq!(a.minimum_size(), b.minimum_size());
+                assert_eq!(a.maximum_size(), b.maximum_size());
+            }
+
+            // The $LogFile begins with a restart page.
+            let logfile = ntfs
+                .file(&mut fs, KnownNtfsFileRecordNumber::LogFile as u64)
+                .unwrap();
+            let data_item = logfile.data(&mut fs, "").unwrap().unwrap();
+            let mut data_value = data_item.to_attribute().value().unwrap();
+            let mut signature = [0u8; 4];
+            data_value.read_exact(&mut fs, &mut signature).unwrap();
+            assert_eq!(&signature, b"RSTR");
+
+            // The root directory lists all system files.
+            let root_dir = ntfs.root_directory(&mut fs).unwrap();
+            let root_index = root_dir.directory_index(&mut fs).unwrap();
+            let mut iter = root_index.entries();
+            let mut names = Vec::new();
+            while let Some(entry) = iter.next(&mut fs) {
+                names.push(
+                    entry
+                        .unwrap()
+                        .key()
+                        .unwrap()
+                        .unwrap()
+                        .name()
+                        .to_string_lossy(),
+                );
+            }
+            assert_eq!(
+                names,
+                [
+                    "$AttrDef", "$BadClus", "$Bitmap", "$Boot", "$Extend", "$LogFile", "$MFT",
+                    "$MFTMirr", "$Secure", "$UpCase", "$Volume", "."
+                ]
+            );
+
+            // The security descriptors for system files are the same as the ones of mkntfs.
+            assert_eq!(
+                security_descriptor(&ntfs, &mut fs, READ_ONLY_SECURITY_ID),
+                security_descriptor(&testfs1_ntfs, &mut testfs1, 0x100)
+            );
+            assert_eq!(
+                security_descriptor(&ntfs, &mut fs, READ_WRITE_SECURITY_ID),
+                security_descriptor(&testfs1_ntfs, &mut testfs1, 0x101)
+            );
+
+            let quotas = ntfs.quotas(&mut fs).unwrap();
+            assert_eq!(quotas.len(), 2);
+
+            // The new filesystem is ready for use.
+            let mut cluster_allocator = ntfs.cluster_allocator(&mut fs).unwrap();
+            let mut root_dir = ntfs.root_directory(&mut fs).unwrap();
+            let mut dir = ntfs
+                .create_dir(&mut fs, &mut cluster_allocator, &mut root_dir, "dir")
+                .unwrap();
+            let mut file = ntfs
+                .create_file(&mut fs, &mut cluster_allocator, &mut dir, "file.bin")
+                .unwrap();
+            let data = (0..100_000u32).map(|i| i as u8).collect::<Vec<u8>>();
+            file.write_data_stream(&mut fs, &mut cluster_allocator, "", &data)
+                .unwrap();
+            assert!(file.file_record_number() >= RESERVED_FILE_RECORD_COUNT);
+            assert_eq!(file.info().unwrap().security_id(), Some(ROOT_SECURITY_ID));
+
+            let file = ntfs.file(&mut fs, file.file_record_number()).unwrap();
+            let data_item = file.data(&mut fs, "").unwrap().unwrap();
+            let mut data_value = data_item.to_attribute().value().unwrap();
+            let mut read_data = vec![0u8; data.len()];
+            data_value.read_exact(&mut fs, &mut read_data).unwrap();
+            assert_eq!(read_data, data);
+        }
+    }
+
+    #[test]
+    fn test_format_errors() {
+        let mut fs = Cursor::new(vec![0u8; VOLUME_SIZE]);
+
+        assert!(matches!(
+            Ntfs::format(&mut fs, NtfsFormatOptions::new().cluster_size(3000)),
+            Err(NtfsError::InvalidClusterSize { .. })
+        ));
+        assert!(matches!(
+            Ntfs::format(&mut fs, NtfsFormatOptions::new().cluster_size(256)),
+            Err(NtfsError::InvalidClusterSize { .. })
+        ));
+        assert!(matches!(
+            Ntfs::format(&mut fs, NtfsFormatOptions::new().cluster_size(131072)),
+            Err(NtfsError::UnsupportedClusterSize { .. })
+        ));
+        assert!(matches!(
+            Ntfs::format(&mut fs, NtfsFormatOptions::new().file_record_size(512)),
+            Err(NtfsError::UnsupportedRecordSize { .. })
+        ));
+        assert!(matches!(
+            Ntfs::format(
+                &mut fs,
+                NtfsFormatOptions::new()
+                    .cluster_size(8192)
+                    .index_record_size(4096)
+            ),
+            Err(NtfsError::UnsupportedRecordSize { .. })
+        ));
+        assert!(matches!(
+            Ntfs::format(
+                &mut fs,
+                NtfsFormatOptions::new().volume_name(&"a".repeat(129))
+            ),
+            Err(NtfsError::InvalidVolumeName { .. })
+        ));
+
+        let mut fs = Cursor::new(vec![0u8; 512 * 1024]);
+        assert!(matches!(
+            Ntfs::format(&mut fs, &NtfsFormatOptions::default()),
+            Err(NtfsError::VolumeTooSmall { .. })
+        ));
+    }
+}
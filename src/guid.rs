@@ -1,7 +1,10 @@
 // Copyright 2021 Colin Finck <colin@reactos.org>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use binread::BinRead;
+use crate::error::{NtfsError, Result};
+use crate::indexes::NtfsIndexEntryKey;
+use binread::io::Cursor;
+use binread::{BinRead, BinReaderExt};
 use core::fmt;
 
 /// Size of a single GUID on disk (= size of all GUID fields).
@@ -16,6 +19,18 @@ pub struct NtfsGuid {
     pub data4: [u8; 8],
 }
 
+impl NtfsGuid {
+    /// Returns the GUID in the same byte representation as on disk.
+    pub(crate) fn to_bytes(&self) -> [u8; GUID_SIZE] {
+        let mut bytes = [0u8; GUID_SIZE];
+        bytes[..4].copy_from_slice(&self.data1.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.data2.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.data3.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.data4);
+        bytes
+    }
+}
+
 impl fmt::Display for NtfsGuid {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -36,6 +51,23 @@ impl fmt::Display for NtfsGuid {
     }
 }
 
+// The $O index of $ObjId uses the Object ID as its key.
+impl NtfsIndexEntryKey for NtfsGuid {
+    fn key_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        if slice.len() < GUID_SIZE {
+            return Err(NtfsError::InvalidIndexEntryKeySize {
+                position,
+                expected: GUID_SIZE as u16,
+                actual: slice.len() as u16,
+            });
+        }
+
+        let mut cursor = Cursor::new(slice);
+        let guid = cursor.read_le::<Self>()?;
+        Ok(guid)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -50,5 +82,9 @@ mod tests {
         };
         let guid_string = guid.to_string();
         assert_eq!(guid_string, "67C8770B-44F1-410A-AB9A-F9B5446F13EE");
+
+        let guid_bytes = guid.to_bytes();
+        let parsed_guid = NtfsGuid::key_from_slice(&guid_bytes, 0).unwrap();
+        assert_eq!(parsed_guid, guid);
     }
 }
@@ -10,6 +10,16 @@ macro_rules! iter_try {
     };
 }
 
+/// Divides `dividend` by `divisor`, rounding up.
+///
+/// This is `u64::div_ceil`, which requires Rust 1.73.
+/// Unlike `(dividend + divisor - 1) / divisor`, it cannot overflow for large dividends.
+#[cfg(feature = "std")]
+#[allow(clippy::manual_is_multiple_of)]
+pub(crate) const fn div_ceil(dividend: u64, divisor: u64) -> u64 {
+    dividend / divisor + (dividend % divisor != 0) as u64
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::fs::File;
@@ -23,4 +33,13 @@ pub mod tests {
             .unwrap();
         Cursor::new(buffer)
     }
+
+    #[test]
+    fn test_div_ceil() {
+        assert_eq!(super::div_ceil(0, 512), 0);
+        assert_eq!(super::div_ceil(1, 512), 1);
+        assert_eq!(super::div_ceil(512, 512), 1);
+        assert_eq!(super::div_ceil(513, 512), 2);
+        assert_eq!(super::div_ceil(u64::MAX, 512), u64::MAX / 512 + 1);
+    }
 }
@@ -2,16 +2,29 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use crate::attribute::{NtfsAttributeItem, NtfsAttributeType};
+use crate::collation::NtfsCollationRule;
 use crate::error::{NtfsError, Result};
+#[cfg(feature = "std")]
+use crate::file::NtfsFile;
+#[cfg(feature = "std")]
+use crate::index_entry::NtfsIndexNodeEntries;
+#[cfg(feature = "std")]
+use crate::index_entry::INDEX_ENTRY_HEADER_SIZE;
 use crate::index_entry::{
     IndexEntryRange, IndexNodeEntryRanges, NtfsIndexEntry, NtfsIndexEntryFlags,
 };
 use crate::indexes::NtfsIndexEntryType;
+use crate::ntfs::Ntfs;
 use crate::structured_values::{NtfsIndexAllocation, NtfsIndexRoot};
+use crate::types::Vcn;
 use alloc::vec::Vec;
 use binread::io::{Read, Seek};
 use core::cmp::Ordering;
 use core::marker::PhantomData;
+#[cfg(feature = "std")]
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::io::Write;
 
 /// Helper structure to iterate over all entries of an index or find a specific one.
 ///
@@ -27,6 +40,7 @@ pub struct NtfsIndex<'n, 'f, E>
 where
     E: NtfsIndexEntryType,
 {
+    collation_rule: u32,
     index_record_size: u32,
     index_root_entry_ranges: IndexNodeEntryRanges<E>,
     index_root_position: u64,
@@ -63,12 +77,14 @@ where
             });
         }
 
+        let collation_rule = index_root.collation_rule_value();
         let index_record_size = index_root.index_record_size();
         let index_root_entry_ranges = index_root.entry_ranges();
         let index_root_position = index_root.position();
         let entry_type = PhantomData;
 
         Ok(Self {
+            collation_rule,
             index_record_size,
             index_root_entry_ranges,
             index_root_position,
@@ -77,6 +93,57 @@ where
         })
     }
 
+    /// Checks that all entries of this index are sorted in strictly ascending order with respect to
+    /// the collation rule of this index (see [`NtfsIndex::collation_rule`]).
+    ///
+    /// This performs an in-order traversal of the entire B-tree, which is equivalent to checking that every
+    /// subnode only contains keys between the keys of its neighboring parent entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index uses a case-insensitive collation rule and
+    /// [`read_upcase_table`][Ntfs::read_upcase_table] had not been called on the passed [`Ntfs`] object.
+    pub fn check_ordering<T>(&self, ntfs: &Ntfs, fs: &mut T) -> Result<()>
+    where
+        T: Read + Seek,
+    {
+        let collation_rule = self.collation_rule()?;
+        let mut previous_key = None::<Vec<u8>>;
+        let mut iter = self.entries();
+
+        while let Some(entry) = iter.next(fs) {
+            let entry = entry?;
+            let key = match entry.key_slice() {
+                Some(key) => key?,
+                None => continue,
+            };
+
+            if let Some(previous_key) = &previous_key {
+                if collation_rule.compare(ntfs, previous_key, key) != Ordering::Less {
+                    return Err(NtfsError::InvalidIndexEntryOrder {
+                        position: entry.position(),
+                        collation_rule,
+                    });
+                }
+            }
+
+            previous_key = Some(key.to_vec());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the rule that is used to sort the keys of this index, as stored in its [`NtfsIndexRoot`].
+    pub fn collation_rule(&self) -> Result<NtfsCollationRule> {
+        NtfsCollationRule::from_value(self.collation_rule, self.index_root_position)
+    }
+
+    /// Returns an [`NtfsIndexCursor`] structure to traverse this index in both directions,
+    /// starting at an arbitrary key.
+    pub fn cursor<'i>(&'i self) -> NtfsIndexCursor<'n, 'f, 'i, E> {
+        NtfsIndexCursor::new(self)
+    }
+
     /// Returns an [`NtfsIndexEntries`] iterator to perform an in-order traversal of this index.
     pub fn entries<'i>(&'i self) -> NtfsIndexEntries<'n, 'f, 'i, E> {
         NtfsIndexEntries::new(self)
@@ -86,6 +153,135 @@ where
     pub fn finder<'i>(&'i self) -> NtfsIndexFinder<'n, 'f, 'i, E> {
         NtfsIndexFinder::new(self)
     }
+
+    /// Reads the Index Record of the subnode at the given Virtual Cluster Number (VCN)
+    /// from the $INDEX_ALLOCATION attribute and returns its entry ranges.
+    fn subnode_entry_ranges<T>(
+        &self,
+        fs: &mut T,
+        subnode_vcn: Vcn,
+    ) -> Result<IndexNodeEntryRanges<E>>
+    where
+        T: Read + Seek,
+    {
+        let index_allocation_item =
+            self.index_allocation_item
+                .as_ref()
+                .ok_or(NtfsError::MissingIndexAllocation {
+                    position: self.index_root_position,
+                })?;
+        let index_allocation_attribute = index_allocation_item.to_attribute();
+        let index_allocation =
+            index_allocation_attribute.structured_value::<_, NtfsIndexAllocation>(fs)?;
+
+        let subnode = index_allocation.record_from_vcn(fs, self.index_record_size, subnode_vcn)?;
+        Ok(subnode.into_entry_ranges())
+    }
+}
+
+/// Patches the keys of all entries of an index of `file` (e.g. a "$I30" directory index) that match a condition,
+/// and writes the changed Index Records and the File Record back to the filesystem.
+///
+/// `matches` decides which entries to patch and `patch` is called with the raw key bytes of each of them.
+/// Only the live part of the B-tree is traversed, so stale entries in unused Index Records are not touched.
+#[cfg(feature = "std")]
+pub(crate) fn update_index_entries<E, T, M, P>(
+    file: &mut NtfsFile,
+    fs: &mut T,
+    index_name: &str,
+    mut matches: M,
+    mut patch: P,
+) -> Result<()>
+where
+    E: NtfsIndexEntryType,
+    T: Read + Write + Seek,
+    M: FnMut(&NtfsIndexEntry<E>) -> bool,
+    P: FnMut(&mut [u8]),
+{
+    /// Returns the ranges of all matching keys relative to `node_position` and adds all subnode VCNs to `subnode_vcns`.
+    fn collect_node<E, M>(
+        entries: NtfsIndexNodeEntries<E>,
+        node_position: u64,
+        matches: &mut M,
+        subnode_vcns: &mut Vec<Vcn>,
+    ) -> Result<Vec<Range<usize>>>
+    where
+        E: NtfsIndexEntryType,
+        M: FnMut(&NtfsIndexEntry<E>) -> bool,
+    {
+        let mut key_ranges = Vec::new();
+
+        for entry in entries {
+            let entry = entry?;
+
+            if let Some(subnode_vcn) = entry.subnode_vcn() {
+                subnode_vcns.push(subnode_vcn?);
+            }
+
+            if entry.key_slice().is_some() && matches(&entry) {
+                let start = (entry.position() - node_position) as usize + INDEX_ENTRY_HEADER_SIZE;
+                let end = start + entry.key_length() as usize;
+                key_ranges.push(start..end);
+            }
+        }
+
+        Ok(key_ranges)
+    }
+
+    let file_position = file.position();
+    let mut subnode_vcns = Vec::new();
+
+    let root_key_ranges = {
+        let index = file.index::<E, T>(fs, index_name)?;
+        let index_root =
+            file.find_resident_attribute_structured_value::<NtfsIndexRoot>(Some(index_name))?;
+        let root_key_ranges = collect_node(
+            index_root.entries()?,
+            file_position,
+            &mut matches,
+            &mut subnode_vcns,
+        )?;
+
+        if let Some(index_allocation_item) = &index.index_allocation_item {
+            let index_allocation_attribute = index_allocation_item.to_attribute();
+            let index_allocation =
+                index_allocation_attribute.structured_value::<_, NtfsIndexAllocation>(fs)?;
+
+            while let Some(vcn) = subnode_vcns.pop() {
+                let mut record =
+                    index_allocation.record_from_vcn(fs, index.index_record_size, vcn)?;
+                let record_position = record.position();
+                let key_ranges = collect_node(
+                    record.entries()?,
+                    record_position,
+                    &mut matches,
+                    &mut subnode_vcns,
+                )?;
+
+                if key_ranges.is_empty() {
+                    continue;
+                }
+
+                for key_range in key_ranges {
+                    patch(&mut record.data_mut()[key_range]);
+                }
+
+                index_allocation.write_record(fs, &mut record)?;
+            }
+        }
+
+        root_key_ranges
+    };
+
+    if root_key_ranges.is_empty() {
+        return Ok(());
+    }
+
+    for key_range in root_key_ranges {
+        patch(&mut file.record_data_mut()[key_range]);
+    }
+
+    file.write_record(fs)
 }
 
 /// Iterator over
@@ -159,23 +355,7 @@ where
                     let subnode_vcn = iter_try!(subnode_vcn);
 
                     // Read the subnode from the filesystem and get an iterator for it.
-                    let index_allocation_item =
-                        iter_try!(self.index.index_allocation_item.as_ref().ok_or_else(|| {
-                            NtfsError::MissingIndexAllocation {
-                                position: self.index.index_root_position,
-                            }
-                        }));
-                    let index_allocation_attribute = index_allocation_item.to_attribute();
-                    let index_allocation =
-                        iter_try!(index_allocation_attribute
-                            .structured_value::<_, NtfsIndexAllocation>(fs));
-
-                    let subnode = iter_try!(index_allocation.record_from_vcn(
-                        fs,
-                        self.index.index_record_size,
-                        subnode_vcn
-                    ));
-                    let subnode_iter = subnode.into_entry_ranges();
+                    let subnode_iter = iter_try!(self.index.subnode_entry_ranges(fs, subnode_vcn));
 
                     let following_entry = if !is_last_entry {
                         // This entry comes after the subnode lexicographically, so save it.
@@ -223,6 +403,389 @@ where
     }
 }
 
+/// Cursor for an ordered traversal of an index in both directions, created by [`NtfsIndex::cursor`].
+///
+/// Contrary to [`NtfsIndexEntries`], which always starts at the first entry, a cursor can be positioned
+/// at the first entry whose key is greater than or equal to a given bound (see [`NtfsIndexCursor::seek`]).
+/// From there, it can move forward via [`NtfsIndexCursor::next`] and backward via [`NtfsIndexCursor::prev`].
+/// This allows for efficient prefix listings and range scans without iterating the entire index.
+///
+/// A new cursor is positioned before the first entry.
+/// Hence, calling [`NtfsIndexCursor::next`] repeatedly yields the same entries as [`NtfsIndexEntries`].
+///
+/// Like the finder, the cursor lends out the returned entries.
+/// Copy the field(s) you need from a returned entry before moving the cursor again.
+#[derive(Clone, Debug)]
+pub struct NtfsIndexCursor<'n, 'f, 'i, E>
+where
+    E: NtfsIndexEntryType,
+{
+    index: &'i NtfsIndex<'n, 'f, E>,
+    /// Path from the root node down to the node of the current position.
+    /// Every level except the last one is positioned at the entry whose subnode is the next level.
+    levels: Vec<IndexCursorLevel<E>>,
+    state: IndexCursorState,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum IndexCursorState {
+    BeforeFirst,
+    AtEntry,
+    AfterLast,
+}
+
+#[derive(Clone, Debug)]
+struct IndexCursorLevel<E>
+where
+    E: NtfsIndexEntryType,
+{
+    node: IndexNodeEntryRanges<E>,
+    entry_ranges: Vec<IndexEntryRange<E>>,
+    position: usize,
+}
+
+impl<E> IndexCursorLevel<E>
+where
+    E: NtfsIndexEntryType,
+{
+    fn new(mut node: IndexNodeEntryRanges<E>) -> Result<Self> {
+        let entry_ranges = node.by_ref().collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            node,
+            entry_ranges,
+            position: 0,
+        })
+    }
+
+    fn entry(&self) -> Result<NtfsIndexEntry<'_, E>> {
+        self.entry_ranges[self.position].to_entry(self.node.data())
+    }
+
+    fn is_at_last_entry(&self) -> Result<bool> {
+        let entry = self.entry()?;
+        Ok(entry.flags().contains(NtfsIndexEntryFlags::LAST_ENTRY))
+    }
+
+    fn subnode_vcn(&self) -> Result<Option<Vcn>> {
+        self.entry()?.subnode_vcn().transpose()
+    }
+}
+
+impl<'n, 'f, 'i, E> NtfsIndexCursor<'n, 'f, 'i, E>
+where
+    E: NtfsIndexEntryType,
+{
+    fn new(index: &'i NtfsIndex<'n, 'f, E>) -> Self {
+        Self {
+            index,
+            levels: Vec::new(),
+            state: IndexCursorState::BeforeFirst,
+        }
+    }
+
+    /// Returns the [`NtfsIndexEntry`] at the current position of the cursor,
+    /// or `None` if the cursor is positioned before the first or after the last entry.
+    pub fn current(&self) -> Option<Result<NtfsIndexEntry<'_, E>>> {
+        if self.state != IndexCursorState::AtEntry {
+            return None;
+        }
+
+        let level = self.levels.last().unwrap();
+        Some(level.entry())
+    }
+
+    /// Returns the subnode VCN of the entry at the current position (if the cursor is positioned at all).
+    fn current_subnode_vcn(&self) -> Result<Option<Vcn>> {
+        match self.levels.last() {
+            Some(level) => level.subnode_vcn(),
+            None => Ok(None),
+        }
+    }
+
+    /// Moves down from the current entry to the first entry of its leftmost leaf node.
+    fn descend_first<T>(&mut self, fs: &mut T) -> Result<()>
+    where
+        T: Read + Seek,
+    {
+        while let Some(subnode_vcn) = self.current_subnode_vcn()? {
+            let subnode = self.index.subnode_entry_ranges(fs, subnode_vcn)?;
+            self.push_level(subnode, |_| 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves down from the current entry to the last entry of its rightmost leaf node.
+    fn descend_last<T>(&mut self, fs: &mut T) -> Result<()>
+    where
+        T: Read + Seek,
+    {
+        while let Some(subnode_vcn) = self.current_subnode_vcn()? {
+            let subnode = self.index.subnode_entry_ranges(fs, subnode_vcn)?;
+            self.push_level(subnode, |count| count - 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the cursor to the next entry and returns it,
+    /// or returns `None` if the cursor has moved past the last entry.
+    ///
+    /// If the cursor is positioned before the first entry, this returns the first entry.
+    pub fn next<'a, T>(&'a mut self, fs: &mut T) -> Option<Result<NtfsIndexEntry<'a, E>>>
+    where
+        T: Read + Seek,
+    {
+        match self.state {
+            IndexCursorState::BeforeFirst => {
+                iter_try!(self.reset_to_root(|_| 0));
+            }
+            IndexCursorState::AtEntry => {
+                self.levels.last_mut().unwrap().position += 1;
+            }
+            IndexCursorState::AfterLast => return None,
+        }
+
+        iter_try!(self.descend_first(fs));
+        iter_try!(self.settle_forward());
+
+        self.current()
+    }
+
+    /// Moves the cursor to the previous entry and returns it,
+    /// or returns `None` if the cursor has moved before the first entry.
+    ///
+    /// If the cursor is positioned after the last entry, this returns the last entry.
+    pub fn prev<'a, T>(&'a mut self, fs: &mut T) -> Option<Result<NtfsIndexEntry<'a, E>>>
+    where
+        T: Read + Seek,
+    {
+        match self.state {
+            IndexCursorState::BeforeFirst => return None,
+            IndexCursorState::AtEntry => (),
+            IndexCursorState::AfterLast => {
+                iter_try!(self.reset_to_root(|count| count - 1));
+            }
+        }
+
+        // Entries in the subnode of the current entry come before the current entry.
+        iter_try!(self.descend_last(fs));
+        iter_try!(self.settle_backward());
+
+        self.current()
+    }
+
+    fn push_level<F>(&mut self, node: IndexNodeEntryRanges<E>, position_fn: F) -> Result<()>
+    where
+        F: Fn(usize) -> usize,
+    {
+        let mut level = IndexCursorLevel::new(node)?;
+
+        // Every valid node has at least the empty "last entry".
+        // Fake a "before first" position for an invalid empty node, so that the cursor doesn't panic.
+        if level.entry_ranges.is_empty() {
+            self.levels.clear();
+            self.state = IndexCursorState::BeforeFirst;
+            return Ok(());
+        }
+
+        level.position = position_fn(level.entry_ranges.len());
+        self.levels.push(level);
+        Ok(())
+    }
+
+    fn reset_to_root<F>(&mut self, position_fn: F) -> Result<()>
+    where
+        F: Fn(usize) -> usize,
+    {
+        self.levels.clear();
+        self.push_level(self.index.index_root_entry_ranges.clone(), position_fn)
+    }
+
+    /// Positions the cursor at the first entry whose key is greater than or equal to the bound
+    /// described by the given comparison function, and returns that entry.
+    ///
+    /// The comparison function works like the one passed to [`NtfsIndexFinder::find`]:
+    /// It compares the bound to the given key and returns [`Ordering::Less`] if the bound comes before the key,
+    /// [`Ordering::Equal`] if the bound matches the key, and [`Ordering::Greater`] if the bound comes after the key.
+    ///
+    /// Returns `None` if all keys are smaller than the bound.
+    /// The cursor is then positioned after the last entry, and [`NtfsIndexCursor::prev`] returns the last entry.
+    ///
+    /// # Example
+    /// The following example lists all files of a directory whose names begin with "foo".
+    ///
+    /// ```ignore
+    /// let index = directory.directory_index(&mut fs).unwrap();
+    /// let mut cursor = index.cursor();
+    /// let mut entry = NtfsFileNameIndex::seek(&mut cursor, &ntfs, &mut fs, "foo");
+    ///
+    /// while let Some(e) = entry {
+    ///     let file_name = e.unwrap().key().unwrap().unwrap();
+    ///     if !file_name.name().to_string_lossy().to_lowercase().starts_with("foo") {
+    ///         break;
+    ///     }
+    ///
+    ///     println!("{}", file_name.name());
+    ///     entry = cursor.next(&mut fs);
+    /// }
+    /// ```
+    pub fn seek<'a, T, F>(&'a mut self, fs: &mut T, cmp: F) -> Option<Result<NtfsIndexEntry<'a, E>>>
+    where
+        T: Read + Seek,
+        F: Fn(&E::KeyType) -> Ordering,
+    {
+        self.seek_by(fs, |entry| entry.key().map(|key| key.map(|key| cmp(&key))))
+    }
+
+    /// Positions the cursor at the first entry whose key is greater than or equal to the given raw key
+    /// with respect to the collation rule of this index (see [`NtfsIndex::collation_rule`]), and returns that entry.
+    ///
+    /// Returns `None` if all keys are smaller than the given one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index uses a case-insensitive collation rule and
+    /// [`read_upcase_table`][Ntfs::read_upcase_table] had not been called on the passed [`Ntfs`] object.
+    pub fn seek_key<'a, T>(
+        &'a mut self,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        key: &[u8],
+    ) -> Option<Result<NtfsIndexEntry<'a, E>>>
+    where
+        T: Read + Seek,
+    {
+        let collation_rule = iter_try!(self.index.collation_rule());
+        self.seek_by(fs, |entry| {
+            entry.key_slice().map(|entry_key| {
+                entry_key.map(|entry_key| collation_rule.compare(ntfs, key, entry_key))
+            })
+        })
+    }
+
+    /// Implementation of [`NtfsIndexCursor::seek`] and [`NtfsIndexCursor::seek_key`].
+    ///
+    /// The comparison function returns `None` for an entry without a key.
+    fn seek_by<'a, T, F>(&'a mut self, fs: &mut T, cmp: F) -> Option<Result<NtfsIndexEntry<'a, E>>>
+    where
+        T: Read + Seek,
+        F: Fn(&NtfsIndexEntry<E>) -> Option<Result<Ordering>>,
+    {
+        iter_try!(self.reset_to_root(|_| 0));
+        if self.levels.is_empty() {
+            return None;
+        }
+
+        loop {
+            // Find the first entry of this node that is greater than or equal to the bound.
+            // This may also be the empty "last entry" of the node.
+            let level = self.levels.last_mut().unwrap();
+
+            loop {
+                let entry = iter_try!(level.entry());
+
+                if let Some(ordering) = cmp(&entry) {
+                    match iter_try!(ordering) {
+                        Ordering::Equal => {
+                            // This is an exact match.
+                            // All keys in the subnode of this entry are smaller, so we are done.
+                            self.state = IndexCursorState::AtEntry;
+                            return self.current();
+                        }
+                        Ordering::Less => break,
+                        Ordering::Greater => {
+                            level.position += 1;
+                            continue;
+                        }
+                    }
+                }
+
+                break;
+            }
+
+            // Any closer match must be in the subnode of this entry (if there is any).
+            match iter_try!(level.subnode_vcn()) {
+                Some(subnode_vcn) => {
+                    let subnode = iter_try!(self.index.subnode_entry_ranges(fs, subnode_vcn));
+                    iter_try!(self.push_level(subnode, |_| 0));
+                    if self.levels.is_empty() {
+                        return None;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        // We ended up in a leaf node, but maybe on its empty "last entry".
+        // Then the entry we are looking for is further up.
+        iter_try!(self.settle_forward());
+
+        self.current()
+    }
+
+    /// Positions the cursor at the first entry of the index and returns it.
+    ///
+    /// Returns `None` if the index is empty.
+    pub fn seek_first<'a, T>(&'a mut self, fs: &mut T) -> Option<Result<NtfsIndexEntry<'a, E>>>
+    where
+        T: Read + Seek,
+    {
+        self.state = IndexCursorState::BeforeFirst;
+        self.next(fs)
+    }
+
+    /// Positions the cursor at the last entry of the index and returns it.
+    ///
+    /// Returns `None` if the index is empty.
+    pub fn seek_last<'a, T>(&'a mut self, fs: &mut T) -> Option<Result<NtfsIndexEntry<'a, E>>>
+    where
+        T: Read + Seek,
+    {
+        self.state = IndexCursorState::AfterLast;
+        self.prev(fs)
+    }
+
+    /// Moves up from an exhausted position until we are at the previous entry (if any).
+    ///
+    /// This is called after having descended to the last entry of a node.
+    /// All entries in front of it come before it, and the entry that leads to a node
+    /// comes after all entries of that node.
+    fn settle_backward(&mut self) -> Result<()> {
+        while let Some(level) = self.levels.last_mut() {
+            if level.position > 0 {
+                // All entries in front of the last one have a key.
+                level.position -= 1;
+                self.state = IndexCursorState::AtEntry;
+                return Ok(());
+            }
+
+            // We have finished this node, and the entry leading here comes after it.
+            self.levels.pop();
+        }
+
+        self.state = IndexCursorState::BeforeFirst;
+        Ok(())
+    }
+
+    /// Moves up from the empty "last entry" of a node until we are at an entry with a key (if any).
+    fn settle_forward(&mut self) -> Result<()> {
+        while let Some(level) = self.levels.last() {
+            if !level.is_at_last_entry()? {
+                self.state = IndexCursorState::AtEntry;
+                return Ok(());
+            }
+
+            // We have finished this node, so the entry leading here comes next.
+            self.levels.pop();
+        }
+
+        self.state = IndexCursorState::AfterLast;
+        Ok(())
+    }
+}
+
 /// Helper structure to efficiently find an entry in an index, created by [`NtfsIndex::finder`].
 ///
 /// This helper is required, because the returned entry borrows from the iterator it was created from.
@@ -255,6 +818,45 @@ where
     where
         T: Read + Seek,
         F: Fn(&E::KeyType) -> Ordering,
+    {
+        self.find_by(fs, |entry| entry.key().map(|key| key.map(|key| cmp(&key))))
+    }
+
+    /// Finds an entry in this index by comparing the given raw key to the raw keys of the entries
+    /// with respect to the collation rule of this index (see [`NtfsIndex::collation_rule`]).
+    /// Returns an [`NtfsIndexEntry`] (if there is one).
+    ///
+    /// This works for every index type, no matter whether there is a comparison function for its
+    /// structured key type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index uses a case-insensitive collation rule and
+    /// [`read_upcase_table`][Ntfs::read_upcase_table] had not been called on the passed [`Ntfs`] object.
+    pub fn find_key<'a, T>(
+        &'a mut self,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        key: &[u8],
+    ) -> Option<Result<NtfsIndexEntry<'a, E>>>
+    where
+        T: Read + Seek,
+    {
+        let collation_rule = iter_try!(self.index.collation_rule());
+        self.find_by(fs, |entry| {
+            entry.key_slice().map(|entry_key| {
+                entry_key.map(|entry_key| collation_rule.compare(ntfs, key, entry_key))
+            })
+        })
+    }
+
+    /// Implementation of [`NtfsIndexFinder::find`] and [`NtfsIndexFinder::find_key`].
+    ///
+    /// The comparison function returns `None` for an entry without a key.
+    fn find_by<'a, T, F>(&'a mut self, fs: &mut T, cmp: F) -> Option<Result<NtfsIndexEntry<'a, E>>>
+    where
+        T: Read + Seek,
+        F: Fn(&NtfsIndexEntry<E>) -> Option<Result<Ordering>>,
     {
         // Always (re)start by iterating through the Index Root entry ranges.
         self.inner_iterator = self.index.index_root_entry_ranges.clone();
@@ -268,11 +870,9 @@ where
             let entry = iter_try!(entry_range.to_entry(self.inner_iterator.data()));
 
             // Check if this entry has a key.
-            if let Some(key) = entry.key() {
-                // The entry has a key, so compare it using the given function.
-                let key = iter_try!(key);
-
-                match cmp(&key) {
+            if let Some(ordering) = cmp(&entry) {
+                // The entry has a key, so it has been compared using the given function.
+                match iter_try!(ordering) {
                     Ordering::Equal => {
                         // We found what we were looking for!
                         // Recreate `entry` from the last `self.inner_iterator` to please the borrow checker.
@@ -295,23 +895,7 @@ where
             // it comes lexicographically AFTER what we're looking for.
             // In both cases, we have to continue iterating in the subnode of this entry (if there is any).
             let subnode_vcn = iter_try!(entry.subnode_vcn()?);
-            let index_allocation_item =
-                iter_try!(self.index.index_allocation_item.as_ref().ok_or_else(|| {
-                    NtfsError::MissingIndexAllocation {
-                        position: self.index.index_root_position,
-                    }
-                }));
-            let index_allocation_attribute = index_allocation_item.to_attribute();
-            let index_allocation = iter_try!(
-                index_allocation_attribute.structured_value::<_, NtfsIndexAllocation>(fs)
-            );
-
-            let subnode = iter_try!(index_allocation.record_from_vcn(
-                fs,
-                self.index.index_record_size,
-                subnode_vcn
-            ));
-            self.inner_iterator = subnode.into_entry_ranges();
+            self.inner_iterator = iter_try!(self.index.subnode_entry_ranges(fs, subnode_vcn));
         }
     }
 }
@@ -389,4 +973,204 @@ mod tests {
 
         assert!(subdir_iter.next(&mut testfs1).is_none());
     }
+
+    #[test]
+    fn test_index_record_write() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+
+        // Find the "many_subdirs" subdirectory, which has an $INDEX_ALLOCATION attribute.
+        let root_dir_index = root_dir.directory_index(&mut testfs1).unwrap();
+        let mut root_dir_finder = root_dir_index.finder();
+        let entry =
+            NtfsFileNameIndex::find(&mut root_dir_finder, &ntfs, &mut testfs1, "many_subdirs")
+                .unwrap()
+                .unwrap();
+        let subdir = entry.to_file(&ntfs, &mut testfs1).unwrap();
+
+        let index_root = subdir
+            .find_resident_attribute_structured_value::<NtfsIndexRoot>(Some("$I30"))
+            .unwrap();
+        let index_record_size = index_root.index_record_size();
+
+        let index_allocation = subdir
+            .attributes_raw()
+            .find(|attribute| attribute.ty().unwrap() == NtfsAttributeType::IndexAllocation)
+            .unwrap()
+            .structured_value::<_, NtfsIndexAllocation>(&mut testfs1)
+            .unwrap();
+
+        let mut record = index_allocation
+            .records(index_record_size)
+            .next(&mut testfs1)
+            .unwrap()
+            .unwrap();
+        let lsn = record.logfile_sequence_number();
+        let entry_count = record.entries::<NtfsFileNameIndex>().unwrap().count();
+
+        // Write the record back twice and read it again.
+        // The USN is incremented each time, but the record stays valid and keeps its LSN.
+        let original_image = testfs1.get_ref().clone();
+        index_allocation
+            .write_record(&mut testfs1, &mut record)
+            .unwrap();
+        index_allocation
+            .write_record(&mut testfs1, &mut record)
+            .unwrap();
+
+        let reread_record = index_allocation
+            .record_from_vcn(&mut testfs1, index_record_size, record.vcn())
+            .unwrap();
+        assert_eq!(reread_record.logfile_sequence_number(), lsn);
+        assert_eq!(
+            reread_record
+                .entries::<NtfsFileNameIndex>()
+                .unwrap()
+                .count(),
+            entry_count
+        );
+
+        // Only the USN and its copies at the end of each sector have changed.
+        let sector_count = index_record_size as usize / ntfs.sector_size() as usize;
+        let changed_bytes = original_image
+            .iter()
+            .zip(testfs1.get_ref())
+            .filter(|(a, b)| a != b)
+            .count();
+        assert!(changed_bytes <= 2 * (1 + sector_count));
+        assert!(changed_bytes > 0);
+    }
+
+    #[test]
+    fn test_index_cursor() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+
+        // Find the "many_subdirs" subdirectory.
+        let root_dir_index = root_dir.directory_index(&mut testfs1).unwrap();
+        let mut root_dir_finder = root_dir_index.finder();
+        let entry =
+            NtfsFileNameIndex::find(&mut root_dir_finder, &ntfs, &mut testfs1, "many_subdirs")
+                .unwrap()
+                .unwrap();
+        let subdir = entry.to_file(&ntfs, &mut testfs1).unwrap();
+
+        let mut dir_names = Vec::with_capacity(512);
+        for i in 1..=512 {
+            dir_names.push(format!("{}", i));
+        }
+
+        dir_names.sort_unstable();
+
+        let subdir_index = subdir.directory_index(&mut testfs1).unwrap();
+        let mut cursor = subdir_index.cursor();
+
+        // Prove that we can iterate backwards through all 512 indexed subdirectories.
+        let entry = cursor.seek_last(&mut testfs1).unwrap().unwrap();
+        let entry_name = entry.key().unwrap().unwrap();
+        assert_eq!(entry_name.name(), dir_names.last().unwrap().as_str());
+
+        for dir_name in dir_names.iter().rev().skip(1) {
+            let entry = cursor.prev(&mut testfs1).unwrap().unwrap();
+            let entry_name = entry.key().unwrap().unwrap();
+            assert_eq!(entry_name.name(), dir_name.as_str());
+        }
+
+        assert!(cursor.prev(&mut testfs1).is_none());
+        assert!(cursor.current().is_none());
+
+        // Prove that we can seek to every subdirectory and continue forward from there.
+        for (i, dir_name) in dir_names.iter().enumerate() {
+            let entry = NtfsFileNameIndex::seek(&mut cursor, &ntfs, &mut testfs1, dir_name)
+                .unwrap()
+                .unwrap();
+            let entry_name = entry.key().unwrap().unwrap();
+            assert_eq!(entry_name.name(), dir_name.as_str());
+
+            match dir_names.get(i + 1) {
+                Some(next_dir_name) => {
+                    let entry = cursor.next(&mut testfs1).unwrap().unwrap();
+                    let entry_name = entry.key().unwrap().unwrap();
+                    assert_eq!(entry_name.name(), next_dir_name.as_str());
+                }
+                None => assert!(cursor.next(&mut testfs1).is_none()),
+            }
+        }
+
+        // Prove that seeking to a non-existing name positions the cursor at the next greater one.
+        let entry = NtfsFileNameIndex::seek(&mut cursor, &ntfs, &mut testfs1, "10a")
+            .unwrap()
+            .unwrap();
+        let entry_name = entry.key().unwrap().unwrap();
+        assert_eq!(entry_name.name(), "11");
+
+        let entry = cursor.prev(&mut testfs1).unwrap().unwrap();
+        let entry_name = entry.key().unwrap().unwrap();
+        assert_eq!(entry_name.name(), "109");
+
+        // Prove that seeking past the last name positions the cursor after the last entry.
+        assert!(NtfsFileNameIndex::seek(&mut cursor, &ntfs, &mut testfs1, "a").is_none());
+
+        let entry = cursor.prev(&mut testfs1).unwrap().unwrap();
+        let entry_name = entry.key().unwrap().unwrap();
+        assert_eq!(entry_name.name(), "99");
+    }
+
+    #[test]
+    fn test_index_collation() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+
+        let root_dir_index = root_dir.directory_index(&mut testfs1).unwrap();
+        assert_eq!(
+            root_dir_index.collation_rule().unwrap(),
+            NtfsCollationRule::FileName
+        );
+        root_dir_index.check_ordering(&ntfs, &mut testfs1).unwrap();
+
+        // Find the "many_subdirs" subdirectory.
+        let mut root_dir_finder = root_dir_index.finder();
+        let entry =
+            NtfsFileNameIndex::find(&mut root_dir_finder, &ntfs, &mut testfs1, "many_subdirs")
+                .unwrap()
+                .unwrap();
+        let subdir = entry.to_file(&ntfs, &mut testfs1).unwrap();
+
+        let subdir_index = subdir.directory_index(&mut testfs1).unwrap();
+        subdir_index.check_ordering(&ntfs, &mut testfs1).unwrap();
+
+        // Prove that we can find all 512 indexed subdirectories by their raw keys.
+        let mut keys = Vec::with_capacity(512);
+        let mut subdir_iter = subdir_index.entries();
+
+        while let Some(entry) = subdir_iter.next(&mut testfs1) {
+            let entry = entry.unwrap();
+            keys.push(entry.key_slice().unwrap().unwrap().to_vec());
+        }
+
+        assert_eq!(keys.len(), 512);
+
+        let mut subdir_finder = subdir_index.finder();
+        let mut subdir_cursor = subdir_index.cursor();
+
+        for key in keys {
+            let entry = subdir_finder
+                .find_key(&ntfs, &mut testfs1, &key)
+                .unwrap()
+                .unwrap();
+            assert_eq!(entry.key_slice().unwrap().unwrap(), key.as_slice());
+
+            let entry = subdir_cursor
+                .seek_key(&ntfs, &mut testfs1, &key)
+                .unwrap()
+                .unwrap();
+            assert_eq!(entry.key_slice().unwrap().unwrap(), key.as_slice());
+        }
+    }
 }
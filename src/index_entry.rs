@@ -19,9 +19,11 @@ use core::marker::PhantomData;
 use core::mem;
 use core::ops::Range;
 use memoffset::offset_of;
+#[cfg(feature = "std")]
+use {crate::attribute::align_to_attribute, alloc::vec::Vec};
 
 /// Size of all [`IndexEntryHeader`] fields plus some reserved bytes.
-const INDEX_ENTRY_HEADER_SIZE: usize = 16;
+pub(crate) const INDEX_ENTRY_HEADER_SIZE: usize = 16;
 
 #[repr(C, packed)]
 struct IndexEntryHeader {
@@ -48,6 +50,99 @@ bitflags! {
     }
 }
 
+/// Builds a new Index Entry that stores data after its key
+/// (which is what [`NtfsIndexEntryHasData`] index types use).
+#[cfg(feature = "std")]
+pub(crate) fn build_data_index_entry(key: &[u8], data: &[u8], subnode_vcn: Option<Vcn>) -> Vec<u8> {
+    let data_offset = (INDEX_ENTRY_HEADER_SIZE + key.len()) as u16;
+    let data_length = data.len() as u16;
+
+    let mut header_start = [0u8; 4];
+    header_start[..2].copy_from_slice(&data_offset.to_le_bytes());
+    header_start[2..].copy_from_slice(&data_length.to_le_bytes());
+
+    build_index_entry(&header_start, key, data, subnode_vcn)
+}
+
+/// Builds a new Index Entry that stores a file reference instead of data
+/// (which is what [`NtfsIndexEntryHasFileReference`] index types use).
+#[cfg(feature = "std")]
+pub(crate) fn build_file_reference_index_entry(
+    file_reference: NtfsFileReference,
+    key: &[u8],
+    subnode_vcn: Option<Vcn>,
+) -> Vec<u8> {
+    build_index_entry(&file_reference.to_bytes(), key, &[], subnode_vcn)
+}
+
+/// Builds the terminating Index Entry of an index node, which has no key.
+#[cfg(feature = "std")]
+pub(crate) fn build_last_index_entry(subnode_vcn: Option<Vcn>) -> Vec<u8> {
+    let mut entry = build_index_entry(&[0u8; 8], &[], &[], subnode_vcn);
+    entry[offset_of!(IndexEntryHeader, flags)] |= NtfsIndexEntryFlags::LAST_ENTRY.bits();
+    entry
+}
+
+#[cfg(feature = "std")]
+fn build_index_entry(
+    header_start: &[u8],
+    key: &[u8],
+    data: &[u8],
+    subnode_vcn: Option<Vcn>,
+) -> Vec<u8> {
+    let mut entry = Vec::new();
+    entry.extend_from_slice(header_start);
+    entry.resize(INDEX_ENTRY_HEADER_SIZE, 0);
+    entry.extend_from_slice(key);
+    entry.extend_from_slice(data);
+    entry.resize(align_to_attribute(entry.len()), 0);
+
+    let key_length = key.len() as u16;
+    let start = offset_of!(IndexEntryHeader, key_length);
+    entry[start..start + 2].copy_from_slice(&key_length.to_le_bytes());
+
+    set_index_entry_subnode_vcn(&mut entry, subnode_vcn);
+    entry
+}
+
+/// Returns the flags stored in the given raw Index Entry.
+#[cfg(feature = "std")]
+pub(crate) fn raw_index_entry_flags(entry: &[u8]) -> NtfsIndexEntryFlags {
+    NtfsIndexEntryFlags::from_bits_truncate(entry[offset_of!(IndexEntryHeader, flags)])
+}
+
+/// Returns the subnode VCN stored at the end of the given raw Index Entry, or `None` if it has no subnode.
+#[cfg(feature = "std")]
+pub(crate) fn raw_index_entry_subnode_vcn(entry: &[u8]) -> Option<Vcn> {
+    if !raw_index_entry_flags(entry).contains(NtfsIndexEntryFlags::HAS_SUBNODE) {
+        return None;
+    }
+
+    let start = entry.len() - mem::size_of::<Vcn>();
+    Some(Vcn::from(LittleEndian::read_i64(&entry[start..])))
+}
+
+/// Adds, replaces, or removes the subnode VCN at the end of the raw Index Entry `entry`,
+/// and updates its length and flags accordingly.
+#[cfg(feature = "std")]
+pub(crate) fn set_index_entry_subnode_vcn(entry: &mut Vec<u8>, subnode_vcn: Option<Vcn>) {
+    let flags_offset = offset_of!(IndexEntryHeader, flags);
+    let length_offset = offset_of!(IndexEntryHeader, index_entry_length);
+
+    if entry[flags_offset] & NtfsIndexEntryFlags::HAS_SUBNODE.bits() != 0 {
+        entry.truncate(entry.len() - mem::size_of::<Vcn>());
+        entry[flags_offset] &= !NtfsIndexEntryFlags::HAS_SUBNODE.bits();
+    }
+
+    if let Some(vcn) = subnode_vcn {
+        entry.extend_from_slice(&i64::from(vcn).to_le_bytes());
+        entry[flags_offset] |= NtfsIndexEntryFlags::HAS_SUBNODE.bits();
+    }
+
+    let index_entry_length = entry.len() as u16;
+    entry[length_offset..length_offset + 2].copy_from_slice(&index_entry_length.to_le_bytes());
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct IndexEntryRange<E>
 where
@@ -200,6 +295,20 @@ where
     ///
     /// The last Index Entry never has a key.
     pub fn key(&self) -> Option<Result<E::KeyType>> {
+        let slice = iter_try!(self.key_slice()?);
+        let position = self.position + INDEX_ENTRY_HEADER_SIZE as u64;
+
+        let key = iter_try!(E::KeyType::key_from_slice(slice, position));
+        Some(Ok(key))
+    }
+
+    /// Returns the raw bytes of the key of this Index Entry,
+    /// or `None` if this Index Entry has no key.
+    ///
+    /// This is what [`NtfsCollationRule::compare`] operates on.
+    ///
+    /// [`NtfsCollationRule::compare`]: crate::NtfsCollationRule::compare
+    pub fn key_slice(&self) -> Option<Result<&'s [u8]>> {
         // The key/stream is only set when the last entry flag is not set.
         // https://flatcap.github.io/linux-ntfs/ntfs/concepts/index_entry.html
         if self.key_length() == 0 || self.flags().contains(NtfsIndexEntryFlags::LAST_ENTRY) {
@@ -208,7 +317,6 @@ where
 
         let start = INDEX_ENTRY_HEADER_SIZE;
         let end = start + self.key_length() as usize;
-        let position = self.position + start as u64;
 
         let slice = self.slice.get(start..end);
         let slice = iter_try!(slice.ok_or(NtfsError::InvalidIndexEntryDataRange {
@@ -217,8 +325,7 @@ where
             size: self.slice.len() as u16
         }));
 
-        let key = iter_try!(E::KeyType::key_from_slice(slice, position));
-        Some(Ok(key))
+        Some(Ok(slice))
     }
 
     /// Returns the length of the key of this Index Entry.
@@ -232,6 +339,12 @@ where
         self.position
     }
 
+    /// Returns the raw bytes of this Index Entry.
+    #[cfg(feature = "std")]
+    pub(crate) fn slice(&self) -> &'s [u8] {
+        self.slice
+    }
+
     /// Returns the Virtual Cluster Number (VCN) of the subnode of this Index Entry,
     /// or `None` if this Index Entry has no subnode.
     pub fn subnode_vcn(&self) -> Option<Result<Vcn>> {
@@ -14,6 +14,12 @@ use binread::io::{Read, Seek};
 use byteorder::{ByteOrder, LittleEndian};
 use core::ops::Range;
 use memoffset::offset_of;
+#[cfg(feature = "std")]
+use {
+    crate::attribute::align_to_attribute,
+    crate::index_entry::{build_last_index_entry, raw_index_entry_flags, NtfsIndexEntryFlags},
+    alloc::vec::Vec,
+};
 
 /// Size of all [`IndexRecordHeader`] fields.
 const INDEX_RECORD_HEADER_SIZE: u32 = 24;
@@ -80,10 +86,64 @@ impl<'n> NtfsIndexRecord<'n> {
         Ok(index_record)
     }
 
+    /// Creates an empty Index Record in memory for the given VCN, which only contains the terminating Index Entry.
+    ///
+    /// `position` is the absolute position of the record within the filesystem.
+    /// Use [`NtfsIndexAllocation::write_record`] to write it to the filesystem.
+    ///
+    /// [`NtfsIndexAllocation::write_record`]: crate::structured_values::NtfsIndexAllocation::write_record
+    #[cfg(feature = "std")]
+    pub(crate) fn new_empty(
+        ntfs: &'n Ntfs,
+        position: u64,
+        index_record_size: u32,
+        vcn: Vcn,
+    ) -> Self {
+        let mut data = vec![0; index_record_size as usize];
+        data[..4].copy_from_slice(b"INDX");
+
+        // The Update Sequence Array directly follows the headers and has one entry per sector,
+        // plus one for the Update Sequence Number itself.
+        let update_sequence_offset = INDEX_RECORD_HEADER_SIZE as usize + INDEX_NODE_HEADER_SIZE;
+        let update_sequence_count = index_record_size as usize / ntfs.sector_size() as usize + 1;
+        LittleEndian::write_u16(&mut data[4..], update_sequence_offset as u16);
+        LittleEndian::write_u16(&mut data[6..], update_sequence_count as u16);
+
+        let start = offset_of!(IndexRecordHeader, vcn);
+        LittleEndian::write_i64(&mut data[start..], i64::from(vcn));
+
+        // The entries begin after the Update Sequence Array, and the entire record is allocated.
+        let entries_offset = align_to_attribute(update_sequence_offset + update_sequence_count * 2)
+            - INDEX_RECORD_HEADER_SIZE as usize;
+        let allocated_size = index_record_size - INDEX_RECORD_HEADER_SIZE;
+        let start = INDEX_RECORD_HEADER_SIZE as usize + offset_of!(IndexNodeHeader, entries_offset);
+        LittleEndian::write_u32(&mut data[start..], entries_offset as u32);
+        let start = INDEX_RECORD_HEADER_SIZE as usize + offset_of!(IndexNodeHeader, index_size);
+        LittleEndian::write_u32(&mut data[start..], entries_offset as u32);
+        let start = INDEX_RECORD_HEADER_SIZE as usize + offset_of!(IndexNodeHeader, allocated_size);
+        LittleEndian::write_u32(&mut data[start..], allocated_size);
+
+        let mut index_record = Self {
+            record: Record::new(ntfs, data, position),
+        };
+
+        // The terminating entry always fits into an empty record.
+        index_record
+            .set_entries(&[build_last_index_entry(None)])
+            .unwrap();
+
+        index_record
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        self.record.data_mut()
+    }
+
     /// Returns an iterator over all entries of this Index Record (cf. [`NtfsIndexEntry`]).
     ///
     /// [`NtfsIndexEntry`]: crate::NtfsIndexEntry
-    pub fn entries<E>(&self) -> Result<NtfsIndexNodeEntries<E>>
+    pub fn entries<E>(&self) -> Result<NtfsIndexNodeEntries<'_, E>>
     where
         E: NtfsIndexEntryType,
     {
@@ -93,6 +153,12 @@ impl<'n> NtfsIndexRecord<'n> {
         Ok(NtfsIndexNodeEntries::new(data, position))
     }
 
+    /// Returns the number of bytes available for Index Entries in this Index Record.
+    #[cfg(feature = "std")]
+    pub(crate) fn entries_capacity(&self) -> u32 {
+        self.index_allocated_size() - self.index_entries_offset()
+    }
+
     fn entries_range_and_position(&self) -> (Range<usize>, u64) {
         let start = INDEX_RECORD_HEADER_SIZE as usize + self.index_entries_offset() as usize;
         let end = INDEX_RECORD_HEADER_SIZE as usize + self.index_data_size() as usize;
@@ -126,6 +192,12 @@ impl<'n> NtfsIndexRecord<'n> {
         LittleEndian::read_u32(&self.record.data()[start..])
     }
 
+    /// Returns the $LogFile Sequence Number (LSN) of the last change to this Index Record that has been
+    /// journaled in the $LogFile.
+    pub fn logfile_sequence_number(&self) -> u64 {
+        self.record.logfile_sequence_number()
+    }
+
     pub(crate) fn into_entry_ranges<E>(self) -> IndexNodeEntryRanges<E>
     where
         E: NtfsIndexEntryType,
@@ -134,6 +206,60 @@ impl<'n> NtfsIndexRecord<'n> {
         IndexNodeEntryRanges::new(self.record.into_data(), entries_range, position)
     }
 
+    #[cfg(feature = "std")]
+    pub(crate) fn position(&self) -> u64 {
+        self.record.position()
+    }
+
+    /// Replaces all entries of this Index Record by the given raw Index Entries, the last of which must be
+    /// the terminating entry.
+    ///
+    /// Returns [`NtfsError::IndexRecordTooSmall`] if the entries don't fit into the allocated size of this Index Record.
+    /// The Index Record is only changed in memory.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_entries(&mut self, entries: &[Vec<u8>]) -> Result<()> {
+        let entries_size = entries.iter().map(Vec::len).sum::<usize>() as u32;
+        let new_index_data_size = self.index_entries_offset() + entries_size;
+
+        if new_index_data_size > self.index_allocated_size() {
+            return Err(NtfsError::IndexRecordTooSmall {
+                position: self.record.position(),
+                required: INDEX_RECORD_HEADER_SIZE + new_index_data_size,
+                allocated: INDEX_RECORD_HEADER_SIZE + self.index_allocated_size(),
+            });
+        }
+
+        let has_subnodes = entries
+            .iter()
+            .any(|entry| raw_index_entry_flags(entry).contains(NtfsIndexEntryFlags::HAS_SUBNODE));
+        let (entries_range, _position) = self.entries_range_and_position();
+        let old_end = entries_range.end;
+
+        let data = self.record.data_mut();
+        let mut offset = entries_range.start;
+        for entry in entries {
+            data[offset..offset + entry.len()].copy_from_slice(entry);
+            offset += entry.len();
+        }
+
+        // Clear the bytes that are no longer used.
+        if offset < old_end {
+            data[offset..old_end].fill(0);
+        }
+
+        let start = INDEX_RECORD_HEADER_SIZE as usize + offset_of!(IndexNodeHeader, index_size);
+        LittleEndian::write_u32(&mut data[start..], new_index_data_size);
+
+        let start = INDEX_RECORD_HEADER_SIZE as usize + offset_of!(IndexNodeHeader, flags);
+        if has_subnodes {
+            data[start] |= HAS_SUBNODES_FLAG;
+        } else {
+            data[start] &= !HAS_SUBNODES_FLAG;
+        }
+
+        Ok(())
+    }
+
     fn validate_signature(record: &Record) -> Result<()> {
         let signature = &record.signature();
         let expected = b"INDX";
@@ -150,7 +276,7 @@ impl<'n> NtfsIndexRecord<'n> {
     }
 
     fn validate_sizes(&self) -> Result<()> {
-        let index_record_size = self.record.len() as u32;
+        let index_record_size = self.record.len();
 
         // The total size allocated for this Index Record must not be larger than
         // the size defined for all index records of this index.
@@ -187,4 +313,13 @@ impl<'n> NtfsIndexRecord<'n> {
         let start = offset_of!(IndexRecordHeader, vcn);
         Vcn::from(LittleEndian::read_i64(&self.record.data()[start..]))
     }
+
+    /// Protects this Index Record for writing and passes the data to the given write function.
+    /// See [`Record::write_with`].
+    pub(crate) fn write_with<F>(&mut self, write_function: F) -> Result<()>
+    where
+        F: FnOnce(&[u8]) -> Result<()>,
+    {
+        self.record.write_with(write_function)
+    }
 }
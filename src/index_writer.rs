@@ -0,0 +1,1033 @@
+// Copyright 2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::attribute::{
+    build_non_resident_attribute, build_resident_attribute, NtfsAttribute, NtfsAttributeFlags,
+    NtfsAttributeType,
+};
+use crate::cluster_allocator::NtfsClusterAllocator;
+use crate::collation::NtfsCollationRule;
+use crate::error::{NtfsError, Result};
+use crate::file::NtfsFile;
+use crate::index_entry::{
+    build_last_index_entry, raw_index_entry_flags, raw_index_entry_subnode_vcn,
+    set_index_entry_subnode_vcn, NtfsIndexEntry, NtfsIndexEntryFlags, NtfsIndexNodeEntries,
+};
+use crate::index_record::{NtfsIndexRecord, INDEX_NODE_HEADER_SIZE};
+use crate::indexes::NtfsIndexEntryType;
+use crate::runlist::NtfsRunlist;
+use crate::structured_values::{NtfsIndexAllocation, NtfsIndexRoot, INDEX_ROOT_HEADER_SIZE};
+use crate::traits::{NtfsReadSeek, NtfsWrite};
+use crate::types::Vcn;
+use alloc::vec::Vec;
+use binread::io::{Read, Seek, SeekFrom};
+use core::cmp::Ordering;
+use core::marker::PhantomData;
+use core::mem;
+use std::io::Write;
+
+/// The $BITMAP attribute of an index is always a multiple of 8 bytes long.
+const INDEX_BITMAP_ALIGNMENT: u64 = 8;
+
+/// A node of an index B-tree that has been loaded into memory for modification.
+struct IndexNode<'n> {
+    /// Raw Index Entries of this node, the last one being the terminating entry without a key.
+    entries: Vec<Vec<u8>>,
+    /// Absolute position of this node within the filesystem, used for error messages.
+    position: u64,
+    /// Index Record of this node, or `None` for the top-level node stored in the $INDEX_ROOT attribute.
+    record: Option<NtfsIndexRecord<'n>>,
+    /// Index of the entry whose subnode is the next node on the path from the top-level node.
+    child_index: usize,
+    /// Whether `entries` has been changed and needs to be written back.
+    dirty: bool,
+}
+
+impl<'n> IndexNode<'n> {
+    fn new(entries: Vec<Vec<u8>>, position: u64, record: Option<NtfsIndexRecord<'n>>) -> Self {
+        Self {
+            entries,
+            position,
+            record,
+            child_index: 0,
+            dirty: false,
+        }
+    }
+
+    /// Returns the number of bytes available for entries in the Index Record of this node.
+    fn capacity(&self) -> usize {
+        self.record.as_ref().unwrap().entries_capacity() as usize
+    }
+
+    fn size(&self) -> usize {
+        entries_size(&self.entries)
+    }
+
+    /// Returns the VCN of the Index Record of this node.
+    fn vcn(&self) -> Vcn {
+        self.record.as_ref().unwrap().vcn()
+    }
+}
+
+/// Modifies an index of a file while keeping it a valid B-tree that is sorted by the collation rule of the index.
+struct IndexWriter<'a, 'n, E, T> {
+    file: &'a mut NtfsFile<'n>,
+    fs: &'a mut T,
+    cluster_allocator: &'a mut NtfsClusterAllocator,
+    index_name: &'a str,
+    collation_rule: NtfsCollationRule,
+    index_record_size: u32,
+    index_root_position: u64,
+    entry_type: PhantomData<E>,
+}
+
+impl<'a, 'n, E, T> IndexWriter<'a, 'n, E, T>
+where
+    E: NtfsIndexEntryType,
+    T: Read + Write + Seek,
+{
+    fn new(
+        file: &'a mut NtfsFile<'n>,
+        fs: &'a mut T,
+        cluster_allocator: &'a mut NtfsClusterAllocator,
+        index_name: &'a str,
+    ) -> Result<Self> {
+        let index_root =
+            file.find_resident_attribute_structured_value::<NtfsIndexRoot>(Some(index_name))?;
+        let collation_rule = index_root.collation_rule()?;
+        let index_record_size = index_root.index_record_size();
+        let index_root_position = index_root.position();
+
+        // Index Records smaller than a cluster are addressed in units of 512-byte blocks.
+        // This is not supported, because Index Records are always addressed in units of clusters here.
+        let cluster_size = file.ntfs().cluster_size();
+        if index_record_size < cluster_size {
+            return Err(NtfsError::UnsupportedIndexRecordSize {
+                position: index_root_position,
+                index_record_size,
+                cluster_size,
+            });
+        }
+
+        Ok(Self {
+            file,
+            fs,
+            cluster_allocator,
+            index_name,
+            collation_rule,
+            index_record_size,
+            index_root_position,
+            entry_type: PhantomData,
+        })
+    }
+
+    /// Allocates a new Index Record and returns an empty node for it.
+    ///
+    /// The $INDEX_ALLOCATION attribute is extended if it has no unused Index Record left.
+    /// For a small index, the $INDEX_ALLOCATION and $BITMAP attributes are created first.
+    fn allocate_node(&mut self) -> Result<IndexNode<'n>> {
+        let ntfs = self.file.ntfs();
+        let index_record_size = self.index_record_size;
+
+        if index_attribute(
+            self.file,
+            NtfsAttributeType::IndexAllocation,
+            self.index_name,
+        )?
+        .is_none()
+        {
+            self.create_index_allocation()?;
+        }
+
+        let (allocation_offset, record_count) = {
+            let attribute = find_index_attribute(
+                self.file,
+                NtfsAttributeType::IndexAllocation,
+                self.index_name,
+            )?;
+            let record_count = attribute.value_length() / index_record_size as u64;
+            (attribute.offset(), record_count)
+        };
+
+        let bitmap = self.read_bitmap()?;
+        let is_used = |record_number: u64| {
+            matches!(
+                bitmap.get((record_number / 8) as usize),
+                Some(byte) if byte & (1 << (record_number % 8)) != 0
+            )
+        };
+        let record_number = (0..record_count)
+            .find(|&record_number| !is_used(record_number))
+            .unwrap_or(record_count);
+
+        if record_number == record_count {
+            self.file.set_attribute_len(
+                self.fs,
+                self.cluster_allocator,
+                NtfsClusterAllocator::allocate,
+                allocation_offset,
+                (record_count + 1) * index_record_size as u64,
+                true,
+            )?;
+        }
+
+        let bitmap_len = bitmap.len() as u64;
+        if record_number >= bitmap_len * 8 {
+            // Extending the $INDEX_ALLOCATION attribute may have moved the $BITMAP attribute.
+            let bitmap_offset =
+                find_index_attribute(self.file, NtfsAttributeType::Bitmap, self.index_name)?
+                    .offset();
+            self.file.set_attribute_len(
+                self.fs,
+                self.cluster_allocator,
+                NtfsClusterAllocator::allocate,
+                bitmap_offset,
+                bitmap_len + INDEX_BITMAP_ALIGNMENT,
+                true,
+            )?;
+        }
+
+        self.set_bitmap_bit(record_number, true)?;
+
+        let offset = record_number * index_record_size as u64;
+        let vcn = Vcn::from((offset / ntfs.cluster_size() as u64) as i64);
+        let record = self.with_index_allocation(|index_allocation, fs| {
+            index_allocation.new_record(fs, index_record_size, vcn)
+        })?;
+        let position = record.position();
+        let entries = vec![build_last_index_entry(None)];
+
+        Ok(IndexNode::new(entries, position, Some(record)))
+    }
+
+    /// Handles a changed node whose parent has no entry apart from the terminating one,
+    /// so that there is no sibling to merge with.
+    ///
+    /// If the parent is the top-level node and the entries fit into the $INDEX_ROOT attribute, they are moved up there.
+    /// Otherwise, only an empty leaf node is removed.
+    fn collapse(&mut self, parent: &mut IndexNode<'n>, node: &mut IndexNode<'n>) -> Result<()> {
+        let is_empty_leaf =
+            node.entries.len() == 1 && raw_index_entry_subnode_vcn(&node.entries[0]).is_none();
+
+        if parent.record.is_none() && self.index_root_fits(node.size())? {
+            parent.entries = mem::take(&mut node.entries);
+        } else if is_empty_leaf {
+            set_index_entry_subnode_vcn(&mut parent.entries[0], None);
+        } else {
+            return Ok(());
+        }
+
+        parent.dirty = true;
+        self.free_node(node)
+    }
+
+    /// Builds empty $INDEX_ALLOCATION and $BITMAP attributes for this index.
+    fn build_index_allocation_attributes(&self) -> [Vec<u8>; 2] {
+        let ntfs = self.file.ntfs();
+        let name = self
+            .index_name
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<u8>>();
+
+        let index_allocation = build_non_resident_attribute(
+            NtfsAttributeType::IndexAllocation as u32,
+            &name,
+            NtfsAttributeFlags::empty(),
+            0,
+            &NtfsRunlist::new(),
+            ntfs.cluster_size(),
+            0,
+            0,
+        );
+        let bitmap = build_resident_attribute(
+            NtfsAttributeType::Bitmap as u32,
+            &name,
+            NtfsAttributeFlags::empty(),
+            0,
+            &[0; INDEX_BITMAP_ALIGNMENT as usize],
+            false,
+        );
+
+        [index_allocation, bitmap]
+    }
+
+    /// Adds empty $INDEX_ALLOCATION and $BITMAP attributes for this index to the File Record
+    /// and writes it back to the filesystem.
+    fn create_index_allocation(&mut self) -> Result<()> {
+        for attribute in self.build_index_allocation_attributes() {
+            self.file.insert_attribute(attribute)?;
+        }
+
+        self.file.write_record(self.fs)
+    }
+
+    /// Returns the index of the first entry of `node` whose key is not less than `key` (or of the terminating entry),
+    /// along with the result of comparing `key` to the key of that entry.
+    fn find_in_node(&self, node: &IndexNode, key: &[u8]) -> Result<(usize, Ordering)> {
+        let ntfs = self.file.ntfs();
+
+        for (index, entry) in node.entries.iter().enumerate() {
+            let entry = NtfsIndexEntry::<E>::new(entry, node.position)?;
+
+            if let Some(entry_key) = entry.key_slice() {
+                let ordering = self.collation_rule.compare(ntfs, key, entry_key?);
+                if ordering == Ordering::Greater {
+                    continue;
+                }
+
+                return Ok((index, ordering));
+            }
+
+            return Ok((index, Ordering::Less));
+        }
+
+        Ok((node.entries.len() - 1, Ordering::Less))
+    }
+
+    /// Marks the Index Record of the given node as unused in the $BITMAP attribute, so that it can be allocated again.
+    fn free_node(&mut self, node: &mut IndexNode<'n>) -> Result<()> {
+        let offset = node.vcn().offset(self.file.ntfs())? as u64;
+        let record_number = offset / self.index_record_size as u64;
+
+        node.entries.clear();
+        node.dirty = false;
+
+        self.set_bitmap_bit(record_number, false)
+    }
+
+    /// Returns whether an $INDEX_ROOT attribute with entries of the given total size fits into the File Record.
+    fn index_root_fits(&self, entries_size: usize) -> Result<bool> {
+        let attribute =
+            find_index_attribute(self.file, NtfsAttributeType::IndexRoot, self.index_name)?;
+        let value_length = INDEX_ROOT_HEADER_SIZE + INDEX_NODE_HEADER_SIZE + entries_size;
+        let attribute_length = attribute
+            .to_resized_resident_bytes(value_length as u32)?
+            .len();
+
+        let mut data_size = self.file.data_size() as usize - attribute.attribute_length() as usize
+            + attribute_length;
+
+        // A small index must leave room for the attributes that turn it into a large one.
+        if index_attribute(
+            self.file,
+            NtfsAttributeType::IndexAllocation,
+            self.index_name,
+        )?
+        .is_none()
+        {
+            data_size += self
+                .build_index_allocation_attributes()
+                .iter()
+                .map(Vec::len)
+                .sum::<usize>();
+        }
+
+        Ok(data_size <= self.file.allocated_size() as usize)
+    }
+
+    fn insert(&mut self, entry: &[u8]) -> Result<()> {
+        let new_entry = NtfsIndexEntry::<E>::new(entry, 0)?;
+        let key = match new_entry.key_slice() {
+            Some(key) => key?,
+            None => &[],
+        };
+
+        // Descend to the leaf node where the key belongs.
+        let mut path = vec![self.read_root()?];
+
+        loop {
+            let node = path.last_mut().unwrap();
+            let (index, ordering) = self.find_in_node(node, key)?;
+            if ordering == Ordering::Equal {
+                return Err(NtfsError::DuplicateIndexEntry {
+                    position: node.position,
+                });
+            }
+
+            node.child_index = index;
+
+            match raw_index_entry_subnode_vcn(&node.entries[index]) {
+                Some(vcn) => {
+                    let subnode = self.read_node(vcn)?;
+                    path.push(subnode);
+                }
+                None => break,
+            }
+        }
+
+        let leaf = path.last_mut().unwrap();
+        leaf.entries.insert(leaf.child_index, entry.to_vec());
+        leaf.dirty = true;
+
+        self.rebalance(path)
+    }
+
+    /// Merges a changed node, which uses less than half of its Index Record, with an adjacent sibling
+    /// if both fit into a single Index Record.
+    ///
+    /// An empty node that cannot be merged takes over entries from its sibling instead, so that no empty nodes remain.
+    fn merge(&mut self, parent: &mut IndexNode<'n>, node: &mut IndexNode<'n>) -> Result<()> {
+        let child_index = parent.child_index;
+        let node_is_left = child_index + 1 < parent.entries.len();
+        let (separator_index, sibling_index) = if node_is_left {
+            (child_index, child_index + 1)
+        } else {
+            (child_index - 1, child_index - 1)
+        };
+
+        let sibling_vcn = match raw_index_entry_subnode_vcn(&parent.entries[sibling_index]) {
+            Some(vcn) => vcn,
+            None => return Ok(()),
+        };
+        let node_is_empty = node.entries.len() == 1;
+
+        let mut sibling = self.read_node(sibling_vcn)?;
+        let (left, right) = if node_is_left {
+            (node, &mut sibling)
+        } else {
+            (&mut sibling, node)
+        };
+
+        // Combine the entries of both nodes and the separating entry of the parent into a single sorted list.
+        // The separator takes over the subnode of the terminating entry of the left node.
+        let (left_last_entry, left_entries) = left.entries.split_last().unwrap();
+        let mut separator = parent.entries[separator_index].clone();
+        set_index_entry_subnode_vcn(&mut separator, raw_index_entry_subnode_vcn(left_last_entry));
+
+        let mut entries = left_entries.to_vec();
+        entries.push(separator);
+        entries.extend_from_slice(&right.entries);
+
+        if entries_size(&entries) <= right.capacity() {
+            // Keep the right node, because the parent entry following the separator already points to it.
+            right.entries = entries;
+            right.dirty = true;
+            self.free_node(left)?;
+            parent.entries.remove(separator_index);
+        } else if node_is_empty {
+            let (left_entries, mut median, right_entries) = split_entries(entries);
+            left.entries = left_entries;
+            left.dirty = true;
+            right.entries = right_entries;
+            right.dirty = true;
+
+            set_index_entry_subnode_vcn(&mut median, Some(left.vcn()));
+            parent.entries[separator_index] = median;
+        } else {
+            return Ok(());
+        }
+
+        parent.dirty = true;
+
+        if sibling.dirty {
+            self.write_node(&mut sibling)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the Index Record at the given VCN into a node.
+    fn read_node(&mut self, vcn: Vcn) -> Result<IndexNode<'n>> {
+        let index_record_size = self.index_record_size;
+        let record = self.with_index_allocation(|index_allocation, fs| {
+            index_allocation.record_from_vcn(fs, index_record_size, vcn)
+        })?;
+        let entries = collect_entries(record.entries::<E>()?)?;
+        let position = record.position();
+
+        Ok(IndexNode::new(entries, position, Some(record)))
+    }
+
+    /// Reads the top-level node from the $INDEX_ROOT attribute.
+    fn read_root(&self) -> Result<IndexNode<'n>> {
+        let index_root = self
+            .file
+            .find_resident_attribute_structured_value::<NtfsIndexRoot>(Some(self.index_name))?;
+        let entries = collect_entries(index_root.entries::<E>()?)?;
+
+        Ok(IndexNode::new(entries, index_root.position(), None))
+    }
+
+    /// Reads the entire value of the $BITMAP attribute of this index, which has a bit for every Index Record.
+    fn read_bitmap(&mut self) -> Result<Vec<u8>> {
+        let attribute =
+            find_index_attribute(self.file, NtfsAttributeType::Bitmap, self.index_name)?;
+        let mut bitmap = vec![0u8; attribute.value_length() as usize];
+        attribute.value()?.read_exact(self.fs, &mut bitmap)?;
+
+        Ok(bitmap)
+    }
+
+    /// Restores the B-tree properties along the given path from the top-level node after its nodes have been changed
+    /// in memory, and writes all changed nodes back to the filesystem.
+    ///
+    /// The nodes are processed bottom-up, because fixing a node may change its parent.
+    fn rebalance(&mut self, mut path: Vec<IndexNode<'n>>) -> Result<()> {
+        for depth in (1..path.len()).rev() {
+            let (parents, nodes) = path.split_at_mut(depth);
+            let parent = parents.last_mut().unwrap();
+            let node = &mut nodes[0];
+
+            if !node.dirty {
+                continue;
+            }
+
+            if node.size() > node.capacity() {
+                self.split(parent, node)?;
+            } else if parent.entries.len() == 1 {
+                self.collapse(parent, node)?;
+            } else if node.size() < node.capacity() / 2 {
+                self.merge(parent, node)?;
+            }
+
+            if node.dirty {
+                self.write_node(node)?;
+            }
+        }
+
+        self.write_root(&mut path[0])
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Result<()> {
+        // Descend to the node that contains the key.
+        let mut path = vec![self.read_root()?];
+
+        loop {
+            let node = path.last_mut().unwrap();
+            let (index, ordering) = self.find_in_node(node, key)?;
+            node.child_index = index;
+
+            if ordering == Ordering::Equal {
+                break;
+            }
+
+            match raw_index_entry_subnode_vcn(&node.entries[index]) {
+                Some(vcn) => {
+                    let subnode = self.read_node(vcn)?;
+                    path.push(subnode);
+                }
+                None => {
+                    return Err(NtfsError::IndexEntryNotFound {
+                        position: self.index_root_position,
+                    })
+                }
+            }
+        }
+
+        let depth = path.len() - 1;
+        let index = path[depth].child_index;
+
+        if let Some(subnode_vcn) = raw_index_entry_subnode_vcn(&path[depth].entries[index]) {
+            // Replace the entry by its in-order predecessor, which is the greatest entry of the subtree left of it.
+            // That entry is always found in a leaf node.
+            let mut vcn = subnode_vcn;
+
+            loop {
+                let mut node = self.read_node(vcn)?;
+                node.child_index = node.entries.len() - 1;
+                let next_vcn = raw_index_entry_subnode_vcn(&node.entries[node.child_index]);
+                path.push(node);
+
+                match next_vcn {
+                    Some(next_vcn) => vcn = next_vcn,
+                    None => break,
+                }
+            }
+
+            let leaf = path.last_mut().unwrap();
+            if leaf.entries.len() < 2 {
+                return Err(NtfsError::EmptyIndexRecord {
+                    position: leaf.position,
+                });
+            }
+
+            let mut predecessor = leaf.entries.remove(leaf.entries.len() - 2);
+            leaf.dirty = true;
+
+            set_index_entry_subnode_vcn(&mut predecessor, Some(subnode_vcn));
+            path[depth].entries[index] = predecessor;
+        } else {
+            path[depth].entries.remove(index);
+        }
+
+        path[depth].dirty = true;
+        self.rebalance(path)
+    }
+
+    /// Marks the given Index Record as used or unused in the $BITMAP attribute of this index.
+    fn set_bitmap_bit(&mut self, record_number: u64, used: bool) -> Result<()> {
+        let byte_offset = record_number / 8;
+        let bit = 1 << (record_number % 8);
+
+        let attribute =
+            find_index_attribute(self.file, NtfsAttributeType::Bitmap, self.index_name)?;
+        if attribute.is_resident() {
+            // Change the value in memory, so that the next write of the File Record doesn't revert it.
+            let value_position = attribute.resident_value()?.data_position().unwrap();
+            let offset = (value_position - self.file.position() + byte_offset) as usize;
+
+            let byte = &mut self.file.record_data_mut()[offset];
+            if used {
+                *byte |= bit;
+            } else {
+                *byte &= !bit;
+            }
+
+            return self.file.write_record(self.fs);
+        }
+
+        let mut value = attribute.value()?;
+        let mut byte = [0u8];
+        value.seek(self.fs, SeekFrom::Start(byte_offset))?;
+        value.read_exact(self.fs, &mut byte)?;
+
+        if used {
+            byte[0] |= bit;
+        } else {
+            byte[0] &= !bit;
+        }
+
+        value.seek(self.fs, SeekFrom::Start(byte_offset))?;
+        value.write_all(self.fs, &byte)
+    }
+
+    /// Splits a node that exceeds its Index Record into two halves and moves the median entry up into the parent.
+    ///
+    /// The node keeps the right half, because the parent entry pointing to it comes after the median.
+    /// The left half is moved into a newly allocated Index Record.
+    fn split(&mut self, parent: &mut IndexNode<'n>, node: &mut IndexNode<'n>) -> Result<()> {
+        let (left_entries, mut median, right_entries) = split_entries(mem::take(&mut node.entries));
+        node.entries = right_entries;
+
+        let mut left = self.allocate_node()?;
+        left.entries = left_entries;
+        self.write_node(&mut left)?;
+
+        set_index_entry_subnode_vcn(&mut median, Some(left.vcn()));
+        parent.entries.insert(parent.child_index, median);
+        parent.dirty = true;
+
+        Ok(())
+    }
+
+    /// Calls the given function with the $INDEX_ALLOCATION attribute of this index.
+    fn with_index_allocation<F, R>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&NtfsIndexAllocation<'n, '_>, &mut T) -> Result<R>,
+    {
+        let item = self.file.find_attribute(
+            self.fs,
+            NtfsAttributeType::IndexAllocation,
+            Some(self.index_name),
+        )?;
+        let attribute = item.to_attribute();
+        let index_allocation = attribute.structured_value::<_, NtfsIndexAllocation>(self.fs)?;
+
+        f(&index_allocation, self.fs)
+    }
+
+    /// Writes the entries of the given node back to its Index Record.
+    fn write_node(&mut self, node: &mut IndexNode<'n>) -> Result<()> {
+        let record = node.record.as_mut().unwrap();
+        record.set_entries(&node.entries)?;
+        self.with_index_allocation(|index_allocation, fs| {
+            index_allocation.write_record(fs, record)
+        })?;
+        node.dirty = false;
+
+        Ok(())
+    }
+
+    /// Writes the top-level node back to the $INDEX_ROOT attribute if it has been changed.
+    ///
+    /// If its entries don't fit into the File Record, they are moved into a new Index Record, which becomes
+    /// the only subnode of the top-level node (turning a small index into a large one).
+    fn write_root(&mut self, root: &mut IndexNode<'n>) -> Result<()> {
+        if !root.dirty {
+            return Ok(());
+        }
+
+        if !self.index_root_fits(root.size())? {
+            let mut node = self.allocate_node()?;
+            let last_entry = build_last_index_entry(Some(node.vcn()));
+            node.entries = mem::replace(&mut root.entries, vec![last_entry]);
+
+            if node.size() > node.capacity() {
+                root.child_index = 0;
+                self.split(root, &mut node)?;
+            }
+
+            self.write_node(&mut node)?;
+        }
+
+        let attribute =
+            find_index_attribute(self.file, NtfsAttributeType::IndexRoot, self.index_name)?;
+        let offset = attribute.offset();
+        let index_root = attribute.resident_structured_value::<NtfsIndexRoot>()?;
+        let value = index_root.value_with_entries(&root.entries);
+        let bytes = attribute.to_resident_bytes_with_value(&value)?;
+
+        self.file.replace_attribute(offset, &bytes)?;
+        self.file.write_record(self.fs)
+    }
+}
+
+/// Collects the raw bytes of all entries of an index node, making sure that they end with a terminating entry.
+fn collect_entries<E>(entries: NtfsIndexNodeEntries<E>) -> Result<Vec<Vec<u8>>>
+where
+    E: NtfsIndexEntryType,
+{
+    let mut raw_entries = Vec::new();
+
+    for entry in entries {
+        let entry = entry?;
+
+        // Validate the subnode VCN, so that it can be read from the raw bytes later.
+        entry.subnode_vcn().transpose()?;
+        raw_entries.push(entry.slice().to_vec());
+    }
+
+    let has_last_entry = matches!(
+        raw_entries.last(),
+        Some(entry) if raw_index_entry_flags(entry).contains(NtfsIndexEntryFlags::LAST_ENTRY)
+    );
+    if !has_last_entry {
+        raw_entries.push(build_last_index_entry(None));
+    }
+
+    Ok(raw_entries)
+}
+
+fn entries_size(entries: &[Vec<u8>]) -> usize {
+    entries.iter().map(Vec::len).sum()
+}
+
+/// Returns the top-level attribute of `file` with the given type that belongs to the index with the given name.
+fn find_index_attribute<'n, 'f>(
+    file: &'f NtfsFile<'n>,
+    ty: NtfsAttributeType,
+    index_name: &str,
+) -> Result<NtfsAttribute<'n, 'f>> {
+    index_attribute(file, ty, index_name)?.ok_or(NtfsError::AttributeNotFound {
+        position: file.position(),
+        ty,
+    })
+}
+
+/// Returns the top-level attribute of `file` with the given type that belongs to the index with the given name,
+/// or `None` if there is no such attribute.
+fn index_attribute<'n, 'f>(
+    file: &'f NtfsFile<'n>,
+    ty: NtfsAttributeType,
+    index_name: &str,
+) -> Result<Option<NtfsAttribute<'n, 'f>>> {
+    for attribute in file.attributes_raw() {
+        if attribute.ty()? == ty && attribute.name()? == index_name {
+            return Ok(Some(attribute));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Inserts the raw Index Entry `entry` into the index with the given name (e.g. "$I30") of `file`,
+/// keeping all entries sorted according to the collation rule of the index.
+///
+/// Nodes that become too large are split.
+/// If the top-level entries no longer fit into the File Record, they are moved into an Index Record,
+/// turning a small index into a large one.
+/// Index Records are allocated via the $BITMAP attribute of the index, and new clusters via the given
+/// [`NtfsClusterAllocator`].
+///
+/// Returns [`NtfsError::DuplicateIndexEntry`] if the index already contains an entry with the same key.
+///
+/// All changed Index Records and the File Record are written back to the filesystem.
+///
+/// # Panics
+///
+/// Panics if the index uses a case-insensitive collation rule and
+/// [`read_upcase_table`][crate::Ntfs::read_upcase_table] had not been called on the [`Ntfs`][crate::Ntfs]
+/// object of `file`.
+pub(crate) fn insert_index_entry<E, T>(
+    file: &mut NtfsFile,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    index_name: &str,
+    entry: &[u8],
+) -> Result<()>
+where
+    E: NtfsIndexEntryType,
+    T: Read + Write + Seek,
+{
+    IndexWriter::<E, T>::new(file, fs, cluster_allocator, index_name)?.insert(entry)
+}
+
+/// Removes the Index Entry with the given key from the index with the given name (e.g. "$I30") of `file`.
+///
+/// Nodes that become less than half full are merged with a sibling where possible, and Index Records that
+/// are no longer needed are marked as unused in the $BITMAP attribute of the index.
+/// If all entries fit into the File Record again, they are moved back into the $INDEX_ROOT attribute.
+///
+/// Returns [`NtfsError::IndexEntryNotFound`] if the index contains no entry with the given key.
+///
+/// All changed Index Records and the File Record are written back to the filesystem.
+///
+/// # Panics
+///
+/// Panics if the index uses a case-insensitive collation rule and
+/// [`read_upcase_table`][crate::Ntfs::read_upcase_table] had not been called on the [`Ntfs`][crate::Ntfs]
+/// object of `file`.
+pub(crate) fn remove_index_entry<E, T>(
+    file: &mut NtfsFile,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    index_name: &str,
+    key: &[u8],
+) -> Result<()>
+where
+    E: NtfsIndexEntryType,
+    T: Read + Write + Seek,
+{
+    IndexWriter::<E, T>::new(file, fs, cluster_allocator, index_name)?.remove(key)
+}
+
+/// Splits the given raw Index Entries of a node into a left half, a median entry, and a right half of roughly
+/// equal size.
+///
+/// The left half gets a new terminating entry, which takes over the subnode of the median.
+/// The subnode of the returned median is removed.
+fn split_entries(mut entries: Vec<Vec<u8>>) -> (Vec<Vec<u8>>, Vec<u8>, Vec<Vec<u8>>) {
+    debug_assert!(entries.len() >= 3);
+
+    // Keep at least one entry on the left and the terminating entry on the right.
+    let half_size = entries_size(&entries) / 2;
+    let mut size = 0;
+    let mut median_index = entries.len() - 2;
+
+    for (index, entry) in entries.iter().enumerate() {
+        size += entry.len();
+        if size > half_size {
+            median_index = index;
+            break;
+        }
+    }
+
+    let median_index = median_index.clamp(1, entries.len() - 2);
+    let right_entries = entries.split_off(median_index + 1);
+    let mut median = entries.pop().unwrap();
+
+    entries.push(build_last_index_entry(raw_index_entry_subnode_vcn(&median)));
+    set_index_entry_subnode_vcn(&mut median, None);
+
+    (entries, median, right_entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_reference::NtfsFileReference;
+    use crate::format::NtfsFormatOptions;
+    use crate::index_entry::build_file_reference_index_entry;
+    use crate::indexes::NtfsFileNameIndex;
+    use crate::ntfs::Ntfs;
+    use crate::structured_values::{build_file_name, NtfsFileAttributeFlags, NtfsFileNamespace};
+    use crate::time::NtfsTime;
+    use std::io::Cursor;
+
+    fn file_name(parent_reference: NtfsFileReference, name: &str) -> Vec<u8> {
+        let name = name.encode_utf16().collect::<Vec<u16>>();
+        build_file_name(
+            parent_reference,
+            NtfsTime::from(0),
+            NtfsFileAttributeFlags::ARCHIVE,
+            NtfsFileNamespace::Posix,
+            &name,
+        )
+    }
+
+    /// Checks that the $I30 index of the given directory contains exactly the given names in the right order,
+    /// and returns the value of its $BITMAP attribute (if any).
+    fn check_directory(
+        ntfs: &Ntfs,
+        fs: &mut Cursor<Vec<u8>>,
+        file_record_number: u64,
+        names: &[String],
+    ) -> Option<Vec<u8>> {
+        let dir = ntfs.file(fs, file_record_number).unwrap();
+        let dir_index = dir.directory_index(fs).unwrap();
+        dir_index.check_ordering(ntfs, fs).unwrap();
+
+        let mut entry_count = 0;
+        let mut iter = dir_index.entries();
+        while let Some(entry) = iter.next(fs) {
+            entry.unwrap();
+            entry_count += 1;
+        }
+        assert_eq!(entry_count, names.len());
+
+        let mut finder = dir_index.finder();
+        for name in names {
+            let entry = NtfsFileNameIndex::find(&mut finder, ntfs, fs, name)
+                .unwrap()
+                .unwrap();
+            assert_eq!(entry.key().unwrap().unwrap().name(), name.as_str());
+        }
+
+        let bitmap = index_attribute(&dir, NtfsAttributeType::Bitmap, "$I30").unwrap()?;
+        Some(bitmap.resident_value().unwrap().data().to_vec())
+    }
+
+    #[test]
+    fn test_insert_and_remove_index_entries() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+
+        let mut dir = ntfs
+            .create_dir(&mut testfs1, &mut cluster_allocator, &mut root_dir, "btree")
+            .unwrap();
+        let dir_reference = dir.file_reference();
+        let dir_record_number = dir.file_record_number();
+
+        // Insert the entries in a scrambled order, so that all nodes are split at some point.
+        // 7 and 400 are coprime, so this yields every name exactly once.
+        let names = (0..400)
+            .map(|i| format!("A file with a rather long name {:03}.txt", i * 7 % 400))
+            .collect::<Vec<String>>();
+
+        for name in &names {
+            let entry = build_file_reference_index_entry(
+                dir_reference,
+                &file_name(dir_reference, name),
+                None,
+            );
+            insert_index_entry::<NtfsFileNameIndex, _>(
+                &mut dir,
+                &mut testfs1,
+                &mut cluster_allocator,
+                "$I30",
+                &entry,
+            )
+            .unwrap();
+        }
+
+        // This has turned the small index into a large one with several Index Records.
+        let bitmap = check_directory(&ntfs, &mut testfs1, dir_record_number, &names).unwrap();
+        let used_record_count = bitmap.iter().map(|byte| byte.count_ones()).sum::<u32>();
+        assert!(used_record_count > 10);
+
+        let index_root = dir
+            .find_resident_attribute_structured_value::<NtfsIndexRoot>(Some("$I30"))
+            .unwrap();
+        assert!(index_root.is_large_index());
+
+        // Inserting a name again must fail.
+        let entry = build_file_reference_index_entry(
+            dir_reference,
+            &file_name(dir_reference, &names[123]),
+            None,
+        );
+        assert!(matches!(
+            insert_index_entry::<NtfsFileNameIndex, _>(
+                &mut dir,
+                &mut testfs1,
+                &mut cluster_allocator,
+                "$I30",
+                &entry,
+            ),
+            Err(NtfsError::DuplicateIndexEntry { .. })
+        ));
+
+        // Remove every second entry, which merges nodes.
+        let mut kept_names = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            if i % 2 == 0 {
+                remove_index_entry::<NtfsFileNameIndex, _>(
+                    &mut dir,
+                    &mut testfs1,
+                    &mut cluster_allocator,
+                    "$I30",
+                    &file_name(dir_reference, name),
+                )
+                .unwrap();
+            } else {
+                kept_names.push(name.clone());
+            }
+        }
+
+        let bitmap = check_directory(&ntfs, &mut testfs1, dir_record_number, &kept_names).unwrap();
+        let kept_record_count = bitmap.iter().map(|byte| byte.count_ones()).sum::<u32>();
+        assert!(kept_record_count < used_record_count);
+
+        // Removing a name that doesn't exist (anymore) must fail.
+        assert!(matches!(
+            remove_index_entry::<NtfsFileNameIndex, _>(
+                &mut dir,
+                &mut testfs1,
+                &mut cluster_allocator,
+                "$I30",
+                &file_name(dir_reference, &names[0]),
+            ),
+            Err(NtfsError::IndexEntryNotFound { .. })
+        ));
+
+        // Remove the remaining entries, which moves them back into the $INDEX_ROOT attribute at some point
+        // and frees all Index Records.
+        for name in kept_names.iter().rev() {
+            remove_index_entry::<NtfsFileNameIndex, _>(
+                &mut dir,
+                &mut testfs1,
+                &mut cluster_allocator,
+                "$I30",
+                &file_name(dir_reference, name),
+            )
+            .unwrap();
+        }
+
+        let bitmap = check_directory(&ntfs, &mut testfs1, dir_record_number, &[]).unwrap();
+        assert!(bitmap.iter().all(|&byte| byte == 0));
+
+        let index_root = dir
+            .find_resident_attribute_structured_value::<NtfsIndexRoot>(Some("$I30"))
+            .unwrap();
+        assert!(!index_root.is_large_index());
+    }
+    #[test]
+    fn test_index_records_smaller_than_clusters() {
+        let mut fs = Cursor::new(vec![0u8; 8 * 1024 * 1024]);
+        let mut ntfs = Ntfs::format(&mut fs, NtfsFormatOptions::new().cluster_size(4096)).unwrap();
+        ntfs.read_upcase_table(&mut fs).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut fs).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut fs).unwrap();
+        let mut dir = ntfs
+            .create_dir(&mut fs, &mut cluster_allocator, &mut root_dir, "dir")
+            .unwrap();
+
+        // Shrink the Index Records of the new directory below the cluster size,
+        // as done by Windows for volumes with 64 KiB clusters.
+        let attribute = dir
+            .attributes_raw()
+            .find(|attribute| matches!(attribute.ty(), Ok(NtfsAttributeType::IndexRoot)))
+            .unwrap();
+        let start = attribute.offset() + attribute.resident_value_offset() as usize;
+        let record_data = dir.record_data_mut();
+        record_data[start + 8..start + 12].copy_from_slice(&1024u32.to_le_bytes());
+        // The size of Index Records smaller than a cluster is given in 512-byte blocks.
+        record_data[start + 12] = 2;
+        dir.write_record(&mut fs).unwrap();
+
+        // Inserting an entry must be refused instead of overwriting Index Records that share a VCN.
+        let mut dir = ntfs.file(&mut fs, dir.file_record_number()).unwrap();
+        assert!(matches!(
+            ntfs.create_file(&mut fs, &mut cluster_allocator, &mut dir, "file"),
+            Err(NtfsError::UnsupportedIndexRecordSize {
+                index_record_size: 1024,
+                cluster_size: 4096,
+                ..
+            })
+        ));
+    }
+}
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use crate::error::Result;
-use crate::index::NtfsIndexFinder;
+use crate::index::{NtfsIndexCursor, NtfsIndexFinder};
 use crate::index_entry::NtfsIndexEntry;
 use crate::indexes::{NtfsIndexEntryHasFileReference, NtfsIndexEntryType};
 use crate::ntfs::Ntfs;
@@ -21,9 +21,9 @@ impl NtfsFileNameIndex {
     /// # Panics
     ///
     /// Panics if [`read_upcase_table`][Ntfs::read_upcase_table] had not been called on the passed [`Ntfs`] object.
-    pub fn find<'a, 'n, T>(
+    pub fn find<'a, T>(
         index_finder: &'a mut NtfsIndexFinder<Self>,
-        ntfs: &'n Ntfs,
+        ntfs: &Ntfs,
         fs: &mut T,
         name: &str,
     ) -> Option<Result<NtfsIndexEntry<'a, Self>>>
@@ -34,6 +34,27 @@ impl NtfsFileNameIndex {
         // There are some corner cases where NTFS uses case-sensitive filenames. These need to be considered!
         index_finder.find(fs, |file_name| name.upcase_cmp(ntfs, &file_name.name()))
     }
+
+    /// Positions the cursor at the first file whose name is greater than or equal to the given name
+    /// and returns the [`NtfsIndexEntry`] (if any).
+    /// The name is compared case-insensitively based on the filesystem's $UpCase table.
+    ///
+    /// This is the starting point for listing all files sharing a common prefix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`read_upcase_table`][Ntfs::read_upcase_table] had not been called on the passed [`Ntfs`] object.
+    pub fn seek<'a, T>(
+        index_cursor: &'a mut NtfsIndexCursor<Self>,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        name: &str,
+    ) -> Option<Result<NtfsIndexEntry<'a, Self>>>
+    where
+        T: Read + Seek,
+    {
+        index_cursor.seek(fs, |file_name| name.upcase_cmp(ntfs, &file_name.name()))
+    }
 }
 
 impl NtfsIndexEntryType for NtfsFileNameIndex {
@@ -15,8 +15,16 @@
 //! [`NtfsIndexRoot`]: crate::structured_values::NtfsIndexRoot
 
 mod file_name;
+mod object_id;
+mod quota;
+mod reparse;
+mod security;
 
 pub use file_name::*;
+pub use object_id::*;
+pub use quota::*;
+pub use reparse::*;
+pub use security::*;
 
 use crate::error::Result;
 use core::fmt;
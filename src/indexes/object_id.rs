@@ -0,0 +1,100 @@
+// Copyright 2021-2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::error::{NtfsError, Result};
+use crate::file_reference::NtfsFileReference;
+use crate::guid::{NtfsGuid, GUID_SIZE};
+use crate::index::NtfsIndexFinder;
+use crate::index_entry::NtfsIndexEntry;
+use crate::indexes::{NtfsIndexEntryData, NtfsIndexEntryHasData, NtfsIndexEntryType};
+use crate::ntfs::Ntfs;
+use binread::io::{Cursor, Read, Seek};
+use binread::{BinRead, BinReaderExt};
+use core::mem;
+
+/// Size of all [`NtfsObjectIdIndexData`] fields.
+pub(crate) const OBJECT_ID_INDEX_DATA_SIZE: usize = mem::size_of::<u64>() + 3 * GUID_SIZE;
+
+/// Defines the [`NtfsIndexEntryType`] for the $O index of the $Extend\$ObjId file.
+///
+/// This index maps the Object IDs of all files on the volume to the files they are assigned to.
+/// Check out [`Ntfs::file_by_object_id`] to comfortably look up a file by its Object ID.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/objid.html>
+#[derive(Clone, Copy, Debug)]
+pub struct NtfsObjectIdIndex;
+
+impl NtfsObjectIdIndex {
+    /// Finds an Object ID in an Object ID index and returns the [`NtfsIndexEntry`] (if any).
+    pub fn find<'a, T>(
+        index_finder: &'a mut NtfsIndexFinder<Self>,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        object_id: &NtfsGuid,
+    ) -> Option<Result<NtfsIndexEntry<'a, Self>>>
+    where
+        T: Read + Seek,
+    {
+        index_finder.find_key(ntfs, fs, &object_id.to_bytes())
+    }
+}
+
+impl NtfsIndexEntryType for NtfsObjectIdIndex {
+    type KeyType = NtfsGuid;
+}
+
+impl NtfsIndexEntryHasData for NtfsObjectIdIndex {
+    type DataType = NtfsObjectIdIndexData;
+}
+
+/// Data of an [`NtfsObjectIdIndex`] entry.
+///
+/// Apart from the file the Object ID is assigned to, this contains the same birth information as
+/// the $OBJECT_ID attribute of that file (see [`NtfsObjectId`]).
+///
+/// [`NtfsObjectId`]: crate::structured_values::NtfsObjectId
+#[derive(BinRead, Clone, Debug)]
+pub struct NtfsObjectIdIndexData {
+    file_reference: NtfsFileReference,
+    birth_volume_id: NtfsGuid,
+    birth_object_id: NtfsGuid,
+    domain_id: NtfsGuid,
+}
+
+impl NtfsObjectIdIndexData {
+    /// Returns the first Object ID that has ever been assigned to the file.
+    pub fn birth_object_id(&self) -> &NtfsGuid {
+        &self.birth_object_id
+    }
+
+    /// Returns the Object ID of the $Volume file of the partition where the file was created.
+    pub fn birth_volume_id(&self) -> &NtfsGuid {
+        &self.birth_volume_id
+    }
+
+    /// Returns the Domain ID of the file.
+    pub fn domain_id(&self) -> &NtfsGuid {
+        &self.domain_id
+    }
+
+    /// Returns an [`NtfsFileReference`] for the file the Object ID is assigned to.
+    pub fn file_reference(&self) -> NtfsFileReference {
+        self.file_reference
+    }
+}
+
+impl NtfsIndexEntryData for NtfsObjectIdIndexData {
+    fn data_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        if slice.len() < OBJECT_ID_INDEX_DATA_SIZE {
+            return Err(NtfsError::InvalidIndexEntryDataSize {
+                position,
+                expected: OBJECT_ID_INDEX_DATA_SIZE as u16,
+                actual: slice.len() as u16,
+            });
+        }
+
+        let mut cursor = Cursor::new(slice);
+        let data = cursor.read_le::<Self>()?;
+        Ok(data)
+    }
+}
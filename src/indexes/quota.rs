@@ -0,0 +1,254 @@
+// Copyright 2021-2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::error::{NtfsError, Result};
+use crate::index::NtfsIndexFinder;
+use crate::index_entry::NtfsIndexEntry;
+use crate::indexes::{
+    NtfsIndexEntryData, NtfsIndexEntryHasData, NtfsIndexEntryKey, NtfsIndexEntryType,
+};
+use crate::ntfs::Ntfs;
+use crate::sid::NtfsSid;
+use crate::time::NtfsTime;
+use binread::io::{Cursor, Read, Seek};
+use binread::{BinRead, BinReaderExt};
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
+use core::mem;
+
+/// Size of all [`QuotaControlEntryHeader`] fields.
+const QUOTA_CONTROL_ENTRY_HEADER_SIZE: usize = 48;
+
+#[allow(unused)]
+#[derive(BinRead, Clone, Debug)]
+struct QuotaControlEntryHeader {
+    version: u32,
+    flags: u32,
+    bytes_used: u64,
+    change_time: NtfsTime,
+    threshold: i64,
+    limit: i64,
+    exceeded_time: NtfsTime,
+}
+
+bitflags! {
+    /// Flags returned by [`NtfsQuotaControlEntry::flags`].
+    pub struct NtfsQuotaFlags: u32 {
+        /// The entry uses the default limits of the volume.
+        const DEFAULT_LIMITS = 0x0000_0001;
+        /// The owner has reached the quota limit.
+        const LIMIT_REACHED = 0x0000_0002;
+        /// The owner has been deleted.
+        const ID_DELETED = 0x0000_0004;
+        /// Quota usage is tracked (only set in the default entry).
+        const TRACKING_ENABLED = 0x0000_0010;
+        /// Quota limits are enforced (only set in the default entry).
+        const ENFORCEMENT_ENABLED = 0x0000_0020;
+        /// Quota tracking has been requested (only set in the default entry).
+        const TRACKING_REQUESTED = 0x0000_0040;
+        /// Exceeding the threshold is logged (only set in the default entry).
+        const LOG_THRESHOLD = 0x0000_0080;
+        /// Exceeding the limit is logged (only set in the default entry).
+        const LOG_LIMIT = 0x0000_0100;
+        /// The quota usage information is out of date (only set in the default entry).
+        const OUT_OF_DATE = 0x0000_0200;
+        /// The quota information is corrupt (only set in the default entry).
+        const CORRUPT = 0x0000_0400;
+        /// There are pending deletions of owners (only set in the default entry).
+        const PENDING_DELETES = 0x0000_0800;
+    }
+}
+
+/// Identifier of an owner of files, as used by the $Quota file and returned by
+/// [`NtfsStandardInformation::owner_id`].
+///
+/// This is the key of [`NtfsQuotaControlIndex`] and the data of [`NtfsQuotaOwnerIndex`].
+///
+/// [`NtfsStandardInformation::owner_id`]: crate::structured_values::NtfsStandardInformation::owner_id
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NtfsQuotaOwnerId(pub u32);
+
+impl NtfsQuotaOwnerId {
+    fn from_slice(slice: &[u8]) -> Option<Self> {
+        if slice.len() < mem::size_of::<u32>() {
+            return None;
+        }
+
+        Some(Self(LittleEndian::read_u32(slice)))
+    }
+}
+
+impl NtfsIndexEntryKey for NtfsQuotaOwnerId {
+    fn key_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        Self::from_slice(slice).ok_or(NtfsError::InvalidIndexEntryKeySize {
+            position,
+            expected: mem::size_of::<u32>() as u16,
+            actual: slice.len() as u16,
+        })
+    }
+}
+
+impl NtfsIndexEntryData for NtfsQuotaOwnerId {
+    fn data_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        Self::from_slice(slice).ok_or(NtfsError::InvalidIndexEntryDataSize {
+            position,
+            expected: mem::size_of::<u32>() as u16,
+            actual: slice.len() as u16,
+        })
+    }
+}
+
+/// Defines the [`NtfsIndexEntryType`] for the $O index of the $Extend\$Quota file.
+///
+/// This index maps the SID of every owner of files to its [`NtfsQuotaOwnerId`].
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/quota.html>
+#[derive(Clone, Copy, Debug)]
+pub struct NtfsQuotaOwnerIndex;
+
+impl NtfsQuotaOwnerIndex {
+    /// Finds the owner with the given SID in a quota owner index and returns the [`NtfsIndexEntry`] (if any).
+    pub fn find<'a, T>(
+        index_finder: &'a mut NtfsIndexFinder<Self>,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        sid: &NtfsSid,
+    ) -> Option<Result<NtfsIndexEntry<'a, Self>>>
+    where
+        T: Read + Seek,
+    {
+        index_finder.find_key(ntfs, fs, &sid.to_bytes())
+    }
+}
+
+impl NtfsIndexEntryType for NtfsQuotaOwnerIndex {
+    type KeyType = NtfsSid;
+}
+
+impl NtfsIndexEntryHasData for NtfsQuotaOwnerIndex {
+    type DataType = NtfsQuotaOwnerId;
+}
+
+/// Defines the [`NtfsIndexEntryType`] for the $Q index of the $Extend\$Quota file.
+///
+/// This index maps every [`NtfsQuotaOwnerId`] to an [`NtfsQuotaControlEntry`] with the quota usage
+/// and limits of that owner.
+/// Check out [`Ntfs::quotas`] to comfortably list them.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/quota.html>
+#[derive(Clone, Copy, Debug)]
+pub struct NtfsQuotaControlIndex;
+
+impl NtfsQuotaControlIndex {
+    /// Finds the quota control entry of the given owner in a quota control index and returns the
+    /// [`NtfsIndexEntry`] (if any).
+    pub fn find<'a, T>(
+        index_finder: &'a mut NtfsIndexFinder<Self>,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        owner_id: NtfsQuotaOwnerId,
+    ) -> Option<Result<NtfsIndexEntry<'a, Self>>>
+    where
+        T: Read + Seek,
+    {
+        index_finder.find_key(ntfs, fs, &owner_id.0.to_le_bytes())
+    }
+}
+
+impl NtfsIndexEntryType for NtfsQuotaControlIndex {
+    type KeyType = NtfsQuotaOwnerId;
+}
+
+impl NtfsIndexEntryHasData for NtfsQuotaControlIndex {
+    type DataType = NtfsQuotaControlEntry;
+}
+
+/// Data of an [`NtfsQuotaControlIndex`] entry, describing the quota usage and limits of a single owner.
+///
+/// The quota control entry of owner ID 1 is special: It stores the default quota settings of the volume
+/// and has no SID.
+#[derive(Clone, Debug)]
+pub struct NtfsQuotaControlEntry {
+    header: QuotaControlEntryHeader,
+    sid: Option<NtfsSid>,
+}
+
+impl NtfsQuotaControlEntry {
+    /// Returns the number of bytes charged to this owner.
+    ///
+    /// This is the sum of [`NtfsStandardInformation::quota_charged`] of all files of this owner.
+    ///
+    /// [`NtfsStandardInformation::quota_charged`]: crate::structured_values::NtfsStandardInformation::quota_charged
+    pub fn bytes_used(&self) -> u64 {
+        self.header.bytes_used
+    }
+
+    /// Returns the time this entry was last changed.
+    pub fn change_time(&self) -> NtfsTime {
+        self.header.change_time
+    }
+
+    /// Returns the time the owner has exceeded the threshold.
+    pub fn exceeded_time(&self) -> NtfsTime {
+        self.header.exceeded_time
+    }
+
+    /// Returns flags set for this entry as specified by [`NtfsQuotaFlags`].
+    pub fn flags(&self) -> NtfsQuotaFlags {
+        NtfsQuotaFlags::from_bits_truncate(self.header.flags)
+    }
+
+    /// Returns the hard quota limit of this owner, in bytes, or `None` if there is no limit.
+    pub fn limit(&self) -> Option<u64> {
+        Self::optional_size(self.header.limit)
+    }
+
+    fn optional_size(value: i64) -> Option<u64> {
+        if value < 0 {
+            None
+        } else {
+            Some(value as u64)
+        }
+    }
+
+    /// Returns the SID of this owner, or `None` for the entry storing the default quota settings.
+    pub fn sid(&self) -> Option<&NtfsSid> {
+        self.sid.as_ref()
+    }
+
+    /// Returns the warning threshold of this owner, in bytes, or `None` if there is no threshold.
+    pub fn threshold(&self) -> Option<u64> {
+        Self::optional_size(self.header.threshold)
+    }
+
+    /// Returns the version of this entry, which is usually 2.
+    pub fn version(&self) -> u32 {
+        self.header.version
+    }
+}
+
+impl NtfsIndexEntryData for NtfsQuotaControlEntry {
+    fn data_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        if slice.len() < QUOTA_CONTROL_ENTRY_HEADER_SIZE {
+            return Err(NtfsError::InvalidIndexEntryDataSize {
+                position,
+                expected: QUOTA_CONTROL_ENTRY_HEADER_SIZE as u16,
+                actual: slice.len() as u16,
+            });
+        }
+
+        let mut cursor = Cursor::new(slice);
+        let header = cursor.read_le::<QuotaControlEntryHeader>()?;
+
+        let mut sid = None;
+        if slice.len() > QUOTA_CONTROL_ENTRY_HEADER_SIZE {
+            let sid_position = position + QUOTA_CONTROL_ENTRY_HEADER_SIZE as u64;
+            sid = Some(NtfsSid::from_slice(
+                &slice[QUOTA_CONTROL_ENTRY_HEADER_SIZE..],
+                sid_position,
+            )?);
+        }
+
+        Ok(Self { header, sid })
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2021-2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::attribute::NtfsAttributeType;
+use crate::error::{NtfsError, Result};
+use crate::file::NtfsFile;
+use crate::file_reference::NtfsFileReference;
+use crate::index::NtfsIndexCursor;
+use crate::index_entry::NtfsIndexEntry;
+use crate::indexes::{NtfsIndexEntryKey, NtfsIndexEntryType};
+use crate::ntfs::Ntfs;
+use crate::structured_values::{NtfsReparsePoint, NtfsReparseTag};
+use alloc::vec::Vec;
+use binread::io::{Read, Seek};
+use byteorder::{ByteOrder, LittleEndian};
+use core::convert::TryInto;
+use core::mem;
+
+/// Size of all [`NtfsReparseIndexKey`] fields.
+const REPARSE_INDEX_KEY_SIZE: usize = mem::size_of::<u32>() + mem::size_of::<u64>();
+
+/// Builds the raw key of the $R index entry for the reparse point with the given tag of the given file.
+#[cfg(feature = "std")]
+pub(crate) fn build_reparse_index_key(
+    tag: NtfsReparseTag,
+    file_reference: NtfsFileReference,
+) -> Vec<u8> {
+    let mut key = Vec::with_capacity(REPARSE_INDEX_KEY_SIZE);
+    key.extend_from_slice(&tag.0.to_le_bytes());
+    key.extend_from_slice(&file_reference.to_bytes());
+    key
+}
+
+/// Defines the [`NtfsIndexEntryType`] for the $R index of the $Extend\$Reparse file.
+///
+/// This index lists all reparse points of the volume, sorted by their tag.
+/// Check out [`Ntfs::reparse_points`] to comfortably iterate over them.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/reparse.html>
+#[derive(Clone, Copy, Debug)]
+pub struct NtfsReparseIndex;
+
+impl NtfsReparseIndex {
+    /// Positions the cursor at the first reparse point with the given tag
+    /// and returns the [`NtfsIndexEntry`] (if any).
+    ///
+    /// The returned entry may have a different tag if there is no reparse point with the given tag.
+    pub fn seek<'a, T>(
+        index_cursor: &'a mut NtfsIndexCursor<Self>,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        tag: NtfsReparseTag,
+    ) -> Option<Result<NtfsIndexEntry<'a, Self>>>
+    where
+        T: Read + Seek,
+    {
+        // The smallest key with this tag has a zero file reference.
+        let mut key = [0u8; REPARSE_INDEX_KEY_SIZE];
+        key[..mem::size_of::<u32>()].copy_from_slice(&tag.0.to_le_bytes());
+
+        index_cursor.seek_key(ntfs, fs, &key)
+    }
+}
+
+impl NtfsIndexEntryType for NtfsReparseIndex {
+    type KeyType = NtfsReparseIndexKey;
+}
+
+/// Key of an [`NtfsReparseIndex`] entry.
+#[derive(Clone, Debug)]
+pub struct NtfsReparseIndexKey {
+    tag: NtfsReparseTag,
+    file_reference: NtfsFileReference,
+}
+
+impl NtfsReparseIndexKey {
+    /// Returns an [`NtfsFileReference`] for the file that is a reparse point.
+    pub fn file_reference(&self) -> NtfsFileReference {
+        self.file_reference
+    }
+
+    /// Returns the tag of the reparse point.
+    pub fn tag(&self) -> NtfsReparseTag {
+        self.tag
+    }
+}
+
+impl NtfsIndexEntryKey for NtfsReparseIndexKey {
+    fn key_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        if slice.len() < REPARSE_INDEX_KEY_SIZE {
+            return Err(NtfsError::InvalidIndexEntryKeySize {
+                position,
+                expected: REPARSE_INDEX_KEY_SIZE as u16,
+                actual: slice.len() as u16,
+            });
+        }
+
+        let tag = NtfsReparseTag(LittleEndian::read_u32(slice));
+        let file_reference = NtfsFileReference::new(
+            slice[mem::size_of::<u32>()..REPARSE_INDEX_KEY_SIZE]
+                .try_into()
+                .unwrap(),
+        );
+
+        Ok(Self {
+            tag,
+            file_reference,
+        })
+    }
+}
+
+/// Iterator over
+///   all reparse points of an NTFS volume (optionally filtered by tag),
+///   sorted by their tag,
+///   returning an [`NtfsFile`] and its [`NtfsReparsePoint`] for each reparse point,
+///   created by [`Ntfs::reparse_points`].
+///
+/// The list of reparse points is read from the $R index when creating this iterator.
+/// Each file is only read when it is returned.
+#[derive(Clone, Debug)]
+pub struct NtfsReparsePoints<'n> {
+    ntfs: &'n Ntfs,
+    keys: alloc::vec::IntoIter<NtfsReparseIndexKey>,
+}
+
+impl<'n> NtfsReparsePoints<'n> {
+    pub(crate) fn new<T>(ntfs: &'n Ntfs, fs: &mut T, tag: Option<NtfsReparseTag>) -> Result<Self>
+    where
+        T: Read + Seek,
+    {
+        let keys = match ntfs.extend_file(fs, "$Reparse")? {
+            Some(reparse_file) => Self::read_keys(ntfs, fs, &reparse_file, tag)?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            ntfs,
+            keys: keys.into_iter(),
+        })
+    }
+
+    /// See [`Iterator::next`].
+    pub fn next<T>(&mut self, fs: &mut T) -> Option<Result<(NtfsFile<'n>, NtfsReparsePoint)>>
+    where
+        T: Read + Seek,
+    {
+        let key = self.keys.next()?;
+        let file = iter_try!(key.file_reference().to_file(self.ntfs, fs));
+        let reparse_point = iter_try!(file.reparse_point(fs).ok_or(NtfsError::AttributeNotFound {
+            position: file.position(),
+            ty: NtfsAttributeType::ReparsePoint,
+        }));
+        let reparse_point = iter_try!(reparse_point);
+
+        Some(Ok((file, reparse_point)))
+    }
+
+    fn read_keys<T>(
+        ntfs: &Ntfs,
+        fs: &mut T,
+        reparse_file: &NtfsFile,
+        tag: Option<NtfsReparseTag>,
+    ) -> Result<Vec<NtfsReparseIndexKey>>
+    where
+        T: Read + Seek,
+    {
+        let reparse_index = reparse_file.index::<NtfsReparseIndex, _>(fs, "$R")?;
+        let mut cursor = reparse_index.cursor();
+        let mut keys = Vec::new();
+
+        // The index is sorted by tag, so all reparse points with a specific tag follow each other.
+        let mut entry = match tag {
+            Some(tag) => NtfsReparseIndex::seek(&mut cursor, ntfs, fs, tag),
+            None => cursor.next(fs),
+        };
+
+        while let Some(e) = entry {
+            let key = match e?.key() {
+                Some(key) => key?,
+                None => break,
+            };
+
+            if matches!(tag, Some(tag) if key.tag() != tag) {
+                break;
+            }
+
+            keys.push(key);
+            entry = cursor.next(fs);
+        }
+
+        Ok(keys)
+    }
+}
@@ -0,0 +1,518 @@
+// Copyright 2021-2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::error::{NtfsError, Result};
+use crate::index::{NtfsIndexCursor, NtfsIndexFinder};
+use crate::index_entry::NtfsIndexEntry;
+use crate::indexes::{
+    NtfsIndexEntryData, NtfsIndexEntryHasData, NtfsIndexEntryKey, NtfsIndexEntryType,
+};
+use crate::ntfs::Ntfs;
+use binread::io::{Cursor, Read, Seek};
+use binread::{BinRead, BinReaderExt};
+use byteorder::{ByteOrder, LittleEndian};
+use core::mem;
+#[cfg(feature = "std")]
+use {
+    crate::attribute::{NtfsAttributeItem, NtfsAttributeType},
+    crate::cluster_allocator::NtfsClusterAllocator,
+    crate::file::{KnownNtfsFileRecordNumber, NtfsFile},
+    crate::index_entry::build_data_index_entry,
+    crate::index_writer::insert_index_entry,
+    crate::traits::{NtfsReadSeek, NtfsWrite},
+    alloc::vec,
+    alloc::vec::Vec,
+    binread::io::SeekFrom,
+    std::io::Write,
+};
+
+/// Size of all [`NtfsSecurityDescriptorHeader`] fields.
+pub(crate) const SECURITY_DESCRIPTOR_HEADER_SIZE: usize = 20;
+
+/// Size of all [`NtfsSecurityHashIndexKey`] fields.
+const SECURITY_HASH_INDEX_KEY_SIZE: usize = 2 * mem::size_of::<u32>();
+
+/// Security IDs below this value are reserved, so the first security descriptor in $Secure gets this one.
+#[cfg(feature = "std")]
+pub(crate) const FIRST_SECURITY_ID: u32 = 0x100;
+
+/// Size of the fixed part of a self-relative security descriptor
+/// (revision, control flags, and the offsets of owner, group, SACL, and DACL).
+#[cfg(feature = "std")]
+const SECURITY_DESCRIPTOR_RELATIVE_SIZE: usize = 20;
+
+/// Revision of all security descriptors supported by Windows.
+#[cfg(feature = "std")]
+const SECURITY_DESCRIPTOR_REVISION: u8 = 1;
+
+/// Flag in the control field of a security descriptor that indicates the self-relative format.
+#[cfg(feature = "std")]
+const SE_SELF_RELATIVE: u16 = 0x8000;
+
+/// The $SDS stream consists of blocks of this size.
+/// Every block holding security descriptors is followed by a block with a mirror copy of it.
+#[cfg(feature = "std")]
+const SDS_BLOCK_SIZE: u64 = 0x40000;
+
+/// Alignment of every entry in the $SDS stream.
+#[cfg(feature = "std")]
+const SDS_ENTRY_ALIGNMENT: u64 = 16;
+
+/// Header in front of every security descriptor in the $SDS stream of the $Secure file.
+///
+/// This is also the data of [`NtfsSecurityHashIndex`] and [`NtfsSecurityIdIndex`] entries, which point
+/// to that header.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/secure.html>
+#[derive(BinRead, Clone, Debug)]
+pub struct NtfsSecurityDescriptorHeader {
+    hash: u32,
+    security_id: u32,
+    offset: u64,
+    length: u32,
+}
+
+impl NtfsSecurityDescriptorHeader {
+    /// Returns the hash of the security descriptor.
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+
+    /// Returns the length of this header plus the security descriptor, in bytes.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Returns the byte offset of this header within the $SDS stream.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the Security ID of the security descriptor, as referenced by
+    /// [`NtfsStandardInformation::security_id`].
+    ///
+    /// [`NtfsStandardInformation::security_id`]: crate::structured_values::NtfsStandardInformation::security_id
+    pub fn security_id(&self) -> u32 {
+        self.security_id
+    }
+
+    /// Returns the raw bytes of this header.
+    #[cfg(feature = "std")]
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SECURITY_DESCRIPTOR_HEADER_SIZE);
+        bytes.extend_from_slice(&self.hash.to_le_bytes());
+        bytes.extend_from_slice(&self.security_id.to_le_bytes());
+        bytes.extend_from_slice(&self.offset.to_le_bytes());
+        bytes.extend_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+}
+
+impl NtfsIndexEntryData for NtfsSecurityDescriptorHeader {
+    fn data_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        if slice.len() < SECURITY_DESCRIPTOR_HEADER_SIZE {
+            return Err(NtfsError::InvalidIndexEntryDataSize {
+                position,
+                expected: SECURITY_DESCRIPTOR_HEADER_SIZE as u16,
+                actual: slice.len() as u16,
+            });
+        }
+
+        let mut cursor = Cursor::new(slice);
+        let header = cursor.read_le::<Self>()?;
+        Ok(header)
+    }
+}
+
+/// Defines the [`NtfsIndexEntryType`] for the $SDH index of the $Secure file.
+///
+/// This index lists all security descriptors of the volume, sorted by their hash.
+/// It is used to find an existing security descriptor before adding a new one.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/secure.html>
+#[derive(Clone, Copy, Debug)]
+pub struct NtfsSecurityHashIndex;
+
+impl NtfsSecurityHashIndex {
+    /// Positions the cursor at the first security descriptor with the given hash
+    /// and returns the [`NtfsIndexEntry`] (if any).
+    ///
+    /// The returned entry may have a different hash if there is no security descriptor with the given hash.
+    pub fn seek<'a, T>(
+        index_cursor: &'a mut NtfsIndexCursor<Self>,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        hash: u32,
+    ) -> Option<Result<NtfsIndexEntry<'a, Self>>>
+    where
+        T: Read + Seek,
+    {
+        // The smallest key with this hash has a zero Security ID.
+        let mut key = [0u8; SECURITY_HASH_INDEX_KEY_SIZE];
+        key[..mem::size_of::<u32>()].copy_from_slice(&hash.to_le_bytes());
+
+        index_cursor.seek_key(ntfs, fs, &key)
+    }
+}
+
+impl NtfsIndexEntryType for NtfsSecurityHashIndex {
+    type KeyType = NtfsSecurityHashIndexKey;
+}
+
+impl NtfsIndexEntryHasData for NtfsSecurityHashIndex {
+    type DataType = NtfsSecurityDescriptorHeader;
+}
+
+/// Key of an [`NtfsSecurityHashIndex`] entry.
+#[derive(Clone, Debug)]
+pub struct NtfsSecurityHashIndexKey {
+    hash: u32,
+    security_id: u32,
+}
+
+impl NtfsSecurityHashIndexKey {
+    /// Returns the hash of the security descriptor.
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+
+    /// Returns the Security ID of the security descriptor.
+    pub fn security_id(&self) -> u32 {
+        self.security_id
+    }
+}
+
+impl NtfsIndexEntryKey for NtfsSecurityHashIndexKey {
+    fn key_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        if slice.len() < SECURITY_HASH_INDEX_KEY_SIZE {
+            return Err(NtfsError::InvalidIndexEntryKeySize {
+                position,
+                expected: SECURITY_HASH_INDEX_KEY_SIZE as u16,
+                actual: slice.len() as u16,
+            });
+        }
+
+        let hash = LittleEndian::read_u32(slice);
+        let security_id = LittleEndian::read_u32(&slice[mem::size_of::<u32>()..]);
+
+        Ok(Self { hash, security_id })
+    }
+}
+
+/// Identifier of a security descriptor in the $Secure file, as returned by
+/// [`NtfsStandardInformation::security_id`].
+///
+/// This is the key of [`NtfsSecurityIdIndex`].
+///
+/// [`NtfsStandardInformation::security_id`]: crate::structured_values::NtfsStandardInformation::security_id
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NtfsSecurityId(pub u32);
+
+impl NtfsIndexEntryKey for NtfsSecurityId {
+    fn key_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        if slice.len() < mem::size_of::<u32>() {
+            return Err(NtfsError::InvalidIndexEntryKeySize {
+                position,
+                expected: mem::size_of::<u32>() as u16,
+                actual: slice.len() as u16,
+            });
+        }
+
+        Ok(Self(LittleEndian::read_u32(slice)))
+    }
+}
+
+/// Defines the [`NtfsIndexEntryType`] for the $SII index of the $Secure file.
+///
+/// This index maps every [`NtfsSecurityId`] to the location of its security descriptor in the $SDS stream.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/files/secure.html>
+#[derive(Clone, Copy, Debug)]
+pub struct NtfsSecurityIdIndex;
+
+impl NtfsSecurityIdIndex {
+    /// Finds the security descriptor with the given Security ID in a Security ID index and returns the
+    /// [`NtfsIndexEntry`] (if any).
+    pub fn find<'a, T>(
+        index_finder: &'a mut NtfsIndexFinder<Self>,
+        ntfs: &Ntfs,
+        fs: &mut T,
+        security_id: NtfsSecurityId,
+    ) -> Option<Result<NtfsIndexEntry<'a, Self>>>
+    where
+        T: Read + Seek,
+    {
+        index_finder.find_key(ntfs, fs, &security_id.0.to_le_bytes())
+    }
+}
+
+impl NtfsIndexEntryType for NtfsSecurityIdIndex {
+    type KeyType = NtfsSecurityId;
+}
+
+impl NtfsIndexEntryHasData for NtfsSecurityIdIndex {
+    type DataType = NtfsSecurityDescriptorHeader;
+}
+
+/// Returns the Security ID of the given self-relative security descriptor in the $Secure file.
+///
+/// If $Secure already contains an identical security descriptor, its Security ID is reused.
+/// Otherwise, the security descriptor is appended to both copies in the $SDS stream (allocating clusters via the
+/// given [`NtfsClusterAllocator`]) and indexed in $SII and $SDH under a new Security ID.
+/// The $Secure file is written back to the filesystem.
+#[cfg(feature = "std")]
+pub(crate) fn insert_security_descriptor<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    security_descriptor: &[u8],
+) -> Result<u32>
+where
+    T: Read + Write + Seek,
+{
+    validate_security_descriptor(security_descriptor)?;
+
+    let hash = security_descriptor_hash(security_descriptor);
+    let mut secure_file = ntfs.file(fs, KnownNtfsFileRecordNumber::Secure as u64)?;
+    if let Some(security_id) =
+        find_security_descriptor(ntfs, fs, &secure_file, hash, security_descriptor)?
+    {
+        return Ok(security_id);
+    }
+
+    // Security IDs and offsets grow with every added security descriptor,
+    // so the last entry of $SII tells us where to continue.
+    let last_header = {
+        let sii_index = secure_file.index::<NtfsSecurityIdIndex, _>(fs, "$SII")?;
+        let mut cursor = sii_index.cursor();
+        let entry = cursor.seek_last(fs).transpose()?;
+        entry.and_then(|entry| entry.data()).transpose()?
+    };
+
+    let length = (SECURITY_DESCRIPTOR_HEADER_SIZE + security_descriptor.len()) as u32;
+    let (security_id, end_offset) = match last_header {
+        Some(header) => (
+            header.security_id() + 1,
+            header.offset() + header.length() as u64,
+        ),
+        None => (FIRST_SECURITY_ID, 0),
+    };
+    let header = NtfsSecurityDescriptorHeader {
+        hash,
+        security_id,
+        offset: sds_entry_offset(end_offset, length),
+        length,
+    };
+
+    let mut entry = header.to_bytes();
+    entry.extend_from_slice(security_descriptor);
+
+    // Write the entry and its mirror copy in the following block.
+    let mirror_offset = header.offset() + SDS_BLOCK_SIZE;
+    let sds_len = sds_value_length(fs, &secure_file)?;
+    let new_sds_len = u64::max(sds_len, mirror_offset + length as u64);
+    // The entries are written directly, so the grown value must be fully initialized.
+    let sds_offset = secure_file.find_data_attribute_offset("$SDS")?;
+    secure_file.set_attribute_len(
+        fs,
+        cluster_allocator,
+        NtfsClusterAllocator::allocate,
+        sds_offset,
+        new_sds_len,
+        true,
+    )?;
+
+    for offset in [header.offset(), mirror_offset] {
+        let item = sds_item(fs, &secure_file)?;
+        let mut value = item.to_attribute().value()?;
+        value.seek(fs, SeekFrom::Start(offset))?;
+        value.write_all(fs, &entry)?;
+    }
+
+    let header_bytes = header.to_bytes();
+    insert_index_entry::<NtfsSecurityIdIndex, T>(
+        &mut secure_file,
+        fs,
+        cluster_allocator,
+        "$SII",
+        &build_data_index_entry(&security_id.to_le_bytes(), &header_bytes, None),
+    )?;
+
+    let mut key = Vec::with_capacity(SECURITY_HASH_INDEX_KEY_SIZE);
+    key.extend_from_slice(&hash.to_le_bytes());
+    key.extend_from_slice(&security_id.to_le_bytes());
+    insert_index_entry::<NtfsSecurityHashIndex, T>(
+        &mut secure_file,
+        fs,
+        cluster_allocator,
+        "$SDH",
+        &build_data_index_entry(&key, &header_bytes, None),
+    )?;
+
+    Ok(security_id)
+}
+
+/// Looks up the given security descriptor with the given hash in the $SDH index and returns its Security ID
+/// (if any).
+#[cfg(feature = "std")]
+fn find_security_descriptor<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    secure_file: &NtfsFile,
+    hash: u32,
+    security_descriptor: &[u8],
+) -> Result<Option<u32>>
+where
+    T: Read + Seek,
+{
+    let sdh_index = secure_file.index::<NtfsSecurityHashIndex, _>(fs, "$SDH")?;
+    let mut cursor = sdh_index.cursor();
+    let mut entry = NtfsSecurityHashIndex::seek(&mut cursor, ntfs, fs, hash);
+
+    // Different security descriptors may have the same hash, and all of them follow each other.
+    while let Some(e) = entry {
+        let header = match e?.data() {
+            Some(header) => header?,
+            None => break,
+        };
+
+        if header.hash() != hash {
+            break;
+        }
+
+        if read_security_descriptor(fs, secure_file, &header)? == security_descriptor {
+            return Ok(Some(header.security_id()));
+        }
+
+        entry = cursor.next(fs);
+    }
+
+    Ok(None)
+}
+
+/// Reads the security descriptor described by `header` from the $SDS stream of the $Secure file.
+#[cfg(feature = "std")]
+pub(crate) fn read_security_descriptor<T>(
+    fs: &mut T,
+    secure_file: &NtfsFile,
+    header: &NtfsSecurityDescriptorHeader,
+) -> Result<Vec<u8>>
+where
+    T: Read + Seek,
+{
+    let length = (header.length() as usize).saturating_sub(SECURITY_DESCRIPTOR_HEADER_SIZE);
+    let mut security_descriptor = vec![0u8; length];
+
+    let item = sds_item(fs, secure_file)?;
+    let mut value = item.to_attribute().value()?;
+    value.seek(
+        fs,
+        SeekFrom::Start(header.offset() + SECURITY_DESCRIPTOR_HEADER_SIZE as u64),
+    )?;
+    value.read_exact(fs, &mut security_descriptor)?;
+
+    Ok(security_descriptor)
+}
+
+/// Returns the offset in the $SDS stream for a new entry of the given length after the last entry,
+/// which ends at `end_offset`.
+///
+/// An entry must not cross a block boundary and never goes into a block with mirror copies.
+#[cfg(feature = "std")]
+fn sds_entry_offset(end_offset: u64, length: u32) -> u64 {
+    let mut offset = (end_offset + SDS_ENTRY_ALIGNMENT - 1) & !(SDS_ENTRY_ALIGNMENT - 1);
+    if offset / SDS_BLOCK_SIZE != (offset + length as u64 - 1) / SDS_BLOCK_SIZE {
+        offset = (offset + SDS_BLOCK_SIZE - 1) & !(SDS_BLOCK_SIZE - 1);
+    }
+
+    if (offset / SDS_BLOCK_SIZE) % 2 == 1 {
+        offset += SDS_BLOCK_SIZE;
+    }
+
+    offset
+}
+
+#[cfg(feature = "std")]
+fn sds_item<'n, 'f, T>(
+    fs: &mut T,
+    secure_file: &'f NtfsFile<'n>,
+) -> Result<NtfsAttributeItem<'n, 'f>>
+where
+    T: Read + Seek,
+{
+    secure_file
+        .data(fs, "$SDS")
+        .ok_or(NtfsError::AttributeNotFound {
+            position: secure_file.position(),
+            ty: NtfsAttributeType::Data,
+        })?
+}
+
+#[cfg(feature = "std")]
+fn sds_value_length<T>(fs: &mut T, secure_file: &NtfsFile) -> Result<u64>
+where
+    T: Read + Seek,
+{
+    let item = sds_item(fs, secure_file)?;
+    let length = item.to_attribute().value_length();
+    Ok(length)
+}
+
+/// Calculates the hash of a security descriptor, as used by the $SDH index.
+#[cfg(feature = "std")]
+fn security_descriptor_hash(security_descriptor: &[u8]) -> u32 {
+    // Only full `u32` values are considered.
+    security_descriptor
+        .chunks_exact(mem::size_of::<u32>())
+        .map(LittleEndian::read_u32)
+        .fold(0u32, |hash, value| value.wrapping_add(hash.rotate_left(3)))
+}
+
+/// Checks that `security_descriptor` starts with the header of a self-relative security descriptor and all offsets
+/// in that header are within bounds.
+#[cfg(feature = "std")]
+fn validate_security_descriptor(security_descriptor: &[u8]) -> Result<()> {
+    let error = Err(NtfsError::InvalidSecurityDescriptor {
+        size: security_descriptor.len(),
+    });
+
+    if security_descriptor.len() < SECURITY_DESCRIPTOR_RELATIVE_SIZE
+        || security_descriptor.len() > SDS_BLOCK_SIZE as usize - SECURITY_DESCRIPTOR_HEADER_SIZE
+    {
+        return error;
+    }
+
+    let revision = security_descriptor[0];
+    let control = LittleEndian::read_u16(&security_descriptor[2..]);
+    if revision != SECURITY_DESCRIPTOR_REVISION || control & SE_SELF_RELATIVE == 0 {
+        return error;
+    }
+
+    // Owner, group, SACL, and DACL are optional, which is indicated by a zero offset.
+    for offset in security_descriptor[4..SECURITY_DESCRIPTOR_RELATIVE_SIZE]
+        .chunks_exact(mem::size_of::<u32>())
+        .map(LittleEndian::read_u32)
+    {
+        if offset as usize >= security_descriptor.len() {
+            return error;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sds_entry_offset() {
+        assert_eq!(sds_entry_offset(0, 0x7c), 0);
+        assert_eq!(sds_entry_offset(0xfc, 0x7c), 0x100);
+
+        // An entry that would cross the block boundary moves to the next block that is no mirror.
+        assert_eq!(sds_entry_offset(0x3ffa0, 0x7c), 0x80000);
+        assert_eq!(sds_entry_offset(0x3ff80, 0x7c), 0x3ff80);
+        assert_eq!(sds_entry_offset(0x40000, 0x7c), 0x80000);
+    }
+}
@@ -43,18 +43,33 @@ extern crate alloc;
 mod helpers;
 
 mod attribute;
+mod attribute_definition;
 pub mod attribute_value;
 mod boot_sector;
+mod cluster_allocator;
+mod collation;
 mod error;
 mod file;
 mod file_reference;
+#[cfg(feature = "std")]
+mod format;
 mod guid;
 mod index;
 mod index_entry;
 mod index_record;
+#[cfg(feature = "std")]
+mod index_writer;
 pub mod indexes;
+#[cfg(feature = "std")]
+mod mft;
 mod ntfs;
+#[cfg(feature = "std")]
+mod overlay;
 mod record;
+#[cfg(feature = "std")]
+mod resize;
+mod runlist;
+mod sid;
 mod string;
 pub mod structured_values;
 mod time;
@@ -63,14 +78,24 @@ pub mod types;
 mod upcase_table;
 
 pub use crate::attribute::*;
+pub use crate::attribute_definition::*;
+pub use crate::cluster_allocator::*;
+pub use crate::collation::*;
 pub use crate::error::*;
 pub use crate::file::*;
 pub use crate::file_reference::*;
+#[cfg(feature = "std")]
+pub use crate::format::*;
 pub use crate::guid::*;
 pub use crate::index::*;
 pub use crate::index_entry::*;
 pub use crate::index_record::*;
 pub use crate::ntfs::*;
+#[cfg(feature = "std")]
+pub use crate::overlay::*;
+pub use crate::runlist::*;
+pub use crate::sid::*;
 pub use crate::string::*;
 pub use crate::time::*;
 pub use crate::traits::*;
+pub use crate::upcase_table::*;
@@ -0,0 +1,192 @@
+// Copyright 2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::attribute::{NtfsAttribute, NtfsAttributeType};
+use crate::cluster_allocator::NtfsClusterAllocator;
+use crate::error::{NtfsError, Result};
+use crate::file::{KnownNtfsFileRecordNumber, NtfsFile};
+use crate::helpers::div_ceil;
+use crate::ntfs::Ntfs;
+use crate::traits::{NtfsReadSeek, NtfsWrite};
+use alloc::vec::Vec;
+use binread::io::{Read, Seek, SeekFrom};
+use std::io::Write;
+
+/// The first File Records are reserved for the system files and their extension records.
+/// Like NTFS-3G, we never allocate one of them for a regular file.
+pub(crate) const RESERVED_FILE_RECORD_COUNT: u64 = 64;
+
+/// Number of File Records that are added when the $MFT runs out of free records.
+const MFT_GROWTH_RECORD_COUNT: u64 = 16;
+
+/// The $MFT:$BITMAP attribute value is always a multiple of 8 bytes long.
+const MFT_BITMAP_ALIGNMENT: u64 = 8;
+
+/// Allocates a free File Record in the $MFT and returns its File Record Number.
+///
+/// The File Record is marked as used in the $MFT:$BITMAP, but its contents are left untouched.
+/// If no File Record is free, the $MFT is extended by a few empty, unused File Records, using the given
+/// [`NtfsClusterAllocator`] (which is written back to $Bitmap afterwards).
+pub(crate) fn allocate_file_record<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+) -> Result<u64>
+where
+    T: Read + Write + Seek,
+{
+    let mut mft = ntfs.file(fs, KnownNtfsFileRecordNumber::MFT as u64)?;
+    let record_count = file_record_count(&mft, fs)?;
+
+    let bitmap = {
+        let bitmap_attribute = mft_bitmap_attribute(&mft)?;
+        let mut bitmap = vec![0u8; bitmap_attribute.value_length() as usize];
+        bitmap_attribute.value()?.read_exact(fs, &mut bitmap)?;
+        bitmap
+    };
+
+    let free_record = (RESERVED_FILE_RECORD_COUNT..record_count)
+        .find(|&n| bitmap[(n / 8) as usize] & (1 << (n % 8)) == 0);
+
+    let file_record_number = match free_record {
+        Some(file_record_number) => file_record_number,
+        None => {
+            extend_mft(&mut mft, fs, cluster_allocator, record_count)?;
+            record_count
+        }
+    };
+
+    set_mft_bitmap_bit(ntfs, fs, file_record_number, true)?;
+    Ok(file_record_number)
+}
+
+/// Extends the $MFT, which currently has `record_count` File Records, by [`MFT_GROWTH_RECORD_COUNT`]
+/// empty File Records that are not in use.
+fn extend_mft<T>(
+    mft: &mut NtfsFile,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    record_count: u64,
+) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    let ntfs = mft.ntfs();
+    let new_record_count = record_count + MFT_GROWTH_RECORD_COUNT;
+
+    // Make sure that the $MFT:$BITMAP has a bit for every new File Record.
+    let bitmap_attribute = mft_bitmap_attribute(mft)?;
+    let bitmap_offset = bitmap_attribute.offset();
+    let bitmap_len = bitmap_attribute.value_length();
+    let new_bitmap_len =
+        div_ceil(new_record_count, 8 * MFT_BITMAP_ALIGNMENT) * MFT_BITMAP_ALIGNMENT;
+
+    if new_bitmap_len > bitmap_len {
+        mft.set_attribute_len(
+            fs,
+            cluster_allocator,
+            NtfsClusterAllocator::allocate_mft,
+            bitmap_offset,
+            new_bitmap_len,
+            true,
+        )?;
+    }
+
+    // Resizing the $BITMAP attribute may have moved the $DATA attribute.
+    let data_offset = mft.find_data_attribute_offset("")?;
+    let new_data_len = new_record_count * ntfs.file_record_size() as u64;
+    mft.set_attribute_len(
+        fs,
+        cluster_allocator,
+        NtfsClusterAllocator::allocate_mft,
+        data_offset,
+        new_data_len,
+        true,
+    )?;
+
+    for file_record_number in record_count..new_record_count {
+        let position = ntfs.file_record_position(fs, file_record_number)?;
+        NtfsFile::new_empty(ntfs, position, file_record_number, 1).write_record(fs)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the number of File Records that are part of the $MFT and have a bit in the $MFT:$BITMAP.
+fn file_record_count<T>(mft: &NtfsFile, fs: &mut T) -> Result<u64>
+where
+    T: Read + Seek,
+{
+    let data_item = mft.data(fs, "").ok_or(NtfsError::AttributeNotFound {
+        position: mft.position(),
+        ty: NtfsAttributeType::Data,
+    })??;
+    let data_record_count =
+        data_item.to_attribute().value_length() / mft.ntfs().file_record_size() as u64;
+    let bitmap_record_count = mft_bitmap_attribute(mft)?.value_length() * 8;
+
+    Ok(u64::min(data_record_count, bitmap_record_count))
+}
+
+/// Marks the given File Record as unused in the $MFT:$BITMAP, so that it can be allocated again.
+pub(crate) fn free_file_record<T>(ntfs: &Ntfs, fs: &mut T, file_record_number: u64) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    set_mft_bitmap_bit(ntfs, fs, file_record_number, false)
+}
+
+/// Returns the unnamed $BITMAP attribute of the $MFT, which has a bit for every File Record that is in use.
+fn mft_bitmap_attribute<'n, 'f>(mft: &'f NtfsFile<'n>) -> Result<NtfsAttribute<'n, 'f>> {
+    for attribute in mft.attributes_raw() {
+        if attribute.ty()? == NtfsAttributeType::Bitmap && attribute.name_length() == 0 {
+            return Ok(attribute);
+        }
+    }
+
+    Err(NtfsError::AttributeNotFound {
+        position: mft.position(),
+        ty: NtfsAttributeType::Bitmap,
+    })
+}
+
+fn set_mft_bitmap_bit<T>(ntfs: &Ntfs, fs: &mut T, file_record_number: u64, used: bool) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    let mft = ntfs.file(fs, KnownNtfsFileRecordNumber::MFT as u64)?;
+    let bitmap_attribute = mft_bitmap_attribute(&mft)?;
+    let mut bitmap_value = bitmap_attribute.value()?;
+    let byte_offset = file_record_number / 8;
+    let bit = 1 << (file_record_number % 8);
+
+    let mut byte = [0u8];
+    bitmap_value.seek(fs, SeekFrom::Start(byte_offset))?;
+    bitmap_value.read_exact(fs, &mut byte)?;
+
+    if used {
+        byte[0] |= bit;
+    } else {
+        byte[0] &= !bit;
+    }
+
+    bitmap_value.seek(fs, SeekFrom::Start(byte_offset))?;
+    bitmap_value.write_all(fs, &byte)
+}
+
+/// Returns the File Record Numbers of all File Records that are marked as used in the $MFT:$BITMAP.
+pub(crate) fn used_file_record_numbers<T>(ntfs: &Ntfs, fs: &mut T) -> Result<Vec<u64>>
+where
+    T: Read + Seek,
+{
+    let mft = ntfs.file(fs, KnownNtfsFileRecordNumber::MFT as u64)?;
+    let record_count = file_record_count(&mft, fs)?;
+
+    let bitmap_attribute = mft_bitmap_attribute(&mft)?;
+    let mut bitmap = vec![0u8; bitmap_attribute.value_length() as usize];
+    bitmap_attribute.value()?.read_exact(fs, &mut bitmap)?;
+
+    Ok((0..record_count)
+        .filter(|&n| bitmap[(n / 8) as usize] & (1 << (n % 8)) != 0)
+        .collect())
+}
@@ -2,14 +2,30 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use crate::attribute::NtfsAttributeType;
+use crate::attribute_definition::{read_attribute_definitions, NtfsAttributeDefinition};
 use crate::boot_sector::BootSector;
+use crate::cluster_allocator::NtfsClusterAllocator;
 use crate::error::{NtfsError, Result};
 use crate::file::{KnownNtfsFileRecordNumber, NtfsFile};
-use crate::structured_values::{NtfsVolumeInformation, NtfsVolumeName};
+#[cfg(feature = "std")]
+use crate::format::{self, NtfsFormatOptions};
+use crate::guid::NtfsGuid;
+use crate::indexes::{
+    NtfsObjectIdIndex, NtfsQuotaControlEntry, NtfsQuotaControlIndex, NtfsQuotaOwnerId,
+    NtfsQuotaOwnerIndex, NtfsReparsePoints, OBJECT_ID_INDEX_DATA_SIZE,
+};
+#[cfg(feature = "std")]
+use crate::resize;
+use crate::sid::NtfsSid;
+use crate::structured_values::{NtfsReparseTag, NtfsVolumeInformation, NtfsVolumeName};
 use crate::traits::NtfsReadSeek;
-use crate::upcase_table::UpcaseTable;
+use crate::upcase_table::{NtfsUpcaseInfo, NtfsUpcaseTableVersion, UpcaseTable};
+use alloc::vec::Vec;
 use binread::io::{Read, Seek, SeekFrom};
 use binread::BinReaderExt;
+use core::mem;
+#[cfg(feature = "std")]
+use std::io::Write;
 
 /// Root structure describing an NTFS filesystem.
 #[derive(Debug)]
@@ -22,12 +38,16 @@ pub struct Ntfs {
     size: u64,
     /// Absolute position of the Master File Table (MFT), in bytes.
     mft_position: u64,
+    /// Absolute position of the backup copy of the first MFT records ($MFTMirr), in bytes.
+    mft_mirror_position: u64,
     /// Size of a single File Record, in bytes.
     file_record_size: u32,
     /// Serial number of the NTFS volume.
     serial_number: u64,
     /// Table of Unicode uppercase characters (only required for case-insensitive comparisons).
     upcase_table: Option<UpcaseTable>,
+    /// Attribute definitions of the $AttrDef file (only required for validating attributes).
+    attribute_definitions: Option<Vec<NtfsAttributeDefinition>>,
 }
 
 impl Ntfs {
@@ -52,34 +72,231 @@ impl Ntfs {
             .checked_mul(sector_size as u64)
             .ok_or(NtfsError::TotalSectorsTooBig { total_sectors })?;
         let mft_position = 0;
+        let mft_mirror_position = 0;
         let file_record_size = bpb.file_record_size()?;
         let serial_number = bpb.serial_number();
         let upcase_table = None;
+        let attribute_definitions = None;
 
         let mut ntfs = Self {
             cluster_size,
             sector_size,
             size,
             mft_position,
+            mft_mirror_position,
             file_record_size,
             serial_number,
             upcase_table,
+            attribute_definitions,
         };
         ntfs.mft_position = bpb.mft_lcn().position(&ntfs)?;
+        ntfs.mft_mirror_position = bpb.mft_mirror_lcn().position(&ntfs)?;
 
         Ok(ntfs)
     }
 
+    /// Returns the stored [`NtfsAttributeDefinition`]s, or `None` if
+    /// [`read_attribute_definitions`][Ntfs::read_attribute_definitions] had not been called.
+    pub(crate) fn attribute_definition_table(&self) -> Option<&[NtfsAttributeDefinition]> {
+        self.attribute_definitions.as_deref()
+    }
+
+    /// Reads the $AttrDef file from the filesystem and returns all [`NtfsAttributeDefinition`]s
+    /// of this NTFS volume.
+    ///
+    /// Use [`read_attribute_definitions`][Ntfs::read_attribute_definitions] to store them in this
+    /// [`Ntfs`] object and validate all attributes against them.
+    pub fn attribute_definitions<T>(&self, fs: &mut T) -> Result<Vec<NtfsAttributeDefinition>>
+    where
+        T: Read + Seek,
+    {
+        read_attribute_definitions(self, fs)
+    }
+
+    /// Reads the $Bitmap file of the filesystem into a new [`NtfsClusterAllocator`], which can allocate
+    /// and free clusters.
+    pub fn cluster_allocator<T>(&self, fs: &mut T) -> Result<NtfsClusterAllocator>
+    where
+        T: Read + Seek,
+    {
+        NtfsClusterAllocator::read(self, fs)
+    }
+
     /// Returns the size of a single cluster, in bytes.
     pub fn cluster_size(&self) -> u32 {
         self.cluster_size
     }
 
+    /// Creates a new empty directory named `name` in the directory `parent` and returns its [`NtfsFile`].
+    ///
+    /// The new directory gets an empty $I30 index, which uses the same Index Record size as the one of `parent`.
+    /// Apart from that, this works like [`Ntfs::create_file`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`read_upcase_table`][Ntfs::read_upcase_table] had not been called.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn create_dir<'n, T>(
+        &'n self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        parent: &mut NtfsFile<'n>,
+        name: &str,
+    ) -> Result<NtfsFile<'n>>
+    where
+        T: Read + Write + Seek,
+    {
+        NtfsFile::create(self, fs, cluster_allocator, parent, name, true)
+    }
+
+    /// Creates a new empty file named `name` in the directory `parent` and returns its [`NtfsFile`].
+    ///
+    /// A free File Record is allocated via the $MFT:$BITMAP.
+    /// If there is none, the $MFT is extended using the given [`NtfsClusterAllocator`].
+    /// The file gets a $STANDARD_INFORMATION attribute with the current time and the Security ID of `parent`
+    /// (or a default one if `parent` has none), and an empty $DATA attribute.
+    /// Names that are no valid MS-DOS names get an additional $FILE_NAME attribute with a generated 8+3 name
+    /// (e.g. "LONGFI~1.TXT").
+    /// Finally, the name is inserted into the $I30 index of `parent`, which is written back to the filesystem.
+    ///
+    /// Returns [`NtfsError::InvalidFileName`] if `name` is no valid NTFS file name and
+    /// [`NtfsError::FileNameAlreadyExists`] if `parent` already contains a file with that name
+    /// (compared case-insensitively).
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`read_upcase_table`][Ntfs::read_upcase_table] had not been called.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn create_file<'n, T>(
+        &'n self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        parent: &mut NtfsFile<'n>,
+        name: &str,
+    ) -> Result<NtfsFile<'n>>
+    where
+        T: Read + Write + Seek,
+    {
+        NtfsFile::create(self, fs, cluster_allocator, parent, name, false)
+    }
+
+    /// Deletes the file or directory named `name` (compared case-insensitively) from the directory `parent`.
+    ///
+    /// This removes the link from the $I30 index of `parent` (along with the accompanying MS-DOS or Win32 name)
+    /// and decrements the hard link count of the file.
+    /// When no link is left, the file is removed from the $O index of $ObjId and the $R index of $Reparse
+    /// (where applicable), all its clusters are freed via the given [`NtfsClusterAllocator`],
+    /// and its File Record and extension File Records are marked as unused in the $MFT:$BITMAP.
+    /// Their sequence numbers are incremented to invalidate any remaining references.
+    /// Security descriptors in $Secure are shared between files and not reference-counted, so they are kept.
+    ///
+    /// Directories must be empty unless `recursive` is `true`, in which case their contents are deleted first.
+    /// Otherwise, [`NtfsError::DirectoryNotEmpty`] is returned.
+    /// Returns [`NtfsError::FileNameNotFound`] if `parent` contains no file named `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`read_upcase_table`][Ntfs::read_upcase_table] had not been called.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn delete<'n, T>(
+        &'n self,
+        fs: &mut T,
+        cluster_allocator: &mut NtfsClusterAllocator,
+        parent: &mut NtfsFile<'n>,
+        name: &str,
+        recursive: bool,
+    ) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        NtfsFile::delete(self, fs, cluster_allocator, parent, name, recursive)
+    }
+
+    /// Returns the [`NtfsFile`] of the housekeeping file with the given name in the $Extend directory
+    /// (e.g. "$ObjId"), or `None` if this volume has no such file.
+    pub(crate) fn extend_file<'n, T>(
+        &'n self,
+        fs: &mut T,
+        name: &str,
+    ) -> Result<Option<NtfsFile<'n>>>
+    where
+        T: Read + Seek,
+    {
+        let extend_directory = self.file(fs, KnownNtfsFileRecordNumber::Extend as u64)?;
+        let extend_index = extend_directory.directory_index(fs)?;
+        let mut iter = extend_index.entries();
+
+        // $Extend only contains a handful of files, so we just compare the names of all of them.
+        // This way, the caller doesn't need to read the $UpCase table for a case-insensitive lookup.
+        while let Some(entry) = iter.next(fs) {
+            let entry = entry?;
+            let file_name = match entry.key() {
+                Some(key) => key?,
+                None => continue,
+            };
+
+            if file_name.name() == name {
+                let file_reference = entry.file_reference();
+                return file_reference.to_file(self, fs).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
     /// Returns the [`NtfsFile`] for the given NTFS File Record Number.
     ///
     /// The first few NTFS files have fixed indexes and contain filesystem
     /// management information (see the [`KnownNtfsFileRecordNumber`] enum).
     pub fn file<'n, T>(&'n self, fs: &mut T, file_record_number: u64) -> Result<NtfsFile<'n>>
+    where
+        T: Read + Seek,
+    {
+        let position = self.file_record_position(fs, file_record_number)?;
+        NtfsFile::new(self, fs, position, file_record_number)
+    }
+
+    /// Looks up the file that has been assigned the given Object ID and returns its [`NtfsFile`].
+    ///
+    /// Object IDs are used by the Distributed Link Tracking service of Windows.
+    /// This function resolves references to such Object IDs, e.g. found in LNK files and jump lists.
+    /// It uses the $O index of the $Extend\$ObjId file (see [`NtfsObjectIdIndex`]).
+    ///
+    /// Returns `None` if no file has been assigned this Object ID or the volume has no $ObjId file.
+    pub fn file_by_object_id<'n, T>(
+        &'n self,
+        fs: &mut T,
+        object_id: &NtfsGuid,
+    ) -> Result<Option<NtfsFile<'n>>>
+    where
+        T: Read + Seek,
+    {
+        let object_id_file = match self.extend_file(fs, "$ObjId")? {
+            Some(object_id_file) => object_id_file,
+            None => return Ok(None),
+        };
+
+        let object_id_index = object_id_file.index::<NtfsObjectIdIndex, _>(fs, "$O")?;
+        let mut object_id_finder = object_id_index.finder();
+        let entry = match NtfsObjectIdIndex::find(&mut object_id_finder, self, fs, object_id) {
+            Some(entry) => entry?,
+            None => return Ok(None),
+        };
+
+        let data = entry.data().ok_or(NtfsError::InvalidIndexEntryDataSize {
+            position: entry.position(),
+            expected: OBJECT_ID_INDEX_DATA_SIZE as u16,
+            actual: 0,
+        })??;
+
+        data.file_reference().to_file(self, fs).map(Some)
+    }
+
+    /// Returns the absolute byte position of the File Record with the given NTFS File Record Number.
+    pub(crate) fn file_record_position<T>(&self, fs: &mut T, file_record_number: u64) -> Result<u64>
     where
         T: Read + Seek,
     {
@@ -106,11 +323,9 @@ impl Ntfs {
         let mut mft_data_value = mft_data_attribute.value()?;
 
         mft_data_value.seek(fs, SeekFrom::Start(offset))?;
-        let position = mft_data_value
+        mft_data_value
             .data_position()
-            .ok_or(NtfsError::InvalidFileRecordNumber { file_record_number })?;
-
-        NtfsFile::new(self, fs, position, file_record_number)
+            .ok_or(NtfsError::InvalidFileRecordNumber { file_record_number })
     }
 
     /// Returns the size of a File Record of this NTFS filesystem, in bytes.
@@ -118,11 +333,161 @@ impl Ntfs {
         self.file_record_size
     }
 
+    /// Formats `fs` with a new, empty NTFS 3.1 filesystem spanning the entire device and returns an
+    /// [`Ntfs`] object for it, with the $UpCase table already read.
+    ///
+    /// This writes the boot sector (and its backup copy in the last sector), the Master File Table (MFT) with
+    /// all system files, a clean $LogFile, and an empty root directory.
+    /// See [`NtfsFormatOptions`] for the available settings.
+    ///
+    /// Everything previously stored in `fs` is lost.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn format<T>(fs: &mut T, options: &NtfsFormatOptions) -> Result<Self>
+    where
+        T: Read + Write + Seek,
+    {
+        format::format(fs, options)
+    }
+
+    /// Returns the absolute byte position of the $MFTMirr file, which is a backup copy of the first
+    /// File Records of the Master File Table (MFT).
+    pub fn mft_mirror_position(&self) -> u64 {
+        self.mft_mirror_position
+    }
+
+    /// Returns the number of File Records that are mirrored in $MFTMirr.
+    ///
+    /// This is at least 4 (covering $MFT, $MFTMirr, $LogFile, and $Volume), or a single cluster
+    /// if that holds more File Records.
+    pub fn mft_mirror_record_count(&self) -> u32 {
+        u32::max(4, self.cluster_size / self.file_record_size)
+    }
+
     /// Returns the absolute byte position of the Master File Table (MFT).
     pub fn mft_position(&self) -> u64 {
         self.mft_position
     }
 
+    /// Looks up the [`NtfsQuotaOwnerId`] of the owner with the given SID.
+    ///
+    /// This uses the $O index of the $Extend\$Quota file (see [`NtfsQuotaOwnerIndex`]).
+    /// Returns `None` if the SID doesn't own any files or the volume has no $Quota file.
+    pub fn quota_owner_id<T>(&self, fs: &mut T, sid: &NtfsSid) -> Result<Option<NtfsQuotaOwnerId>>
+    where
+        T: Read + Seek,
+    {
+        let quota_file = match self.extend_file(fs, "$Quota")? {
+            Some(quota_file) => quota_file,
+            None => return Ok(None),
+        };
+
+        let owner_index = quota_file.index::<NtfsQuotaOwnerIndex, _>(fs, "$O")?;
+        let mut owner_finder = owner_index.finder();
+        let entry = match NtfsQuotaOwnerIndex::find(&mut owner_finder, self, fs, sid) {
+            Some(entry) => entry?,
+            None => return Ok(None),
+        };
+
+        let owner_id = entry.data().ok_or(NtfsError::InvalidIndexEntryDataSize {
+            position: entry.position(),
+            expected: mem::size_of::<u32>() as u16,
+            actual: 0,
+        })??;
+
+        Ok(Some(owner_id))
+    }
+
+    /// Looks up the SID of the owner with the given [`NtfsQuotaOwnerId`], e.g. as returned by
+    /// [`NtfsStandardInformation::owner_id`].
+    ///
+    /// This uses the $Q index of the $Extend\$Quota file (see [`NtfsQuotaControlIndex`]).
+    /// Returns `None` if there is no such owner, the owner has no SID (like the entry storing the
+    /// default quota settings), or the volume has no $Quota file.
+    ///
+    /// [`NtfsStandardInformation::owner_id`]: crate::structured_values::NtfsStandardInformation::owner_id
+    pub fn quota_owner_sid<T>(
+        &self,
+        fs: &mut T,
+        owner_id: NtfsQuotaOwnerId,
+    ) -> Result<Option<NtfsSid>>
+    where
+        T: Read + Seek,
+    {
+        let quota_file = match self.extend_file(fs, "$Quota")? {
+            Some(quota_file) => quota_file,
+            None => return Ok(None),
+        };
+
+        let control_index = quota_file.index::<NtfsQuotaControlIndex, _>(fs, "$Q")?;
+        let mut control_finder = control_index.finder();
+        let entry = match NtfsQuotaControlIndex::find(&mut control_finder, self, fs, owner_id) {
+            Some(entry) => entry?,
+            None => return Ok(None),
+        };
+
+        let control_entry = match entry.data() {
+            Some(control_entry) => control_entry?,
+            None => return Ok(None),
+        };
+
+        Ok(control_entry.sid().cloned())
+    }
+
+    /// Returns the quota usage and limits of every owner of files on this volume, sorted by their
+    /// [`NtfsQuotaOwnerId`].
+    ///
+    /// This reads all entries of the $Q index of the $Extend\$Quota file (see [`NtfsQuotaControlIndex`]).
+    /// The entry with owner ID 1 stores the default quota settings of the volume and has no SID.
+    /// Returns an empty list if the volume has no $Quota file.
+    pub fn quotas<T>(&self, fs: &mut T) -> Result<Vec<(NtfsQuotaOwnerId, NtfsQuotaControlEntry)>>
+    where
+        T: Read + Seek,
+    {
+        let quota_file = match self.extend_file(fs, "$Quota")? {
+            Some(quota_file) => quota_file,
+            None => return Ok(Vec::new()),
+        };
+
+        let control_index = quota_file.index::<NtfsQuotaControlIndex, _>(fs, "$Q")?;
+        let mut iter = control_index.entries();
+        let mut quotas = Vec::new();
+
+        while let Some(entry) = iter.next(fs) {
+            let entry = entry?;
+            let owner_id = match entry.key() {
+                Some(key) => key?,
+                None => continue,
+            };
+            let control_entry = match entry.data() {
+                Some(data) => data?,
+                None => continue,
+            };
+
+            quotas.push((owner_id, control_entry));
+        }
+
+        Ok(quotas)
+    }
+
+    /// Reads the $AttrDef file from the filesystem and stores its [`NtfsAttributeDefinition`]s in this
+    /// [`Ntfs`] object.
+    ///
+    /// Afterwards, attributes are validated against these definitions when their values are accessed:
+    /// Undefined attribute types, non-resident attributes of resident-only types, and value sizes out of
+    /// the defined bounds are reported as errors.
+    /// [`NtfsAttribute::ty_name`] also needs these definitions.
+    ///
+    /// [`NtfsAttribute::ty_name`]: crate::NtfsAttribute::ty_name
+    pub fn read_attribute_definitions<T>(&mut self, fs: &mut T) -> Result<()>
+    where
+        T: Read + Seek,
+    {
+        let attribute_definitions = read_attribute_definitions(self, fs)?;
+        self.attribute_definitions = Some(attribute_definitions);
+        Ok(())
+    }
+
     /// Reads the $UpCase file from the filesystem and stores it in this [`Ntfs`] object.
     ///
     /// This function only needs to be called if case-insensitive comparisons are later performed
@@ -136,6 +501,60 @@ impl Ntfs {
         Ok(())
     }
 
+    /// Returns an [`NtfsReparsePoints`] iterator over all reparse points of this NTFS volume,
+    /// optionally only those with the given tag.
+    ///
+    /// This uses the $R index of the $Extend\$Reparse file (see [`NtfsReparseIndex`]),
+    /// so it is much faster than scanning all files for a $REPARSE_POINT attribute.
+    ///
+    /// [`NtfsReparseIndex`]: crate::indexes::NtfsReparseIndex
+    pub fn reparse_points<'n, T>(
+        &'n self,
+        fs: &mut T,
+        tag: Option<NtfsReparseTag>,
+    ) -> Result<NtfsReparsePoints<'n>>
+    where
+        T: Read + Seek,
+    {
+        NtfsReparsePoints::new(self, fs, tag)
+    }
+
+    /// Resizes this NTFS filesystem to span `new_size` bytes, like `ntfsresize` does.
+    ///
+    /// Growing extends the $Bitmap file by the added clusters (which are free) and the $Bad stream of $BadClus.
+    /// `fs` must already provide the additional space.
+    ///
+    /// Shrinking first moves all clusters beyond the new end of the volume (including those of the $MFT,
+    /// $MFTMirr, $LogFile, and $Bitmap) to free clusters before it, and then truncates $Bitmap.
+    /// Afterwards, `fs` can be truncated to `new_size` bytes.
+    /// Returns [`NtfsError::UnmovableCluster`] if a used cluster beyond the new end cannot be moved
+    /// (a bad cluster, the File Record of the $MFT itself, or a cluster not belonging to any file), and
+    /// [`NtfsError::NotEnoughFreeClusters`] if the remaining volume is too small to hold all data.
+    /// Both are checked before anything is changed.
+    /// Any later error while moving clusters (e.g. a moved attribute whose runlist no longer fits into its
+    /// File Record, or an I/O error) leaves the shrink partially applied:
+    /// The attributes moved so far reference their new clusters and $Bitmap is updated accordingly,
+    /// but the volume keeps its previous size.
+    ///
+    /// In both cases, the total sector count in the boot sector is updated, and its backup copy is written to
+    /// the new last sector.
+    /// Any [`NtfsClusterAllocator`] or [`NtfsFile`] object of this filesystem needs to be reloaded afterwards.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn resize<T>(&mut self, fs: &mut T, new_size: u64) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        resize::resize(self, fs, new_size)?;
+
+        // Pick up the new size and the position of a moved $MFTMirr from the updated boot sector.
+        let ntfs = Self::new(fs)?;
+        self.size = ntfs.size;
+        self.mft_mirror_position = ntfs.mft_mirror_position;
+
+        Ok(())
+    }
+
     /// Returns the root directory of this NTFS volume as an [`NtfsFile`].
     pub fn root_directory<'n, T>(&'n self, fs: &mut T) -> Result<NtfsFile<'n>>
     where
@@ -159,6 +578,18 @@ impl Ntfs {
         self.size
     }
 
+    /// Returns an [`NtfsUpcaseInfo`] describing the Upcase Table of this NTFS volume, read from the $Info data
+    /// stream of the $UpCase file.
+    ///
+    /// Only filesystems created by Windows 8 or later (and by recent versions of mkntfs) have this stream,
+    /// which is why the return value is further encapsulated in an `Option`.
+    pub fn upcase_info<T>(&self, fs: &mut T) -> Option<Result<NtfsUpcaseInfo>>
+    where
+        T: Read + Seek,
+    {
+        NtfsUpcaseInfo::read(self, fs)
+    }
+
     /// Returns the stored [`UpcaseTable`].
     ///
     /// # Panics
@@ -170,6 +601,29 @@ impl Ntfs {
             .expect("You need to call read_upcase_table first")
     }
 
+    /// Reads the $UpCase file from the filesystem and compares it with the built-in Upcase Tables of the known
+    /// Windows versions.
+    ///
+    /// Returns the matching [`NtfsUpcaseTableVersion`], or `None` if the table of this volume differs from all
+    /// known ones (e.g. because it is corrupted or was created by an unknown tool).
+    pub fn upcase_table_version<T>(&self, fs: &mut T) -> Result<Option<NtfsUpcaseTableVersion>>
+    where
+        T: Read + Seek,
+    {
+        let upcase_table = UpcaseTable::read(self, fs)?;
+        Ok(upcase_table.version())
+    }
+
+    /// Stores the built-in Upcase Table of the given Windows version in this [`Ntfs`] object,
+    /// as an alternative to [`read_upcase_table`][Ntfs::read_upcase_table].
+    ///
+    /// Only use this as a fallback if the $UpCase file of the filesystem is corrupted.
+    /// File name comparisons with a different table than the one the filesystem was created with may fail to find
+    /// existing files or accept names that collide with existing ones.
+    pub fn use_default_upcase_table(&mut self, version: NtfsUpcaseTableVersion) {
+        self.upcase_table = Some(UpcaseTable::new(version));
+    }
+
     /// Returns an [`NtfsVolumeInformation`] containing general information about
     /// the volume, like the NTFS version.
     pub fn volume_info<T>(&self, fs: &mut T) -> Result<NtfsVolumeInformation>
@@ -202,6 +656,8 @@ impl Ntfs {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::index_entry::build_data_index_entry;
+    use crate::index_writer::insert_index_entry;
 
     #[test]
     fn test_basics() {
@@ -212,6 +668,392 @@ mod tests {
         assert_eq!(ntfs.size(), 2096640);
     }
 
+    #[test]
+    fn test_create_file() {
+        use crate::file::NtfsFileFlags;
+        use crate::indexes::NtfsFileNameIndex;
+        use crate::structured_values::NtfsFileNamespace;
+
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+
+        // This name is no valid MS-DOS name, so it gets an additional generated one.
+        let file = ntfs
+            .create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "Hello World.txt",
+            )
+            .unwrap();
+        assert!(file.file_record_number() >= 64);
+        assert_eq!(file.flags(), NtfsFileFlags::IN_USE);
+        assert_eq!(file.hard_link_count(), 2);
+
+        // This name is a valid MS-DOS name, so it only gets a single name.
+        let mut dir = ntfs
+            .create_dir(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "newdir",
+            )
+            .unwrap();
+        assert_ne!(dir.file_record_number(), file.file_record_number());
+        assert!(dir.is_directory());
+        assert_eq!(dir.hard_link_count(), 1);
+
+        // This name is only valid in the POSIX namespace.
+        let posix_file = ntfs
+            .create_file(&mut testfs1, &mut cluster_allocator, &mut dir, "a:b")
+            .unwrap();
+
+        // Look up everything from scratch.
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let root_dir_index = root_dir.directory_index(&mut testfs1).unwrap();
+        root_dir_index.check_ordering(&ntfs, &mut testfs1).unwrap();
+        let mut root_dir_finder = root_dir_index.finder();
+
+        for (name, namespace) in [
+            ("hello world.txt", NtfsFileNamespace::Win32),
+            ("HELLOW~1.TXT", NtfsFileNamespace::Dos),
+        ] {
+            let entry = NtfsFileNameIndex::find(&mut root_dir_finder, &ntfs, &mut testfs1, name)
+                .unwrap()
+                .unwrap();
+            assert_eq!(entry.key().unwrap().unwrap().namespace(), namespace);
+
+            let found_file = entry.to_file(&ntfs, &mut testfs1).unwrap();
+            assert_eq!(found_file.file_record_number(), file.file_record_number());
+        }
+
+        let file = ntfs.file(&mut testfs1, file.file_record_number()).unwrap();
+        let file_name = file
+            .name(&mut testfs1, Some(NtfsFileNamespace::Win32), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(file_name.name(), "Hello World.txt");
+        assert_eq!(
+            file_name.parent_directory_reference().file_record_number(),
+            KnownNtfsFileRecordNumber::RootDirectory as u64
+        );
+        let data_item = file.data(&mut testfs1, "").unwrap().unwrap();
+        assert_eq!(data_item.to_attribute().value().unwrap().len(), 0);
+
+        let entry = NtfsFileNameIndex::find(&mut root_dir_finder, &ntfs, &mut testfs1, "NEWDIR")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            entry.key().unwrap().unwrap().namespace(),
+            NtfsFileNamespace::Win32AndDos
+        );
+        let dir = entry.to_file(&ntfs, &mut testfs1).unwrap();
+        let dir_index = dir.directory_index(&mut testfs1).unwrap();
+        let mut dir_finder = dir_index.finder();
+        let entry = NtfsFileNameIndex::find(&mut dir_finder, &ntfs, &mut testfs1, "a:b")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            entry.key().unwrap().unwrap().namespace(),
+            NtfsFileNamespace::Posix
+        );
+        assert_eq!(
+            entry.file_reference().file_record_number(),
+            posix_file.file_record_number()
+        );
+
+        // Names are compared case-insensitively, and invalid names are rejected.
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        assert!(matches!(
+            ntfs.create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "HELLO WORLD.TXT"
+            ),
+            Err(NtfsError::FileNameAlreadyExists { .. })
+        ));
+        assert!(matches!(
+            ntfs.create_file(&mut testfs1, &mut cluster_allocator, &mut root_dir, "a/b"),
+            Err(NtfsError::InvalidFileName { .. })
+        ));
+    }
+
+    #[test]
+    fn test_delete() {
+        use crate::file::NtfsFileFlags;
+        use crate::indexes::NtfsFileNameIndex;
+
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+
+        let mut file = ntfs
+            .create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "Hello World.txt",
+            )
+            .unwrap();
+        let file_record_number = file.file_record_number();
+
+        let mut dir = ntfs
+            .create_dir(&mut testfs1, &mut cluster_allocator, &mut root_dir, "dir")
+            .unwrap();
+        let dir_record_number = dir.file_record_number();
+        let inner_file = ntfs
+            .create_file(&mut testfs1, &mut cluster_allocator, &mut dir, "inner")
+            .unwrap();
+        let inner_file_record_number = inner_file.file_record_number();
+
+        // Creating the files may have extended the $MFT, which is not undone.
+        // Only the clusters of the file data must be freed again.
+        let free_cluster_count = cluster_allocator.free_cluster_count();
+        file.set_len(&mut testfs1, &mut cluster_allocator, "", 10000)
+            .unwrap();
+        assert!(cluster_allocator.free_cluster_count() < free_cluster_count);
+
+        // Non-empty directories are only deleted recursively.
+        assert!(matches!(
+            ntfs.delete(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "dir",
+                false
+            ),
+            Err(NtfsError::DirectoryNotEmpty { .. })
+        ));
+
+        // Deleting the Win32 name also deletes the MS-DOS name.
+        ntfs.delete(
+            &mut testfs1,
+            &mut cluster_allocator,
+            &mut root_dir,
+            "HELLO WORLD.TXT",
+            false,
+        )
+        .unwrap();
+        ntfs.delete(
+            &mut testfs1,
+            &mut cluster_allocator,
+            &mut root_dir,
+            "DIR",
+            true,
+        )
+        .unwrap();
+
+        // The names are gone and all records and clusters are free again.
+        let root_dir_index = root_dir.directory_index(&mut testfs1).unwrap();
+        root_dir_index.check_ordering(&ntfs, &mut testfs1).unwrap();
+        let mut root_dir_finder = root_dir_index.finder();
+        for name in ["Hello World.txt", "HELLOW~1.TXT", "dir"] {
+            assert!(
+                NtfsFileNameIndex::find(&mut root_dir_finder, &ntfs, &mut testfs1, name).is_none()
+            );
+        }
+
+        for file_record_number in [
+            file_record_number,
+            dir_record_number,
+            inner_file_record_number,
+        ] {
+            let file = ntfs.file(&mut testfs1, file_record_number).unwrap();
+            assert!(!file.flags().contains(NtfsFileFlags::IN_USE));
+            assert_eq!(file.sequence_number(), 2);
+        }
+
+        assert_eq!(cluster_allocator.free_cluster_count(), free_cluster_count);
+        let cluster_allocator_on_disk = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        assert_eq!(
+            cluster_allocator_on_disk.free_cluster_count(),
+            free_cluster_count
+        );
+
+        // A freed File Record is reused with the incremented sequence number.
+        let new_file = ntfs
+            .create_file(&mut testfs1, &mut cluster_allocator, &mut root_dir, "new")
+            .unwrap();
+        assert_eq!(new_file.file_record_number(), file_record_number);
+        assert_eq!(new_file.sequence_number(), 2);
+
+        assert!(matches!(
+            ntfs.delete(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "dir",
+                true
+            ),
+            Err(NtfsError::FileNameNotFound { .. })
+        ));
+        assert!(matches!(
+            ntfs.delete(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "$MFT",
+                false
+            ),
+            Err(NtfsError::UnsupportedOperationOnSystemFile { .. })
+        ));
+    }
+
+    #[test]
+    fn test_file_by_object_id() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let ntfs = Ntfs::new(&mut testfs1).unwrap();
+
+        // The $O index of $ObjId exists, but mkntfs doesn't assign any Object IDs.
+        let object_id_file = ntfs.extend_file(&mut testfs1, "$ObjId").unwrap().unwrap();
+        let object_id_index = object_id_file
+            .index::<NtfsObjectIdIndex, _>(&mut testfs1, "$O")
+            .unwrap();
+        assert_eq!(
+            object_id_index.collation_rule().unwrap(),
+            crate::collation::NtfsCollationRule::NtofsUlongs
+        );
+        object_id_index.check_ordering(&ntfs, &mut testfs1).unwrap();
+
+        let object_id = NtfsGuid {
+            data1: 0x67c8770b,
+            data2: 0x44f1,
+            data3: 0x410a,
+            data4: [0xab, 0x9a, 0xf9, 0xb5, 0x44, 0x6f, 0x13, 0xee],
+        };
+        assert!(ntfs
+            .file_by_object_id(&mut testfs1, &object_id)
+            .unwrap()
+            .is_none());
+
+        // Assign the Object ID to the root directory and look it up again.
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let mut data = root_dir.file_reference().to_bytes().to_vec();
+        data.resize(OBJECT_ID_INDEX_DATA_SIZE, 0);
+        let entry = build_data_index_entry(&object_id.to_bytes(), &data, None);
+
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let mut object_id_file = ntfs.extend_file(&mut testfs1, "$ObjId").unwrap().unwrap();
+        insert_index_entry::<NtfsObjectIdIndex, _>(
+            &mut object_id_file,
+            &mut testfs1,
+            &mut cluster_allocator,
+            "$O",
+            &entry,
+        )
+        .unwrap();
+
+        let file = ntfs
+            .file_by_object_id(&mut testfs1, &object_id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            file.file_record_number(),
+            KnownNtfsFileRecordNumber::RootDirectory as u64
+        );
+
+        assert!(ntfs
+            .extend_file(&mut testfs1, "$DoesNotExist")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_quotas() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let ntfs = Ntfs::new(&mut testfs1).unwrap();
+
+        // mkntfs creates the entry storing the default quota settings and one for the Administrators group.
+        let quotas = ntfs.quotas(&mut testfs1).unwrap();
+        assert_eq!(quotas.len(), 2);
+
+        let (owner_id, control_entry) = &quotas[0];
+        assert_eq!(*owner_id, NtfsQuotaOwnerId(1));
+        assert!(control_entry.sid().is_none());
+        assert!(control_entry
+            .flags()
+            .contains(crate::indexes::NtfsQuotaFlags::DEFAULT_LIMITS));
+        assert_eq!(control_entry.limit(), None);
+
+        let administrators_sid = NtfsSid::new(1, 5, &[32, 544]).unwrap();
+        let (owner_id, control_entry) = &quotas[1];
+        assert_eq!(*owner_id, NtfsQuotaOwnerId(256));
+        assert_eq!(control_entry.sid(), Some(&administrators_sid));
+
+        // Prove that both quota indexes link owner IDs and SIDs.
+        assert_eq!(
+            ntfs.quota_owner_id(&mut testfs1, &administrators_sid)
+                .unwrap(),
+            Some(NtfsQuotaOwnerId(256))
+        );
+        assert_eq!(
+            ntfs.quota_owner_sid(&mut testfs1, NtfsQuotaOwnerId(256))
+                .unwrap(),
+            Some(administrators_sid)
+        );
+        assert_eq!(
+            ntfs.quota_owner_sid(&mut testfs1, NtfsQuotaOwnerId(1))
+                .unwrap(),
+            None
+        );
+
+        let users_sid = NtfsSid::new(1, 5, &[32, 545]).unwrap();
+        assert_eq!(ntfs.quota_owner_id(&mut testfs1, &users_sid).unwrap(), None);
+
+        let quota_file = ntfs.extend_file(&mut testfs1, "$Quota").unwrap().unwrap();
+        let owner_index = quota_file
+            .index::<NtfsQuotaOwnerIndex, _>(&mut testfs1, "$O")
+            .unwrap();
+        assert_eq!(
+            owner_index.collation_rule().unwrap(),
+            crate::collation::NtfsCollationRule::NtofsSid
+        );
+        owner_index.check_ordering(&ntfs, &mut testfs1).unwrap();
+
+        let control_index = quota_file
+            .index::<NtfsQuotaControlIndex, _>(&mut testfs1, "$Q")
+            .unwrap();
+        assert_eq!(
+            control_index.collation_rule().unwrap(),
+            crate::collation::NtfsCollationRule::NtofsUlong
+        );
+        control_index.check_ordering(&ntfs, &mut testfs1).unwrap();
+    }
+
+    #[test]
+    fn test_reparse_points() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let ntfs = Ntfs::new(&mut testfs1).unwrap();
+
+        let reparse_file = ntfs.extend_file(&mut testfs1, "$Reparse").unwrap().unwrap();
+        let reparse_index = reparse_file
+            .index::<crate::indexes::NtfsReparseIndex, _>(&mut testfs1, "$R")
+            .unwrap();
+        assert_eq!(
+            reparse_index.collation_rule().unwrap(),
+            crate::collation::NtfsCollationRule::NtofsUlongs
+        );
+        reparse_index.check_ordering(&ntfs, &mut testfs1).unwrap();
+
+        // mkntfs doesn't create any reparse points.
+        let mut iter = ntfs.reparse_points(&mut testfs1, None).unwrap();
+        assert!(iter.next(&mut testfs1).is_none());
+
+        let mut iter = ntfs
+            .reparse_points(&mut testfs1, Some(NtfsReparseTag::SYMLINK))
+            .unwrap();
+        assert!(iter.next(&mut testfs1).is_none());
+
+        let root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        assert!(root_dir.reparse_point(&mut testfs1).is_none());
+    }
+
     #[test]
     fn test_volume_info() {
         let mut testfs1 = crate::helpers::tests::testfs1();
@@ -0,0 +1,540 @@
+// Copyright 2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::error::Result;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use binread::io::{Read, Seek, SeekFrom};
+use core::convert::TryInto;
+use core::mem;
+use std::io::{self, Write};
+
+/// Number of bytes of a patch that [`NtfsOverlay::read_delta`] reads at once.
+/// This keeps a corrupted length field from allocating a huge buffer before any data has been read.
+const DELTA_CHUNK_SIZE: usize = 65536;
+
+/// Copy-on-write overlay over a read-only filesystem image.
+///
+/// [`NtfsOverlay`] wraps any `Read + Seek` image and implements `Read + Write + Seek` itself.
+/// All writes are recorded in an in-memory delta and never reach the wrapped image, while reads return
+/// the image contents with the delta applied.
+/// This makes all write functions of this crate usable on an image that must not be modified,
+/// e.g. to try out changes first.
+///
+/// The delta consists of non-overlapping patches, which can be retrieved via [`NtfsOverlay::patches`],
+/// applied to a writable copy of the image via [`NtfsOverlay::apply`], or saved to and loaded from
+/// a side file via [`NtfsOverlay::write_delta`] and [`NtfsOverlay::read_delta`].
+///
+/// Writes beyond the end of the wrapped image extend the overlay, just like they would extend a file.
+///
+/// The delta is always kept entirely in memory, so all written bytes occupy memory until the overlay
+/// is dropped or [`NtfsOverlay::clear`] is called.
+/// Side files only serve to save and load the delta, they are never used as a backing store.
+#[derive(Clone, Debug)]
+pub struct NtfsOverlay<T>
+where
+    T: Read + Seek,
+{
+    base: T,
+    base_len: u64,
+    /// Patches of the delta, keyed by their offset.
+    /// Overlapping and adjacent patches are always merged, so that a read never needs to combine patches.
+    patches: BTreeMap<u64, Vec<u8>>,
+    position: u64,
+}
+
+impl<T> NtfsOverlay<T>
+where
+    T: Read + Seek,
+{
+    /// Creates a new [`NtfsOverlay`] with an empty delta over the given image.
+    pub fn new(mut base: T) -> Result<Self> {
+        let base_len = base.seek(SeekFrom::End(0))?;
+
+        Ok(Self {
+            base,
+            base_len,
+            patches: BTreeMap::new(),
+            position: 0,
+        })
+    }
+
+    /// Writes all patches of the delta to `target`, which is usually a writable copy of the wrapped image
+    /// or the image itself once all changes have been verified.
+    pub fn apply<W>(&self, target: &mut W) -> Result<()>
+    where
+        W: Write + Seek,
+    {
+        for (offset, data) in self.patches() {
+            target.seek(SeekFrom::Start(offset))?;
+            target.write_all(data)?;
+        }
+
+        target.flush()?;
+        Ok(())
+    }
+
+    /// Discards all changes recorded in the delta.
+    pub fn clear(&mut self) {
+        self.patches.clear();
+    }
+
+    /// Returns a reference to the wrapped image.
+    pub fn get_ref(&self) -> &T {
+        &self.base
+    }
+
+    /// Returns the wrapped image, discarding the delta.
+    pub fn into_inner(self) -> T {
+        self.base
+    }
+
+    /// Returns `true` if any changes have been recorded in the delta.
+    pub fn is_modified(&self) -> bool {
+        !self.patches.is_empty()
+    }
+
+    /// Returns an iterator over all patches of the delta as (offset, bytes) pairs, in ascending order
+    /// of their offsets.
+    pub fn patches(&self) -> impl Iterator<Item = (u64, &[u8])> {
+        self.patches
+            .iter()
+            .map(|(&offset, data)| (offset, data.as_slice()))
+    }
+
+    /// Reads patches previously saved via [`NtfsOverlay::write_delta`] from `reader` and records them
+    /// in the delta, as if they were written to the overlay.
+    ///
+    /// `reader` must end right after a complete patch.
+    /// If it ends within a patch or contains a patch beyond the maximum position, an error is returned,
+    /// and only the patches before that one have been recorded.
+    pub fn read_delta<R>(&mut self, reader: &mut R) -> Result<()>
+    where
+        R: Read,
+    {
+        let mut header = [0u8; 2 * mem::size_of::<u64>()];
+        let mut chunk = vec![0u8; DELTA_CHUNK_SIZE];
+
+        while read_patch_header(reader, &mut header)? {
+            let (offset, len) = header.split_at(mem::size_of::<u64>());
+            let offset = u64::from_le_bytes(offset.try_into().unwrap());
+            let len = u64::from_le_bytes(len.try_into().unwrap());
+            patch_end(offset, len)?;
+
+            // The data only grows as far as `reader` actually provides it.
+            let mut data = Vec::new();
+            let mut remaining = len;
+
+            while remaining > 0 {
+                let chunk_len = u64::min(remaining, DELTA_CHUNK_SIZE as u64) as usize;
+                reader.read_exact(&mut chunk[..chunk_len])?;
+                data.extend_from_slice(&chunk[..chunk_len]);
+                remaining -= chunk_len as u64;
+            }
+
+            self.record_patch(offset, &data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the total size of the overlay in bytes, which is the size of the wrapped image
+    /// unless the delta extends beyond it.
+    pub fn size(&self) -> u64 {
+        let delta_end = self
+            .patches
+            .iter()
+            .next_back()
+            .map(|(&offset, data)| offset + data.len() as u64)
+            .unwrap_or(0);
+
+        u64::max(self.base_len, delta_end)
+    }
+
+    /// Writes all patches of the delta to `writer`, so that they can be kept in a side file and loaded
+    /// again via [`NtfsOverlay::read_delta`].
+    ///
+    /// Each patch is stored as its offset and length (both as little-endian 64-bit integers),
+    /// followed by its bytes.
+    pub fn write_delta<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        for (offset, data) in self.patches() {
+            writer.write_all(&offset.to_le_bytes())?;
+            writer.write_all(&(data.len() as u64).to_le_bytes())?;
+            writer.write_all(data)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Records `data` at `offset` in the delta, merging it with all overlapping and adjacent patches.
+    fn record_patch(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let end = patch_end(offset, data.len() as u64)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // Patches never overlap, so their ends ascend along with their offsets.
+        let mut merged_offsets = self
+            .patches
+            .range(..=end)
+            .rev()
+            .take_while(|(&patch_offset, patch)| patch_offset + patch.len() as u64 >= offset)
+            .map(|(&patch_offset, _)| patch_offset)
+            .collect::<Vec<u64>>();
+
+        // Extend a patch starting at or before `offset` in place instead of copying it.
+        // This keeps sequential writes, which always extend the preceding patch, linear.
+        let (merged_start, mut merged) = match merged_offsets.last() {
+            Some(&patch_offset) if patch_offset <= offset => {
+                merged_offsets.pop();
+                (patch_offset, self.patches.remove(&patch_offset).unwrap())
+            }
+            _ => (offset, Vec::new()),
+        };
+
+        let start = (offset - merged_start) as usize;
+        let data_end = start + data.len();
+        if merged.len() < data_end {
+            merged.resize(data_end, 0);
+        }
+        merged[start..data_end].copy_from_slice(data);
+
+        // All remaining patches start within the new data, but the last one may reach beyond it.
+        for patch_offset in merged_offsets {
+            let patch = self.patches.remove(&patch_offset).unwrap();
+            let patch_end = patch_offset + patch.len() as u64;
+
+            if patch_end > end {
+                merged.extend_from_slice(&patch[(end - patch_offset) as usize..]);
+            }
+        }
+
+        self.patches.insert(merged_start, merged);
+        Ok(())
+    }
+}
+
+/// Returns the end of a patch of `len` bytes at `offset`, or an error if it would exceed the maximum position.
+fn patch_end(offset: u64, len: u64) -> io::Result<u64> {
+    offset.checked_add(len).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "patch extends beyond the maximum position",
+        )
+    })
+}
+
+/// Fills `header` with the header of the next patch of a side file.
+/// Returns `false` if `reader` has already reached its end, and an error if it ends within the header.
+fn read_patch_header<R>(reader: &mut R, header: &mut [u8]) -> io::Result<bool>
+where
+    R: Read,
+{
+    let mut filled = 0;
+
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "side file ends within a patch header",
+                ))
+            }
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+impl<T> Read for NtfsOverlay<T>
+where
+    T: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.size();
+        if self.position >= len {
+            return Ok(0);
+        }
+
+        let position = self.position;
+        let end = u64::min(position + buf.len() as u64, len);
+        let buf = &mut buf[..(end - position) as usize];
+
+        // Read what the wrapped image has and zero-fill the rest.
+        let base_end = u64::min(end, u64::max(position, self.base_len));
+        let (base_buf, zero_buf) = buf.split_at_mut((base_end - position) as usize);
+        if !base_buf.is_empty() {
+            self.base.seek(SeekFrom::Start(position))?;
+            self.base.read_exact(base_buf)?;
+        }
+        zero_buf.fill(0);
+
+        // Apply all patches overlapping the requested range.
+        for (&patch_offset, patch) in self.patches.range(..end).rev() {
+            let patch_end = patch_offset + patch.len() as u64;
+            if patch_end <= position {
+                break;
+            }
+
+            let start = u64::max(patch_offset, position);
+            let stop = u64::min(patch_end, end);
+            buf[(start - position) as usize..(stop - position) as usize].copy_from_slice(
+                &patch[(start - patch_offset) as usize..(stop - patch_offset) as usize],
+            );
+        }
+
+        self.position = end;
+        Ok(buf.len())
+    }
+}
+
+impl<T> Seek for NtfsOverlay<T>
+where
+    T: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.position = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => (self.size(), n),
+            SeekFrom::Current(n) => (self.position, n),
+        };
+
+        let new_position = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.wrapping_neg() as u64)
+        };
+
+        match new_position {
+            Some(n) => {
+                self.position = n;
+                Ok(n)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl<T> Write for NtfsOverlay<T>
+where
+    T: Read + Seek,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = patch_end(self.position, buf.len() as u64)?;
+        self.record_patch(self.position, buf)?;
+        self.position = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ntfs::Ntfs;
+    use crate::traits::NtfsReadSeek;
+    use std::io::Cursor;
+
+    fn read_all<T: Read + Seek>(overlay: &mut NtfsOverlay<T>) -> Vec<u8> {
+        let mut data = Vec::new();
+        overlay.seek(SeekFrom::Start(0)).unwrap();
+        overlay.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_patches() {
+        let base = Cursor::new((0..64u8).collect::<Vec<u8>>());
+        let mut overlay = NtfsOverlay::new(base).unwrap();
+        assert!(!overlay.is_modified());
+        assert_eq!(overlay.size(), 64);
+
+        overlay.seek(SeekFrom::Start(10)).unwrap();
+        overlay.write_all(&[0xaa; 4]).unwrap();
+        overlay.seek(SeekFrom::Start(20)).unwrap();
+        overlay.write_all(&[0xbb; 4]).unwrap();
+
+        // This write overlaps the second patch and is adjacent to the first one, so all are merged.
+        overlay.seek(SeekFrom::Start(14)).unwrap();
+        overlay.write_all(&[0xcc; 8]).unwrap();
+
+        let patches = overlay.patches().collect::<Vec<_>>();
+        let mut expected = vec![0xaa; 4];
+        expected.extend_from_slice(&[0xcc; 8]);
+        expected.extend_from_slice(&[0xbb; 2]);
+        assert_eq!(patches, vec![(10, expected.as_slice())]);
+
+        // Writes beyond the end extend the overlay and leave a gap of zeros.
+        overlay.seek(SeekFrom::End(4)).unwrap();
+        overlay.write_all(&[0xdd; 2]).unwrap();
+        assert_eq!(overlay.size(), 70);
+
+        let data = read_all(&mut overlay);
+        assert_eq!(data[..10], (0..10).collect::<Vec<u8>>());
+        assert_eq!(data[10..24], expected);
+        assert_eq!(data[24..64], (24..64).collect::<Vec<u8>>());
+        assert_eq!(data[64..], [0, 0, 0, 0, 0xdd, 0xdd]);
+
+        // Reads starting within a patch return the right part of it.
+        let mut buf = [0u8; 4];
+        overlay.seek(SeekFrom::Start(12)).unwrap();
+        overlay.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0xaa, 0xaa, 0xcc, 0xcc]);
+
+        // The wrapped image is never modified.
+        assert_eq!(*overlay.get_ref().get_ref(), (0..64).collect::<Vec<u8>>());
+
+        overlay.clear();
+        assert!(!overlay.is_modified());
+        assert_eq!(read_all(&mut overlay), (0..64).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_sequential_writes() {
+        let base = Cursor::new(vec![0u8; 64]);
+        let mut overlay = NtfsOverlay::new(base).unwrap();
+
+        // Many small sequential writes end up in a single patch.
+        // Copying that patch on every write would make this test take minutes.
+        let chunk = (0..=255u8).collect::<Vec<u8>>();
+        overlay.seek(SeekFrom::Start(32)).unwrap();
+        for _ in 0..32768 {
+            overlay.write_all(&chunk).unwrap();
+        }
+
+        let patches = overlay.patches().collect::<Vec<_>>();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].0, 32);
+        assert_eq!(patches[0].1.len(), 32768 * chunk.len());
+        assert!(patches[0]
+            .1
+            .chunks(chunk.len())
+            .all(|c| c == chunk.as_slice()));
+
+        // Writing in front of the patch and bridging the gap to it also merges everything.
+        overlay.seek(SeekFrom::Start(8)).unwrap();
+        overlay.write_all(&[0xaa; 4]).unwrap();
+        overlay.seek(SeekFrom::Start(12)).unwrap();
+        overlay.write_all(&[0xbb; 24]).unwrap();
+
+        let patches = overlay.patches().collect::<Vec<_>>();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].0, 8);
+        assert_eq!(patches[0].1[..4], [0xaa; 4]);
+        assert_eq!(patches[0].1[4..28], [0xbb; 24]);
+        assert_eq!(patches[0].1[28..32], [4, 5, 6, 7]);
+        assert_eq!(overlay.size(), 32 + 32768 * chunk.len() as u64);
+    }
+
+    #[test]
+    fn test_overlay_filesystem() {
+        let testfs1 = crate::helpers::tests::testfs1();
+        let original = testfs1.get_ref().clone();
+        let mut overlay = NtfsOverlay::new(testfs1).unwrap();
+
+        // Use the write functions of the crate on the overlay.
+        let mut ntfs = Ntfs::new(&mut overlay).unwrap();
+        ntfs.read_upcase_table(&mut overlay).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut overlay).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut overlay).unwrap();
+        let data = (0..10000).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut file = ntfs
+            .create_file(
+                &mut overlay,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "new.dat",
+            )
+            .unwrap();
+        file.write_data_stream(&mut overlay, &mut cluster_allocator, "", &data)
+            .unwrap();
+        let file_record_number = file.file_record_number();
+
+        let file = ntfs.file(&mut overlay, file_record_number).unwrap();
+        let data_item = file.data(&mut overlay, "").unwrap().unwrap();
+        let data_attribute = data_item.to_attribute();
+        let mut data_value = data_attribute.value().unwrap();
+        let mut read_data = vec![0u8; data_value.len() as usize];
+        data_value.read_exact(&mut overlay, &mut read_data).unwrap();
+        assert_eq!(read_data, data);
+
+        assert!(overlay.is_modified());
+        assert_eq!(*overlay.get_ref().get_ref(), original);
+        let modified = read_all(&mut overlay);
+
+        // Applying the delta to a copy of the image yields the same contents as the overlay.
+        let mut copy = Cursor::new(original.clone());
+        overlay.apply(&mut copy).unwrap();
+        assert_eq!(*copy.get_ref(), modified);
+
+        // The delta survives a round trip through a side file.
+        let mut side_file = Vec::new();
+        overlay.write_delta(&mut side_file).unwrap();
+        let mut reloaded = NtfsOverlay::new(Cursor::new(original)).unwrap();
+        reloaded.read_delta(&mut side_file.as_slice()).unwrap();
+        assert_eq!(
+            reloaded.patches().collect::<Vec<_>>(),
+            overlay.patches().collect::<Vec<_>>()
+        );
+        assert_eq!(read_all(&mut reloaded), modified);
+    }
+
+    #[test]
+    fn test_invalid_delta() {
+        let base = Cursor::new(vec![0u8; 64]);
+        let mut overlay = NtfsOverlay::new(base).unwrap();
+        overlay.seek(SeekFrom::Start(8)).unwrap();
+        overlay.write_all(&[0xaa; 4]).unwrap();
+
+        let mut side_file = Vec::new();
+        overlay.write_delta(&mut side_file).unwrap();
+        assert_eq!(side_file.len(), 20);
+
+        // A side file may only end after a complete patch.
+        for len in [4, 16, 18].iter() {
+            let mut reloaded = NtfsOverlay::new(Cursor::new(vec![0u8; 64])).unwrap();
+            assert!(reloaded.read_delta(&mut &side_file[..*len]).is_err());
+            assert!(!reloaded.is_modified());
+        }
+
+        // A patch length exceeding the remaining side file is not allocated upfront.
+        let mut huge_patch = Vec::new();
+        huge_patch.extend_from_slice(&0u64.to_le_bytes());
+        huge_patch.extend_from_slice(&(u64::MAX / 2).to_le_bytes());
+        huge_patch.extend_from_slice(&[0xbb; 4]);
+        assert!(overlay.read_delta(&mut huge_patch.as_slice()).is_err());
+
+        // Patches and writes must not extend beyond the maximum position.
+        let mut overflowing_patch = Vec::new();
+        overflowing_patch.extend_from_slice(&(u64::MAX - 1).to_le_bytes());
+        overflowing_patch.extend_from_slice(&4u64.to_le_bytes());
+        overflowing_patch.extend_from_slice(&[0xcc; 4]);
+        assert!(overlay
+            .read_delta(&mut overflowing_patch.as_slice())
+            .is_err());
+
+        overlay.seek(SeekFrom::Start(u64::MAX - 1)).unwrap();
+        let error = overlay.write(&[0xdd; 4]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(overlay.stream_position().unwrap(), u64::MAX - 1);
+
+        assert_eq!(overlay.patches().collect::<Vec<_>>(), [(8, &[0xaa; 4][..])]);
+    }
+}
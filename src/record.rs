@@ -37,6 +37,10 @@ impl<'n> Record<'n> {
         &self.data
     }
 
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
     pub(crate) fn fixup(&mut self) -> Result<()> {
         let update_sequence_number = self.update_sequence_number();
         let mut array_position = self.update_sequence_array_start() as usize;
@@ -104,6 +108,71 @@ impl<'n> Record<'n> {
         self.position
     }
 
+    pub(crate) fn logfile_sequence_number(&self) -> u64 {
+        let start = offset_of!(RecordHeader, logfile_sequence_number);
+        LittleEndian::read_u64(&self.data[start..])
+    }
+
+    /// Applies the multi-sector protection to the record data, which is the inverse of [`Record::fixup`].
+    ///
+    /// The Update Sequence Number (USN) is incremented first.
+    /// Then the last 2 bytes of each sector are saved into the Update Sequence Array (USA) and replaced
+    /// by the new USN.
+    /// This way, a sector that has not been written completely can be detected on the next read.
+    fn protect(&mut self) -> Result<()> {
+        let update_sequence_number = self.next_update_sequence_number();
+        let mut array_position = self.update_sequence_array_start() as usize;
+        let array_end =
+            self.update_sequence_offset() as usize + self.update_sequence_size() as usize;
+        let sector_size = self.ntfs.sector_size() as usize;
+
+        // Check all bounds before modifying anything.
+        let sector_count = array_end.saturating_sub(array_position) / mem::size_of::<u16>();
+        if sector_count * sector_size > self.data.len() {
+            return Err(NtfsError::UpdateSequenceArrayExceedsRecordSize {
+                position: self.position,
+                array_count: self.update_sequence_array_count(),
+                sector_size: self.ntfs.sector_size(),
+                record_size: self.data.len(),
+            });
+        }
+
+        let mut sector_position = sector_size - mem::size_of::<u16>();
+
+        while array_position < array_end {
+            let array_position_end = array_position + mem::size_of::<u16>();
+            let sector_position_end = sector_position + mem::size_of::<u16>();
+
+            // Save the actual 2 bytes at the end of the sector and replace them by the USN.
+            self.data
+                .copy_within(sector_position..sector_position_end, array_position);
+            self.data[sector_position..sector_position_end]
+                .copy_from_slice(&update_sequence_number);
+
+            array_position = array_position_end;
+            sector_position += sector_size;
+        }
+
+        let start = self.update_sequence_offset() as usize;
+        let end = start + mem::size_of::<u16>();
+        self.data[start..end].copy_from_slice(&update_sequence_number);
+
+        Ok(())
+    }
+
+    /// Returns the Update Sequence Number (USN) to be used for the next write of this record.
+    ///
+    /// Like Windows, we skip the values 0 and 0xFFFF when the USN wraps around.
+    fn next_update_sequence_number(&self) -> [u8; 2] {
+        let update_sequence_number = u16::from_le_bytes(self.update_sequence_number());
+        let next = match update_sequence_number.wrapping_add(1) {
+            0 | 0xFFFF => 1,
+            next => next,
+        };
+
+        next.to_le_bytes()
+    }
+
     pub(crate) fn signature(&self) -> [u8; 4] {
         self.data[span_of!(RecordHeader, signature)]
             .try_into()
@@ -139,4 +208,79 @@ impl<'n> Record<'n> {
         let update_sequence_count = LittleEndian::read_u16(&self.data[start..]);
         update_sequence_count as u32 * mem::size_of::<u16>() as u32
     }
+
+    /// Protects the record data for writing and passes it to the given function, which is supposed to write
+    /// it to the filesystem.
+    ///
+    /// The protection is undone afterwards, so that the record remains fixed up in memory.
+    /// The new Update Sequence Number is kept, so that further writes continue incrementing it.
+    ///
+    /// The $LogFile Sequence Number (LSN) of the record is left untouched.
+    /// This crate doesn't record any changes in the $LogFile, and inventing a new LSN could make Windows
+    /// consider the record newer than the journal.
+    pub(crate) fn write_with<F>(&mut self, write_function: F) -> Result<()>
+    where
+        F: FnOnce(&[u8]) -> Result<()>,
+    {
+        self.protect()?;
+        let write_result = write_function(&self.data);
+
+        // This cannot fail, because we have just put the USN at every place checked by `fixup`.
+        self.fixup()?;
+
+        write_result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binread::io::{Read, Seek, SeekFrom};
+
+    #[test]
+    fn test_record_protect() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let ntfs = Ntfs::new(&mut testfs1).unwrap();
+
+        // Read the raw File Record of the $MFT file.
+        let mut raw_data = vec![0u8; ntfs.file_record_size() as usize];
+        testfs1.seek(SeekFrom::Start(ntfs.mft_position())).unwrap();
+        testfs1.read_exact(&mut raw_data).unwrap();
+
+        let mut record = Record::new(&ntfs, raw_data.clone(), ntfs.mft_position());
+        record.fixup().unwrap();
+        let fixed_up_data = record.data().to_vec();
+        let usn = u16::from_le_bytes(record.update_sequence_number());
+
+        // Protecting the record again must yield the raw data, except for the incremented USN.
+        let mut protected_data = Vec::new();
+        record
+            .write_with(|data| {
+                protected_data = data.to_vec();
+                Ok(())
+            })
+            .unwrap();
+
+        // The record must be fixed up again, only keeping the incremented USN.
+        let new_usn = u16::from_le_bytes(record.update_sequence_number());
+        assert_eq!(new_usn, usn + 1);
+
+        let usa_offset = record.update_sequence_offset() as usize;
+        let mut expected_fixed_up_data = fixed_up_data;
+        expected_fixed_up_data[usa_offset..usa_offset + 2].copy_from_slice(&new_usn.to_le_bytes());
+        assert_eq!(record.data(), &expected_fixed_up_data[..]);
+
+        let mut expected_data = raw_data;
+        let sector_size = ntfs.sector_size() as usize;
+        expected_data[usa_offset..usa_offset + 2].copy_from_slice(&new_usn.to_le_bytes());
+        for sector in expected_data.chunks_exact_mut(sector_size) {
+            sector[sector_size - 2..].copy_from_slice(&new_usn.to_le_bytes());
+        }
+        assert_eq!(protected_data, expected_data);
+
+        // The USN skips 0xFFFF and 0 when wrapping around.
+        let start = record.update_sequence_offset() as usize;
+        record.data[start..start + 2].copy_from_slice(&0xFFFEu16.to_le_bytes());
+        assert_eq!(record.next_update_sequence_number(), 1u16.to_le_bytes());
+    }
 }
@@ -0,0 +1,505 @@
+// Copyright 2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::attribute::NtfsAttribute;
+use crate::boot_sector::BootSector;
+use crate::cluster_allocator::{bitmap_size, NtfsClusterAllocator};
+use crate::error::{NtfsError, Result};
+use crate::file::{KnownNtfsFileRecordNumber, NtfsFileFlags};
+use crate::helpers::div_ceil;
+use crate::mft::used_file_record_numbers;
+use crate::ntfs::Ntfs;
+use crate::runlist::{NtfsRun, NtfsRunlist};
+use crate::types::Lcn;
+use alloc::vec::Vec;
+use binread::io::{Read, Seek, SeekFrom};
+use binread::BinReaderExt;
+use core::ops::Range;
+use std::io::Write;
+
+/// Name of the $DATA stream of $BadClus, which maps all bad clusters of the volume and spans the entire volume.
+const BAD_CLUSTERS_STREAM_NAME: &str = "$Bad";
+
+/// Resizes the given filesystem to `new_size` bytes.
+///
+/// This is the implementation of [`Ntfs::resize`].
+pub(crate) fn resize<T>(ntfs: &Ntfs, fs: &mut T, new_size: u64) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    let sector_size = ntfs.sector_size() as u64;
+    let cluster_size = ntfs.cluster_size() as u64;
+
+    // Like `Ntfs::format`, we leave out the last sector for the backup boot sector.
+    let new_total_sectors = (new_size / sector_size)
+        .checked_sub(1)
+        .ok_or(NtfsError::VolumeTooSmall { size: new_size })?;
+    let new_cluster_count = new_total_sectors * sector_size / cluster_size;
+
+    let mut cluster_allocator = ntfs.cluster_allocator(fs)?;
+
+    if new_cluster_count < cluster_allocator.cluster_count() {
+        let file_record_numbers = used_file_record_numbers(ntfs, fs)?;
+        let moved_cluster_count = check_shrink(
+            ntfs,
+            fs,
+            &cluster_allocator,
+            &file_record_numbers,
+            new_cluster_count,
+        )?;
+
+        cluster_allocator.resize(new_cluster_count);
+        let free_cluster_count = cluster_allocator.free_cluster_count();
+        if moved_cluster_count > free_cluster_count {
+            return Err(NtfsError::NotEnoughFreeClusters {
+                requested: moved_cluster_count,
+                available: free_cluster_count,
+            });
+        }
+
+        let result = move_clusters(
+            ntfs,
+            fs,
+            &mut cluster_allocator,
+            &file_record_numbers,
+            new_cluster_count,
+        );
+
+        // Every moved attribute has already been written along with its new clusters,
+        // but clusters of a failed move may have been freed again in the meantime.
+        cluster_allocator.write(ntfs, fs)?;
+        result?;
+    } else {
+        cluster_allocator.resize(new_cluster_count);
+    }
+
+    // The in-memory bitmap has already been resized, so this also writes it back.
+    let mut bitmap_file = ntfs.file(fs, KnownNtfsFileRecordNumber::Bitmap as u64)?;
+    let bitmap_offset = bitmap_file.find_data_attribute_offset("")?;
+    bitmap_file.set_attribute_len(
+        fs,
+        &mut cluster_allocator,
+        NtfsClusterAllocator::allocate,
+        bitmap_offset,
+        bitmap_size(new_cluster_count),
+        true,
+    )?;
+
+    resize_bad_clusters(ntfs, fs, new_cluster_count)?;
+    let mft_mirror_lcn = update_mft_mirror(ntfs, fs)?;
+    write_boot_sectors(fs, new_total_sectors, sector_size, mft_mirror_lcn)?;
+    fs.flush()?;
+
+    Ok(())
+}
+
+/// Checks that all clusters at or beyond the LCN `new_cluster_count` can be moved when shrinking the volume,
+/// and returns their number.
+///
+/// Clusters can be moved if they belong to a non-resident attribute of a File Record that is in use.
+/// This is not the case for bad clusters, for the clusters holding the File Record of the $MFT itself
+/// (which are referenced by the boot sector), and for clusters that are marked as used without belonging
+/// to any file.
+fn check_shrink<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    cluster_allocator: &NtfsClusterAllocator,
+    file_record_numbers: &[u64],
+    new_cluster_count: u64,
+) -> Result<u64>
+where
+    T: Read + Seek,
+{
+    let cluster_size = ntfs.cluster_size() as u64;
+    let mft_lcn = ntfs.mft_position() / cluster_size;
+    let mft_record_end = mft_lcn + div_ceil(ntfs.file_record_size() as u64, cluster_size);
+
+    if mft_record_end > new_cluster_count {
+        return Err(NtfsError::UnmovableCluster {
+            lcn: Lcn::from(u64::max(mft_lcn, new_cluster_count)),
+        });
+    }
+
+    let mut moved_ranges = Vec::<Range<u64>>::new();
+
+    for &file_record_number in file_record_numbers {
+        let file = ntfs.file(fs, file_record_number)?;
+        if !file.flags().contains(NtfsFileFlags::IN_USE) {
+            continue;
+        }
+
+        for attribute in file.attributes_raw() {
+            if attribute.is_resident() {
+                continue;
+            }
+
+            let is_bad_clusters_stream = file_record_number
+                == KnownNtfsFileRecordNumber::BadClus as u64
+                && attribute.name()? == BAD_CLUSTERS_STREAM_NAME;
+            let data_runs = attribute.non_resident_value()?.data_runs();
+
+            for run in &NtfsRunlist::from_data_runs(data_runs)? {
+                let lcn = match run.lcn() {
+                    Some(lcn) => u64::from(lcn),
+                    None => continue,
+                };
+
+                let range = u64::max(lcn, new_cluster_count)..lcn + run.cluster_count();
+                if range.is_empty() {
+                    continue;
+                }
+
+                if is_bad_clusters_stream {
+                    return Err(NtfsError::UnmovableCluster {
+                        lcn: Lcn::from(range.start),
+                    });
+                }
+
+                moved_ranges.push(range);
+            }
+        }
+    }
+
+    // Every used cluster beyond the new end must be covered by a range that can be moved.
+    moved_ranges.sort_by_key(|range| range.start);
+    let mut lcn = new_cluster_count;
+    let cluster_count = cluster_allocator.cluster_count();
+
+    for range in moved_ranges
+        .iter()
+        .chain(Some(&(cluster_count..cluster_count)))
+    {
+        if let Some(unmovable_lcn) =
+            (lcn..range.start).find(|&lcn| cluster_allocator.is_allocated(Lcn::from(lcn)))
+        {
+            return Err(NtfsError::UnmovableCluster {
+                lcn: Lcn::from(unmovable_lcn),
+            });
+        }
+
+        lcn = u64::max(lcn, range.end);
+    }
+
+    Ok(moved_ranges
+        .iter()
+        .map(|range| range.end - range.start)
+        .sum())
+}
+
+/// Moves all clusters at or beyond the LCN `new_cluster_count` of the given File Records to free clusters
+/// before that LCN.
+fn move_clusters<T>(
+    ntfs: &Ntfs,
+    fs: &mut T,
+    cluster_allocator: &mut NtfsClusterAllocator,
+    file_record_numbers: &[u64],
+    new_cluster_count: u64,
+) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    for &file_record_number in file_record_numbers {
+        let mut file = ntfs.file(fs, file_record_number)?;
+        if !file.flags().contains(NtfsFileFlags::IN_USE) {
+            continue;
+        }
+
+        // Keep the $MFT close to its beginning if possible.
+        let allocate = if file_record_number == KnownNtfsFileRecordNumber::MFT as u64 {
+            NtfsClusterAllocator::allocate_mft
+        } else {
+            NtfsClusterAllocator::allocate
+        };
+
+        file.move_clusters_below(fs, cluster_allocator, allocate, new_cluster_count)?;
+    }
+
+    Ok(())
+}
+
+/// Truncates or extends the $Bad stream of $BadClus, so that it spans `new_cluster_count` clusters.
+fn resize_bad_clusters<T>(ntfs: &Ntfs, fs: &mut T, new_cluster_count: u64) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    let mut bad_clusters_file = ntfs.file(fs, KnownNtfsFileRecordNumber::BadClus as u64)?;
+    let offset = bad_clusters_file.find_data_attribute_offset(BAD_CLUSTERS_STREAM_NAME)?;
+    let attribute = NtfsAttribute::new(&bad_clusters_file, offset, None);
+
+    let data_runs = attribute.non_resident_value()?.data_runs();
+    let mut runlist = NtfsRunlist::from_data_runs(data_runs)?;
+    let cluster_count = runlist.cluster_count();
+
+    if new_cluster_count > cluster_count {
+        runlist.push(NtfsRun::sparse(new_cluster_count - cluster_count));
+    } else {
+        runlist.split_off_clusters(new_cluster_count);
+    }
+
+    let volume_size = new_cluster_count * ntfs.cluster_size() as u64;
+    let bytes = attribute.to_resized_non_resident_bytes(&runlist, volume_size, volume_size);
+    bad_clusters_file.replace_attribute(offset, &bytes)?;
+    bad_clusters_file.write_record(fs)
+}
+
+/// Copies the mirrored File Records of the $MFT to $MFTMirr again, which may have been moved, and returns
+/// the LCN of $MFTMirr (or `None` if it has no clusters).
+fn update_mft_mirror<T>(ntfs: &Ntfs, fs: &mut T) -> Result<Option<Lcn>>
+where
+    T: Read + Write + Seek,
+{
+    let mft_mirror = ntfs.file(fs, KnownNtfsFileRecordNumber::MFTMirr as u64)?;
+    let offset = mft_mirror.find_data_attribute_offset("")?;
+    let attribute = NtfsAttribute::new(&mft_mirror, offset, None);
+    let runlist = NtfsRunlist::from_data_runs(attribute.non_resident_value()?.data_runs())?;
+    let mft_mirror_lcn = match runlist.runs().first().and_then(NtfsRun::lcn) {
+        Some(mft_mirror_lcn) => mft_mirror_lcn,
+        None => return Ok(None),
+    };
+
+    let file_record_size = ntfs.file_record_size() as usize;
+    let mut records = vec![0u8; ntfs.mft_mirror_record_count() as usize * file_record_size];
+
+    for (file_record_number, record) in records.chunks_mut(file_record_size).enumerate() {
+        let position = ntfs.file_record_position(fs, file_record_number as u64)?;
+        fs.seek(SeekFrom::Start(position))?;
+        fs.read_exact(record)?;
+    }
+
+    runlist.write_at(ntfs, fs, 0, &records)?;
+    Ok(Some(mft_mirror_lcn))
+}
+
+/// Updates the total sector count and the $MFTMirr LCN in the boot sector, and writes its backup copy
+/// to the new last sector.
+fn write_boot_sectors<T>(
+    fs: &mut T,
+    total_sectors: u64,
+    sector_size: u64,
+    mft_mirror_lcn: Option<Lcn>,
+) -> Result<()>
+where
+    T: Read + Write + Seek,
+{
+    fs.seek(SeekFrom::Start(0))?;
+    let mut boot_sector = fs.read_le::<BootSector>()?;
+    boot_sector.bpb_mut().set_total_sectors(total_sectors);
+    if let Some(mft_mirror_lcn) = mft_mirror_lcn {
+        boot_sector.bpb_mut().set_mft_mirror_lcn(mft_mirror_lcn);
+    }
+    let bytes = boot_sector.to_bytes();
+
+    fs.seek(SeekFrom::Start(0))?;
+    fs.write_all(&bytes)?;
+    fs.seek(SeekFrom::Start(total_sectors * sector_size))?;
+    fs.write_all(&bytes)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::NtfsFormatOptions;
+    use crate::traits::NtfsReadSeek;
+    use std::io::Cursor;
+
+    const VOLUME_SIZE: usize = 8 * 1024 * 1024;
+
+    fn formatted_volume() -> Cursor<Vec<u8>> {
+        let mut fs = Cursor::new(vec![0u8; VOLUME_SIZE]);
+        Ntfs::format(&mut fs, &NtfsFormatOptions::new()).unwrap();
+        fs
+    }
+
+    fn read_data(
+        ntfs: &Ntfs,
+        fs: &mut Cursor<Vec<u8>>,
+        file_record_number: u64,
+        name: &str,
+    ) -> Vec<u8> {
+        let file = ntfs.file(fs, file_record_number).unwrap();
+        let data_item = file.data(fs, name).unwrap().unwrap();
+        let data_attribute = data_item.to_attribute();
+        let mut data_value = data_attribute.value().unwrap();
+
+        let mut data = vec![0u8; data_value.len() as usize];
+        data_value.read_exact(fs, &mut data).unwrap();
+        data
+    }
+
+    fn bad_clusters_len(ntfs: &Ntfs, fs: &mut Cursor<Vec<u8>>) -> u64 {
+        let file = ntfs
+            .file(fs, KnownNtfsFileRecordNumber::BadClus as u64)
+            .unwrap();
+        let data_item = file.data(fs, BAD_CLUSTERS_STREAM_NAME).unwrap().unwrap();
+        data_item.to_attribute().value_length()
+    }
+
+    #[test]
+    fn test_grow() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let old_size = testfs1.get_ref().len();
+        let new_size = old_size + 1024 * 1024;
+        testfs1.get_mut().resize(new_size, 0);
+
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        let cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        let old_cluster_count = cluster_allocator.cluster_count();
+        let old_free_cluster_count = cluster_allocator.free_cluster_count();
+
+        ntfs.resize(&mut testfs1, new_size as u64).unwrap();
+
+        let cluster_size = ntfs.cluster_size() as u64;
+        let new_cluster_count = (new_size as u64 - 512) / cluster_size;
+        assert_eq!(ntfs.size(), new_size as u64 - 512);
+        assert_eq!(
+            bad_clusters_len(&ntfs, &mut testfs1),
+            new_cluster_count * cluster_size
+        );
+
+        // The backup boot sector has moved to the new last sector.
+        let bytes = testfs1.get_ref();
+        assert_eq!(bytes[..512], bytes[new_size - 512..]);
+
+        // The new clusters are free and can be used.
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+        ntfs.read_upcase_table(&mut testfs1).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut testfs1).unwrap();
+        assert_eq!(cluster_allocator.cluster_count(), new_cluster_count);
+        // $Bitmap itself needs an additional cluster for the new clusters.
+        let bitmap_cluster_count =
+            |cluster_count| div_ceil(bitmap_size(cluster_count), cluster_size);
+        assert_eq!(
+            cluster_allocator.free_cluster_count(),
+            old_free_cluster_count + new_cluster_count
+                - old_cluster_count
+                - (bitmap_cluster_count(new_cluster_count)
+                    - bitmap_cluster_count(old_cluster_count))
+        );
+
+        let data = (0..(old_free_cluster_count + 16) * cluster_size)
+            .map(|i| i as u8)
+            .collect::<Vec<u8>>();
+        let mut root_dir = ntfs.root_directory(&mut testfs1).unwrap();
+        let mut file = ntfs
+            .create_file(
+                &mut testfs1,
+                &mut cluster_allocator,
+                &mut root_dir,
+                "big.dat",
+            )
+            .unwrap();
+        file.write_data_stream(&mut testfs1, &mut cluster_allocator, "", &data)
+            .unwrap();
+        let file_record_number = file.file_record_number();
+        assert_eq!(read_data(&ntfs, &mut testfs1, file_record_number, ""), data);
+    }
+
+    #[test]
+    fn test_shrink() {
+        let mut fs = formatted_volume();
+        let mut ntfs = Ntfs::new(&mut fs).unwrap();
+        ntfs.read_upcase_table(&mut fs).unwrap();
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut fs).unwrap();
+        let mut root_dir = ntfs.root_directory(&mut fs).unwrap();
+
+        let data = (0..1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+        let mut file = ntfs
+            .create_file(&mut fs, &mut cluster_allocator, &mut root_dir, "data.bin")
+            .unwrap();
+        file.write_data_stream(&mut fs, &mut cluster_allocator, "", &data)
+            .unwrap();
+        let file_record_number = file.file_record_number();
+        let old_mft_mirror_position = ntfs.mft_mirror_position();
+
+        // $MFTMirr, $LogFile, and $Bitmap are located beyond the new end and need to be moved.
+        let new_size = VOLUME_SIZE / 2;
+        ntfs.resize(&mut fs, new_size as u64).unwrap();
+        let cluster_size = ntfs.cluster_size() as u64;
+        let end_position = ntfs.size() / cluster_size * cluster_size;
+        assert!(old_mft_mirror_position >= end_position);
+        assert!(ntfs.mft_mirror_position() < end_position);
+        fs.get_mut().truncate(new_size);
+
+        let bytes = fs.get_ref();
+        assert_eq!(bytes[..512], bytes[new_size - 512..]);
+
+        let mut ntfs = Ntfs::new(&mut fs).unwrap();
+        ntfs.read_upcase_table(&mut fs).unwrap();
+        let new_cluster_count = (new_size as u64 - 512) / cluster_size;
+        assert_eq!(ntfs.size(), new_size as u64 - 512);
+        assert_eq!(
+            bad_clusters_len(&ntfs, &mut fs),
+            new_cluster_count * cluster_size
+        );
+
+        let cluster_allocator = ntfs.cluster_allocator(&mut fs).unwrap();
+        assert_eq!(cluster_allocator.cluster_count(), new_cluster_count);
+
+        // No file references a cluster beyond the new end anymore.
+        for file_record_number in used_file_record_numbers(&ntfs, &mut fs).unwrap() {
+            let file = ntfs.file(&mut fs, file_record_number).unwrap();
+            for attribute in file.attributes_raw().filter(|a| !a.is_resident()) {
+                let data_runs = attribute.non_resident_value().unwrap().data_runs();
+                for run in &NtfsRunlist::from_data_runs(data_runs).unwrap() {
+                    if let Some(lcn) = run.lcn() {
+                        assert!(u64::from(lcn) + run.cluster_count() <= new_cluster_count);
+                        assert!(cluster_allocator.is_allocated(lcn));
+                    }
+                }
+            }
+        }
+
+        // $MFTMirr has been copied to its new location.
+        let file_record_size = ntfs.file_record_size() as usize;
+        for file_record_number in 0..ntfs.mft_mirror_record_count() as usize {
+            let position = ntfs
+                .file_record_position(&mut fs, file_record_number as u64)
+                .unwrap() as usize;
+            let mirror_position =
+                ntfs.mft_mirror_position() as usize + file_record_number * file_record_size;
+            let bytes = fs.get_ref();
+            assert_eq!(
+                bytes[position..position + file_record_size],
+                bytes[mirror_position..mirror_position + file_record_size]
+            );
+        }
+
+        assert_eq!(read_data(&ntfs, &mut fs, file_record_number, ""), data);
+        ntfs.volume_info(&mut fs).unwrap();
+    }
+
+    #[test]
+    fn test_shrink_errors() {
+        let mut fs = formatted_volume();
+        let mut ntfs = Ntfs::new(&mut fs).unwrap();
+
+        // The $LogFile of 2 MiB alone doesn't fit into the remaining free clusters.
+        let new_size = 2 * 1024 * 1024;
+        assert!(matches!(
+            ntfs.resize(&mut fs, new_size),
+            Err(NtfsError::NotEnoughFreeClusters { .. })
+        ));
+
+        // Clusters that are marked as used without belonging to any file cannot be moved.
+        let mut cluster_allocator = ntfs.cluster_allocator(&mut fs).unwrap();
+        let free_cluster_count = cluster_allocator.free_cluster_count();
+        cluster_allocator.allocate(free_cluster_count).unwrap();
+        cluster_allocator.write(&ntfs, &mut fs).unwrap();
+
+        let new_size = VOLUME_SIZE as u64 / 2;
+        assert!(matches!(
+            ntfs.resize(&mut fs, new_size),
+            Err(NtfsError::UnmovableCluster { .. })
+        ));
+
+        // A failed shrink leaves the volume untouched.
+        let ntfs = Ntfs::new(&mut fs).unwrap();
+        assert_eq!(ntfs.size(), VOLUME_SIZE as u64 - 512);
+        let bytes = fs.get_ref();
+        assert_eq!(bytes[..512], bytes[VOLUME_SIZE - 512..]);
+    }
+}
@@ -0,0 +1,449 @@
+// Copyright 2021-2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::attribute_value::NtfsDataRuns;
+use crate::error::Result;
+#[cfg(feature = "std")]
+use crate::ntfs::Ntfs;
+use crate::types::Lcn;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use binread::io::{Read, Seek, SeekFrom};
+use core::mem;
+#[cfg(feature = "std")]
+use core::ops::Range;
+use core::slice;
+#[cfg(feature = "std")]
+use std::io::Write;
+
+/// A single run of an [`NtfsRunlist`], which maps a number of consecutive clusters of a non-resident value
+/// to the filesystem.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NtfsRun {
+    lcn: Option<Lcn>,
+    cluster_count: u64,
+}
+
+impl NtfsRun {
+    /// Creates a run of `cluster_count` clusters starting at the given Logical Cluster Number (LCN).
+    pub fn new(lcn: Lcn, cluster_count: u64) -> Self {
+        Self {
+            lcn: Some(lcn),
+            cluster_count,
+        }
+    }
+
+    /// Creates a "sparse" run of `cluster_count` clusters, which occupies no clusters on the filesystem
+    /// and reads as zeros.
+    pub fn sparse(cluster_count: u64) -> Self {
+        Self {
+            lcn: None,
+            cluster_count,
+        }
+    }
+
+    /// Returns the number of clusters of this run.
+    pub fn cluster_count(&self) -> u64 {
+        self.cluster_count
+    }
+
+    /// Returns `true` if this is a "sparse" run.
+    pub fn is_sparse(&self) -> bool {
+        self.lcn.is_none()
+    }
+
+    /// Returns the Logical Cluster Number (LCN) of the first cluster of this run,
+    /// or `None` if this is a "sparse" run.
+    pub fn lcn(&self) -> Option<Lcn> {
+        self.lcn
+    }
+
+    /// Returns the size of the Data Run header for this run, in bytes, with `previous_lcn` being the LCN of
+    /// the last non-sparse run before it (or zero).
+    ///
+    /// Also returns the byte counts of the cluster count and the relative LCN.
+    fn encoded_header(&self, previous_lcn: u64) -> (usize, u8, u8) {
+        let cluster_count_byte_count = unsigned_byte_count(self.cluster_count);
+        let lcn_byte_count = match self.lcn {
+            // A Data Run without a relative LCN is a "sparse" Data Run.
+            None => 0,
+            Some(lcn) => {
+                let relative_lcn = u64::from(lcn).wrapping_sub(previous_lcn) as i64;
+                signed_byte_count(relative_lcn)
+            }
+        };
+
+        let size = 1 + cluster_count_byte_count as usize + lcn_byte_count as usize;
+        (size, cluster_count_byte_count, lcn_byte_count)
+    }
+
+    /// Returns `true` if `next` directly continues this run and both can be merged.
+    fn is_continued_by(&self, next: &NtfsRun) -> bool {
+        match (self.lcn, next.lcn) {
+            (None, None) => true,
+            (Some(lcn), Some(next_lcn)) => {
+                u64::from(lcn).checked_add(self.cluster_count) == Some(u64::from(next_lcn))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// An in-memory list of runs, describing where the clusters of a non-resident value are stored.
+///
+/// This is the decoded form of the Data Runs (also called "mapping pairs") read by [`NtfsDataRuns`].
+/// Runlists are output by [`NtfsClusterAllocator`] when allocating clusters.
+///
+/// [`NtfsClusterAllocator`]: crate::NtfsClusterAllocator
+/// [`NtfsDataRuns`]: crate::attribute_value::NtfsDataRuns
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NtfsRunlist {
+    runs: Vec<NtfsRun>,
+}
+
+impl NtfsRunlist {
+    /// Creates an empty runlist.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes all Data Runs of the given [`NtfsDataRuns`] iterator into a runlist.
+    pub fn from_data_runs(data_runs: NtfsDataRuns) -> Result<Self> {
+        let cluster_size = data_runs.ntfs().cluster_size() as u64;
+        let mut runlist = Self::new();
+
+        for data_run in data_runs {
+            let data_run = data_run?;
+            let cluster_count = data_run.allocated_size() / cluster_size;
+
+            let run = match data_run.data_position() {
+                Some(position) => NtfsRun::new(Lcn::from(position / cluster_size), cluster_count),
+                None => NtfsRun::sparse(cluster_count),
+            };
+
+            runlist.runs.push(run);
+        }
+
+        Ok(runlist)
+    }
+
+    /// Returns the total number of clusters covered by all runs (including "sparse" ones).
+    pub fn cluster_count(&self) -> u64 {
+        self.runs.iter().map(|run| run.cluster_count).sum()
+    }
+
+    /// Encodes this runlist into the on-disk Data Runs (also called "mapping pairs") of a non-resident attribute.
+    ///
+    /// Each Data Run stores its cluster count and its LCN relative to the previous non-sparse run, using as few
+    /// bytes as possible.
+    /// The output is terminated by a zero byte and has the size returned by [`NtfsRunlist::encoded_size`].
+    ///
+    /// This is the inverse of [`NtfsDataRuns`].
+    ///
+    /// [`NtfsDataRuns`]: crate::attribute_value::NtfsDataRuns
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.encoded_size());
+        let mut previous_lcn = 0;
+
+        for run in &self.runs {
+            let (_, cluster_count_byte_count, lcn_byte_count) = run.encoded_header(previous_lcn);
+
+            // The lower nibble of the header byte indicates the length of the cluster count,
+            // the upper nibble indicates the length of the relative LCN.
+            data.push((lcn_byte_count << 4) | cluster_count_byte_count);
+            data.extend_from_slice(
+                &run.cluster_count.to_le_bytes()[..cluster_count_byte_count as usize],
+            );
+
+            if let Some(lcn) = run.lcn {
+                let lcn = u64::from(lcn);
+                let relative_lcn = lcn.wrapping_sub(previous_lcn);
+                data.extend_from_slice(&relative_lcn.to_le_bytes()[..lcn_byte_count as usize]);
+                previous_lcn = lcn;
+            }
+        }
+
+        // A zero byte marks the end of the Data Runs.
+        data.push(0);
+        data
+    }
+
+    /// Returns the size of the encoded Data Runs of this runlist, in bytes (including the terminating zero byte).
+    ///
+    /// Use this to check whether a non-resident attribute fits into its File Record.
+    /// See [`NtfsRunlist::encoded_size_of_first`] for splitting a runlist across multiple File Records.
+    pub fn encoded_size(&self) -> usize {
+        self.encoded_size_of_first(self.runs.len())
+    }
+
+    /// Returns the size of the encoded Data Runs of the first `run_count` runs of this runlist, in bytes
+    /// (including the terminating zero byte).
+    ///
+    /// Together with [`NtfsRunlist::split_off`], this helps splitting a runlist across multiple File Records.
+    /// Note that the relative LCNs of each part start from zero again, so each part must be encoded separately.
+    pub fn encoded_size_of_first(&self, run_count: usize) -> usize {
+        let mut size = 0;
+        let mut previous_lcn = 0;
+
+        for run in self.runs.iter().take(run_count) {
+            size += run.encoded_header(previous_lcn).0;
+
+            if let Some(lcn) = run.lcn {
+                previous_lcn = u64::from(lcn);
+            }
+        }
+
+        size + mem::size_of::<u8>()
+    }
+
+    /// Returns `true` if this runlist contains no runs.
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// Returns an iterator over all runs of this runlist.
+    pub fn iter(&self) -> slice::Iter<'_, NtfsRun> {
+        self.runs.iter()
+    }
+
+    /// Appends a run to the end of this runlist.
+    ///
+    /// If the run directly continues the last run, both are merged into a single run.
+    /// Runs without any clusters are ignored.
+    pub fn push(&mut self, run: NtfsRun) {
+        if run.cluster_count == 0 {
+            return;
+        }
+
+        if let Some(last_run) = self.runs.last_mut() {
+            if last_run.is_continued_by(&run) {
+                last_run.cluster_count += run.cluster_count;
+                return;
+            }
+        }
+
+        self.runs.push(run);
+    }
+
+    /// Returns a slice of all runs of this runlist.
+    pub fn runs(&self) -> &[NtfsRun] {
+        &self.runs
+    }
+
+    /// Splits this runlist into two at the given run index.
+    ///
+    /// This runlist keeps the runs `[0, at)` and the returned runlist contains the runs `[at, len)`.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        Self {
+            runs: self.runs.split_off(at),
+        }
+    }
+
+    /// Splits this runlist into two after the given number of clusters.
+    ///
+    /// This runlist keeps the first `cluster_count` clusters and the returned runlist contains all clusters after that.
+    /// A run crossing the split point is divided into two runs.
+    pub fn split_off_clusters(&mut self, cluster_count: u64) -> Self {
+        let mut remaining_cluster_count = cluster_count;
+
+        for (i, run) in self.runs.iter_mut().enumerate() {
+            if remaining_cluster_count >= run.cluster_count {
+                remaining_cluster_count -= run.cluster_count;
+                continue;
+            }
+
+            if remaining_cluster_count == 0 {
+                return self.split_off(i);
+            }
+
+            // Divide this run.
+            let second_run = NtfsRun {
+                lcn: run
+                    .lcn
+                    .map(|lcn| Lcn::from(u64::from(lcn) + remaining_cluster_count)),
+                cluster_count: run.cluster_count - remaining_cluster_count,
+            };
+            run.cluster_count = remaining_cluster_count;
+
+            let mut second_part = self.split_off(i + 1);
+            second_part.runs.insert(0, second_run);
+            return second_part;
+        }
+
+        Self::new()
+    }
+
+    /// Writes `buf` to the clusters of this runlist, starting at the given byte offset.
+    ///
+    /// Parts of `buf` that fall into "sparse" runs are skipped, so they should only contain zeros.
+    /// Writing beyond the last cluster of this runlist is silently truncated.
+    #[cfg(feature = "std")]
+    pub(crate) fn write_at<T>(&self, ntfs: &Ntfs, fs: &mut T, offset: u64, buf: &[u8]) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let cluster_size = ntfs.cluster_size() as u64;
+        let end = offset + buf.len() as u64;
+        let mut run_start = 0u64;
+
+        for run in &self.runs {
+            let run_end = run_start + run.cluster_count * cluster_size;
+
+            if let Some(lcn) = run.lcn {
+                let write_start = u64::max(offset, run_start);
+                let write_end = u64::min(end, run_end);
+
+                if write_start < write_end {
+                    let position = lcn.position(ntfs)? + (write_start - run_start);
+                    let buf_range = (write_start - offset) as usize..(write_end - offset) as usize;
+
+                    fs.seek(SeekFrom::Start(position))?;
+                    fs.write_all(&buf[buf_range])?;
+                }
+            }
+
+            if run_end >= end {
+                break;
+            }
+
+            run_start = run_end;
+        }
+
+        Ok(())
+    }
+
+    /// Fills the given byte range of the clusters of this runlist with zeros.
+    ///
+    /// "Sparse" runs are skipped, because they read as zeros anyway.
+    #[cfg(feature = "std")]
+    pub(crate) fn write_zeros<T>(&self, ntfs: &Ntfs, fs: &mut T, range: Range<u64>) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        const ZERO_BUFFER_SIZE: u64 = 4096;
+        let zeros = [0u8; ZERO_BUFFER_SIZE as usize];
+        let mut offset = range.start;
+
+        while offset < range.end {
+            let length = u64::min(range.end - offset, ZERO_BUFFER_SIZE);
+            self.write_at(ntfs, fs, offset, &zeros[..length as usize])?;
+            offset += length;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a NtfsRunlist {
+    type Item = &'a NtfsRun;
+    type IntoIter = slice::Iter<'a, NtfsRun>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Returns the minimum number of bytes to store the given value as an unsigned little-endian integer
+/// that is also never misinterpreted as a negative signed one.
+fn unsigned_byte_count(value: u64) -> u8 {
+    if value > i64::MAX as u64 {
+        mem::size_of::<u64>() as u8
+    } else {
+        signed_byte_count(value as i64)
+    }
+}
+
+/// Returns the minimum number of bytes (at least 1) to store the given value as a signed little-endian integer.
+fn signed_byte_count(value: i64) -> u8 {
+    let mut byte_count = 1;
+
+    while byte_count < mem::size_of::<i64>() as u8 {
+        // Check if sign-extending the lower `byte_count` bytes yields the value again.
+        let unused_bits = (mem::size_of::<i64>() as u32 - byte_count as u32) * 8;
+        if value.wrapping_shl(unused_bits).wrapping_shr(unused_bits) == value {
+            break;
+        }
+
+        byte_count += 1;
+    }
+
+    byte_count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runlist_push() {
+        let mut runlist = NtfsRunlist::new();
+        runlist.push(NtfsRun::new(Lcn::from(100), 10));
+        runlist.push(NtfsRun::new(Lcn::from(110), 5));
+        runlist.push(NtfsRun::new(Lcn::from(200), 1));
+        runlist.push(NtfsRun::sparse(3));
+        runlist.push(NtfsRun::sparse(4));
+        runlist.push(NtfsRun::new(Lcn::from(201), 0));
+
+        assert_eq!(
+            runlist.runs(),
+            &[
+                NtfsRun::new(Lcn::from(100), 15),
+                NtfsRun::new(Lcn::from(200), 1),
+                NtfsRun::sparse(7),
+            ]
+        );
+        assert_eq!(runlist.cluster_count(), 23);
+
+        let mut second_part = runlist.split_off_clusters(18);
+        assert_eq!(
+            runlist.runs(),
+            &[
+                NtfsRun::new(Lcn::from(100), 15),
+                NtfsRun::new(Lcn::from(200), 1),
+                NtfsRun::sparse(2)
+            ]
+        );
+        assert_eq!(second_part.runs(), &[NtfsRun::sparse(5)]);
+        assert!(second_part.split_off_clusters(5).is_empty());
+        assert_eq!(runlist.split_off_clusters(15).cluster_count(), 3);
+        assert_eq!(runlist.runs(), &[NtfsRun::new(Lcn::from(100), 15)]);
+    }
+
+    #[test]
+    fn test_runlist_encode() {
+        let mut runlist = NtfsRunlist::new();
+        runlist.push(NtfsRun::new(Lcn::from(0x10), 0x20));
+        runlist.push(NtfsRun::sparse(0x80));
+        runlist.push(NtfsRun::new(Lcn::from(0x12345), 1));
+        runlist.push(NtfsRun::new(Lcn::from(0x100), 0xffff));
+
+        // 0x20 clusters at LCN 0x10.
+        let mut expected = vec![0x11, 0x20, 0x10];
+        // 0x80 sparse clusters (the cluster count needs 2 bytes to remain positive).
+        expected.extend_from_slice(&[0x02, 0x80, 0x00]);
+        // 1 cluster at LCN 0x10 + 0x12335.
+        // The sparse run doesn't change the base for the relative LCN.
+        expected.extend_from_slice(&[0x31, 0x01, 0x35, 0x23, 0x01]);
+        // 0xffff clusters at LCN 0x12345 - 0x12245.
+        expected.extend_from_slice(&[0x33, 0xff, 0xff, 0x00, 0xbb, 0xdd, 0xfe]);
+        // End marker.
+        expected.push(0x00);
+
+        assert_eq!(runlist.encode(), expected);
+        assert_eq!(runlist.encoded_size(), expected.len());
+        assert_eq!(runlist.encoded_size_of_first(2), 7);
+        assert_eq!(NtfsRunlist::new().encode(), [0]);
+
+        // Check that our decoder agrees.
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let ntfs = crate::ntfs::Ntfs::new(&mut testfs1).unwrap();
+        let data = runlist.encode();
+        let data_runs = NtfsDataRuns::new(&ntfs, &data, 0);
+        assert_eq!(NtfsRunlist::from_data_runs(data_runs).unwrap(), runlist);
+
+        // Each part of a split runlist is encoded separately.
+        let second_part = runlist.split_off(2);
+        assert_eq!(runlist.encoded_size(), 7);
+        assert_eq!(second_part.encode()[..5], [0x31, 0x01, 0x45, 0x23, 0x01]);
+    }
+}
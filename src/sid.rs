@@ -0,0 +1,161 @@
+// Copyright 2021-2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::error::{NtfsError, Result};
+use crate::indexes::NtfsIndexEntryKey;
+use alloc::vec::Vec;
+use arrayvec::ArrayVec;
+use byteorder::{ByteOrder, LittleEndian};
+use core::fmt;
+use core::mem;
+
+/// Size of the fixed part of a SID (revision, sub authority count, and identifier authority).
+const SID_HEADER_SIZE: usize = 8;
+
+/// Windows limits the number of sub authorities of a SID to 15.
+const SID_MAX_SUB_AUTHORITIES: usize = 15;
+
+/// A Security Identifier (SID), used for identifying users and groups in Windows.
+///
+/// NTFS stores SIDs in security descriptors and in the $Quota file.
+///
+/// Reference: <https://docs.microsoft.com/en-us/windows/win32/secauthz/security-identifiers>
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NtfsSid {
+    revision: u8,
+    identifier_authority: [u8; 6],
+    sub_authorities: ArrayVec<u32, SID_MAX_SUB_AUTHORITIES>,
+}
+
+impl NtfsSid {
+    /// Creates a SID from the given revision, identifier authority, and sub authorities.
+    ///
+    /// Returns `None` if more than 15 sub authorities are given.
+    pub fn new(revision: u8, identifier_authority: u64, sub_authorities: &[u32]) -> Option<Self> {
+        let mut sid = Self {
+            revision,
+            identifier_authority: [0u8; 6],
+            sub_authorities: ArrayVec::new(),
+        };
+
+        // The identifier authority is a 48-bit big-endian value.
+        sid.identifier_authority
+            .copy_from_slice(&identifier_authority.to_be_bytes()[2..]);
+        sid.sub_authorities
+            .try_extend_from_slice(sub_authorities)
+            .ok()?;
+
+        Some(sid)
+    }
+
+    pub(crate) fn from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        let expected = if slice.len() >= SID_HEADER_SIZE {
+            SID_HEADER_SIZE + slice[1] as usize * mem::size_of::<u32>()
+        } else {
+            SID_HEADER_SIZE
+        };
+
+        if slice.len() < expected || slice[1] as usize > SID_MAX_SUB_AUTHORITIES {
+            return Err(NtfsError::InvalidSidSize {
+                position,
+                expected,
+                actual: slice.len(),
+            });
+        }
+
+        let mut identifier_authority = [0u8; 6];
+        identifier_authority.copy_from_slice(&slice[2..SID_HEADER_SIZE]);
+
+        let sub_authorities = slice[SID_HEADER_SIZE..expected]
+            .chunks_exact(mem::size_of::<u32>())
+            .map(LittleEndian::read_u32)
+            .collect();
+
+        Ok(Self {
+            revision: slice[0],
+            identifier_authority,
+            sub_authorities,
+        })
+    }
+
+    /// Returns the 48-bit identifier authority, e.g. 5 for the NT Authority.
+    pub fn identifier_authority(&self) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes[2..].copy_from_slice(&self.identifier_authority);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// Returns the revision of this SID, which is always 1.
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    /// Returns the size of this SID on disk, in bytes.
+    pub fn size(&self) -> usize {
+        SID_HEADER_SIZE + self.sub_authorities.len() * mem::size_of::<u32>()
+    }
+
+    /// Returns the sub authorities of this SID.
+    /// The last one is the relative identifier (RID).
+    pub fn sub_authorities(&self) -> &[u32] {
+        &self.sub_authorities
+    }
+
+    /// Returns the SID in the same byte representation as on disk.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.push(self.revision);
+        bytes.push(self.sub_authorities.len() as u8);
+        bytes.extend_from_slice(&self.identifier_authority);
+
+        for sub_authority in &self.sub_authorities {
+            bytes.extend_from_slice(&sub_authority.to_le_bytes());
+        }
+
+        bytes
+    }
+}
+
+impl fmt::Display for NtfsSid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "S-{}-{}", self.revision, self.identifier_authority())?;
+
+        for sub_authority in &self.sub_authorities {
+            write!(f, "-{}", sub_authority)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The $O index of $Quota uses the SID of the owner as its key.
+impl NtfsIndexEntryKey for NtfsSid {
+    fn key_from_slice(slice: &[u8], position: u64) -> Result<Self> {
+        Self::from_slice(slice, position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sid() {
+        // BUILTIN\Administrators
+        let sid = NtfsSid::new(1, 5, &[32, 544]).unwrap();
+        assert_eq!(sid.to_string(), "S-1-5-32-544");
+        assert_eq!(sid.size(), 16);
+
+        let sid_bytes = sid.to_bytes();
+        assert_eq!(
+            sid_bytes,
+            [1, 2, 0, 0, 0, 0, 0, 5, 32, 0, 0, 0, 0x20, 0x02, 0, 0]
+        );
+
+        let parsed_sid = NtfsSid::key_from_slice(&sid_bytes, 0).unwrap();
+        assert_eq!(parsed_sid, sid);
+
+        assert!(NtfsSid::key_from_slice(&sid_bytes[..12], 0).is_err());
+        assert!(NtfsSid::new(1, 5, &[0; 16]).is_none());
+    }
+}
@@ -1,6 +1,8 @@
 // Copyright 2021 Colin Finck <colin@reactos.org>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+#[cfg(feature = "std")]
+use crate::attribute::align_to_attribute;
 use crate::attribute::{NtfsAttribute, NtfsAttributeType};
 use crate::attribute_value::{NtfsAttributeValue, NtfsNonResidentAttributeValue};
 use crate::error::{NtfsError, Result};
@@ -14,7 +16,11 @@ use crate::types::Vcn;
 use arrayvec::ArrayVec;
 use binread::io::{Cursor, Read, Seek, SeekFrom};
 use binread::{BinRead, BinReaderExt};
+#[cfg(feature = "std")]
+use byteorder::{ByteOrder, LittleEndian};
 use core::mem;
+#[cfg(feature = "std")]
+use {alloc::vec::Vec, memoffset::offset_of};
 
 /// Size of all [`AttributeListEntryHeader`] fields.
 const ATTRIBUTE_LIST_ENTRY_HEADER_SIZE: usize = 26;
@@ -25,6 +31,7 @@ const NAME_MAX_SIZE: usize = (u8::MAX as usize) * mem::size_of::<u16>();
 
 #[allow(unused)]
 #[derive(BinRead, Clone, Debug)]
+#[repr(C, packed)]
 struct AttributeListEntryHeader {
     /// Type of the attribute, known types are in [`NtfsAttributeType`].
     ty: u32,
@@ -44,6 +51,104 @@ struct AttributeListEntryHeader {
     instance: u16,
 }
 
+/// Builds the raw bytes of an Attribute List entry for the attribute with the given type code and name
+/// (as raw UTF-16 bytes), which is stored in the File Record referenced by `base_file_reference`.
+#[cfg(feature = "std")]
+pub(crate) fn build_attribute_list_entry(
+    type_code: u32,
+    name: &[u8],
+    lowest_vcn: Vcn,
+    base_file_reference: NtfsFileReference,
+    instance: u16,
+) -> Vec<u8> {
+    let name_offset = ATTRIBUTE_LIST_ENTRY_HEADER_SIZE;
+    let list_entry_length = align_to_attribute(name_offset + name.len());
+    let mut entry = vec![0u8; list_entry_length];
+
+    LittleEndian::write_u32(
+        &mut entry[offset_of!(AttributeListEntryHeader, ty)..],
+        type_code,
+    );
+    LittleEndian::write_u16(
+        &mut entry[offset_of!(AttributeListEntryHeader, list_entry_length)..],
+        list_entry_length as u16,
+    );
+    entry[offset_of!(AttributeListEntryHeader, name_length)] =
+        (name.len() / mem::size_of::<u16>()) as u8;
+    entry[offset_of!(AttributeListEntryHeader, name_offset)] = name_offset as u8;
+    LittleEndian::write_i64(
+        &mut entry[offset_of!(AttributeListEntryHeader, lowest_vcn)..],
+        lowest_vcn.into(),
+    );
+    set_raw_attribute_list_entry_location(&mut entry, base_file_reference, instance);
+    entry[name_offset..name_offset + name.len()].copy_from_slice(name);
+
+    entry
+}
+
+/// Inserts the given raw Attribute List entry into `entries`, keeping them sorted by attribute type.
+#[cfg(feature = "std")]
+pub(crate) fn insert_raw_attribute_list_entry(entries: &mut Vec<Vec<u8>>, entry: Vec<u8>) {
+    let type_code = raw_attribute_list_entry_type_code(&entry);
+    let index = entries
+        .iter()
+        .position(|e| raw_attribute_list_entry_type_code(e) > type_code)
+        .unwrap_or(entries.len());
+    entries.insert(index, entry);
+}
+
+/// Splits the given raw $ATTRIBUTE_LIST value into the raw bytes of its entries.
+#[cfg(feature = "std")]
+pub(crate) fn raw_attribute_list_entries(mut value: &[u8]) -> Vec<Vec<u8>> {
+    let mut entries = Vec::new();
+
+    while value.len() >= ATTRIBUTE_LIST_ENTRY_HEADER_SIZE {
+        let start = offset_of!(AttributeListEntryHeader, list_entry_length);
+        let list_entry_length = LittleEndian::read_u16(&value[start..]) as usize;
+        if list_entry_length < ATTRIBUTE_LIST_ENTRY_HEADER_SIZE || list_entry_length > value.len() {
+            break;
+        }
+
+        entries.push(value[..list_entry_length].to_vec());
+        value = &value[list_entry_length..];
+    }
+
+    entries
+}
+
+/// Returns the File Record Number and the instance of the attribute described by the given raw Attribute List entry.
+#[cfg(feature = "std")]
+pub(crate) fn raw_attribute_list_entry_location(entry: &[u8]) -> (u64, u16) {
+    let start = offset_of!(AttributeListEntryHeader, base_file_reference);
+    let file_reference =
+        NtfsFileReference::new(LittleEndian::read_u64(&entry[start..]).to_le_bytes());
+    let start = offset_of!(AttributeListEntryHeader, instance);
+    let instance = LittleEndian::read_u16(&entry[start..]);
+
+    (file_reference.file_record_number(), instance)
+}
+
+/// Returns the attribute type code stored in the given raw Attribute List entry.
+#[cfg(feature = "std")]
+pub(crate) fn raw_attribute_list_entry_type_code(entry: &[u8]) -> u32 {
+    LittleEndian::read_u32(&entry[offset_of!(AttributeListEntryHeader, ty)..])
+}
+
+/// Sets the File Record and the instance of the attribute described by the given raw Attribute List entry.
+#[cfg(feature = "std")]
+pub(crate) fn set_raw_attribute_list_entry_location(
+    entry: &mut [u8],
+    base_file_reference: NtfsFileReference,
+    instance: u16,
+) {
+    let start = offset_of!(AttributeListEntryHeader, base_file_reference);
+    entry[start..start + 8].copy_from_slice(&base_file_reference.to_bytes());
+    LittleEndian::write_u16(
+        &mut entry[offset_of!(AttributeListEntryHeader, instance)..],
+        instance,
+    );
+}
+
 /// Structure of an $ATTRIBUTE_LIST attribute.
 ///
 /// When a File Record lacks space to incorporate further attributes, NTFS creates an additional File Record,
@@ -228,7 +333,7 @@ impl NtfsAttributeListEntry {
     }
 
     /// Gets the attribute name and returns it wrapped in an [`NtfsString`].
-    pub fn name(&self) -> NtfsString {
+    pub fn name(&self) -> NtfsString<'_> {
         NtfsString(&self.name)
     }
 
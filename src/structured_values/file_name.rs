@@ -8,16 +8,48 @@ use crate::file_reference::NtfsFileReference;
 use crate::indexes::NtfsIndexEntryKey;
 use crate::string::NtfsString;
 use crate::structured_values::{NtfsFileAttributeFlags, NtfsStructuredValue};
+#[cfg(feature = "std")]
+use crate::structured_values::{
+    NtfsReparseTag, NtfsStandardInformation, STRUCTURAL_FILE_ATTRIBUTES,
+};
 use crate::time::NtfsTime;
 use arrayvec::ArrayVec;
 use binread::io::{Cursor, Read, Seek};
 use binread::{BinRead, BinReaderExt};
+#[cfg(feature = "std")]
+use byteorder::{ByteOrder, LittleEndian};
 use core::mem;
 use enumn::N;
+#[cfg(feature = "std")]
+use {
+    alloc::string::{String, ToString},
+    alloc::vec::Vec,
+};
 
 /// Size of all [`FileNameHeader`] fields.
 const FILE_NAME_HEADER_SIZE: usize = 66;
 
+/// Offset of the `allocated_size` field of a [`FileNameHeader`], which is directly followed by the `data_size` field.
+#[cfg(feature = "std")]
+const FILE_NAME_ALLOCATED_SIZE_OFFSET: usize = 40;
+
+/// Offset of the `creation_time` field of a [`FileNameHeader`], which is directly followed by the other file times.
+#[cfg(feature = "std")]
+const FILE_NAME_CREATION_TIME_OFFSET: usize = 8;
+
+/// Offset of the `file_attributes` field of a [`FileNameHeader`].
+#[cfg(feature = "std")]
+const FILE_NAME_FILE_ATTRIBUTES_OFFSET: usize = 56;
+
+/// Offset of the `reparse_point_tag` field of a [`FileNameHeader`].
+#[cfg(feature = "std")]
+const FILE_NAME_REPARSE_POINT_TAG_OFFSET: usize = 60;
+
+/// Offset of the `name_length` field of a [`FileNameHeader`], which directly follows the fields duplicated from
+/// other attributes.
+#[cfg(feature = "std")]
+const FILE_NAME_NAME_LENGTH_OFFSET: usize = 64;
+
 /// The smallest FileName attribute has a name containing just a single character.
 const FILE_NAME_MIN_SIZE: usize = FILE_NAME_HEADER_SIZE + mem::size_of::<u16>();
 
@@ -41,6 +73,266 @@ struct FileNameHeader {
     namespace: u8,
 }
 
+/// Characters that are not allowed in Win32 file names, in addition to the control characters.
+#[cfg(feature = "std")]
+const WIN32_INVALID_CHARACTERS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// Characters that are not allowed in MS-DOS 8+3 file names, in addition to the Win32 ones.
+#[cfg(feature = "std")]
+const DOS_INVALID_CHARACTERS: &[char] = &[' ', '+', ',', '.', ';', '=', '[', ']'];
+
+/// Builds the raw value of a new $FILE_NAME attribute with all file times set to `time` and zero file sizes.
+#[cfg(feature = "std")]
+pub(crate) fn build_file_name(
+    parent_directory_reference: NtfsFileReference,
+    time: NtfsTime,
+    file_attributes: NtfsFileAttributeFlags,
+    namespace: NtfsFileNamespace,
+    name: &[u16],
+) -> Vec<u8> {
+    let mut value = Vec::with_capacity(FILE_NAME_HEADER_SIZE + mem::size_of_val(name));
+    value.extend_from_slice(&parent_directory_reference.to_bytes());
+
+    // Creation, modification, MFT record modification, and access time.
+    for _ in 0..4 {
+        value.extend_from_slice(&time.nt_timestamp().to_le_bytes());
+    }
+
+    // Allocated size and data size are zero, followed by the file attributes and a zero reparse point tag.
+    value.resize(
+        FILE_NAME_ALLOCATED_SIZE_OFFSET + 2 * mem::size_of::<u64>(),
+        0,
+    );
+    value.extend_from_slice(&file_attributes.bits().to_le_bytes());
+    value.extend_from_slice(&0u32.to_le_bytes());
+
+    value.push(name.len() as u8);
+    value.push(namespace as u8);
+
+    for code_unit in name {
+        value.extend_from_slice(&code_unit.to_le_bytes());
+    }
+
+    value
+}
+
+/// Builds the raw values of all $FILE_NAME attributes for a new hard link named `name`.
+///
+/// Names that are valid Win32 and MS-DOS names (ignoring case) get a single [`NtfsFileNamespace::Win32AndDos`] value,
+/// and names that aren't valid Win32 names get a single [`NtfsFileNamespace::Posix`] value.
+/// All other names get a [`NtfsFileNamespace::Win32`] value followed by a [`NtfsFileNamespace::Dos`] value with a
+/// generated 8+3 name.
+/// `dos_name_exists` is called to check that the generated name doesn't exist in the directory yet.
+///
+/// Returns [`NtfsError::InvalidFileName`] if `name` is no valid NTFS file name.
+#[cfg(feature = "std")]
+pub(crate) fn build_file_names<F>(
+    parent_directory_reference: NtfsFileReference,
+    time: NtfsTime,
+    file_attributes: NtfsFileAttributeFlags,
+    name: &str,
+    dos_name_exists: F,
+) -> Result<Vec<Vec<u8>>>
+where
+    F: FnMut(&str) -> Result<bool>,
+{
+    let utf16_name = validate_file_name(name)?;
+    let build = |namespace, name: &[u16]| {
+        build_file_name(
+            parent_directory_reference,
+            time,
+            file_attributes,
+            namespace,
+            name,
+        )
+    };
+
+    if !is_win32_name(name) {
+        return Ok(vec![build(NtfsFileNamespace::Posix, &utf16_name)]);
+    }
+
+    if is_dos_name(name) {
+        return Ok(vec![build(NtfsFileNamespace::Win32AndDos, &utf16_name)]);
+    }
+
+    let dos_name = generate_dos_name(name, dos_name_exists)?;
+    let utf16_dos_name = dos_name.encode_utf16().collect::<Vec<u16>>();
+
+    Ok(vec![
+        build(NtfsFileNamespace::Win32, &utf16_name),
+        build(NtfsFileNamespace::Dos, &utf16_dos_name),
+    ])
+}
+
+/// Copies the file times, file sizes, file attributes, and reparse point tag from the raw bytes of the `source`
+/// $FILE_NAME structure to the raw bytes of `file_name`, keeping its parent directory reference and name.
+#[cfg(feature = "std")]
+pub(crate) fn copy_file_name_information(file_name: &mut [u8], source: &[u8]) {
+    let range = mem::size_of::<NtfsFileReference>()..FILE_NAME_NAME_LENGTH_OFFSET;
+    file_name[range.clone()].copy_from_slice(&source[range]);
+}
+
+/// Maps the characters of `name` to valid MS-DOS characters, skipping spaces and dots.
+#[cfg(feature = "std")]
+fn dos_characters(name: &str) -> impl Iterator<Item = char> + '_ {
+    name.chars().filter(|&c| c != ' ' && c != '.').map(|c| {
+        if is_dos_character(c) {
+            c.to_ascii_uppercase()
+        } else {
+            '_'
+        }
+    })
+}
+
+/// Generates an MS-DOS 8+3 name for `name` in the style of Windows (e.g. "LONGFI~1.TXT"),
+/// which is the first one for which `exists` returns `false`.
+#[cfg(feature = "std")]
+fn generate_dos_name<F>(name: &str, mut exists: F) -> Result<String>
+where
+    F: FnMut(&str) -> Result<bool>,
+{
+    // The extension begins after the last dot, unless that dot is the first character.
+    let (base, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+
+    let mut base = dos_characters(base).collect::<String>();
+    if base.is_empty() {
+        base.push('_');
+    }
+
+    let extension = dos_characters(extension).take(3).collect::<String>();
+
+    // The base name is shortened to make room for the "~" suffix, which always fits as long as it has less
+    // than 8 characters.
+    for number in 1..1_000_000 {
+        let suffix = format!("~{}", number);
+        let base_length = usize::min(base.len(), 8 - suffix.len());
+
+        let mut dos_name = format!("{}{}", &base[..base_length], suffix);
+        if !extension.is_empty() {
+            dos_name.push('.');
+            dos_name.push_str(&extension);
+        }
+
+        if !exists(&dos_name)? {
+            return Ok(dos_name);
+        }
+    }
+
+    Err(NtfsError::InvalidFileName {
+        name: name.to_string(),
+    })
+}
+
+/// Returns whether `c` may be part of an MS-DOS 8+3 name (in lowercase or uppercase).
+#[cfg(feature = "std")]
+fn is_dos_character(c: char) -> bool {
+    c.is_ascii_graphic()
+        && !WIN32_INVALID_CHARACTERS.contains(&c)
+        && !DOS_INVALID_CHARACTERS.contains(&c)
+}
+
+/// Returns whether `name` is a valid MS-DOS 8+3 name when ignoring the case of its letters.
+#[cfg(feature = "std")]
+fn is_dos_name(name: &str) -> bool {
+    let (base, extension) = name.split_once('.').unwrap_or((name, ""));
+    let is_valid_part =
+        |part: &str, max_length| part.len() <= max_length && part.chars().all(is_dos_character);
+
+    !base.is_empty() && is_valid_part(base, 8) && is_valid_part(extension, 3)
+}
+
+/// Returns whether `name` is a valid Win32 file name.
+#[cfg(feature = "std")]
+fn is_win32_name(name: &str) -> bool {
+    !name.ends_with(' ')
+        && !name.ends_with('.')
+        && !name
+            .chars()
+            .any(|c| c.is_control() || WIN32_INVALID_CHARACTERS.contains(&c))
+}
+
+/// Stores the given reparse point tag in the raw bytes of a $FILE_NAME structure and sets the
+/// [`NtfsFileAttributeFlags::REPARSE_POINT`] flag.
+#[cfg(feature = "std")]
+pub(crate) fn set_file_name_reparse_point_tag(file_name: &mut [u8], tag: NtfsReparseTag) {
+    let start = FILE_NAME_FILE_ATTRIBUTES_OFFSET;
+    let file_attributes =
+        NtfsFileAttributeFlags::from_bits_truncate(LittleEndian::read_u32(&file_name[start..]))
+            | NtfsFileAttributeFlags::REPARSE_POINT;
+    LittleEndian::write_u32(&mut file_name[start..], file_attributes.bits());
+
+    let start = FILE_NAME_REPARSE_POINT_TAG_OFFSET;
+    LittleEndian::write_u32(&mut file_name[start..], tag.0);
+}
+
+/// Updates the file sizes duplicated in the raw bytes of a $FILE_NAME structure,
+/// e.g. in the key of a directory Index Entry.
+#[cfg(feature = "std")]
+pub(crate) fn set_file_name_sizes(file_name: &mut [u8], allocated_size: u64, data_size: u64) {
+    let start = FILE_NAME_ALLOCATED_SIZE_OFFSET;
+    LittleEndian::write_u64(&mut file_name[start..], allocated_size);
+
+    let start = start + mem::size_of::<u64>();
+    LittleEndian::write_u64(&mut file_name[start..], data_size);
+}
+
+/// Updates the file times and file attributes duplicated in the raw bytes of a $FILE_NAME structure
+/// to the ones of `info`.
+///
+/// The [`STRUCTURAL_FILE_ATTRIBUTES`] and the [`NtfsFileAttributeFlags::IS_DIRECTORY`] flag,
+/// which only exists in $FILE_NAME structures, are kept.
+#[cfg(feature = "std")]
+pub(crate) fn set_file_name_times_and_attributes(
+    file_name: &mut [u8],
+    info: &NtfsStandardInformation,
+) {
+    let times = [
+        info.creation_time(),
+        info.modification_time(),
+        info.mft_record_modification_time(),
+        info.access_time(),
+    ];
+
+    for (i, time) in times.iter().enumerate() {
+        let start = FILE_NAME_CREATION_TIME_OFFSET + i * mem::size_of::<NtfsTime>();
+        LittleEndian::write_u64(&mut file_name[start..], time.nt_timestamp());
+    }
+
+    let start = FILE_NAME_FILE_ATTRIBUTES_OFFSET;
+    let old_file_attributes =
+        NtfsFileAttributeFlags::from_bits_truncate(LittleEndian::read_u32(&file_name[start..]));
+    let kept_file_attributes = STRUCTURAL_FILE_ATTRIBUTES | NtfsFileAttributeFlags::IS_DIRECTORY;
+    let file_attributes = (info.file_attributes() - kept_file_attributes)
+        | (old_file_attributes & kept_file_attributes);
+    LittleEndian::write_u32(&mut file_name[start..], file_attributes.bits());
+}
+
+/// Returns the UTF-16 code units of `name` if it is a valid NTFS file name in the POSIX namespace,
+/// or [`NtfsError::InvalidFileName`] otherwise.
+///
+/// Such names must neither be empty nor longer than 255 UTF-16 code units, must not contain a slash or a NUL character,
+/// and must not be "." or "..".
+#[cfg(feature = "std")]
+pub(crate) fn validate_file_name(name: &str) -> Result<Vec<u16>> {
+    let utf16_name = name.encode_utf16().collect::<Vec<u16>>();
+
+    if utf16_name.is_empty()
+        || utf16_name.len() > u8::MAX as usize
+        || name.contains(['/', '\0'])
+        || name == "."
+        || name == ".."
+    {
+        return Err(NtfsError::InvalidFileName {
+            name: name.to_string(),
+        });
+    }
+
+    Ok(utf16_name)
+}
+
 /// Character set constraint of the filename, returned by [`NtfsFileName::namespace`].
 ///
 /// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/concepts/filename_namespace.html>
@@ -204,7 +496,7 @@ impl NtfsFileName {
     }
 
     /// Gets the file name and returns it wrapped in an [`NtfsString`].
-    pub fn name(&self) -> NtfsString {
+    pub fn name(&self) -> NtfsString<'_> {
         NtfsString(&self.name)
     }
 
@@ -339,4 +631,49 @@ mod tests {
             NtfsString(&[b'$', 0, b'M', 0, b'F', 0, b'T', 0])
         );
     }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_generate_dos_name() {
+        assert!(is_dos_name("README.TXT"));
+        assert!(is_dos_name("readme.txt"));
+        assert!(!is_dos_name("longfilename.txt"));
+        assert!(!is_dos_name("file.html"));
+        assert!(!is_dos_name("a.b.c"));
+        assert!(!is_dos_name("a+b"));
+        assert!(!is_win32_name("a:b"));
+        assert!(!is_win32_name("trailing."));
+
+        let never_exists = |_: &str| Ok(false);
+        assert_eq!(
+            generate_dos_name("Long File Name.html", never_exists).unwrap(),
+            "LONGFI~1.HTM"
+        );
+        assert_eq!(
+            generate_dos_name(".bashrc", never_exists).unwrap(),
+            "BASHRC~1"
+        );
+        assert_eq!(
+            generate_dos_name("a+b.c.d", never_exists).unwrap(),
+            "A_BC~1.D"
+        );
+
+        // Existing names are skipped, and the base name is shortened further for longer suffixes.
+        let exists = |name: &str| Ok(name != "LONGFI~9.TXT" && name != "LONGF~10.TXT");
+        assert_eq!(
+            generate_dos_name("longfilename.txt", exists).unwrap(),
+            "LONGFI~9.TXT"
+        );
+        let exists = |name: &str| Ok(name.starts_with("LONGFI"));
+        assert_eq!(
+            generate_dos_name("longfilename.txt", exists).unwrap(),
+            "LONGF~10.TXT"
+        );
+
+        assert!(validate_file_name("").is_err());
+        assert!(validate_file_name("..").is_err());
+        assert!(validate_file_name("a/b").is_err());
+        assert!(validate_file_name(&"x".repeat(256)).is_err());
+        assert_eq!(validate_file_name("a:b").unwrap().len(), 3);
+    }
 }
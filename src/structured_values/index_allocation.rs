@@ -8,9 +8,13 @@ use crate::index_record::NtfsIndexRecord;
 use crate::ntfs::Ntfs;
 use crate::structured_values::NtfsStructuredValue;
 use crate::traits::NtfsReadSeek;
+#[cfg(feature = "std")]
+use crate::traits::NtfsWrite;
 use crate::types::Vcn;
 use binread::io::{Read, Seek, SeekFrom};
 use core::iter::FusedIterator;
+#[cfg(feature = "std")]
+use std::io::Write;
 
 /// Structure of an $INDEX_ALLOCATION attribute.
 ///
@@ -49,19 +53,8 @@ impl<'n, 'f> NtfsIndexAllocation<'n, 'f> {
     where
         T: Read + Seek,
     {
-        // Seek to the byte offset of the given VCN.
-        let mut value = self.value.clone();
-        let offset = vcn.offset(self.ntfs)?;
-        value.seek(fs, SeekFrom::Current(offset))?;
-
-        if value.stream_position() >= value.len() {
-            return Err(NtfsError::VcnOutOfBoundsInIndexAllocation {
-                position: self.value.data_position().unwrap(),
-                vcn,
-            });
-        }
-
         // Get the record.
+        let value = self.value_at_vcn(fs, vcn)?;
         let record = NtfsIndexRecord::new(self.ntfs, fs, value, index_record_size)?;
 
         // Validate that the VCN in the record is the requested one.
@@ -76,12 +69,71 @@ impl<'n, 'f> NtfsIndexAllocation<'n, 'f> {
         Ok(record)
     }
 
+    /// Returns a new, empty [`NtfsIndexRecord`] for the given Virtual Cluster Number (VCN), which only exists in memory.
+    ///
+    /// The caller is responsible for having allocated the VCN in the $BITMAP attribute of the index.
+    /// Use [`NtfsIndexAllocation::write_record`] to write the record to the filesystem.
+    #[cfg(feature = "std")]
+    pub(crate) fn new_record<T>(
+        &self,
+        fs: &mut T,
+        index_record_size: u32,
+        vcn: Vcn,
+    ) -> Result<NtfsIndexRecord<'n>>
+    where
+        T: Read + Seek,
+    {
+        let value = self.value_at_vcn(fs, vcn)?;
+        let position = value.data_position().unwrap();
+
+        Ok(NtfsIndexRecord::new_empty(
+            self.ntfs,
+            position,
+            index_record_size,
+            vcn,
+        ))
+    }
+
     /// Returns an iterator over all Index Records of this $INDEX_ALLOCATION attribute (cf. [`NtfsIndexRecord`]).
     ///
     /// Each Index Record is fully read, fixed up, and validated.
     pub fn records(&self, index_record_size: u32) -> NtfsIndexRecords<'n, 'f> {
         NtfsIndexRecords::new(self.clone(), index_record_size)
     }
+
+    /// Returns a clone of the attribute value seeked to the byte offset of the given VCN.
+    fn value_at_vcn<T>(&self, fs: &mut T, vcn: Vcn) -> Result<NtfsAttributeValue<'n, 'f>>
+    where
+        T: Read + Seek,
+    {
+        let mut value = self.value.clone();
+        let offset = vcn.offset(self.ntfs)?;
+        value.seek(fs, SeekFrom::Current(offset))?;
+
+        if value.stream_position() >= value.len() {
+            return Err(NtfsError::VcnOutOfBoundsInIndexAllocation {
+                position: self.value.data_position().unwrap(),
+                vcn,
+            });
+        }
+
+        Ok(value)
+    }
+
+    /// Writes the given [`NtfsIndexRecord`] back to its VCN in this $INDEX_ALLOCATION attribute.
+    ///
+    /// The Update Sequence Number (USN) of the record is incremented and the multi-sector protection is applied
+    /// on the fly, so that the [`NtfsIndexRecord`] remains usable afterwards.
+    /// The $LogFile Sequence Number (LSN) is kept as is, because no changes are recorded in the $LogFile.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn write_record<T>(&self, fs: &mut T, record: &mut NtfsIndexRecord<'n>) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        let mut value = self.value_at_vcn(fs, record.vcn())?;
+        record.write_with(|data| value.write_all(fs, data))
+    }
 }
 
 impl<'n, 'f> NtfsStructuredValue<'n, 'f> for NtfsIndexAllocation<'n, 'f> {
@@ -3,20 +3,25 @@
 
 use crate::attribute::NtfsAttributeType;
 use crate::attribute_value::{NtfsAttributeValue, NtfsResidentAttributeValue};
+use crate::collation::NtfsCollationRule;
 use crate::error::{NtfsError, Result};
+#[cfg(feature = "std")]
+use crate::index_entry::{build_last_index_entry, raw_index_entry_flags, NtfsIndexEntryFlags};
 use crate::index_entry::{IndexNodeEntryRanges, NtfsIndexNodeEntries};
 use crate::index_record::{IndexNodeHeader, INDEX_NODE_HEADER_SIZE};
 use crate::indexes::NtfsIndexEntryType;
 use crate::structured_values::{
     NtfsStructuredValue, NtfsStructuredValueFromResidentAttributeValue,
 };
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
 use binread::io::{Read, Seek};
 use byteorder::{ByteOrder, LittleEndian};
 use core::ops::Range;
 use memoffset::offset_of;
 
 /// Size of all [`IndexRootHeader`] fields plus some reserved bytes.
-const INDEX_ROOT_HEADER_SIZE: usize = 16;
+pub(crate) const INDEX_ROOT_HEADER_SIZE: usize = 16;
 
 #[repr(C, packed)]
 struct IndexRootHeader {
@@ -26,6 +31,50 @@ struct IndexRootHeader {
     clusters_per_index_record: i8,
 }
 
+/// Appends the node header and the given raw Index Entries (the last of which must be the terminating entry)
+/// to the raw value of an $INDEX_ROOT attribute.
+#[cfg(feature = "std")]
+fn append_index_root_node(value: &mut Vec<u8>, entries: &[Vec<u8>]) {
+    let entries_size = entries.iter().map(Vec::len).sum::<usize>();
+    let index_size = (INDEX_NODE_HEADER_SIZE + entries_size) as u32;
+    let has_subnodes = entries
+        .iter()
+        .any(|entry| raw_index_entry_flags(entry).contains(NtfsIndexEntryFlags::HAS_SUBNODE));
+
+    // The node header is followed by the entries, and the entire node is allocated.
+    let start = value.len();
+    value.extend_from_slice(&(INDEX_NODE_HEADER_SIZE as u32).to_le_bytes());
+    value.extend_from_slice(&index_size.to_le_bytes());
+    value.extend_from_slice(&index_size.to_le_bytes());
+    value.push(if has_subnodes { LARGE_INDEX_FLAG } else { 0 });
+    value.resize(start + INDEX_NODE_HEADER_SIZE, 0);
+
+    for entry in entries {
+        value.extend_from_slice(entry);
+    }
+}
+
+/// Builds the raw value of a new $INDEX_ROOT attribute for an empty small index.
+///
+/// `ty` is the type of the indexed attribute (e.g. $FILE_NAME for directories) or zero for view indexes.
+#[cfg(feature = "std")]
+pub(crate) fn build_empty_index_root(
+    ty: u32,
+    collation_rule: u32,
+    index_record_size: u32,
+    clusters_per_index_record: i8,
+) -> Vec<u8> {
+    let mut value = Vec::new();
+    value.extend_from_slice(&ty.to_le_bytes());
+    value.extend_from_slice(&collation_rule.to_le_bytes());
+    value.extend_from_slice(&index_record_size.to_le_bytes());
+    value.extend_from_slice(&clusters_per_index_record.to_le_bytes());
+    value.resize(INDEX_ROOT_HEADER_SIZE, 0);
+
+    append_index_root_node(&mut value, &[build_last_index_entry(None)]);
+    value
+}
+
 /// Structure of an $INDEX_ROOT attribute.
 ///
 /// This attribute describes the top-level nodes of a B-tree.
@@ -65,6 +114,24 @@ impl<'f> NtfsIndexRoot<'f> {
         Ok(index_root)
     }
 
+    /// Returns the number of clusters per Index Record, or the number of 512-byte blocks if
+    /// Index Records are smaller than a cluster, as stored in the header.
+    #[cfg(feature = "std")]
+    pub(crate) fn clusters_per_index_record(&self) -> i8 {
+        let start = offset_of!(IndexRootHeader, clusters_per_index_record);
+        self.slice[start] as i8
+    }
+
+    /// Returns the rule that is used to sort the keys of this index.
+    pub fn collation_rule(&self) -> Result<NtfsCollationRule> {
+        NtfsCollationRule::from_value(self.collation_rule_value(), self.position)
+    }
+
+    pub(crate) fn collation_rule_value(&self) -> u32 {
+        let start = offset_of!(IndexRootHeader, collation_rule);
+        LittleEndian::read_u32(&self.slice[start..])
+    }
+
     /// Returns an iterator over all top-level nodes of the B-tree.
     pub fn entries<E>(&self) -> Result<NtfsIndexNodeEntries<'f, E>>
     where
@@ -77,8 +144,8 @@ impl<'f> NtfsIndexRoot<'f> {
     }
 
     fn entries_range_and_position(&self) -> (Range<usize>, u64) {
-        let start = INDEX_ROOT_HEADER_SIZE as usize + self.index_entries_offset() as usize;
-        let end = INDEX_ROOT_HEADER_SIZE as usize + self.index_data_size() as usize;
+        let start = INDEX_ROOT_HEADER_SIZE + self.index_entries_offset() as usize;
+        let end = INDEX_ROOT_HEADER_SIZE + self.index_data_size() as usize;
         let position = self.position + start as u64;
 
         (start..end, position)
@@ -131,6 +198,15 @@ impl<'f> NtfsIndexRoot<'f> {
         self.position
     }
 
+    /// Returns the raw value of this $INDEX_ROOT attribute with all entries replaced by the given raw Index Entries,
+    /// the last of which must be the terminating entry.
+    #[cfg(feature = "std")]
+    pub(crate) fn value_with_entries(&self, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut value = self.slice[..INDEX_ROOT_HEADER_SIZE].to_vec();
+        append_index_root_node(&mut value, entries);
+        value
+    }
+
     fn validate_sizes(&self) -> Result<()> {
         let (entries_range, _position) = self.entries_range_and_position();
 
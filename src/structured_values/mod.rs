@@ -8,6 +8,7 @@ mod file_name;
 mod index_allocation;
 mod index_root;
 mod object_id;
+mod reparse_point;
 mod standard_information;
 mod volume_information;
 mod volume_name;
@@ -17,6 +18,7 @@ pub use file_name::*;
 pub use index_allocation::*;
 pub use index_root::*;
 pub use object_id::*;
+pub use reparse_point::*;
 pub use standard_information::*;
 pub use volume_information::*;
 pub use volume_name::*;
@@ -71,6 +73,20 @@ bitflags! {
     }
 }
 
+/// File attributes that reflect how the data of a file is stored.
+/// They are kept when writing file attributes via [`NtfsFile::set_info`], because they must match
+/// the attributes of the file.
+///
+/// [`NtfsFile::set_info`]: crate::file::NtfsFile::set_info
+#[cfg(feature = "std")]
+pub(crate) const STRUCTURAL_FILE_ATTRIBUTES: NtfsFileAttributeFlags =
+    NtfsFileAttributeFlags::from_bits_truncate(
+        NtfsFileAttributeFlags::SPARSE_FILE.bits()
+            | NtfsFileAttributeFlags::REPARSE_POINT.bits()
+            | NtfsFileAttributeFlags::COMPRESSED.bits()
+            | NtfsFileAttributeFlags::ENCRYPTED.bits(),
+    );
+
 /// Trait implemented by every NTFS attribute structured value.
 pub trait NtfsStructuredValue<'n, 'f>: Sized {
     const TY: NtfsAttributeType;
@@ -0,0 +1,427 @@
+// Copyright 2021-2022 Colin Finck <colin@reactos.org>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::attribute::NtfsAttributeType;
+use crate::attribute_value::{NtfsAttributeValue, NtfsResidentAttributeValue};
+use crate::error::{NtfsError, Result};
+use crate::guid::{NtfsGuid, GUID_SIZE};
+use crate::string::NtfsString;
+use crate::structured_values::{
+    NtfsStructuredValue, NtfsStructuredValueFromResidentAttributeValue,
+};
+use alloc::vec;
+use alloc::vec::Vec;
+use binread::io::{Cursor, Read, Seek};
+use binread::{BinRead, BinReaderExt};
+use byteorder::{ByteOrder, LittleEndian};
+use core::fmt;
+#[cfg(feature = "std")]
+use core::mem;
+use core::ops::Range;
+
+/// Size of all [`ReparsePointHeader`] fields.
+const REPARSE_POINT_HEADER_SIZE: usize = 8;
+
+/// Windows refuses to store more than 16 KiB of reparse data for a single file.
+pub(crate) const REPARSE_POINT_MAX_SIZE: usize = 16 * 1024;
+
+/// Size of the fields preceding the path buffer of a mount point.
+const MOUNT_POINT_HEADER_SIZE: usize = 8;
+
+/// Size of the fields preceding the path buffer of a symbolic link.
+const SYMBOLIC_LINK_HEADER_SIZE: usize = 12;
+
+/// Flag of a symbolic link denoting that the substitute name is a path relative to the directory of the link.
+const SYMBOLIC_LINK_FLAG_RELATIVE: u32 = 0x0000_0001;
+
+#[allow(unused)]
+#[derive(BinRead, Clone, Debug)]
+struct ReparsePointHeader {
+    tag: u32,
+    data_length: u16,
+    reserved: u16,
+}
+
+/// Builds the raw value of a $REPARSE_POINT attribute for a symbolic link or mount point (junction).
+///
+/// `relative` is only stored for symbolic links.
+/// The names of mount points are null-terminated, just like Windows creates them.
+#[cfg(feature = "std")]
+pub(crate) fn build_reparse_link(
+    tag: NtfsReparseTag,
+    substitute_name: &str,
+    print_name: &str,
+    relative: bool,
+) -> Vec<u8> {
+    let utf16 = |name: &str| {
+        name.encode_utf16()
+            .flat_map(|code_unit| code_unit.to_le_bytes())
+            .collect::<Vec<u8>>()
+    };
+    let substitute_name = utf16(substitute_name);
+    let print_name = utf16(print_name);
+    let is_symbolic_link = tag == NtfsReparseTag::SYMLINK;
+    let terminator_size = if is_symbolic_link {
+        0
+    } else {
+        mem::size_of::<u16>()
+    };
+
+    // The name offsets are relative to the beginning of the path buffer.
+    let print_name_offset = substitute_name.len() + terminator_size;
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data.extend_from_slice(&(substitute_name.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(print_name_offset as u16).to_le_bytes());
+    data.extend_from_slice(&(print_name.len() as u16).to_le_bytes());
+
+    if is_symbolic_link {
+        let flags = if relative {
+            SYMBOLIC_LINK_FLAG_RELATIVE
+        } else {
+            0
+        };
+        data.extend_from_slice(&flags.to_le_bytes());
+    }
+
+    for name in [substitute_name, print_name] {
+        data.extend_from_slice(&name);
+        data.resize(data.len() + terminator_size, 0);
+    }
+
+    let mut value = Vec::with_capacity(REPARSE_POINT_HEADER_SIZE + data.len());
+    value.extend_from_slice(&tag.0.to_le_bytes());
+    value.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    value.extend_from_slice(&0u16.to_le_bytes());
+    value.extend_from_slice(&data);
+    value
+}
+
+/// Tag of a reparse point, identifying the filesystem filter driver that processes it.
+///
+/// Reparse tags are defined by Microsoft and third parties.
+/// This crate only knows some common tags, which are available as associated constants.
+///
+/// Reference: <https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/c8e77b37-3909-4fe6-a4ea-2b9d423b1ee4>
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NtfsReparseTag(pub u32);
+
+impl NtfsReparseTag {
+    /// Mount point, also known as junction.
+    pub const MOUNT_POINT: Self = Self(0xA000_0003);
+    /// Hierarchical Storage Management.
+    pub const HSM: Self = Self(0xC000_0004);
+    /// Single-Instance Storage.
+    pub const SIS: Self = Self(0x8000_0007);
+    /// Distributed File System.
+    pub const DFS: Self = Self(0x8000_000A);
+    /// Symbolic link.
+    pub const SYMLINK: Self = Self(0xA000_000C);
+    /// Data Deduplication.
+    pub const DEDUP: Self = Self(0x8000_0013);
+    /// Windows Overlay Filter, used for files compressed via "compact.exe /exe".
+    pub const WOF: Self = Self(0x8000_0017);
+    /// Cloud Files placeholder (e.g. of OneDrive), without any cloud-specific flags.
+    pub const CLOUD: Self = Self(0x9000_001A);
+    /// Application execution alias.
+    pub const APPEXECLINK: Self = Self(0x8000_001B);
+    /// Symbolic link created by the Windows Subsystem for Linux.
+    pub const LX_SYMLINK: Self = Self(0xA000_001D);
+    /// UNIX domain socket created by the Windows Subsystem for Linux.
+    pub const AF_UNIX: Self = Self(0x8000_0023);
+
+    /// Returns whether this tag has been defined by Microsoft.
+    ///
+    /// Reparse points with other tags additionally contain a GUID (see [`NtfsReparsePoint::guid`]).
+    pub fn is_microsoft(&self) -> bool {
+        self.0 & 0x8000_0000 != 0
+    }
+
+    /// Returns whether the reparse point redirects to another named entity
+    /// (like symbolic links and mount points do).
+    pub fn is_name_surrogate(&self) -> bool {
+        self.0 & 0x2000_0000 != 0
+    }
+
+    /// Returns whether this tag belongs to the Cloud Files filter.
+    ///
+    /// Cloud Files placeholders encode additional flags into the tag, so they are not always equal to
+    /// [`NtfsReparseTag::CLOUD`].
+    pub fn is_cloud(&self) -> bool {
+        self.0 & 0xFFFF_0FFF == Self::CLOUD.0
+    }
+}
+
+impl fmt::Display for NtfsReparseTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x}", self.0)
+    }
+}
+
+/// Structure of a $REPARSE_POINT attribute.
+///
+/// This optional attribute turns a file into a reparse point.
+/// Its data is processed by the filesystem filter driver identified by [`NtfsReparsePoint::tag`].
+/// Symbolic links and mount points (junctions) are the most popular examples, and their data can be decoded
+/// via [`NtfsReparsePoint::link`].
+///
+/// A $REPARSE_POINT attribute can be resident or non-resident.
+///
+/// Reference: <https://flatcap.github.io/linux-ntfs/ntfs/attributes/reparse_point.html>
+#[derive(Clone, Debug)]
+pub struct NtfsReparsePoint {
+    tag: NtfsReparseTag,
+    guid: Option<NtfsGuid>,
+    data: Vec<u8>,
+    data_position: u64,
+}
+
+impl NtfsReparsePoint {
+    fn new<T>(r: &mut T, position: u64, value_length: u64) -> Result<Self>
+    where
+        T: Read + Seek,
+    {
+        // Report the bound that has been violated.
+        let expected = if value_length < REPARSE_POINT_HEADER_SIZE as u64 {
+            Some(REPARSE_POINT_HEADER_SIZE)
+        } else if value_length > REPARSE_POINT_MAX_SIZE as u64 {
+            Some(REPARSE_POINT_MAX_SIZE)
+        } else {
+            None
+        };
+
+        if let Some(expected) = expected {
+            return Err(NtfsError::InvalidStructuredValueSize {
+                position,
+                ty: NtfsAttributeType::ReparsePoint,
+                expected: expected as u64,
+                actual: value_length,
+            });
+        }
+
+        let header = r.read_le::<ReparsePointHeader>()?;
+        let tag = NtfsReparseTag(header.tag);
+
+        let mut guid = None;
+        let mut data_offset = REPARSE_POINT_HEADER_SIZE;
+        if !tag.is_microsoft() {
+            guid = Some(r.read_le::<NtfsGuid>()?);
+            data_offset += GUID_SIZE;
+        }
+
+        let expected_length = (data_offset + header.data_length as usize) as u64;
+        if expected_length > value_length {
+            return Err(NtfsError::InvalidStructuredValueSize {
+                position,
+                ty: NtfsAttributeType::ReparsePoint,
+                expected: expected_length,
+                actual: value_length,
+            });
+        }
+
+        let mut data = vec![0u8; header.data_length as usize];
+        r.read_exact(&mut data)?;
+        let data_position = position + data_offset as u64;
+
+        Ok(Self {
+            tag,
+            guid,
+            data,
+            data_position,
+        })
+    }
+
+    /// Returns the raw reparse data, which is interpreted by the filter driver identified by
+    /// [`NtfsReparsePoint::tag`].
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the GUID of the third-party filter driver processing this reparse point,
+    /// or `None` if this reparse point has a Microsoft tag (see [`NtfsReparseTag::is_microsoft`]).
+    pub fn guid(&self) -> Option<&NtfsGuid> {
+        self.guid.as_ref()
+    }
+
+    /// Decodes the reparse data of a symbolic link or mount point (junction).
+    ///
+    /// Returns `None` if this reparse point is neither of them.
+    pub fn link(&self) -> Option<Result<NtfsReparseLink<'_>>> {
+        let (header_size, relative) = match self.tag {
+            NtfsReparseTag::MOUNT_POINT => (MOUNT_POINT_HEADER_SIZE, false),
+            NtfsReparseTag::SYMLINK => {
+                let flags = iter_try!(self.data_range(8..SYMBOLIC_LINK_HEADER_SIZE));
+                let relative = LittleEndian::read_u32(flags) & SYMBOLIC_LINK_FLAG_RELATIVE != 0;
+                (SYMBOLIC_LINK_HEADER_SIZE, relative)
+            }
+            _ => return None,
+        };
+
+        let header = iter_try!(self.data_range(0..MOUNT_POINT_HEADER_SIZE));
+        let substitute_name_offset = LittleEndian::read_u16(&header[0..]) as usize;
+        let substitute_name_length = LittleEndian::read_u16(&header[2..]) as usize;
+        let print_name_offset = LittleEndian::read_u16(&header[4..]) as usize;
+        let print_name_length = LittleEndian::read_u16(&header[6..]) as usize;
+
+        // The name offsets are relative to the beginning of the path buffer.
+        let start = header_size + substitute_name_offset;
+        let substitute_name = iter_try!(self.data_range(start..start + substitute_name_length));
+
+        let start = header_size + print_name_offset;
+        let print_name = iter_try!(self.data_range(start..start + print_name_length));
+
+        Some(Ok(NtfsReparseLink {
+            substitute_name: NtfsString(substitute_name),
+            print_name: NtfsString(print_name),
+            relative,
+        }))
+    }
+
+    fn data_range(&self, range: Range<usize>) -> Result<&[u8]> {
+        self.data
+            .get(range.clone())
+            .ok_or(NtfsError::InvalidReparsePointDataRange {
+                position: self.data_position,
+                range,
+                size: self.data.len(),
+            })
+    }
+
+    /// Returns the tag identifying the filesystem filter driver that processes this reparse point.
+    pub fn tag(&self) -> NtfsReparseTag {
+        self.tag
+    }
+}
+
+impl<'n, 'f> NtfsStructuredValue<'n, 'f> for NtfsReparsePoint {
+    const TY: NtfsAttributeType = NtfsAttributeType::ReparsePoint;
+
+    fn from_attribute_value<T>(fs: &mut T, value: NtfsAttributeValue<'n, 'f>) -> Result<Self>
+    where
+        T: Read + Seek,
+    {
+        let position = value.data_position().unwrap();
+        let value_length = value.len();
+
+        let mut value_attached = value.attach(fs);
+        Self::new(&mut value_attached, position, value_length)
+    }
+}
+
+impl<'n, 'f> NtfsStructuredValueFromResidentAttributeValue<'n, 'f> for NtfsReparsePoint {
+    fn from_resident_attribute_value(value: NtfsResidentAttributeValue<'f>) -> Result<Self> {
+        let position = value.data_position().unwrap();
+        let value_length = value.len();
+
+        let mut cursor = Cursor::new(value.data());
+        Self::new(&mut cursor, position, value_length)
+    }
+}
+
+/// Decoded reparse data of a symbolic link or mount point (junction), returned by [`NtfsReparsePoint::link`].
+#[derive(Clone, Debug)]
+pub struct NtfsReparseLink<'a> {
+    substitute_name: NtfsString<'a>,
+    print_name: NtfsString<'a>,
+    relative: bool,
+}
+
+impl<'a> NtfsReparseLink<'a> {
+    /// Returns whether [`NtfsReparseLink::substitute_name`] is a path relative to the directory
+    /// containing the link.
+    ///
+    /// This is only ever the case for symbolic links.
+    pub fn is_relative(&self) -> bool {
+        self.relative
+    }
+
+    /// Returns the user-friendly link target, as displayed by Windows Explorer.
+    pub fn print_name(&self) -> &NtfsString<'a> {
+        &self.print_name
+    }
+
+    /// Returns the link target as processed by Windows.
+    ///
+    /// For absolute links, this is an NT namespace path like `\??\C:\Windows`.
+    pub fn substitute_name(&self) -> &NtfsString<'a> {
+        &self.substitute_name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .flat_map(|code_unit| code_unit.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_reparse_point_symlink() {
+        let substitute_name = utf16("\\??\\C:\\Windows");
+        let print_name = utf16("C:\\Windows");
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&(substitute_name.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(substitute_name.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(print_name.len() as u16).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&substitute_name);
+        data.extend_from_slice(&print_name);
+
+        let mut value = Vec::new();
+        value.extend_from_slice(&NtfsReparseTag::SYMLINK.0.to_le_bytes());
+        value.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        value.extend_from_slice(&0u16.to_le_bytes());
+        value.extend_from_slice(&data);
+
+        let value_length = value.len() as u64;
+        let mut cursor = Cursor::new(value.as_slice());
+        let reparse_point = NtfsReparsePoint::new(&mut cursor, 0, value_length).unwrap();
+        assert_eq!(reparse_point.tag(), NtfsReparseTag::SYMLINK);
+        assert!(reparse_point.tag().is_microsoft());
+        assert!(reparse_point.tag().is_name_surrogate());
+        assert!(reparse_point.guid().is_none());
+        assert_eq!(reparse_point.data(), data.as_slice());
+
+        let link = reparse_point.link().unwrap().unwrap();
+        assert!(!link.is_relative());
+        assert_eq!(link.substitute_name(), "\\??\\C:\\Windows");
+        assert_eq!(link.print_name(), "C:\\Windows");
+
+        // A truncated path buffer must be detected.
+        value.truncate(value.len() - 2);
+        value[4..6].copy_from_slice(&(data.len() as u16 - 2).to_le_bytes());
+
+        let value_length = value.len() as u64;
+        let mut cursor = Cursor::new(value.as_slice());
+        let reparse_point = NtfsReparsePoint::new(&mut cursor, 0, value_length).unwrap();
+        assert!(matches!(
+            reparse_point.link(),
+            Some(Err(NtfsError::InvalidReparsePointDataRange { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_reparse_point_size() {
+        let value = vec![0u8; REPARSE_POINT_MAX_SIZE + 1];
+        for (value_length, expected) in [
+            (4, REPARSE_POINT_HEADER_SIZE),
+            (value.len(), REPARSE_POINT_MAX_SIZE),
+        ]
+        .iter()
+        {
+            let mut cursor = Cursor::new(&value[..*value_length]);
+            let error = NtfsReparsePoint::new(&mut cursor, 0, *value_length as u64).unwrap_err();
+            assert!(matches!(
+                error,
+                NtfsError::InvalidStructuredValueSize { expected: e, actual: a, .. }
+                    if e == *expected as u64 && a == *value_length as u64
+            ));
+        }
+    }
+}
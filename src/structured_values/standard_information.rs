@@ -4,18 +4,30 @@
 use crate::attribute::NtfsAttributeType;
 use crate::attribute_value::{NtfsAttributeValue, NtfsResidentAttributeValue};
 use crate::error::{NtfsError, Result};
+#[cfg(feature = "std")]
+use crate::structured_values::STRUCTURAL_FILE_ATTRIBUTES;
 use crate::structured_values::{
     NtfsFileAttributeFlags, NtfsStructuredValue, NtfsStructuredValueFromResidentAttributeValue,
 };
 use crate::time::NtfsTime;
 use binread::io::{Cursor, Read, Seek};
 use binread::{BinRead, BinReaderExt};
+#[cfg(feature = "std")]
+use {alloc::vec::Vec, byteorder::ByteOrder, byteorder::LittleEndian, core::mem};
 
 /// Size of all [`StandardInformationData`] fields plus some reserved bytes.
 const STANDARD_INFORMATION_SIZE_NTFS1: usize = 48;
 
 /// Size of all [`StandardInformationData`] plus [`StandardInformationDataNtfs3`] fields.
-const STANDARD_INFORMATION_SIZE_NTFS3: usize = 72;
+pub(crate) const STANDARD_INFORMATION_SIZE_NTFS3: usize = 72;
+
+/// Offset of the `file_attributes` field of a [`StandardInformationDataNtfs1`], which directly follows the file times.
+#[cfg(feature = "std")]
+const STANDARD_INFORMATION_FILE_ATTRIBUTES_OFFSET: usize = 32;
+
+/// Offset of the `security_id` field of a [`StandardInformationDataNtfs3`], which directly follows the Owner ID.
+#[cfg(feature = "std")]
+const STANDARD_INFORMATION_SECURITY_ID_OFFSET: usize = 52;
 
 #[derive(BinRead, Clone, Debug)]
 struct StandardInformationDataNtfs1 {
@@ -37,6 +49,80 @@ struct StandardInformationDataNtfs3 {
     usn: u64,
 }
 
+/// Builds the raw value of a new NTFS 3.x $STANDARD_INFORMATION attribute, with all file times set to `time`.
+#[cfg(feature = "std")]
+pub(crate) fn build_standard_information(
+    time: NtfsTime,
+    file_attributes: NtfsFileAttributeFlags,
+    security_id: u32,
+) -> Vec<u8> {
+    let mut value = Vec::with_capacity(STANDARD_INFORMATION_SIZE_NTFS3);
+
+    // Creation, modification, MFT record modification, and access time.
+    for _ in 0..4 {
+        value.extend_from_slice(&time.nt_timestamp().to_le_bytes());
+    }
+
+    value.extend_from_slice(&file_attributes.bits().to_le_bytes());
+
+    // Maximum versions, version, Class ID, and Owner ID are all zero.
+    value.resize(STANDARD_INFORMATION_SIZE_NTFS1 + mem::size_of::<u32>(), 0);
+    value.extend_from_slice(&security_id.to_le_bytes());
+
+    // Quota charged and Update Sequence Number (USN) are zero as well.
+    value.resize(STANDARD_INFORMATION_SIZE_NTFS3, 0);
+    value
+}
+
+/// Adds the given file attributes to the raw bytes of a $STANDARD_INFORMATION value.
+#[cfg(feature = "std")]
+pub(crate) fn insert_standard_information_file_attributes(
+    value: &mut [u8],
+    file_attributes: NtfsFileAttributeFlags,
+) {
+    let start = STANDARD_INFORMATION_FILE_ATTRIBUTES_OFFSET;
+    let old_file_attributes = LittleEndian::read_u32(&value[start..]);
+    LittleEndian::write_u32(
+        &mut value[start..],
+        old_file_attributes | file_attributes.bits(),
+    );
+}
+
+/// Writes the given Security ID to the raw bytes of an NTFS 3.x $STANDARD_INFORMATION value.
+#[cfg(feature = "std")]
+pub(crate) fn set_standard_information_security_id(value: &mut [u8], security_id: u32) {
+    let start = STANDARD_INFORMATION_SECURITY_ID_OFFSET;
+    LittleEndian::write_u32(&mut value[start..], security_id);
+}
+
+/// Writes the file times and file attributes of `info` to the raw bytes of a $STANDARD_INFORMATION value.
+///
+/// The [`STRUCTURAL_FILE_ATTRIBUTES`] of the existing value are kept.
+#[cfg(feature = "std")]
+pub(crate) fn set_standard_information_times_and_attributes(
+    value: &mut [u8],
+    info: &NtfsStandardInformation,
+) {
+    let data = &info.ntfs1_data;
+    let times = [
+        data.creation_time,
+        data.modification_time,
+        data.mft_record_modification_time,
+        data.access_time,
+    ];
+
+    for (i, time) in times.iter().enumerate() {
+        let start = i * mem::size_of::<NtfsTime>();
+        LittleEndian::write_u64(&mut value[start..], time.nt_timestamp());
+    }
+
+    let start = STANDARD_INFORMATION_FILE_ATTRIBUTES_OFFSET;
+    let old_file_attributes = LittleEndian::read_u32(&value[start..]);
+    let structural = STRUCTURAL_FILE_ATTRIBUTES.bits();
+    let file_attributes = (data.file_attributes & !structural) | (old_file_attributes & structural);
+    LittleEndian::write_u32(&mut value[start..], file_attributes);
+}
+
 /// Structure of a $STANDARD_INFORMATION attribute.
 ///
 /// Among other things, this is the place where the file times and "File Attributes"
@@ -117,11 +203,22 @@ impl NtfsStandardInformation {
     }
 
     /// Returns the Owner ID of the file, if stored via NTFS 3.x file information.
+    ///
+    /// Use [`Ntfs::quota_owner_sid`] to look up the SID of the owner and [`Ntfs::quotas`] for the
+    /// quota usage of all owners.
+    ///
+    /// [`Ntfs::quota_owner_sid`]: crate::Ntfs::quota_owner_sid
+    /// [`Ntfs::quotas`]: crate::Ntfs::quotas
     pub fn owner_id(&self) -> Option<u32> {
         self.ntfs3_data.as_ref().map(|x| x.owner_id)
     }
 
     /// Returns the quota charged by this file, if stored via NTFS 3.x file information.
+    ///
+    /// This is added to [`NtfsQuotaControlEntry::bytes_used`] of the owner returned by
+    /// [`NtfsStandardInformation::owner_id`].
+    ///
+    /// [`NtfsQuotaControlEntry::bytes_used`]: crate::indexes::NtfsQuotaControlEntry::bytes_used
     pub fn quota_charged(&self) -> Option<u64> {
         self.ntfs3_data.as_ref().map(|x| x.quota_charged)
     }
@@ -131,6 +228,61 @@ impl NtfsStandardInformation {
         self.ntfs3_data.as_ref().map(|x| x.security_id)
     }
 
+    /// Sets the time this file was last accessed.
+    ///
+    /// Use [`NtfsFile::set_info`] to write the change to the filesystem.
+    ///
+    /// [`NtfsFile::set_info`]: crate::NtfsFile::set_info
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_access_time(&mut self, access_time: NtfsTime) {
+        self.ntfs1_data.access_time = access_time;
+    }
+
+    /// Sets the time this file was created.
+    ///
+    /// Use [`NtfsFile::set_info`] to write the change to the filesystem.
+    ///
+    /// [`NtfsFile::set_info`]: crate::NtfsFile::set_info
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_creation_time(&mut self, creation_time: NtfsTime) {
+        self.ntfs1_data.creation_time = creation_time;
+    }
+
+    /// Sets the flags that a user can set for a file (Read-Only, Hidden, System, Archive, etc.).
+    ///
+    /// Use [`NtfsFile::set_info`] to write the change to the filesystem.
+    ///
+    /// [`NtfsFile::set_info`]: crate::NtfsFile::set_info
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_file_attributes(&mut self, file_attributes: NtfsFileAttributeFlags) {
+        self.ntfs1_data.file_attributes = file_attributes.bits();
+    }
+
+    /// Sets the time the MFT record of this file was last modified.
+    ///
+    /// Use [`NtfsFile::set_info`] to write the change to the filesystem.
+    ///
+    /// [`NtfsFile::set_info`]: crate::NtfsFile::set_info
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_mft_record_modification_time(&mut self, mft_record_modification_time: NtfsTime) {
+        self.ntfs1_data.mft_record_modification_time = mft_record_modification_time;
+    }
+
+    /// Sets the time this file was last modified.
+    ///
+    /// Use [`NtfsFile::set_info`] to write the change to the filesystem.
+    ///
+    /// [`NtfsFile::set_info`]: crate::NtfsFile::set_info
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn set_modification_time(&mut self, modification_time: NtfsTime) {
+        self.ntfs1_data.modification_time = modification_time;
+    }
+
     /// Returns the Update Sequence Number (USN) of the file, if stored via NTFS 3.x file information.
     pub fn usn(&self) -> Option<u64> {
         self.ntfs3_data.as_ref().map(|x| x.usn)
@@ -185,7 +337,7 @@ mod tests {
         let mut mft_attributes = mft.attributes_raw();
 
         // Check the StandardInformation attribute of the MFT.
-        let attribute = mft_attributes.nth(0).unwrap();
+        let attribute = mft_attributes.next().unwrap();
         assert_eq!(
             attribute.ty().unwrap(),
             NtfsAttributeType::StandardInformation,
@@ -10,6 +10,8 @@ use crate::structured_values::{
 use binread::io::{Cursor, Read, Seek};
 use binread::{BinRead, BinReaderExt};
 use bitflags::bitflags;
+#[cfg(feature = "std")]
+use {alloc::vec, alloc::vec::Vec, core::mem};
 
 /// Size of all [`VolumeInformationData`] fields.
 const VOLUME_INFORMATION_SIZE: usize = 12;
@@ -22,6 +24,17 @@ struct VolumeInformationData {
     flags: u16,
 }
 
+/// Builds the raw value of a new $VOLUME_INFORMATION attribute with the given NTFS version and no flags.
+#[cfg(feature = "std")]
+pub(crate) fn build_volume_information(major_version: u8, minor_version: u8) -> Vec<u8> {
+    // The reserved field is followed by the version numbers and the flags.
+    let mut value = vec![0u8; VOLUME_INFORMATION_SIZE];
+    let start = mem::size_of::<u64>();
+    value[start] = major_version;
+    value[start + 1] = minor_version;
+    value
+}
+
 bitflags! {
     /// Flags returned by [`NtfsVolumeInformation::flags`].
     pub struct NtfsVolumeFlags: u16 {
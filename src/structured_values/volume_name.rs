@@ -13,7 +13,7 @@ use binread::io::{Cursor, Read, Seek};
 use core::mem;
 
 /// The largest VolumeName attribute has a name containing 128 UTF-16 code points (256 bytes).
-const VOLUME_NAME_MAX_SIZE: usize = 128 * mem::size_of::<u16>();
+pub(crate) const VOLUME_NAME_MAX_SIZE: usize = 128 * mem::size_of::<u16>();
 
 /// Structure of a $VOLUME_NAME attribute.
 ///
@@ -54,7 +54,7 @@ impl NtfsVolumeName {
     }
 
     /// Gets the volume name and returns it wrapped in an [`NtfsString`].
-    pub fn name(&self) -> NtfsString {
+    pub fn name(&self) -> NtfsString<'_> {
         NtfsString(&self.name)
     }
 
@@ -1,6 +1,8 @@
 use crate::error::{NtfsError, Result};
 use binread::io;
 use binread::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "std")]
+use std::io::Write;
 
 /// Trait to read/seek in a source by the help of a temporarily passed mutable reference to the filesystem reader.
 ///
@@ -49,3 +51,42 @@ pub trait NtfsReadSeek {
     /// See [`std::io::Seek::stream_position`].
     fn stream_position(&self) -> u64;
 }
+
+/// Trait to write to a source by the help of a temporarily passed mutable reference to the filesystem writer.
+///
+/// This is the writing counterpart of [`NtfsReadSeek`].
+/// Writes only ever overwrite existing data: They neither change the length of a value nor allocate any clusters.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub trait NtfsWrite: NtfsReadSeek {
+    /// See [`std::io::Write::write`].
+    ///
+    /// Returns the number of bytes written, which is less than `buf.len()` if the end of the value
+    /// has been reached.
+    fn write<T>(&mut self, fs: &mut T, buf: &[u8]) -> Result<usize>
+    where
+        T: Read + Write + Seek;
+
+    /// See [`std::io::Write::write_all`].
+    fn write_all<T>(&mut self, fs: &mut T, mut buf: &[u8]) -> Result<()>
+    where
+        T: Read + Write + Seek,
+    {
+        // This implementation follows https://github.com/rust-lang/rust/blob/5662d9343f0696efcc38a1264656737c9f22d427/library/std/src/io/mod.rs
+        while !buf.is_empty() {
+            match self.write(fs, buf) {
+                Ok(0) => {
+                    return Err(NtfsError::Io(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    )));
+                }
+                Ok(n) => buf = &buf[n..],
+                Err(NtfsError::Io(e)) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
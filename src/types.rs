@@ -6,7 +6,7 @@
 use crate::error::{NtfsError, Result};
 use crate::ntfs::Ntfs;
 use binread::BinRead;
-use derive_more::{Binary, Display, From, LowerHex, Octal, UpperHex};
+use derive_more::{Binary, Display, From, Into, LowerHex, Octal, UpperHex};
 
 /// A Logical Cluster Number (LCN).
 ///
@@ -21,6 +21,7 @@ use derive_more::{Binary, Display, From, LowerHex, Octal, UpperHex};
     Display,
     Eq,
     From,
+    Into,
     LowerHex,
     Octal,
     Ord,
@@ -64,6 +65,7 @@ impl Lcn {
     Display,
     Eq,
     From,
+    Into,
     LowerHex,
     Octal,
     Ord,
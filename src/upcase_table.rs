@@ -6,7 +6,9 @@ use crate::error::{NtfsError, Result};
 use crate::file::KnownNtfsFileRecordNumber;
 use crate::ntfs::Ntfs;
 use crate::traits::NtfsReadSeek;
-use binread::io::{Read, Seek};
+use alloc::vec::Vec;
+use binread::io::{Cursor, Read, Seek};
+use binread::{BinRead, BinReaderExt};
 use core::convert::TryInto;
 use core::mem;
 
@@ -16,19 +18,330 @@ const UPCASE_CHARACTER_COUNT: usize = 65536;
 /// Hence, the table has a size of 128 KiB.
 const UPCASE_TABLE_SIZE: u64 = (UPCASE_CHARACTER_COUNT * mem::size_of::<u16>()) as u64;
 
+/// Size of all [`UpcaseInfoData`] fields.
+const UPCASE_INFO_SIZE: usize = 32;
+
+/// Name of the data stream of the $UpCase file that stores an [`NtfsUpcaseInfo`].
+pub(crate) const UPCASE_INFO_STREAM_NAME: &str = "$Info";
+
+/// Ranges of lowercase characters of the Windows 8 Upcase Table (first, last, difference),
+/// whose uppercase characters are obtained by adding a constant difference.
+const WINDOWS8_UPCASE_RANGES: [(u16, u16, i32); 36] = [
+    (0x0061, 0x007a, -32),
+    (0x00e0, 0x00f6, -32),
+    (0x00f8, 0x00fe, -32),
+    (0x0256, 0x0257, -205),
+    (0x028a, 0x028b, -217),
+    (0x037b, 0x037d, 130),
+    (0x03ad, 0x03af, -37),
+    (0x03b1, 0x03c1, -32),
+    (0x03c3, 0x03cb, -32),
+    (0x03cd, 0x03ce, -63),
+    (0x0430, 0x044f, -32),
+    (0x0450, 0x045f, -80),
+    (0x0561, 0x0586, -48),
+    (0x1f00, 0x1f07, 8),
+    (0x1f10, 0x1f15, 8),
+    (0x1f20, 0x1f27, 8),
+    (0x1f30, 0x1f37, 8),
+    (0x1f40, 0x1f45, 8),
+    (0x1f60, 0x1f67, 8),
+    (0x1f70, 0x1f71, 74),
+    (0x1f72, 0x1f75, 86),
+    (0x1f76, 0x1f77, 100),
+    (0x1f78, 0x1f79, 128),
+    (0x1f7a, 0x1f7b, 112),
+    (0x1f7c, 0x1f7d, 126),
+    (0x1f80, 0x1f87, 8),
+    (0x1f90, 0x1f97, 8),
+    (0x1fa0, 0x1fa7, 8),
+    (0x1fb0, 0x1fb1, 8),
+    (0x1fd0, 0x1fd1, 8),
+    (0x1fe0, 0x1fe1, 8),
+    (0x2170, 0x217f, -16),
+    (0x24d0, 0x24e9, -26),
+    (0x2c30, 0x2c5e, -48),
+    (0x2d00, 0x2d25, -7264),
+    (0xff41, 0xff5a, -32),
+];
+
+/// Ranges of the Windows 8 Upcase Table in which uppercase and lowercase characters alternate (first, last).
+/// Every second character from `first` to `last` is a lowercase one, whose uppercase character directly precedes it.
+const WINDOWS8_UPCASE_ALTERNATING_RANGES: [(u16, u16); 30] = [
+    (0x0101, 0x012f),
+    (0x0133, 0x0137),
+    (0x013a, 0x0148),
+    (0x014b, 0x0177),
+    (0x017a, 0x017e),
+    (0x0183, 0x0185),
+    (0x01a1, 0x01a5),
+    (0x01b4, 0x01b6),
+    (0x01ce, 0x01dc),
+    (0x01df, 0x01ef),
+    (0x01f9, 0x021f),
+    (0x0223, 0x0233),
+    (0x0247, 0x024f),
+    (0x0371, 0x0373),
+    (0x03d9, 0x03ef),
+    (0x0461, 0x0481),
+    (0x048b, 0x04bf),
+    (0x04c2, 0x04ce),
+    (0x04d1, 0x0523),
+    (0x1e01, 0x1e95),
+    (0x1ea1, 0x1eff),
+    (0x2c68, 0x2c6c),
+    (0x2c81, 0x2ce3),
+    (0xa641, 0xa65f),
+    (0xa663, 0xa66d),
+    (0xa681, 0xa697),
+    (0xa723, 0xa72f),
+    (0xa733, 0xa76f),
+    (0xa77a, 0xa77c),
+    (0xa77f, 0xa787),
+];
+
+/// All remaining lowercase characters of the Windows 8 Upcase Table along with their uppercase characters.
+const WINDOWS8_UPCASE_CHARACTERS: [(u16, u16); 71] = [
+    (0x00ff, 0x0178),
+    (0x0180, 0x0243),
+    (0x0188, 0x0187),
+    (0x018c, 0x018b),
+    (0x0192, 0x0191),
+    (0x0195, 0x01f6),
+    (0x0199, 0x0198),
+    (0x019a, 0x023d),
+    (0x019e, 0x0220),
+    (0x01a8, 0x01a7),
+    (0x01ad, 0x01ac),
+    (0x01b0, 0x01af),
+    (0x01b9, 0x01b8),
+    (0x01bd, 0x01bc),
+    (0x01bf, 0x01f7),
+    (0x01c6, 0x01c4),
+    (0x01c9, 0x01c7),
+    (0x01cc, 0x01ca),
+    (0x01dd, 0x018e),
+    (0x01f3, 0x01f1),
+    (0x01f5, 0x01f4),
+    (0x023c, 0x023b),
+    (0x0242, 0x0241),
+    (0x0250, 0x2c6f),
+    (0x0251, 0x2c6d),
+    (0x0253, 0x0181),
+    (0x0254, 0x0186),
+    (0x0259, 0x018f),
+    (0x025b, 0x0190),
+    (0x0260, 0x0193),
+    (0x0263, 0x0194),
+    (0x0268, 0x0197),
+    (0x0269, 0x0196),
+    (0x026b, 0x2c62),
+    (0x026f, 0x019c),
+    (0x0271, 0x2c6e),
+    (0x0272, 0x019d),
+    (0x0275, 0x019f),
+    (0x027d, 0x2c64),
+    (0x0280, 0x01a6),
+    (0x0283, 0x01a9),
+    (0x0288, 0x01ae),
+    (0x0289, 0x0244),
+    (0x028c, 0x0245),
+    (0x0292, 0x01b7),
+    (0x0377, 0x0376),
+    (0x03ac, 0x0386),
+    (0x03cc, 0x038c),
+    (0x03d7, 0x03cf),
+    (0x03f2, 0x03f9),
+    (0x03f8, 0x03f7),
+    (0x03fb, 0x03fa),
+    (0x04cf, 0x04c0),
+    (0x1d79, 0xa77d),
+    (0x1d7d, 0x2c63),
+    (0x1f51, 0x1f59),
+    (0x1f53, 0x1f5b),
+    (0x1f55, 0x1f5d),
+    (0x1f57, 0x1f5f),
+    (0x1fb3, 0x1fbc),
+    (0x1fc3, 0x1fcc),
+    (0x1fe5, 0x1fec),
+    (0x1ff3, 0x1ffc),
+    (0x214e, 0x2132),
+    (0x2184, 0x2183),
+    (0x2c61, 0x2c60),
+    (0x2c65, 0x023a),
+    (0x2c66, 0x023e),
+    (0x2c73, 0x2c72),
+    (0x2c76, 0x2c75),
+    (0xa78c, 0xa78b),
+];
+
+/// Known versions of the Upcase Table, as created by Windows when formatting a volume.
+///
+/// See [`Ntfs::upcase_table_version`] to check which one is used by a filesystem.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NtfsUpcaseTableVersion {
+    /// The Upcase Table of Windows 8 and later versions.
+    ///
+    /// This is also the table created by mkntfs of NTFS-3G.
+    Windows8,
+}
+
+impl NtfsUpcaseTableVersion {
+    const ALL: [Self; 1] = [Self::Windows8];
+
+    /// Returns the (major, minor, build) version of the first Windows release that created this Upcase Table.
+    #[cfg(feature = "std")]
+    fn os_version(&self) -> (u32, u32, u32) {
+        match self {
+            Self::Windows8 => (6, 2, 9200),
+        }
+    }
+}
+
+#[derive(BinRead, Clone, Debug)]
+struct UpcaseInfoData {
+    length: u32,
+    _reserved: u32,
+    crc64: u64,
+    os_major_version: u32,
+    os_minor_version: u32,
+    os_build_number: u32,
+    service_pack_major_version: u16,
+    service_pack_minor_version: u16,
+}
+
+/// Structure of the $Info data stream of the $UpCase file.
+///
+/// Since Windows 8, this stream describes the Upcase Table along with the Windows version that created it.
+/// You can easily access it via [`Ntfs::upcase_info`].
+#[derive(Clone, Debug)]
+pub struct NtfsUpcaseInfo {
+    info: UpcaseInfoData,
+}
+
+impl NtfsUpcaseInfo {
+    /// Returns the CRC64 checksum of the Upcase Table.
+    ///
+    /// It can be verified via [`NtfsUpcaseInfo::matches`].
+    pub fn crc64(&self) -> u64 {
+        self.info.crc64
+    }
+
+    /// Returns the length of this structure, in bytes.
+    pub fn length(&self) -> u32 {
+        self.info.length
+    }
+
+    /// Returns `true` if the stored CRC64 checksum matches the given raw Upcase Table.
+    pub fn matches(&self, upcase_table: &[u8]) -> bool {
+        self.crc64() == upcase_table_crc64(upcase_table)
+    }
+
+    /// Returns the build number of the Windows version that created the Upcase Table.
+    pub fn os_build_number(&self) -> u32 {
+        self.info.os_build_number
+    }
+
+    /// Returns the major version of the Windows version that created the Upcase Table (e.g. `6` for Windows 8).
+    pub fn os_major_version(&self) -> u32 {
+        self.info.os_major_version
+    }
+
+    /// Returns the minor version of the Windows version that created the Upcase Table (e.g. `2` for Windows 8).
+    pub fn os_minor_version(&self) -> u32 {
+        self.info.os_minor_version
+    }
+
+    /// Returns the major Service Pack version of the Windows version that created the Upcase Table.
+    pub fn service_pack_major_version(&self) -> u16 {
+        self.info.service_pack_major_version
+    }
+
+    /// Returns the minor Service Pack version of the Windows version that created the Upcase Table.
+    pub fn service_pack_minor_version(&self) -> u16 {
+        self.info.service_pack_minor_version
+    }
+
+    /// Reads the $Info data stream of the $UpCase file from the given filesystem.
+    ///
+    /// Returns `None` if the stream does not exist (which is the case for filesystems created before Windows 8).
+    pub(crate) fn read<T>(ntfs: &Ntfs, fs: &mut T) -> Option<Result<Self>>
+    where
+        T: Read + Seek,
+    {
+        let upcase_file = iter_try!(ntfs.file(fs, KnownNtfsFileRecordNumber::UpCase as u64));
+        let data_item = iter_try!(upcase_file.data(fs, UPCASE_INFO_STREAM_NAME)?);
+        let data_attribute = data_item.to_attribute();
+
+        let position = data_attribute.position();
+        let value_length = data_attribute.value_length();
+        if value_length < UPCASE_INFO_SIZE as u64 {
+            return Some(Err(NtfsError::InvalidStructuredValueSize {
+                position,
+                ty: NtfsAttributeType::Data,
+                expected: UPCASE_INFO_SIZE as u64,
+                actual: value_length,
+            }));
+        }
+
+        let mut data_value = iter_try!(data_attribute.value());
+        let mut data = [0u8; UPCASE_INFO_SIZE];
+        iter_try!(data_value.read_exact(fs, &mut data));
+
+        let info = iter_try!(Cursor::new(data).read_le::<UpcaseInfoData>());
+        Some(Ok(Self { info }))
+    }
+}
+
 /// Manages a table for converting characters to uppercase.
 /// This table is used for case-insensitive file name comparisons.
 ///
 /// NTFS stores such a table in the special $UpCase file on every filesystem.
 /// As this table is slightly different depending on the Windows version used for creating the filesystem,
 /// it is very important to always read the table from the filesystem itself.
-/// Hence, this table is not hardcoded into the crate.
+/// The built-in tables of the known Windows versions (see [`NtfsUpcaseTableVersion`]) are only meant for
+/// creating new filesystems and as a fallback if the $UpCase file is corrupted.
 #[derive(Clone, Debug)]
 pub(crate) struct UpcaseTable {
     uppercase_characters: Vec<u16>,
 }
 
 impl UpcaseTable {
+    /// Creates the built-in [`UpcaseTable`] of the given Windows version.
+    pub(crate) fn new(version: NtfsUpcaseTableVersion) -> Self {
+        let (ranges, alternating_ranges, characters) = match version {
+            NtfsUpcaseTableVersion::Windows8 => (
+                &WINDOWS8_UPCASE_RANGES,
+                &WINDOWS8_UPCASE_ALTERNATING_RANGES,
+                &WINDOWS8_UPCASE_CHARACTERS,
+            ),
+        };
+
+        let mut uppercase_characters = (0..UPCASE_CHARACTER_COUNT)
+            .map(|character| character as u16)
+            .collect::<Vec<u16>>();
+
+        for &(first, last, difference) in ranges {
+            for character in first..=last {
+                uppercase_characters[character as usize] = (character as i32 + difference) as u16;
+            }
+        }
+
+        for &(first, last) in alternating_ranges {
+            for character in (first..=last).step_by(2) {
+                uppercase_characters[character as usize] = character - 1;
+            }
+        }
+
+        for &(lowercase, uppercase) in characters {
+            uppercase_characters[lowercase as usize] = uppercase;
+        }
+
+        Self {
+            uppercase_characters,
+        }
+    }
+
     /// Reads the $UpCase file from the given filesystem into a new [`UpcaseTable`] object.
     pub(crate) fn read<T>(ntfs: &Ntfs, fs: &mut T) -> Result<Self>
     where
@@ -68,12 +381,76 @@ impl UpcaseTable {
         })
     }
 
+    /// Returns the raw bytes of this table, as stored in the $UpCase file.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        self.uppercase_characters
+            .iter()
+            .flat_map(|character| character.to_le_bytes())
+            .collect()
+    }
+
     /// Returns the uppercase variant of the given UCS-2 character (i.e. a Unicode character
     /// from the Basic Multilingual Plane) based on the stored conversion table.
     /// A character without an uppercase equivalent is returned as-is.
     pub(crate) fn u16_to_uppercase(&self, character: u16) -> u16 {
         self.uppercase_characters[character as usize]
     }
+
+    /// Returns the known Windows version whose built-in table is identical to this one, if any.
+    pub(crate) fn version(&self) -> Option<NtfsUpcaseTableVersion> {
+        NtfsUpcaseTableVersion::ALL.iter().copied().find(|version| {
+            UpcaseTable::new(*version).uppercase_characters == self.uppercase_characters
+        })
+    }
+}
+
+/// Builds the raw bytes of the built-in $UpCase table of the given Windows version for a new filesystem.
+#[cfg(feature = "std")]
+pub(crate) fn build_upcase_table(version: NtfsUpcaseTableVersion) -> Vec<u8> {
+    UpcaseTable::new(version).to_bytes()
+}
+
+/// Builds the raw value of the $Info data stream of the $UpCase file for the given raw Upcase Table,
+/// which is the built-in one of the given Windows version.
+#[cfg(feature = "std")]
+pub(crate) fn build_upcase_info(version: NtfsUpcaseTableVersion, upcase_table: &[u8]) -> Vec<u8> {
+    let (os_major_version, os_minor_version, os_build_number) = version.os_version();
+
+    let mut value = Vec::with_capacity(UPCASE_INFO_SIZE);
+    value.extend_from_slice(&(UPCASE_INFO_SIZE as u32).to_le_bytes());
+    value.extend_from_slice(&0u32.to_le_bytes());
+    value.extend_from_slice(&upcase_table_crc64(upcase_table).to_le_bytes());
+    value.extend_from_slice(&os_major_version.to_le_bytes());
+    value.extend_from_slice(&os_minor_version.to_le_bytes());
+    value.extend_from_slice(&os_build_number.to_le_bytes());
+
+    // No Service Pack.
+    value.resize(UPCASE_INFO_SIZE, 0);
+    value
+}
+
+/// Calculates the CRC64 checksum of the given raw Upcase Table like Windows does for the $Info data stream.
+///
+/// This is a reflected CRC64 with the polynomial 0x9a6c9329ac4bc9b5 (in reflected form),
+/// whose initial value and final XOR value have all bits set.
+fn upcase_table_crc64(upcase_table: &[u8]) -> u64 {
+    const POLYNOMIAL: u64 = 0x9a6c_9329_ac4b_c9b5;
+
+    let crc = upcase_table.iter().fold(u64::MAX, |mut crc, byte| {
+        crc ^= *byte as u64;
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+        }
+
+        crc
+    });
+
+    !crc
 }
 
 #[cfg(test)]
@@ -95,4 +472,54 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_upcase_table_versions() {
+        let mut testfs1 = crate::helpers::tests::testfs1();
+        let mut ntfs = Ntfs::new(&mut testfs1).unwrap();
+
+        // testfs1 has been created by mkntfs, which writes the Upcase Table of Windows 8 along with an $Info stream.
+        let upcase_table = UpcaseTable::read(&ntfs, &mut testfs1).unwrap();
+        let windows8 = UpcaseTable::new(NtfsUpcaseTableVersion::Windows8);
+        assert_eq!(
+            upcase_table.uppercase_characters,
+            windows8.uppercase_characters
+        );
+        assert_eq!(
+            ntfs.upcase_table_version(&mut testfs1).unwrap(),
+            Some(NtfsUpcaseTableVersion::Windows8)
+        );
+
+        let upcase_info = ntfs.upcase_info(&mut testfs1).unwrap().unwrap();
+        assert_eq!(upcase_info.length(), 32);
+        assert!(upcase_info.matches(&windows8.to_bytes()));
+
+        // This CRC64 is stored in $UpCase:$Info by Windows 10 (version 10.0.19041) when formatting a volume.
+        let crc64 = upcase_table_crc64(&build_upcase_table(NtfsUpcaseTableVersion::Windows8));
+        assert_eq!(crc64, 0xdadc_7e77_6b1b_690c);
+        assert_eq!(upcase_info.crc64(), crc64);
+
+        // Windows doesn't convert all lowercase characters of recent Unicode versions.
+        assert_eq!(windows8.u16_to_uppercase(0x0265), 0x0265);
+
+        // A modified table is reported as unknown.
+        let data_position = {
+            let upcase_file = ntfs
+                .file(&mut testfs1, KnownNtfsFileRecordNumber::UpCase as u64)
+                .unwrap();
+            let data_item = upcase_file.data(&mut testfs1, "").unwrap().unwrap();
+            let data_value = data_item.to_attribute().value().unwrap();
+            data_value.data_position().unwrap()
+        };
+        let position = data_position as usize + b'a' as usize * mem::size_of::<u16>();
+        testfs1.get_mut()[position] = b'a';
+        assert_eq!(ntfs.upcase_table_version(&mut testfs1).unwrap(), None);
+
+        // The built-in table can replace it.
+        ntfs.use_default_upcase_table(NtfsUpcaseTableVersion::Windows8);
+        assert_eq!(
+            ntfs.upcase_table().u16_to_uppercase(b'a' as u16),
+            b'A' as u16
+        );
+    }
 }